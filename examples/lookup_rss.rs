@@ -0,0 +1,101 @@
+//! Measures the peak RSS of looking up one large blob, to compare builds with and without the `mmap` feature.
+//!
+//! `lookup_rss write <dir> [MiB]` stores a blob of that size (default 256) uncompressed in a new store at `<dir>`,
+//! `lookup_rss read <dir>` looks it up again in a fresh process and prints the peak RSS from `/proc/self/status`.
+
+use std::{env, fs, path::PathBuf, process::ExitCode};
+
+use evs::{
+    objects::Object,
+    store::{HashAlgo, HashDisplay, ObjectStore as _, Store},
+};
+use tracing::Span;
+
+fn store(dir: PathBuf) -> Store {
+    Store::new(dir, false, HashAlgo::Sha256, 0, u64::MAX, false)
+}
+
+fn peak_rss() -> String {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find(|line| line.starts_with("VmHWM:"))
+                .map(|line| line.trim_start_matches("VmHWM:").trim().to_owned())
+        })
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let span = Span::current();
+
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["write", dir, rest @ ..] => {
+            let mib = rest.first().map_or(Ok(256), |mib| mib.parse::<usize>());
+
+            let Ok(mib) = mib else {
+                eprintln!("The size has to be a number of MiB.");
+
+                return ExitCode::FAILURE;
+            };
+
+            let store = store(PathBuf::from(dir));
+
+            store.create_layout(&span).unwrap();
+
+            // Pseudo-random bytes, so the stored object is about as large as the blob
+            let mut state = 0x2545_f491_4f6c_dd1du64;
+            let content = (0..mib * 1024 * 1024)
+                .map(|_| {
+                    state ^= state << 13u32;
+                    state ^= state >> 7u32;
+                    state ^= state << 17u32;
+
+                    state.to_le_bytes()[0]
+                })
+                .collect::<Vec<_>>();
+
+            let hash = store.insert(&span, Object::Blob(content)).unwrap();
+
+            fs::write(
+                PathBuf::from(dir).join("blob"),
+                HashDisplay(&hash).to_string(),
+            )
+            .unwrap();
+
+            println!("Stored {} MiB as \"{}\".", mib, HashDisplay(&hash));
+        }
+        ["read", dir] => {
+            let name = fs::read_to_string(PathBuf::from(dir).join("blob")).unwrap();
+
+            let (_, obj) = store(PathBuf::from(dir)).lookup(&span, &name).unwrap();
+
+            let Object::Blob(content) = obj else {
+                eprintln!("\"{}\" is not a blob.", name);
+
+                return ExitCode::FAILURE;
+            };
+
+            println!(
+                "Looked up {} bytes with mmap {}, peak RSS {}.",
+                content.len(),
+                if cfg!(feature = "mmap") { "on" } else { "off" },
+                peak_rss()
+            );
+        }
+        _ => {
+            eprintln!("Usage: lookup_rss write <dir> [MiB] | lookup_rss read <dir>");
+
+            return ExitCode::FAILURE;
+        }
+    }
+
+    ExitCode::SUCCESS
+}
@@ -1,24 +1,41 @@
 use std::{
     borrow::Cow,
-    io::{Write as _, stdout},
+    env,
+    io::{self, Write as _, stdout},
+    iter::once,
     path::{Path, PathBuf},
-    time::SystemTime,
+    sync::Mutex,
+    time::{Duration, SystemTime},
 };
 
 use ahash::AHashSet;
-use clap::{ArgAction, CommandFactory as _, Parser, Subcommand, ValueHint};
-use clap_complete::ArgValueCompleter;
+use clap::{
+    ArgAction, CommandFactory as _, Parser, Subcommand, ValueEnum, ValueHint, value_parser,
+};
+use clap_complete::{ArgValueCompleter, Shell, generate};
+use glob::Pattern;
+use rayon::ThreadPoolBuilder;
+use time::OffsetDateTime;
 use tracing::{Span, info, trace};
 
 use crate::{
-    diff::DiffSide,
+    config::{CONFIG_FILE, Config},
+    diff::{DiffMode, DiffSide},
     error::{CorruptState, EvsError},
-    objects::Object,
-    repo::Repository,
-    store::HashDisplay,
-    util::{partial_canonicalize, repo_ref_completer},
+    objects::{DateDisplay, Object, ObjectJson},
+    repo::{Author, DEFAULT_MAX_TREE_DEPTH, OpenMode, Repository, ResetMode, Subtrees},
+    store::{DEFAULT_CHUNK_SIZE, DEFAULT_COMPRESSION_LEVEL, HashAlgo, HashDisplay},
+    summary,
+    util::{
+        Progress, SizeDisplay, edit_message, get_color, local_now, parse_date, parse_duration,
+        parse_identity, partial_canonicalize, repo_ref_completer, set_summary_sink,
+        walk_stack_size,
+    },
 };
 
+/// Written to the file the editor opens when committing without `-m`.
+const COMMIT_MESSAGE_TEMPLATE: &str = "\n# Enter the commit message. Lines starting with '#' are ignored, an empty message aborts the commit.\n";
+
 pub const VERBOSITY_NONE: u8 = 0;
 pub const VERBOSITY_LOG: u8 = 1;
 pub const VERBOSITY_TRACE: u8 = 2;
@@ -29,6 +46,10 @@ pub const VERBOSITY_ALL: u8 = 3;
 /// Basically a git clone.
 #[derive(Parser, Debug)]
 #[command(version, about = "Ev source control")]
+#[allow(
+    clippy::struct_excessive_bools,
+    reason = "These are independent flags."
+)]
 pub struct Cli {
     /// Increases the verbosity level by one each time it appears.
     #[arg(short, action(ArgAction::Count), global(true))]
@@ -42,14 +63,67 @@ pub struct Cli {
     #[arg(long, global(true))]
     pub force_color: bool,
 
+    /// Suppresses summary messages, but still prints errors.
+    #[arg(short, long, global(true))]
+    pub quiet: bool,
+
+    /// Searches for the repository from this path instead of the current directory, relative paths are then resolved against its workspace.
+    #[arg(long, global(true), value_hint(ValueHint::DirPath))]
+    pub repo: Option<PathBuf>,
+
+    /// Writes progress events of long operations to stderr in the given format.
+    #[arg(long, global(true))]
+    pub progress: Option<ProgressFormat>,
+
+    /// The gzip level for newly written objects, from 0 (none) to 9 (best).
+    #[arg(
+        long,
+        global(true),
+        default_value_t = DEFAULT_COMPRESSION_LEVEL,
+        value_parser(value_parser!(u32).range(0..=9))
+    )]
+    pub compression: u32,
+
+    /// Files larger than this many bytes are stored as chunks of this size.
+    #[arg(
+        long,
+        global(true),
+        default_value_t = DEFAULT_CHUNK_SIZE,
+        value_parser(value_parser!(u64).range(1..))
+    )]
+    pub chunk_size: u64,
+
+    /// Reads every object from disk instead of caching recently looked up ones.
+    #[arg(long, global(true))]
+    pub no_cache: bool,
+
+    /// Directories and trees nested deeper than this are rejected instead of walked.
+    #[arg(long, global(true), default_value_t = DEFAULT_MAX_TREE_DEPTH)]
+    pub max_tree_depth: usize,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ProgressFormat {
+    /// One JSON object per line, e.g. `{"phase":"hashing","done":1,"total":null}`.
+    Json,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Initializes an evs repository in the current directory.
     Init {
+        /// Whether to create the workspace and its missing parent directories or not.
+        #[arg(short, long)]
+        parents: bool,
+        /// Makes the repository group-writable, so it can be shared between users of a group.
+        #[arg(long)]
+        shared: bool,
+        /// The digest objects are named by, this cannot be changed later.
+        #[arg(long, value_enum, default_value_t)]
+        hash: HashAlgo,
         /// The location of the workspace.
         #[arg(value_hint(ValueHint::DirPath))]
         path: Option<PathBuf>,
@@ -59,18 +133,71 @@ pub enum Commands {
         /// Whether to check all objects in the store or only the required ones.
         #[arg(short, long)]
         all: bool,
+        /// Skips the check if the store has not changed since the last successful one.
+        #[arg(long)]
+        fast: bool,
+        /// Reads every object again instead of only checking that the blobs verified by earlier checks exist.
+        #[arg(long)]
+        full: bool,
+        /// Moves unreadable objects into `.evs/corrupt` and reports them instead of stopping at the first one.
+        #[arg(long, conflicts_with("fast"))]
+        repair: bool,
+        /// The number of threads to check objects with, defaults to one per core.
+        #[arg(
+            short,
+            long,
+            value_parser(value_parser!(u32).range(1..)),
+            value_hint(ValueHint::Other)
+        )]
+        jobs: Option<u32>,
+    },
+    /// Validates only the objects reachable from the given ref.
+    Verify {
+        #[arg(
+            default_value = "HEAD",
+            add(ArgValueCompleter::new(repo_ref_completer))
+        )]
+        r#ref: String,
+    },
+    /// Checks the signature of a commit, unsigned commits are reported as such.
+    VerifyCommit {
+        #[arg(
+            default_value = "HEAD",
+            add(ArgValueCompleter::new(repo_ref_completer))
+        )]
+        r#ref: String,
     },
     /// Prints the given object from the store.
     Cat {
-        /// Prints the raw bytes of an object in msgpack format.
+        /// Prints the raw bytes of an object in msgpack format, as stored, so chunk lists are not reassembled.
         #[arg(short, long)]
         raw: bool,
+        /// Prints the object as pretty JSON, with hashes as hex and raw bytes as base64.
+        #[arg(long, conflicts_with("raw"))]
+        json: bool,
+        /// Prints only the kind of the object and its size in bytes or entries.
+        #[arg(short, long, conflicts_with_all(["raw", "json"]))]
+        r#type: bool,
         /// The object to print.
         #[arg(add(ArgValueCompleter::new(repo_ref_completer)))]
         r#ref: String,
     },
     /// Adds the given files and directories to the evs store and stage.
     Add {
+        /// Whether to skip unreadable files and directories with a warning instead of failing.
+        #[arg(long)]
+        skip_unreadable: bool,
+        /// The number of threads to hash files with, defaults to one per core.
+        #[arg(
+            short,
+            long,
+            value_parser(value_parser!(u32).range(1..)),
+            value_hint(ValueHint::Other)
+        )]
+        jobs: Option<u32>,
+        /// Stages the whole workspace, ignoring the given paths.
+        #[arg(short, long)]
+        all: bool,
         /// The list of files and directories to add.
         #[arg(value_hint(ValueHint::AnyPath))]
         paths: Vec<PathBuf>,
@@ -80,42 +207,132 @@ pub enum Commands {
         /// Whether to remove the given path entirely or merely unstage changes.
         #[arg(short, long)]
         delete: bool,
+        /// Keeps the files of `--delete` in the workspace instead of deleting them.
+        #[arg(long, requires("delete"))]
+        cached: bool,
+        /// Deletes files from the workspace without asking for confirmation.
+        #[arg(short, long, requires("delete"), conflicts_with("cached"))]
+        yes: bool,
         /// The list of files and directories to remove.
         #[arg(value_hint(ValueHint::AnyPath))]
         paths: Vec<PathBuf>,
     },
     /// Commits the current stage to the commit chain.
     Commit {
-        /// Whether to modify the previous commit instead of creating a new one or not.
+        /// Whether to modify the previous commit instead of creating a new one or not, keeping its committer and date unless given and always its author.
         #[arg(long)]
         amend: bool,
-        /// The commit message, currently not optional.
+        /// The commit message, opens `$EDITOR` if not given and not amending.
         #[arg(short, long, value_hint(ValueHint::Other))]
         message: Option<String>,
-        /// The committer name, currently not optional.
+        /// The committer name, defaults to `EVS_AUTHOR_NAME` or `user.name` of the config.
         #[arg(short, long, value_hint(ValueHint::Username))]
         name: Option<String>,
-        /// The committer email, currently not optional.
+        /// The committer email, defaults to `EVS_AUTHOR_EMAIL` or `user.email` of the config.
         #[arg(short, long, value_hint(ValueHint::Other))]
         email: Option<String>,
+        /// Whether to skip the length limits on the commit info or not.
+        #[arg(long)]
+        unlimited: bool,
+        /// Allows committing without an email.
+        #[arg(long)]
+        anonymous: bool,
+        /// Skips the pre-commit and post-commit hooks.
+        #[arg(long)]
+        no_verify: bool,
+        /// Allows committing a stage that is the same as the tree of the parent commit.
+        #[arg(long)]
+        allow_empty: bool,
+        /// The RFC 3339 commit date to use instead of now, e.g. `2024-01-31T12:00:00Z`.
+        #[arg(
+            long,
+            env("EVS_COMMIT_DATE"),
+            value_parser(parse_date),
+            value_hint(ValueHint::Other)
+        )]
+        date: Option<OffsetDateTime>,
+        /// Who wrote the change as `Name <email>`, defaults to the committer.
+        #[arg(long, value_parser(parse_identity), value_hint(ValueHint::Other))]
+        author: Option<(String, String)>,
+        /// The RFC 3339 date the change was written at, defaults to the commit date.
+        #[arg(long, value_parser(parse_date), value_hint(ValueHint::Other))]
+        author_date: Option<OffsetDateTime>,
+        /// Signs the commit with the Ed25519 key in `.evs/signing_key`.
+        #[arg(long)]
+        sign: bool,
     },
     /// Prints the commit log of a commit.
     Log {
         /// The maximum number of commits to log.
         #[arg(short, long, default_value_t = 5, value_hint(ValueHint::Other))]
         limit: usize,
-        /// Prints every commit on only one line.
+        /// The maximum number of generations to follow along all parents.
+        #[arg(long, value_hint(ValueHint::Other))]
+        depth: Option<usize>,
+        /// Prints every commit on only one line, as the short hash and the first line of the message.
         #[arg(short, long)]
         oneline: bool,
+        /// Prints every commit with `%h`, `%H`, `%an`, `%ae`, `%ad`, `%cn`, `%ce`, `%cd` and `%s` replaced by its short hash, hash, author name, email and date, committer name, email and date and subject.
+        #[arg(long, conflicts_with("oneline"), value_hint(ValueHint::Other))]
+        format: Option<String>,
+        /// Prints one stable, machine-readable record per commit, see `Repository::log`.
+        #[arg(long, conflicts_with_all(["oneline", "format"]))]
+        porcelain: bool,
         /// The commit to start the log from.
         #[arg(
             default_value = "HEAD",
             add(ArgValueCompleter::new(repo_ref_completer))
         )]
         r#ref: String,
+        /// Only prints the commits that changed this file or directory.
+        #[arg(value_hint(ValueHint::AnyPath))]
+        path: Option<PathBuf>,
+    },
+    /// Prints each line of a file with the commit that last changed it.
+    Blame {
+        /// The commit to start from.
+        #[arg(
+            long,
+            default_value = "HEAD",
+            add(ArgValueCompleter::new(repo_ref_completer))
+        )]
+        r#ref: String,
+        /// The file to annotate.
+        #[arg(value_hint(ValueHint::FilePath))]
+        path: PathBuf,
     },
     /// Collects all unreferenced store objects and deletes them.
-    Gc,
+    Gc {
+        /// Keeps unreferenced objects written more recently than this, e.g. `90s`, `5m` or `1d`.
+        #[arg(
+            long,
+            default_value = "5m",
+            value_parser(parse_duration),
+            value_hint(ValueHint::Other)
+        )]
+        grace: Duration,
+        /// Keeps everything HEAD pointed at more recently than this, according to the reflog.
+        #[arg(
+            long,
+            default_value = "14d",
+            value_parser(parse_duration),
+            value_hint(ValueHint::Other)
+        )]
+        keep_for: Duration,
+        /// Deletes without asking for confirmation.
+        #[arg(short, long)]
+        yes: bool,
+        /// Afterwards stores older versions of files as deltas against newer ones
+        /// and moves all reachable objects into a pack file.
+        #[arg(long)]
+        pack: bool,
+    },
+    /// Deletes all objects unreachable from HEAD, the stage, the branches and the tags right away.
+    Prune {
+        /// Only prints the names of the objects that would be deleted.
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
     /// Prints the resolved store object of a given path.
     Resolve {
         /// The store expression to resolve.
@@ -143,15 +360,46 @@ pub enum Commands {
             add(ArgValueCompleter::new(repo_ref_completer))
         )]
         to: Option<String>,
+        /// Prints the number of changed lines per file instead of the full diff.
+        #[arg(long)]
+        stat: bool,
+        /// Prints only the path and whether it was added, modified or deleted.
+        #[arg(long, conflicts_with("stat"))]
+        name_status: bool,
+        /// Diffs binary files as text with invalid UTF-8 replaced instead of as a hex dump.
+        #[arg(long, conflicts_with_all(["stat", "name_status"]))]
+        text: bool,
+        /// Prints one stable, machine-readable record per changed file, see `DiffMode::Porcelain`.
+        #[arg(long, conflicts_with_all(["stat", "name_status", "text"]))]
+        porcelain: bool,
+        /// A glob pattern of repository paths to leave out, can be given multiple times.
+        #[arg(long, value_hint(ValueHint::Other))]
+        exclude: Vec<Pattern>,
         /// The paths to compare. Defaults to the current directory.
         // TODO: FIX?
         #[arg(default_value = ".", value_hint(ValueHint::AnyPath))]
         paths: Vec<PathBuf>,
     },
     /// Prints the repository status including commit status, changes, staged changes and object count.
-    Status,
+    Status {
+        /// A glob pattern of repository paths to leave out, can be given multiple times.
+        #[arg(long, value_hint(ValueHint::Other))]
+        exclude: Vec<Pattern>,
+        /// Prints one stable, machine-readable record per changed path, see `Repository::status`.
+        #[arg(long)]
+        porcelain: bool,
+    },
     /// Shows the diff generated by the commit of the given path.
     Show {
+        /// Prints the number of changed lines per file instead of the full diff.
+        #[arg(long)]
+        stat: bool,
+        /// Prints only the path and whether it was added, modified or deleted.
+        #[arg(long, conflicts_with("stat"))]
+        name_status: bool,
+        /// Diffs binary files as text with invalid UTF-8 replaced instead of as a hex dump.
+        #[arg(long, conflicts_with_all(["stat", "name_status"]))]
+        text: bool,
         /// The commit to show the diff of.
         #[arg(add(ArgValueCompleter::new(repo_ref_completer)))]
         r#ref: String,
@@ -161,10 +409,181 @@ pub enum Commands {
         /// Whether or not to discard staged changes.
         #[arg(short, long)]
         force: bool,
+        /// Also deletes untracked files that are not ignored.
+        #[arg(long)]
+        clean: bool,
         /// The commit to checkout.
         #[arg(add(ArgValueCompleter::new(repo_ref_completer)))]
         r#ref: String,
     },
+    /// Overwrites workspace files with their staged content or their content in a commit.
+    Restore {
+        /// The commit to take the files from instead of the stage.
+        #[arg(short, long, add(ArgValueCompleter::new(repo_ref_completer)))]
+        source: Option<String>,
+        /// The files and directories to restore.
+        #[arg(required(true), value_hint(ValueHint::AnyPath))]
+        paths: Vec<PathBuf>,
+    },
+    /// Moves HEAD and the branch it is on to a commit, resetting the stage unless `--soft`.
+    Reset {
+        /// Only moves HEAD, keeping the stage.
+        #[arg(long, conflicts_with("hard"))]
+        soft: bool,
+        /// Also restores the workspace to the commit, discarding local changes.
+        #[arg(long)]
+        hard: bool,
+        /// Skips the confirmation of `--hard`.
+        #[arg(short, long, requires("hard"))]
+        force: bool,
+        /// The commit to reset to.
+        #[arg(default_value("HEAD"), add(ArgValueCompleter::new(repo_ref_completer)))]
+        r#ref: String,
+    },
+    /// Prints the number of commits between an ancestor and a descendant commit.
+    Count {
+        /// The ancestor commit to count from.
+        #[arg(add(ArgValueCompleter::new(repo_ref_completer)))]
+        from: String,
+        /// The descendant commit to count to.
+        #[arg(
+            default_value = "HEAD",
+            add(ArgValueCompleter::new(repo_ref_completer))
+        )]
+        to: String,
+    },
+    /// Exports the graph of all reachable store objects in the Graphviz DOT format.
+    Graph {
+        /// The file to write the graph to, `-` for stdout.
+        #[arg(default_value = "-", value_hint(ValueHint::FilePath))]
+        output: PathBuf,
+    },
+    /// Rebuilds a missing or corrupt stage.
+    ResetStage {
+        /// Rebuilds the stage from the tree of the current commit.
+        #[arg(long, required(true))]
+        from_head: bool,
+    },
+    /// Rewrites all store objects with the current compression setting.
+    Recompress,
+    /// Counts the objects in the store by kind and sums their sizes.
+    CountObjects,
+    /// Recreates a missing store directory and its bootstrap objects.
+    RepairStore,
+    /// Prints metadata about the repository.
+    Info,
+    /// Lists the branches or creates a new one at HEAD.
+    Branch {
+        /// The name of the branch to create.
+        #[arg(value_hint(ValueHint::Other))]
+        name: Option<String>,
+    },
+    /// Lists the entries of a tree, or of the tree of a commit, one per line.
+    LsTree {
+        /// Lists the entries of subtrees instead of the subtrees themselves.
+        #[arg(short, long)]
+        recursive: bool,
+        /// The tree or commit to list.
+        #[arg(add(ArgValueCompleter::new(repo_ref_completer)))]
+        r#ref: String,
+    },
+    /// Lists the tags or creates a new one.
+    Tag {
+        /// The name of the tag to create.
+        #[arg(value_hint(ValueHint::Other))]
+        name: Option<String>,
+        /// The object to tag.
+        #[arg(default_value("HEAD"), add(ArgValueCompleter::new(repo_ref_completer)))]
+        r#ref: String,
+        /// Creates an annotated tag object with this message.
+        #[arg(short, long, value_hint(ValueHint::Other))]
+        message: Option<String>,
+        /// The tagger recorded in an annotated tag.
+        #[arg(long, requires("message"), value_hint(ValueHint::Username))]
+        tagger: Option<String>,
+    },
+    /// Writes the tree of a commit as a tar archive.
+    Archive {
+        /// The file to write, stdout if not given.
+        #[arg(short, long, value_hint(ValueHint::FilePath))]
+        output: Option<PathBuf>,
+        /// The tree or commit to archive.
+        #[arg(
+            default_value = "HEAD",
+            add(ArgValueCompleter::new(repo_ref_completer))
+        )]
+        r#ref: String,
+    },
+    /// Writes all objects reachable from a ref into a single file that `unbundle` imports.
+    Bundle {
+        /// The bundle file to write.
+        #[arg(short, long, value_hint(ValueHint::FilePath))]
+        output: PathBuf,
+        /// The ref to bundle, a branch or HEAD on a branch is recreated by `unbundle`.
+        #[arg(
+            default_value = "HEAD",
+            add(ArgValueCompleter::new(repo_ref_completer))
+        )]
+        r#ref: String,
+    },
+    /// Imports the objects of a bundle and creates the branch it was made from.
+    Unbundle {
+        /// The branch to create instead of the one the bundle was made from.
+        #[arg(short, long, value_hint(ValueHint::Other))]
+        branch: Option<String>,
+        /// The bundle file to read.
+        #[arg(value_hint(ValueHint::FilePath))]
+        input: PathBuf,
+    },
+    /// Merges a commit into HEAD and records the result as a commit with both as parents.
+    Merge {
+        /// The commit message, defaults to "Merge <ref>".
+        #[arg(short, long, value_hint(ValueHint::Other))]
+        message: Option<String>,
+        /// The committer name, defaults to `EVS_AUTHOR_NAME` or `user.name` of the config.
+        #[arg(short, long, value_hint(ValueHint::Username))]
+        name: Option<String>,
+        /// The committer email, defaults to `EVS_AUTHOR_EMAIL` or `user.email` of the config.
+        #[arg(short, long, value_hint(ValueHint::Other))]
+        email: Option<String>,
+        /// The commit to merge.
+        #[arg(add(ArgValueCompleter::new(repo_ref_completer)))]
+        r#ref: String,
+    },
+    /// Commits the changes of a single commit on top of HEAD, with its message and author.
+    CherryPick {
+        /// The committer name, defaults to `EVS_AUTHOR_NAME` or `user.name` of the config.
+        #[arg(short, long, value_hint(ValueHint::Username))]
+        name: Option<String>,
+        /// The committer email, defaults to `EVS_AUTHOR_EMAIL` or `user.email` of the config.
+        #[arg(short, long, value_hint(ValueHint::Other))]
+        email: Option<String>,
+        /// The commit to apply, merges are applied relative to their first parent.
+        #[arg(add(ArgValueCompleter::new(repo_ref_completer)))]
+        r#ref: String,
+    },
+    /// Commits the inverse of the changes of a single commit on top of HEAD.
+    Revert {
+        /// The committer name, defaults to `EVS_AUTHOR_NAME` or `user.name` of the config.
+        #[arg(short, long, value_hint(ValueHint::Username))]
+        name: Option<String>,
+        /// The committer email, defaults to `EVS_AUTHOR_EMAIL` or `user.email` of the config.
+        #[arg(short, long, value_hint(ValueHint::Other))]
+        email: Option<String>,
+        /// The commit to revert, merges are reverted relative to their first parent.
+        #[arg(add(ArgValueCompleter::new(repo_ref_completer)))]
+        r#ref: String,
+    },
+    /// Prints the latest common ancestor of two commits, or the null hash if there is none.
+    #[clap(hide(true))]
+    MergeBase {
+        /// The first commit.
+        #[arg(add(ArgValueCompleter::new(repo_ref_completer)))]
+        a: String,
+        /// The second commit.
+        #[arg(add(ArgValueCompleter::new(repo_ref_completer)))]
+        b: String,
+    },
     #[doc(hidden)]
     #[clap(hide(true))]
     Mangen {
@@ -174,6 +593,30 @@ pub enum Commands {
     #[doc(hidden)]
     #[clap(hide(true))]
     Completion,
+    /// Prints a static completion script for the given shell.
+    #[clap(hide(true))]
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+impl Cli {
+    /// Runs the command, with `--quiet` dropping its summary lines while it runs but not its errors.
+    #[inline]
+    pub fn run(&self) -> Result<(), EvsError> {
+        if !self.quiet {
+            return self.command.run(self);
+        }
+
+        let previous = set_summary_sink(Some(Box::new(io::sink())));
+
+        let res = self.command.run(self);
+
+        set_summary_sink(previous);
+
+        res
+    }
 }
 
 impl Commands {
@@ -189,13 +632,18 @@ impl Commands {
         let current = Span::current();
 
         macro_rules! get_repo {
-            () => {{
-                info!(
-                    "Searching for repository starting from {:?}:",
-                    AsRef::<Path>::as_ref(".")
-                );
+            () => {
+                get_repo!(OpenMode::Normal)
+            };
+            ($mode:expr) => {{
+                let start = options
+                    .repo
+                    .as_deref()
+                    .unwrap_or(AsRef::<Path>::as_ref("."));
+
+                info!("Searching for repository starting from {:?}:", start);
 
-                let repo = Repository::find(&current, ".", options)?;
+                let repo = Repository::find(&current, start, $mode, options)?;
 
                 info!("Found repository at {:?}.", repo.repository);
 
@@ -204,46 +652,177 @@ impl Commands {
         }
 
         match self {
-            Commands::Init { path } => {
+            Commands::Init {
+                parents,
+                shared,
+                hash,
+                path,
+            } => {
                 let path = path.as_ref().map_or(".".into(), ToOwned::to_owned);
 
                 info!("Creating repository at {:?}...", path);
 
-                let repo = Repository::create(&current, path, options)?;
+                let repo = Repository::create(&current, path, *parents, *shared, *hash, options)?;
 
                 info!("Created repository.");
 
                 drop(repo);
 
-                println!("Repository initialized successfully.");
+                summary!("Repository initialized successfully.");
             }
-            Commands::Check { all } => {
-                let repo = get_repo!();
+            Commands::Check {
+                all,
+                repair: true,
+                jobs,
+                ..
+            } => {
+                let repo = get_repo!(OpenMode::SkipStage);
+
+                let repair = with_jobs(*jobs, options, || repo.check_repair(&current, *all))??;
+
+                drop(repo);
+
+                for (name, err) in &repair.quarantined {
+                    println!("Quarantined {:?}: {}", name, err);
+                }
+
+                let mut missing = repair.missing.iter().collect::<Vec<_>>();
+
+                missing.sort_unstable();
+
+                for hash in missing {
+                    println!("Missing \"{}\"", HashDisplay(hash));
+                }
+
+                if !repair.missing.is_empty() {
+                    return Err(EvsError::CorruptStateDetected(
+                        CorruptState::MissingObjects(repair.missing),
+                    ));
+                }
+
+                summary!(
+                    "Repository checked, {} object(s) quarantined.",
+                    repair.quarantined.len()
+                );
+            }
+            Commands::Check {
+                all,
+                fast,
+                full,
+                jobs,
+                ..
+            } => {
+                let repo = get_repo!(OpenMode::SkipStage);
 
-                repo.check(&current, *all)?;
+                let unchanged =
+                    with_jobs(*jobs, options, || repo.check(&current, *all, *fast, *full))??;
 
                 drop(repo);
 
-                println!("Repository checked successfully.");
+                if let Some(checked) = unchanged {
+                    summary!(
+                        "Repository unchanged since the last check at {}.",
+                        DateDisplay(&checked)
+                    );
+                } else {
+                    summary!("Repository checked successfully.");
+                }
+            }
+            Commands::Verify { r#ref } => {
+                let repo = get_repo!();
+
+                let (hash, count) = repo.verify(&current, r#ref)?;
+
+                summary!(
+                    "Verified {} object(s) reachable from \"{}\".",
+                    count,
+                    HashDisplay(&hash)
+                );
+            }
+            Commands::VerifyCommit { r#ref } => {
+                let repo = get_repo!();
+
+                match repo.verify_commit(&current, r#ref)? {
+                    (hash, Some(key)) => println!(
+                        "Commit \"{}\" has a good signature by key {}.",
+                        HashDisplay(&hash),
+                        HashDisplay(&key)
+                    ),
+                    (hash, None) => println!("Commit \"{}\" is unsigned.", HashDisplay(&hash)),
+                }
+            }
+            Commands::Cat {
+                r#type: true,
+                r#ref,
+                ..
+            } => {
+                let repo = get_repo!();
+
+                let (hash, info) = repo.object_info(&current, r#ref)?;
+
+                info!("Printing info of \"{}\":", HashDisplay(&hash));
+
+                println!("{}", info);
+            }
+            Commands::Cat {
+                raw: true, r#ref, ..
+            } => {
+                let repo = get_repo!();
+
+                let (hash, mut object) = repo.read_raw(&current, r#ref)?;
+
+                info!("Printing raw object \"{}\":", HashDisplay(&hash));
+
+                // Errors of the object are wrapped, failing to write to stdout is ignored like elsewhere
+                if let Err(e) = io::copy(&mut object, &mut stdout().lock())
+                    && let Some(inner) = e.into_inner()
+                    && let Ok(e) = inner.downcast::<EvsError>()
+                {
+                    return Err(*e);
+                }
             }
-            Commands::Cat { raw, r#ref } => {
+            Commands::Cat { json, r#ref, .. } => {
                 let repo = get_repo!();
 
-                let (hash, obj) = repo.lookup(&current, r#ref)?;
+                let (hash, obj) = match repo.lookup_raw(&current, r#ref)? {
+                    (hash, Object::ChunkList(chunks)) if !*json => {
+                        info!("Streaming chunked blob \"{}\":", HashDisplay(&hash));
+
+                        return print_chunked_blob(repo.chunk_reader(hash, chunks));
+                    }
+                    // JSON holds the whole content anyway
+                    (hash, Object::ChunkList(_)) => {
+                        repo.lookup(&current, format!("{}", HashDisplay(&hash)))?
+                    }
+                    found => found,
+                };
 
                 info!("Printing object \"{}\":", HashDisplay(&hash));
 
-                if !raw {
-                    println!("{}", obj);
+                if *json {
+                    // Serializing to a string with only string keys cannot fail
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&ObjectJson(&obj)).unwrap()
+                    );
                 } else {
-                    let content = rmp_serde::to_vec(&obj)?;
-
-                    let _ = stdout().write_all(&content);
+                    println!("{}", obj);
                 }
             }
-            Commands::Add { paths } => {
+            Commands::Add {
+                skip_unreadable,
+                jobs,
+                all,
+                paths,
+            } => {
                 let mut repo = get_repo!();
 
+                let paths = if *all {
+                    Cow::Owned(vec![repo.workspace.clone()])
+                } else {
+                    Cow::Borrowed(paths)
+                };
+
                 trace!("Adding {} paths:", paths.len());
 
                 let (set, map) = DiffSide::Tree(repo.info.stage()).read(
@@ -253,28 +832,64 @@ impl Commands {
                     &[AsRef::<Path>::as_ref("").to_path_buf()],
                     &[],
                     &AHashSet::new(),
+                    repo.max_depth,
                 )?;
 
                 drop(map);
 
-                for file in paths {
-                    repo.add(&current, file, &set, options)?;
+                let skipped = Mutex::new(Vec::new());
 
-                    info!("Added {:?}", file);
-                }
+                let subtrees = Subtrees::default();
+
+                let progress = Progress::new("adding", Some(paths.len()));
+
+                with_jobs(*jobs, options, || {
+                    for file in paths.iter() {
+                        repo.add(
+                            &current,
+                            workspace_path(&repo, options, file),
+                            &set,
+                            skip_unreadable.then_some(&skipped),
+                            &subtrees,
+                            options,
+                        )?;
+
+                        progress.step();
+
+                        info!("Added {:?}", file);
+                    }
+
+                    Ok::<(), EvsError>(())
+                })??;
 
                 info!("Finished adding.");
+
+                let skipped = skipped.into_inner().unwrap();
+
+                if !skipped.is_empty() {
+                    summary!("Skipped {} unreadable path(s):", skipped.len());
+
+                    for path in skipped {
+                        summary!("    {:?}", path);
+                    }
+                }
             }
-            Commands::Sub { delete, paths } => {
+            Commands::Sub {
+                delete,
+                cached,
+                yes,
+                paths,
+            } => {
                 let mut repo = get_repo!();
 
                 trace!("Removing {} paths:", paths.len());
 
-                for file in paths {
-                    repo.sub(&current, file, *delete)?;
+                let resolved = paths
+                    .iter()
+                    .map(|file| workspace_path(&repo, options, file))
+                    .collect::<Vec<_>>();
 
-                    info!("Removed {:?}", file);
-                }
+                repo.sub(&current, &resolved, *delete, *cached, *yes)?;
 
                 info!("Finished removing.");
             }
@@ -283,44 +898,101 @@ impl Commands {
                 message,
                 name,
                 email,
+                unlimited,
+                anonymous,
+                no_verify,
+                allow_empty,
+                date,
+                author,
+                author_date,
+                sign,
             } => {
                 let mut repo = get_repo!();
 
-                let time = SystemTime::now();
+                let mut time = date.unwrap_or_else(local_now);
+
+                let mut author = author.clone();
+                let mut author_date = *author_date;
 
                 let mut message = message.as_ref().map(Cow::Borrowed);
                 let mut name = name.as_ref().map(Cow::Borrowed);
                 let mut email = email.as_ref().map(Cow::Borrowed);
 
-                let mut amend_parent = None;
+                let mut amend_parents = None;
 
                 if *amend {
-                    let (_, Object::Commit(commit)) = repo.lookup(&current, "HEAD")? else {
-                        return Err(EvsError::CorruptStateDetected(
-                            CorruptState::HeadIsNotACommit,
-                        ));
+                    let commit = match repo.lookup(&current, "HEAD")? {
+                        (_, Object::Commit(commit)) => commit,
+                        (_, Object::Null) => return Err(EvsError::NothingToAmend),
+                        _ => {
+                            return Err(EvsError::CorruptStateDetected(
+                                CorruptState::HeadIsNotACommit,
+                            ));
+                        }
                     };
 
                     trace!("Amending with {:?}", commit);
 
+                    author.get_or_insert((commit.author_name.clone(), commit.author_email.clone()));
+                    author_date = author_date.or_else(|| commit.author_offset_date());
+
+                    // Keeps the original date unless the author or the date is overridden.
+                    if name.is_none()
+                        && email.is_none()
+                        && date.is_none()
+                        && let Some(original) = commit.offset_date()
+                    {
+                        time = original;
+                    }
+
                     message.get_or_insert(Cow::Owned(commit.msg));
                     name.get_or_insert(Cow::Owned(commit.name));
                     email.get_or_insert(Cow::Owned(commit.email));
-                    amend_parent = Some(commit.parent);
+                    amend_parents = Some(commit.parents);
+                }
+
+                if name.is_none() || (email.is_none() && !*anonymous) {
+                    let (default_name, default_email) = default_identity(&repo)?;
+
+                    name = name.or(default_name.map(Cow::Owned));
+
+                    if !*anonymous {
+                        email = email.or(default_email.map(Cow::Owned));
+                    }
                 }
 
                 let Some(name) = name else {
                     return Err(EvsError::MissingCommitInfo("committer name"));
                 };
 
+                if *anonymous {
+                    email.get_or_insert(Cow::Owned(String::new()));
+                }
+
                 let Some(email) = email else {
                     return Err(EvsError::MissingCommitInfo("commiter email"));
                 };
 
-                let Some(message) = message else {
-                    return Err(EvsError::MissingCommitInfo("commit message"));
+                let message = match message {
+                    Some(message) => message,
+                    None => Cow::Owned(edit_message(
+                        &current,
+                        &repo.repository,
+                        COMMIT_MESSAGE_TEMPLATE,
+                    )?),
                 };
 
+                let author = (author.is_some() || author_date.is_some()).then(|| {
+                    let (author_name, author_email) =
+                        author.unwrap_or_else(|| (name.to_string(), email.to_string()));
+
+                    Author {
+                        name: author_name,
+                        email: author_email,
+                        date: author_date.unwrap_or(time),
+                    }
+                });
+
                 trace!(
                     "Committing by {} <{}> at {:?} with message of length {}",
                     name,
@@ -331,36 +1003,109 @@ impl Commands {
 
                 let commit = repo.commit(
                     &current,
-                    amend_parent,
+                    amend_parents,
                     message.into_owned(),
                     name.into_owned(),
                     email.into_owned(),
                     time,
+                    author,
+                    *unlimited,
+                    !*no_verify,
+                    *allow_empty,
+                    *sign,
                     options,
                 )?;
 
                 info!("Finished committing.");
 
-                println!("HEAD is now at \"{}\".", HashDisplay(&commit));
+                summary!("HEAD is now at \"{}\".", HashDisplay(&commit));
             }
             Commands::Log {
                 r#ref,
                 limit,
+                depth,
                 oneline,
+                format,
+                porcelain,
+                path,
             } => {
                 let repo = get_repo!();
 
-                repo.log(&current, r#ref, *limit, *oneline, options)?;
+                repo.log(
+                    &current,
+                    r#ref,
+                    *limit,
+                    *depth,
+                    *oneline,
+                    format.as_deref(),
+                    *porcelain,
+                    path.as_deref()
+                        .map(|path| workspace_path(&repo, options, path))
+                        .as_deref(),
+                    options,
+                )?;
 
                 info!("Finished printing log.");
             }
-            Commands::Gc => {
+            Commands::Blame { r#ref, path } => {
                 let repo = get_repo!();
 
-                repo.gc(&current, options)?;
+                repo.blame(
+                    &current,
+                    r#ref,
+                    &workspace_path(&repo, options, path),
+                    options,
+                )?;
+
+                info!("Finished printing blame.");
+            }
+            Commands::Gc {
+                grace,
+                keep_for,
+                yes,
+                pack,
+            } => {
+                let repo = get_repo!();
+
+                let (count, size) = repo.gc(&current, *grace, *keep_for, *yes, options)?;
+
+                summary!(
+                    "Reclaimed {} object(s) with a total size of {}.",
+                    count,
+                    SizeDisplay(size, get_color(options))
+                );
+
+                if *pack {
+                    let (deltas, size, packed) = repo.pack(&current, options)?;
+
+                    summary!(
+                        "Stored {} object(s) as deltas, saving {}.",
+                        deltas,
+                        SizeDisplay(size, get_color(options))
+                    );
+
+                    summary!("Moved {} object(s) into a pack file.", packed);
+                }
 
                 info!("Finished collecting garbage.");
             }
+            Commands::Prune { dry_run } => {
+                let repo = get_repo!();
+
+                let (count, size) = repo.prune(&current, *dry_run, options)?;
+
+                if *dry_run {
+                    summary!("Would delete {} object(s).", count);
+                } else {
+                    summary!(
+                        "Reclaimed {} object(s) with a total size of {}.",
+                        count,
+                        SizeDisplay(size, get_color(options))
+                    );
+                }
+
+                info!("Finished pruning.");
+            }
             Commands::Resolve { r#ref } => {
                 let repo = get_repo!();
 
@@ -374,6 +1119,11 @@ impl Commands {
                 staged,
                 from,
                 to,
+                stat,
+                name_status,
+                text,
+                porcelain,
+                exclude,
                 paths,
             } => {
                 let repo = get_repo!();
@@ -413,7 +1163,7 @@ impl Commands {
                     paths
                         .iter()
                         .map(|p| {
-                            partial_canonicalize(&current, p)
+                            partial_canonicalize(&current, workspace_path(&repo, options, p))
                                 .map_err(|e| (e, p.clone()))?
                                 .strip_prefix(&repo.workspace)
                                 .map(Path::to_path_buf)
@@ -421,31 +1171,331 @@ impl Commands {
                         })
                         .collect::<Result<Vec<_>, _>>()?,
                     repo.get_ignores(&current, options)?,
+                    exclude,
+                    if *porcelain {
+                        DiffMode::Porcelain
+                    } else {
+                        DiffMode::from_flags(*stat, *name_status, *text)
+                    },
                     options,
                 )?;
 
                 info!("Finished diff.");
             }
-            Commands::Status => {
+            Commands::Status { exclude, porcelain } => {
                 let repo = get_repo!();
 
-                repo.status(&current, options)?;
+                repo.status(&current, exclude, *porcelain, options)?;
 
                 info!("Finished reporting status.");
             }
-            Commands::Show { r#ref } => {
+            Commands::Branch { name } => {
                 let repo = get_repo!();
 
-                repo.show(&current, r#ref, options)?;
+                if let Some(name) = name {
+                    repo.create_branch(&current, name, repo.info.head())?;
+
+                    summary!(
+                        "Created branch {:?} at \"{}\".",
+                        name,
+                        HashDisplay(&repo.info.head())
+                    );
+                } else {
+                    for (name, hash) in repo.list_branches(&current)? {
+                        let marker = if repo.info.branch() == Some(name.as_str()) {
+                            '*'
+                        } else {
+                            ' '
+                        };
+
+                        println!("{} {} \"{}\"", marker, name, HashDisplay(&hash));
+                    }
+                }
+            }
+            Commands::Info => {
+                let repo = get_repo!();
+
+                repo.info(&current, options)?;
+            }
+            Commands::Show {
+                stat,
+                name_status,
+                text,
+                r#ref,
+            } => {
+                let repo = get_repo!();
+
+                repo.show(
+                    &current,
+                    r#ref,
+                    DiffMode::from_flags(*stat, *name_status, *text),
+                    options,
+                )?;
 
                 info!("Finished showing commit.");
             }
-            Commands::Checkout { force, r#ref } => {
+            Commands::Restore { source, paths } => {
+                let repo = get_repo!();
+
+                let paths = paths
+                    .iter()
+                    .map(|path| workspace_path(&repo, options, path))
+                    .collect::<Vec<_>>();
+
+                let count = repo.restore(&current, &paths, source.as_deref())?;
+
+                summary!("Restored {} file(s).", count);
+            }
+            Commands::Checkout {
+                force,
+                clean,
+                r#ref,
+            } => {
+                let mut repo = get_repo!();
+
+                let hash = repo.checkout(&current, r#ref, *force, *clean, options)?;
+
+                summary!("Checked out \"{}\" successfully.", HashDisplay(&hash));
+
+                if repo.info.branch().is_none() {
+                    summary!("HEAD is now detached, new commits will not move any branch.");
+                }
+            }
+            Commands::Reset {
+                soft,
+                hard,
+                force,
+                r#ref,
+            } => {
                 let mut repo = get_repo!();
 
-                let hash = repo.checkout(&current, r#ref, *force, options)?;
+                let hash = repo.reset(
+                    &current,
+                    r#ref,
+                    ResetMode::from_flags(*soft, *hard),
+                    *force,
+                    options,
+                )?;
+
+                summary!("HEAD is now at \"{}\".", HashDisplay(&hash));
+            }
+            Commands::Count { from, to } => {
+                let repo = get_repo!();
+
+                let count = repo.count(&current, from, to)?;
+
+                trace!(
+                    "Counted {} commit(s) from \"{}\" to \"{}\".",
+                    count, from, to
+                );
+
+                println!("{}", count);
+            }
+            Commands::Graph { output } => {
+                let repo = get_repo!();
+
+                repo.graph(&current, output, options)?;
+
+                info!("Finished exporting graph.");
+            }
+            Commands::ResetStage { .. } => {
+                let mut repo = get_repo!(OpenMode::SkipStage);
+
+                let stage = repo.reset_stage(&current, options)?;
+
+                summary!("Stage is now at \"{}\".", HashDisplay(&stage));
+            }
+            Commands::Recompress => {
+                let repo = get_repo!();
+
+                let (count, before, after) = repo.store.recompress(&current)?;
+
+                let print_color = get_color(options);
+
+                summary!(
+                    "Recompressed {} object(s), store size went from {} to {}.",
+                    count,
+                    SizeDisplay(before, print_color),
+                    SizeDisplay(after, print_color)
+                );
+            }
+            Commands::CountObjects => {
+                let repo = get_repo!();
+
+                let stats = repo.store.stats(&current)?;
+
+                let print_color = get_color(options);
+
+                println!("  {} object(s) in the store", stats.count);
 
-                println!("Checked out \"{}\" successfully.", HashDisplay(&hash));
+                for (kind, count) in &stats.kinds {
+                    println!("    {} {}", count, kind);
+                }
+
+                println!(
+                    "  Size on disk is {}, decompressed {}",
+                    SizeDisplay(stats.compressed, print_color),
+                    SizeDisplay(stats.decompressed, print_color)
+                );
+            }
+            Commands::RepairStore => {
+                let repo = get_repo!(OpenMode::RepairStore);
+
+                let (head, stage) = repo.repair_store(&current, options)?;
+
+                summary!("Reinserted the bootstrap objects.");
+
+                if !head {
+                    summary!(
+                        "HEAD \"{}\" is still missing, restore its objects from a backup.",
+                        HashDisplay(&repo.info.head())
+                    );
+                }
+
+                if !stage {
+                    summary!(
+                        "Stage \"{}\" is still missing, restore its objects or use `evs reset-stage --from-head`.",
+                        HashDisplay(&repo.info.stage())
+                    );
+                }
+            }
+            Commands::LsTree { recursive, r#ref } => {
+                let repo = get_repo!();
+
+                for (hash, name) in repo.ls_tree(&current, r#ref, *recursive)? {
+                    println!("{} {}", HashDisplay(&hash), name.as_bytes().escape_ascii());
+                }
+
+                info!("Finished listing tree.");
+            }
+            Commands::Tag {
+                name,
+                r#ref,
+                message,
+                tagger,
+            } => {
+                let repo = get_repo!();
+
+                if let Some(name) = name {
+                    let (target, _) = repo.lookup(&current, r#ref)?;
+
+                    let hash = repo.create_tag(
+                        &current,
+                        name,
+                        target,
+                        message.clone(),
+                        tagger.clone().unwrap_or_default(),
+                        SystemTime::now(),
+                    )?;
+
+                    summary!("Created tag {:?} at \"{}\".", name, HashDisplay(&hash));
+                } else {
+                    for (name, hash) in repo.list_tags(&current)? {
+                        println!("{} \"{}\"", name, HashDisplay(&hash));
+                    }
+                }
+
+                info!("Finished tagging.");
+            }
+            Commands::Archive { output, r#ref } => {
+                let repo = get_repo!();
+
+                let tree = repo.archive(&current, r#ref, output.as_deref())?;
+
+                if let Some(output) = output {
+                    summary!("Archived \"{}\" to {:?}.", HashDisplay(&tree), output);
+                }
+
+                info!("Finished archiving.");
+            }
+            Commands::Bundle { output, r#ref } => {
+                let repo = get_repo!();
+
+                let (hash, count) = repo.bundle(&current, r#ref, output)?;
+
+                summary!(
+                    "Bundled {} object(s) reachable from \"{}\" to {:?}.",
+                    count,
+                    HashDisplay(&hash),
+                    output
+                );
+            }
+            Commands::Unbundle { branch, input } => {
+                let mut repo = get_repo!();
+
+                let (hash, branch, count) =
+                    repo.unbundle(&current, input, branch.as_deref(), options)?;
+
+                match branch {
+                    Some(branch) => summary!(
+                        "Imported {} object(s), branch {:?} is at \"{}\".",
+                        count,
+                        branch,
+                        HashDisplay(&hash)
+                    ),
+                    None => summary!(
+                        "Imported {} object(s) reachable from \"{}\".",
+                        count,
+                        HashDisplay(&hash)
+                    ),
+                }
+            }
+            Commands::Merge {
+                message,
+                name,
+                email,
+                r#ref,
+            } => {
+                let mut repo = get_repo!();
+
+                let (name, email) = committer(&repo, name.as_ref(), email.as_ref())?;
+
+                match repo.merge(
+                    &current,
+                    r#ref,
+                    message.clone(),
+                    name,
+                    email,
+                    local_now(),
+                    options,
+                )? {
+                    Some(commit) => summary!("Merged into \"{}\".", HashDisplay(&commit)),
+                    None => summary!("Already up to date."),
+                }
+
+                info!("Finished merging.");
+            }
+            Commands::CherryPick { name, email, r#ref } => {
+                let mut repo = get_repo!();
+
+                let (name, email) = committer(&repo, name.as_ref(), email.as_ref())?;
+
+                let commit =
+                    repo.cherry_pick(&current, r#ref, name, email, local_now(), options)?;
+
+                info!("Finished cherry-picking.");
+
+                summary!("HEAD is now at \"{}\".", HashDisplay(&commit));
+            }
+            Commands::Revert { name, email, r#ref } => {
+                let mut repo = get_repo!();
+
+                let (name, email) = committer(&repo, name.as_ref(), email.as_ref())?;
+
+                let commit = repo.revert(&current, r#ref, name, email, local_now(), options)?;
+
+                info!("Finished reverting.");
+
+                summary!("HEAD is now at \"{}\".", HashDisplay(&commit));
+            }
+            Commands::MergeBase { a, b } => {
+                let repo = get_repo!();
+
+                let base = repo.merge_base(&current, a, b, options)?;
+
+                println!("{}", HashDisplay(&base));
+
+                info!("Finished finding merge base.");
             }
             Commands::Mangen { dir } => {
                 let command = Cli::command();
@@ -453,8 +1503,259 @@ impl Commands {
                 clap_mangen::generate_to(command, dir).map_err(|e| (e, dir.clone()))?;
             }
             Commands::Completion => unreachable!("Fake command for completion engine"),
+            Commands::Completions { shell } => {
+                let mut command = Cli::command();
+
+                let name = command.get_name().to_owned();
+
+                generate(*shell, &mut command, name, &mut stdout());
+            }
         }
 
         Ok(())
     }
 }
+
+/// Relative paths given with `--repo` are meant relative to its workspace, not the current directory.
+fn workspace_path<'a>(
+    repo: &Repository,
+    options: &Cli,
+    path: &'a Path,
+) -> Cow<'a, Path> {
+    if options.repo.is_some() && path.is_relative() {
+        Cow::Owned(repo.workspace.join(path))
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+/// Runs `f` on a pool of `jobs` threads, or on the default pool with one per core.
+fn with_jobs<R: Send, F: FnOnce() -> R + Send>(
+    jobs: Option<u32>,
+    options: &Cli,
+    f: F,
+) -> Result<R, EvsError> {
+    let Some(jobs) = jobs else {
+        return Ok(f());
+    };
+
+    trace!("Using {} thread(s).", jobs);
+
+    Ok(ThreadPoolBuilder::new()
+        .num_threads(usize::try_from(jobs).unwrap_or(usize::MAX))
+        .stack_size(walk_stack_size(options.max_tree_depth))
+        .build()?
+        .install(f))
+}
+
+/// The given committer name and email, with missing ones taken from `default_identity`.
+fn committer(
+    repo: &Repository,
+    name: Option<&String>,
+    email: Option<&String>,
+) -> Result<(String, String), EvsError> {
+    let (default_name, default_email) = if name.is_none() || email.is_none() {
+        default_identity(repo)?
+    } else {
+        (None, None)
+    };
+
+    let Some(name) = name.cloned().or(default_name) else {
+        return Err(EvsError::MissingCommitInfo("committer name"));
+    };
+
+    let Some(email) = email.cloned().or(default_email) else {
+        return Err(EvsError::MissingCommitInfo("commiter email"));
+    };
+
+    Ok((name, email))
+}
+
+/// The committer name and email from `EVS_AUTHOR_NAME` and `EVS_AUTHOR_EMAIL`, then `.evs/config` and then the global config.
+/// Prints a chunked blob like the `Display` of `Object::Blob`, but without holding more than one chunk.
+fn print_chunked_blob(mut reader: impl io::Read) -> Result<(), EvsError> {
+    let mut stdout = stdout().lock();
+
+    // Failing to write to stdout is ignored like elsewhere
+    let _ = writeln!(stdout, "Blob:");
+
+    // Errors of the chunks are wrapped
+    if let Err(e) = io::copy(&mut reader, &mut EscapeAscii(&mut stdout))
+        && let Some(inner) = e.into_inner()
+        && let Ok(e) = inner.downcast::<EvsError>()
+    {
+        return Err(*e);
+    }
+
+    let _ = writeln!(stdout);
+
+    Ok(())
+}
+
+/// Writes everything as `escape_ascii` shows it.
+struct EscapeAscii<W: io::Write>(W);
+
+impl<W: io::Write> io::Write for EscapeAscii<W> {
+    fn write(
+        &mut self,
+        buf: &[u8],
+    ) -> io::Result<usize> {
+        write!(self.0, "{}", buf.escape_ascii())?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+fn default_identity(repo: &Repository) -> Result<(Option<String>, Option<String>), EvsError> {
+    let mut name = env::var("EVS_AUTHOR_NAME").ok();
+    let mut email = env::var("EVS_AUTHOR_EMAIL").ok();
+
+    for path in once(repo.repository.join(CONFIG_FILE)).chain(Config::global_path()) {
+        if name.is_some() && email.is_some() {
+            break;
+        }
+
+        let config = Config::load(&Span::current(), &path)?;
+
+        name = name.or(config.user.name);
+        email = email.or(config.user.email);
+    }
+
+    trace!("Default identity is {:?} <{:?}>.", name, email);
+
+    Ok((name, email))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use tracing::Span;
+
+    use crate::{
+        error::EvsError,
+        objects::Object,
+        repo::{OpenMode, Repository},
+        store::{HashDisplay, ObjectStore as _},
+        testing::{Capture, TempDir, cli},
+        util::set_summary_sink,
+    };
+
+    #[test]
+    fn quiet_init_prints_nothing_but_still_fails() {
+        let dir = TempDir::new();
+        let path = dir.path().to_str().unwrap();
+
+        let capture = Capture::default();
+        let previous = set_summary_sink(Some(Box::new(capture.clone())));
+
+        let first = cli(["-q", "init", path]).run();
+        let second = cli(["-q", "init", path]).run();
+
+        assert_eq!(capture.output(), "", "nothing is printed");
+
+        let other = TempDir::new();
+
+        let loud = cli(["init", other.path().to_str().unwrap()]).run();
+
+        set_summary_sink(previous);
+
+        assert!(first.is_ok(), "the first init succeeds");
+        assert!(
+            matches!(second, Err(EvsError::IOError(..))),
+            "the second init still fails"
+        );
+        assert!(
+            loud.is_ok() && capture.output() == "Repository initialized successfully.\n",
+            "without -q the summary is printed"
+        );
+    }
+
+    #[test]
+    fn anonymous_commits_show_an_unknown_email() {
+        let dir = TempDir::new();
+        let path = dir.path().to_str().unwrap();
+
+        cli(["init", path]).run().unwrap();
+        dir.write("a", "1");
+        cli(["--repo", path, "add", "a"]).run().unwrap();
+        cli([
+            "--repo",
+            path,
+            "commit",
+            "--anonymous",
+            "-n",
+            "Anon",
+            "-m",
+            "anonymous",
+        ])
+        .run()
+        .unwrap();
+
+        assert!(
+            cli(["--repo", path, "log"]).run().is_ok(),
+            "log shows the anonymous commit"
+        );
+
+        let options = cli(["log"]);
+        let repo = Repository::open(&Span::current(), path, OpenMode::Normal, &options).unwrap();
+
+        let (_, commit) = repo
+            .store
+            .lookup(
+                &Span::current(),
+                &format!("{}", HashDisplay(&repo.info.head())),
+            )
+            .unwrap();
+
+        let Object::Commit(inner) = &commit else {
+            unreachable!("HEAD is a commit");
+        };
+
+        let shown = format!("{commit}");
+
+        assert_eq!(inner.email, "", "no email is stored");
+        assert!(
+            shown.contains("Anon <unknown>") && !shown.contains("<>"),
+            "the missing email is shown as unknown"
+        );
+    }
+
+    #[test]
+    fn log_runs_on_the_repo_option_from_another_directory() {
+        let dir = TempDir::new();
+        let path = dir.path().to_str().unwrap();
+
+        cli(["init", path]).run().unwrap();
+        dir.write("a", "1");
+        cli(["--repo", path, "add", "a"]).run().unwrap();
+        cli([
+            "--repo", path, "commit", "-n", "T", "-e", "t@e.x", "-m", "x",
+        ])
+        .run()
+        .unwrap();
+
+        assert!(
+            !env::current_dir().unwrap().starts_with(dir.path()),
+            "the tests run outside of the repository"
+        );
+        assert!(
+            cli(["--repo", path, "log"]).run().is_ok(),
+            "log finds the repository through --repo"
+        );
+
+        let empty = TempDir::new();
+
+        assert!(
+            matches!(
+                cli(["--repo", empty.path().to_str().unwrap(), "log"]).run(),
+                Err(EvsError::RepositoryNotFound(start)) if start == empty.path().canonicalize().unwrap()
+            ),
+            "a --repo without a repository is reported"
+        );
+    }
+}
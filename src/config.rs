@@ -0,0 +1,64 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use tracing::{Span, debug, instrument, trace};
+
+use crate::error::EvsError;
+
+/// The name of the config file in the repository and in the global config directory.
+pub const CONFIG_FILE: &str = "config";
+
+/// The settings read from a TOML config file, missing ones are `None`.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub user: UserConfig,
+}
+
+/// The `[user]` section.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct UserConfig {
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+impl Config {
+    /// Reads the config file at `path`, a missing one has no settings.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn load(
+        parent: &Span,
+        path: &Path,
+    ) -> Result<Self, EvsError> {
+        debug!("Config::load({:?})", path);
+
+        if !path.exists() {
+            trace!("Missing config file substituted with an empty one.");
+
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path).map_err(|e| (e, path.to_path_buf()))?;
+
+        let config =
+            toml::from_str(&content).map_err(|e| EvsError::ConfigInvalid(path.to_path_buf(), e))?;
+
+        trace!("Read config file successfully.");
+
+        Ok(config)
+    }
+
+    /// `$XDG_CONFIG_HOME/evs/config`, falling back to `~/.config/evs/config`.
+    #[inline]
+    #[must_use]
+    pub fn global_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::home_dir().map(|home| home.join(".config")))
+            .map(|dir| dir.join("evs").join(CONFIG_FILE))
+    }
+}
@@ -0,0 +1,123 @@
+use std::mem;
+
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+
+/// Matches are searched at this granularity, so shorter common runs are inserted instead.
+const BLOCK_SIZE: usize = 16;
+
+/// One step of rebuilding the target, a patch is a serialized list of them.
+#[derive(Serialize, Deserialize, Debug)]
+enum Instruction {
+    /// Copies `len` bytes of the base starting at `offset`.
+    Copy {
+        offset: u64,
+        len: u64,
+    },
+    Insert(Vec<u8>),
+}
+
+/// Encodes `target` as copies from `base` and inserted bytes, see `apply`.
+#[inline]
+#[must_use]
+pub fn encode(
+    base: &[u8],
+    target: &[u8],
+) -> Vec<u8> {
+    let mut blocks = AHashMap::new();
+
+    for (index, block) in base.chunks_exact(BLOCK_SIZE).enumerate() {
+        blocks
+            .entry(block)
+            .or_insert(index.saturating_mul(BLOCK_SIZE));
+    }
+
+    let mut instructions = Vec::new();
+    let mut pending = Vec::new();
+    let mut position = 0usize;
+
+    while let Some(rest) = target.get(position..)
+        && let Some(&byte) = rest.first()
+    {
+        let Some(mut offset) = rest
+            .get(..BLOCK_SIZE)
+            .and_then(|block| blocks.get(block))
+            .copied()
+        else {
+            pending.push(byte);
+
+            position = position.saturating_add(1);
+
+            continue;
+        };
+
+        let forward = base
+            .get(offset..)
+            .unwrap_or_default()
+            .iter()
+            .zip(rest)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let mut len = forward;
+
+        // The bytes before the found block might match as well.
+        while let Some(previous) = offset.checked_sub(1)
+            && pending
+                .last()
+                .is_some_and(|last| base.get(previous) == Some(last))
+        {
+            pending.pop();
+
+            offset = previous;
+            len = len.saturating_add(1);
+        }
+
+        if !pending.is_empty() {
+            instructions.push(Instruction::Insert(mem::take(&mut pending)));
+        }
+
+        instructions.push(Instruction::Copy {
+            offset: u64::try_from(offset).unwrap(),
+            len: u64::try_from(len).unwrap(),
+        });
+
+        position = position.saturating_add(forward);
+    }
+
+    if !pending.is_empty() {
+        instructions.push(Instruction::Insert(pending));
+    }
+
+    let Ok(patch) = rmp_serde::to_vec(&instructions) else {
+        unreachable!("patch encoder failed: io error on vec");
+    };
+
+    patch
+}
+
+/// Rebuilds the target of `encode`, `None` if the patch does not fit the base.
+#[inline]
+#[must_use]
+pub fn apply(
+    base: &[u8],
+    patch: &[u8],
+) -> Option<Vec<u8>> {
+    let instructions = rmp_serde::from_slice::<Vec<Instruction>>(patch).ok()?;
+
+    let mut target = Vec::new();
+
+    for instruction in instructions {
+        match instruction {
+            Instruction::Copy { offset, len } => {
+                let start = usize::try_from(offset).ok()?;
+                let end = start.checked_add(usize::try_from(len).ok()?)?;
+
+                target.extend_from_slice(base.get(start..end)?);
+            }
+            Instruction::Insert(data) => target.extend_from_slice(&data),
+        }
+    }
+
+    Some(target)
+}
@@ -3,7 +3,7 @@ use std::{
     ffi::OsString,
     fmt::Write as _,
     fs,
-    io::{BufRead as _, Write as _, stdout},
+    io::{self, BufRead as _, Write as _, stdout},
     path::{Path, PathBuf},
     str::FromStr as _,
     sync::Mutex,
@@ -12,17 +12,60 @@ use std::{
 use ahash::{AHashMap, AHashSet};
 use glob::Pattern;
 use rayon::iter::{IntoParallelRefIterator as _, ParallelBridge as _, ParallelIterator as _};
-use similar::{DiffableStr, TextDiff, udiff::UnifiedDiff};
+use similar::{ChangeTag, DiffableStr, TextDiff, udiff::UnifiedDiff};
 use tracing::{Span, debug, instrument, trace};
 
 use crate::{
     cli::Cli,
     error::{CorruptState, EvsError},
     objects::Object,
-    store::{Hash, HashDisplay, Store},
-    util::{ADD_COLOR, INFO_COLOR, NONE_COLOR, SUB_COLOR, get_color},
+    store::{Hash, HashDisplay, ObjectStore},
+    util::{
+        ADD_COLOR, INFO_COLOR, MOD_COLOR, NONE_COLOR, SUB_COLOR, check_depth, get_color,
+        matches_any,
+    },
 };
 
+/// How a diff is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffMode {
+    /// The unified diff format.
+    Patch,
+    /// Per file line counts and a total.
+    Stat,
+    /// A status letter per changed file, sorted by path.
+    NameStatus,
+    /// The unified diff format, with binary files decoded lossily instead of hex dumped.
+    Text,
+    /// For tools, a `code NUL path LF` record per changed file, sorted by path and never colored.
+    /// The code is `A` for added, `M` for modified and `D` for deleted files.
+    Porcelain,
+}
+
+impl DiffMode {
+    /// Picks the mode from the mutually exclusive command line flags.
+    #[inline]
+    #[must_use]
+    pub fn from_flags(
+        stat: bool,
+        name_status: bool,
+        text: bool,
+    ) -> DiffMode {
+        if stat {
+            DiffMode::Stat
+        } else if name_status {
+            DiffMode::NameStatus
+        } else if text {
+            DiffMode::Text
+        } else {
+            DiffMode::Patch
+        }
+    }
+}
+
+/// The paths read from a diffside and their contents.
+pub type SideContent = (AHashSet<PathBuf>, AHashMap<PathBuf, Vec<u8>>);
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum DiffSide {
     Tree(Hash),
@@ -31,33 +74,50 @@ pub enum DiffSide {
 
 impl DiffSide {
     #[inline]
+    #[allow(clippy::too_many_arguments, reason = "Reasonable here.")]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
     pub fn diff_with<F: AsRef<[PathBuf]>, I: AsRef<[Pattern]>>(
         from: Self,
         to: Self,
         parent: &Span,
-        store: &Store,
+        store: &dyn ObjectStore,
         files: F,
         ignores: I,
+        excludes: &[Pattern],
+        mode: DiffMode,
         options: &Cli,
     ) -> Result<(), EvsError> {
         debug!(
-            "Diffside::diff_with({:?}, {:?}, store, {:?}, {} ignores)",
+            "Diffside::diff_with({:?}, {:?}, store, {:?}, {} ignores, {} excludes, {:?})",
             from,
             to,
             files.as_ref(),
-            ignores.as_ref().len()
+            ignores.as_ref().len(),
+            excludes.len(),
+            mode
         );
 
-        Self::diff_with_(from, to, store, files.as_ref(), ignores.as_ref(), options)
+        Self::diff_with_(
+            from,
+            to,
+            store,
+            files.as_ref(),
+            ignores.as_ref(),
+            excludes,
+            mode,
+            options,
+        )
     }
 
+    #[allow(clippy::too_many_arguments, reason = "Reasonable here.")]
     fn diff_with_(
         from: Self,
         to: Self,
-        store: &Store,
+        store: &dyn ObjectStore,
         files: &[PathBuf],
         ignores: &[Pattern],
+        excludes: &[Pattern],
+        mode: DiffMode,
         options: &Cli,
     ) -> Result<(), EvsError> {
         if from == to {
@@ -66,21 +126,23 @@ impl DiffSide {
 
         let current = Span::current();
 
-        let lhs = from.read(&current, "", store, files, ignores, &AHashSet::new())?;
-
-        trace!("Read 'from' diff source: {:?}.", lhs.0);
-
-        let rhs = to.read(&current, "", store, files, ignores, &lhs.0)?;
-
-        trace!("Read 'to' diff source: {:?}.", rhs.0);
+        let (lhs, rhs) = Self::read_both(
+            from,
+            to,
+            &current,
+            store,
+            files,
+            ignores,
+            excludes,
+            options.max_tree_depth,
+        )?;
 
         let removals = lhs.0.difference(&rhs.0);
         let insertions = rhs.0.difference(&lhs.0);
         let modifications = lhs.0.intersection(&rhs.0);
 
         #[allow(clippy::indexing_slicing, reason = "The keys are in the map as well.")]
-        DiffFormat::print(
-            &current,
+        let (removals, insertions, modifications) = (
             removals.map(|e| (e.clone(), &lhs.1[e])),
             insertions.map(|e| (e.clone(), &rhs.1[e])),
             modifications.filter_map(|e| {
@@ -89,33 +151,141 @@ impl DiffSide {
 
                 (rhs != lhs).then(|| (e.clone(), lhs, rhs))
             }),
-            options,
         );
 
+        match mode {
+            DiffMode::Patch | DiffMode::Text => {
+                DiffFormat::print(
+                    &current,
+                    removals,
+                    insertions,
+                    modifications,
+                    mode == DiffMode::Text,
+                    options,
+                );
+            }
+            DiffMode::Stat => {
+                DiffFormat::print_stat(
+                    &current,
+                    removals,
+                    insertions,
+                    modifications,
+                    &mut stdout(),
+                    options,
+                );
+            }
+            DiffMode::NameStatus => {
+                DiffFormat::print_name_status(
+                    &current,
+                    removals.map(|(path, _)| path),
+                    insertions.map(|(path, _)| path),
+                    modifications.map(|(path, _, _)| path),
+                    options,
+                );
+            }
+            DiffMode::Porcelain => {
+                DiffFormat::print_porcelain(
+                    &current,
+                    removals.map(|(path, _)| path),
+                    insertions.map(|(path, _)| path),
+                    modifications.map(|(path, _, _)| path),
+                );
+            }
+        }
+
         Ok(())
     }
 
+    /// Reads both sides of a diff, leaving out the paths matching any of `excludes` on either.
     #[inline]
-    #[allow(
-        clippy::type_complexity,
-        reason = "It's not even that complex, I might do something later."
-    )]
+    #[allow(clippy::too_many_arguments, reason = "Reasonable here.")]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn read_both<F: AsRef<[PathBuf]>, I: AsRef<[Pattern]>>(
+        from: Self,
+        to: Self,
+        parent: &Span,
+        store: &dyn ObjectStore,
+        files: F,
+        ignores: I,
+        excludes: &[Pattern],
+        max_depth: usize,
+    ) -> Result<(SideContent, SideContent), EvsError> {
+        debug!(
+            "Diffside::read_both({:?}, {:?}, store, {:?}, {} ignores, {} excludes, {})",
+            from,
+            to,
+            files.as_ref(),
+            ignores.as_ref().len(),
+            excludes.len(),
+            max_depth
+        );
+
+        Self::read_both_(
+            from,
+            to,
+            store,
+            files.as_ref(),
+            ignores.as_ref(),
+            excludes,
+            max_depth,
+        )
+    }
+
+    fn read_both_(
+        from: Self,
+        to: Self,
+        store: &dyn ObjectStore,
+        files: &[PathBuf],
+        ignores: &[Pattern],
+        excludes: &[Pattern],
+        max_depth: usize,
+    ) -> Result<(SideContent, SideContent), EvsError> {
+        let current = Span::current();
+
+        let mut lhs = from.read(
+            &current,
+            "",
+            store,
+            files,
+            ignores,
+            &AHashSet::new(),
+            max_depth,
+        )?;
+
+        trace!("Read 'from' diff source: {:?}.", lhs.0);
+
+        let mut rhs = to.read(&current, "", store, files, ignores, &lhs.0, max_depth)?;
+
+        trace!("Read 'to' diff source: {:?}.", rhs.0);
+
+        lhs.0.retain(|p| !matches_any(excludes, p));
+        rhs.0.retain(|p| !matches_any(excludes, p));
+
+        trace!("Applied {} exclude(s).", excludes.len());
+
+        Ok((lhs, rhs))
+    }
+
+    #[inline]
+    #[allow(clippy::too_many_arguments, reason = "Reasonable here.")]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
     pub fn read<O: AsRef<Path>, F: AsRef<[PathBuf]>, I: AsRef<[Pattern]>>(
         self,
         parent: &Span,
         origin: O,
-        store: &Store,
+        store: &dyn ObjectStore,
         filter: F,
         ignores: I,
         overrides: &AHashSet<PathBuf>,
-    ) -> Result<(AHashSet<PathBuf>, AHashMap<PathBuf, Vec<u8>>), EvsError> {
+        max_depth: usize,
+    ) -> Result<SideContent, EvsError> {
         debug!(
-            "Diffside::read(self, {:?}, store, {:?}, {} ignores, {:?})",
+            "Diffside::read(self, {:?}, store, {:?}, {} ignores, {:?}, {})",
             origin.as_ref(),
             filter.as_ref(),
             ignores.as_ref().len(),
-            overrides
+            overrides,
+            max_depth
         );
 
         self.read_(
@@ -124,13 +294,10 @@ impl DiffSide {
             filter.as_ref(),
             ignores.as_ref(),
             overrides,
+            max_depth,
         )
     }
 
-    #[allow(
-        clippy::type_complexity,
-        reason = "It's not even that complex, I might do something later."
-    )]
     #[allow(
         clippy::too_many_lines,
         reason = "It's barely over the limit and it's totally fine."
@@ -138,13 +305,16 @@ impl DiffSide {
     fn read_(
         self,
         origin: &Path,
-        store: &Store,
+        store: &dyn ObjectStore,
         filter: &[PathBuf],
         ignores: &[Pattern],
         overrides: &AHashSet<PathBuf>,
-    ) -> Result<(AHashSet<PathBuf>, AHashMap<PathBuf, Vec<u8>>), EvsError> {
+        max_depth: usize,
+    ) -> Result<SideContent, EvsError> {
         let current = Span::current();
 
+        check_depth(origin, max_depth)?;
+
         let sum_set = Mutex::new(AHashSet::new());
         let sum_map = Mutex::new(AHashMap::new());
 
@@ -185,7 +355,7 @@ impl DiffSide {
                         trace!("Found path content \"{}\".", HashDisplay(&entry_hash));
 
                         match content {
-                            Object::Blob(content) => {
+                            Object::Blob(content) | Object::Symlink(content) => {
                                 trace!("Inserting blob {:?}...", path);
 
                                 sum_set.lock().unwrap().insert(path.clone());
@@ -194,8 +364,9 @@ impl DiffSide {
                             Object::Tree(_) => {
                                 trace!("Reading tree {:?}...", path);
 
-                                let (set, map) = DiffSide::Tree(entry_hash)
-                                    .read(&current, path, store, filter, ignores, overrides)?;
+                                let (set, map) = DiffSide::Tree(entry_hash).read(
+                                    &current, path, store, filter, ignores, overrides, max_depth,
+                                )?;
 
                                 let mut sum_set = sum_set.lock().unwrap();
 
@@ -225,6 +396,17 @@ impl DiffSide {
                                     CorruptState::NonContentInTree(hash, entry_hash, "commit"),
                                 ));
                             }
+                            Object::Tag(_) => {
+                                return Err(EvsError::CorruptStateDetected(
+                                    CorruptState::NonContentInTree(hash, entry_hash, "tag"),
+                                ));
+                            }
+                            Object::Delta { .. } => {
+                                return Err(EvsError::CorruptStateDetected(
+                                    CorruptState::NonContentInTree(hash, entry_hash, "delta"),
+                                ));
+                            }
+                            Object::ChunkList(_) => unreachable!("lookup reassembles chunk lists"),
                         }
 
                         Ok(())
@@ -252,18 +434,28 @@ impl DiffSide {
                             .iter()
                             .any(|f| path.starts_with(f) || f.starts_with(&path))
                             || (!overrides.iter().any(|o| o.starts_with(&path))
-                                && ignores
-                                    .iter()
-                                    .any(|i| path.ancestors().any(|a| i.matches_path(a))))
+                                && matches_any(ignores, &path))
                         {
                             trace!("Filtered path {:?}.", path);
 
                             return Ok(());
                         }
 
+                        let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+
                         let entry = entry.path();
 
-                        if entry.is_file() {
+                        if is_symlink {
+                            trace!("Inserting symlink {:?}...", path);
+
+                            let target = fs::read_link(&entry).map_err(|e| (e, path.clone()))?;
+
+                            sum_set.lock().unwrap().insert(path.clone());
+                            sum_map
+                                .lock()
+                                .unwrap()
+                                .insert(path, target.into_os_string().into_encoded_bytes());
+                        } else if entry.is_file() {
                             trace!("Inserting blob {:?}...", path);
 
                             sum_set.lock().unwrap().insert(path.clone());
@@ -274,8 +466,9 @@ impl DiffSide {
                         } else if entry.is_dir() {
                             trace!("Reading tree {:?}...", path);
 
-                            let (set, map) = DiffSide::Local(entry)
-                                .read(&current, path, store, filter, ignores, overrides)?;
+                            let (set, map) = DiffSide::Local(entry).read(
+                                &current, path, store, filter, ignores, overrides, max_depth,
+                            )?;
 
                             let mut sum_set = sum_set.lock().unwrap();
 
@@ -337,19 +530,17 @@ impl DiffFormat {
         removals: R,
         insertions: I,
         modifications: M,
+        text: bool,
         options: &Cli,
     ) {
-        debug!("DiffFormat::print(...)");
+        debug!("DiffFormat::print(..., {})", text);
 
         let print_color = get_color(options);
 
         for removal in removals {
-            let text = match removal.1.as_ref().as_str() {
-                Some(text) => Cow::Borrowed(text),
-                None => Cow::Owned(DiffFormat::binary_to_text(removal.1.as_ref())),
-            };
+            let content = DiffFormat::as_text(removal.1.as_ref(), text);
 
-            let diff = TextDiff::from_lines(text.as_ref(), "");
+            let diff = TextDiff::from_lines(content.as_ref(), "");
 
             let mut diff = diff.unified_diff();
 
@@ -366,12 +557,9 @@ impl DiffFormat {
         }
 
         for insertion in insertions {
-            let text = match insertion.1.as_ref().as_str() {
-                Some(text) => Cow::Borrowed(text),
-                None => Cow::Owned(DiffFormat::binary_to_text(insertion.1.as_ref())),
-            };
+            let content = DiffFormat::as_text(insertion.1.as_ref(), text);
 
-            let diff = TextDiff::from_lines("", text.as_ref());
+            let diff = TextDiff::from_lines("", content.as_ref());
 
             let mut diff = diff.unified_diff();
 
@@ -393,6 +581,10 @@ impl DiffFormat {
                 modification.2.as_ref().as_str(),
             ) {
                 (Some(a_text), Some(b_text)) => (Cow::Borrowed(a_text), Cow::Borrowed(b_text)),
+                (_, _) if text => (
+                    String::from_utf8_lossy(modification.1.as_ref()),
+                    String::from_utf8_lossy(modification.2.as_ref()),
+                ),
                 (_, _) => (
                     Cow::Owned(DiffFormat::binary_to_text(modification.1.as_ref())),
                     Cow::Owned(DiffFormat::binary_to_text(modification.2.as_ref())),
@@ -420,6 +612,192 @@ impl DiffFormat {
         }
     }
 
+    #[inline]
+    #[instrument(parent = parent, level = "debug", skip_all)]
+    pub fn print_stat<
+        RB: AsRef<[u8]>,
+        RP: AsRef<Path>,
+        R: IntoIterator<Item = (RP, RB)>,
+        IB: AsRef<[u8]>,
+        IP: AsRef<Path>,
+        I: IntoIterator<Item = (IP, IB)>,
+        MBL: AsRef<[u8]>,
+        MBR: AsRef<[u8]>,
+        MP: AsRef<Path>,
+        M: IntoIterator<Item = (MP, MBL, MBR)>,
+        W: io::Write,
+    >(
+        parent: &Span,
+        removals: R,
+        insertions: I,
+        modifications: M,
+        out: &mut W,
+        options: &Cli,
+    ) {
+        debug!("DiffFormat::print_stat(...)");
+
+        let print_color = get_color(options);
+
+        let add_color = if print_color { ADD_COLOR } else { "" };
+        let sub_color = if print_color { SUB_COLOR } else { "" };
+        let none_color = if print_color { NONE_COLOR } else { "" };
+
+        let (mut files, mut total_added, mut total_removed) = (0usize, 0usize, 0usize);
+
+        let stats = removals
+            .into_iter()
+            .map(|(path, content)| {
+                let lines = content
+                    .as_ref()
+                    .as_str()
+                    .map(|text| (0, text.lines().count()));
+
+                (path.as_ref().to_path_buf(), lines)
+            })
+            .chain(insertions.into_iter().map(|(path, content)| {
+                let lines = content
+                    .as_ref()
+                    .as_str()
+                    .map(|text| (text.lines().count(), 0));
+
+                (path.as_ref().to_path_buf(), lines)
+            }))
+            .chain(modifications.into_iter().map(|(path, lhs, rhs)| {
+                let lines = match (lhs.as_ref().as_str(), rhs.as_ref().as_str()) {
+                    (Some(lhs), Some(rhs)) => {
+                        Some(TextDiff::from_lines(lhs, rhs).iter_all_changes().fold(
+                            (0usize, 0usize),
+                            |(added, removed), change| match change.tag() {
+                                ChangeTag::Insert => (added.saturating_add(1), removed),
+                                ChangeTag::Delete => (added, removed.saturating_add(1)),
+                                ChangeTag::Equal => (added, removed),
+                            },
+                        ))
+                    }
+                    (_, _) => None,
+                };
+
+                (path.as_ref().to_path_buf(), lines)
+            }));
+
+        for (path, lines) in stats {
+            files = files.saturating_add(1);
+
+            if let Some((added, removed)) = lines {
+                total_added = total_added.saturating_add(added);
+                total_removed = total_removed.saturating_add(removed);
+
+                let _ = writeln!(
+                    out,
+                    " {} | {}+{}{} {}-{}{}",
+                    path.display(),
+                    add_color,
+                    added,
+                    none_color,
+                    sub_color,
+                    removed,
+                    none_color
+                );
+            } else {
+                let _ = writeln!(out, " {} | Bin", path.display());
+            }
+        }
+
+        let _ = writeln!(
+            out,
+            " {} file(s) changed, {}{} insertion(s){}, {}{} deletion(s){}",
+            files, add_color, total_added, none_color, sub_color, total_removed, none_color
+        );
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", skip_all)]
+    pub fn print_name_status<
+        R: IntoIterator<Item = PathBuf>,
+        I: IntoIterator<Item = PathBuf>,
+        M: IntoIterator<Item = PathBuf>,
+    >(
+        parent: &Span,
+        removals: R,
+        insertions: I,
+        modifications: M,
+        options: &Cli,
+    ) {
+        debug!("DiffFormat::print_name_status(...)");
+
+        let print_color = get_color(options);
+
+        let add_color = if print_color { ADD_COLOR } else { "" };
+        let sub_color = if print_color { SUB_COLOR } else { "" };
+        let mod_color = if print_color { MOD_COLOR } else { "" };
+        let none_color = if print_color { NONE_COLOR } else { "" };
+
+        let mut entries = removals
+            .into_iter()
+            .map(|path| (path, 'D', sub_color))
+            .chain(insertions.into_iter().map(|path| (path, 'A', add_color)))
+            .chain(modifications.into_iter().map(|path| (path, 'M', mod_color)))
+            .collect::<Vec<_>>();
+
+        entries.sort_unstable();
+
+        let mut stdout = stdout();
+
+        for (path, status, color) in entries {
+            let _ = writeln!(
+                stdout,
+                "{}{}{}\t{}",
+                color,
+                status,
+                none_color,
+                path.display()
+            );
+        }
+    }
+
+    /// See `DiffMode::Porcelain`.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", skip_all)]
+    pub fn print_porcelain<
+        R: IntoIterator<Item = PathBuf>,
+        I: IntoIterator<Item = PathBuf>,
+        M: IntoIterator<Item = PathBuf>,
+    >(
+        parent: &Span,
+        removals: R,
+        insertions: I,
+        modifications: M,
+    ) {
+        debug!("DiffFormat::print_porcelain(...)");
+
+        let mut entries = removals
+            .into_iter()
+            .map(|path| (path, 'D'))
+            .chain(insertions.into_iter().map(|path| (path, 'A')))
+            .chain(modifications.into_iter().map(|path| (path, 'M')))
+            .collect::<Vec<_>>();
+
+        entries.sort_unstable();
+
+        let mut stdout = stdout().lock();
+
+        for (path, status) in entries {
+            let _ = writeln!(stdout, "{}\0{}", status, path.display());
+        }
+    }
+
+    /// Borrows valid UTF-8, other content is decoded lossily with `text` and hex dumped without.
+    fn as_text(
+        content: &[u8],
+        text: bool,
+    ) -> Cow<'_, str> {
+        match content.as_str() {
+            Some(content) => Cow::Borrowed(content),
+            None if text => String::from_utf8_lossy(content),
+            None => Cow::Owned(DiffFormat::binary_to_text(content)),
+        }
+    }
+
     #[inline]
     #[must_use]
     pub fn binary_to_text(binary: &[u8]) -> String {
@@ -501,3 +879,31 @@ impl DiffFormat {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tracing::Span;
+
+    use super::DiffFormat;
+    use crate::testing::cli;
+
+    #[test]
+    fn stat_counts_changed_lines() {
+        let mut out = vec![];
+
+        DiffFormat::print_stat(
+            &Span::current(),
+            [("gone", "x\ny\n")],
+            [("new", "a\n")],
+            [("changed", "1\n2\n3\n", "1\nX\n3\n4\n")],
+            &mut out,
+            &cli(["--no-color", "diff"]),
+        );
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            " gone | +0 -2\n new | +1 -0\n changed | +2 -1\n 3 file(s) changed, 3 insertion(s), 3 deletion(s)\n",
+            "every file and the total are counted"
+        );
+    }
+}
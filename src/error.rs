@@ -7,12 +7,15 @@ use std::{
     num::ParseIntError,
     ops::Deref,
     path::PathBuf,
+    process::ExitStatus,
     str::Utf8Error,
 };
 
 use ahash::AHashSet;
 use glob::PatternError;
+use rayon::ThreadPoolBuildError;
 use rmp_serde::{decode, encode};
+use toml::de::Error as TomlError;
 
 use crate::store::{Hash, HashDisplay, PartialHash};
 
@@ -21,25 +24,118 @@ pub enum EvsError {
     IOError(io::Error, PathBuf),
     MissingRepository(PathBuf),
     CorruptStateDetected(CorruptState),
-    RepositoryNotFound,
+    RepositoryNotFound(PathBuf),
     RepositoryLocked(TryLockError, PathBuf),
     ObjectNotInStore(String),
-    AmbiguousObject(String, OsString),
+    AmbiguousObject(String, Vec<String>),
     RepositoryInfoCorrupt(decode::Error), //TODO: MOVE TO CorruptState
     PathOutsideOfRepo(PathBuf),
     PathNotInStage(PathBuf),
+    PathNotInTree(PathBuf, Hash),
+    NotATextFile(PathBuf),
     IntegerParseError(ParseIntError),
     NotACommit(Hash),
     NotATree(Hash),
     NoPreviousCommit,
+    NothingToAmend,
+    EmptyCommitMessage,
+    EditorFailed(ExitStatus),
+    NothingToCommit,
     PatternError(PatternError),
+    NoGlobMatches(String),
     PathError(Utf8Error, Vec<u8>),
     UncommittedChanges,
     EncoderFailed(encode::Error),
     MissingCommitInfo(&'static str),
+    NotAnAncestor(Hash, Hash),
+    MissingWorkspace(PathBuf),
+    FieldTooLong(&'static str, usize, usize),
+    TreeTooDeep(PathBuf, usize),
+    DirectoryLoop(PathBuf),
+    InvalidEmail(String),
+    MissingStage(Hash),
+    IndexCorrupt(decode::Error),
+    ConfigInvalid(PathBuf, TomlError),
+    ThreadPoolFailed(ThreadPoolBuildError),
+    DuplicateTreeEntry(String),
+    HookFailed(&'static str, ExitStatus),
+    InvalidBranchName(String),
+    BranchExists(String),
+    BranchDiverged(String),
+    InvalidTagName(String),
+    TagExists(String),
+    ProtectedPath(PathBuf),
+    InvalidAncestry(String),
+    MergeConflict(PathBuf),
+    MissingSigningKey(PathBuf),
+    InvalidSigningKey(PathBuf),
+    InvalidSignature(Hash),
+    InvalidBundle(PathBuf, &'static str),
+}
+
+impl EvsError {
+    /// The process exit code for this error: 1 if something was not found, 2 for invalid usage,
+    /// 3 for a corrupt repository and 4 for failed IO or external programs.
+    #[inline]
+    #[must_use]
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            EvsError::MissingRepository(_)
+            | EvsError::RepositoryNotFound(_)
+            | EvsError::ObjectNotInStore(_)
+            | EvsError::PathNotInStage(_)
+            | EvsError::PathNotInTree(..)
+            | EvsError::MissingWorkspace(_)
+            | EvsError::NoGlobMatches(_)
+            | EvsError::NoPreviousCommit
+            | EvsError::MissingSigningKey(_)
+            | EvsError::NothingToAmend => 1,
+            EvsError::AmbiguousObject(..)
+            | EvsError::PathOutsideOfRepo(_)
+            | EvsError::IntegerParseError(_)
+            | EvsError::NotACommit(_)
+            | EvsError::NotATree(_)
+            | EvsError::NotATextFile(_)
+            | EvsError::EmptyCommitMessage
+            | EvsError::NothingToCommit
+            | EvsError::PatternError(_)
+            | EvsError::PathError(..)
+            | EvsError::UncommittedChanges
+            | EvsError::MissingCommitInfo(_)
+            | EvsError::NotAnAncestor(..)
+            | EvsError::FieldTooLong(..)
+            | EvsError::TreeTooDeep(..)
+            | EvsError::DirectoryLoop(_)
+            | EvsError::InvalidEmail(_)
+            | EvsError::ConfigInvalid(..)
+            | EvsError::DuplicateTreeEntry(_)
+            | EvsError::InvalidBranchName(_)
+            | EvsError::BranchExists(_)
+            | EvsError::BranchDiverged(_)
+            | EvsError::InvalidTagName(_)
+            | EvsError::TagExists(_)
+            | EvsError::ProtectedPath(_)
+            | EvsError::InvalidAncestry(_)
+            | EvsError::MergeConflict(_)
+            | EvsError::InvalidSigningKey(_) => 2,
+            EvsError::CorruptStateDetected(_)
+            | EvsError::RepositoryInfoCorrupt(_)
+            | EvsError::MissingStage(_)
+            | EvsError::IndexCorrupt(_)
+            | EvsError::EncoderFailed(_)
+            | EvsError::InvalidSignature(_)
+            | EvsError::InvalidBundle(..) => 3,
+            EvsError::IOError(..)
+            | EvsError::RepositoryLocked(..)
+            | EvsError::EditorFailed(_)
+            | EvsError::ThreadPoolFailed(_)
+            | EvsError::HookFailed(..) => 4,
+        }
+    }
 }
 
 impl Display for EvsError {
+    #[allow(clippy::too_many_lines, reason = "One arm per variant.")]
     #[inline]
     fn fmt(
         &self,
@@ -47,27 +143,46 @@ impl Display for EvsError {
     ) -> fmt::Result {
         match self {
             EvsError::IOError(err, pb) => write!(f, "IO Error on {:?}: {}", pb, err),
-            EvsError::MissingRepository(pb) => write!(f, "No repository found at`{:?}", pb),
+            EvsError::MissingRepository(pb) => write!(
+                f,
+                "No repository found in workspace {:?} (use `evs init` to create one)",
+                pb
+            ),
             EvsError::CorruptStateDetected(cs) => write!(f, "Corrupt state: {}", cs),
-            EvsError::RepositoryNotFound => write!(f, "No repository was found"),
+            EvsError::RepositoryNotFound(pb) => write!(
+                f,
+                "No repository found in {:?} or any parent directory (use `evs init` to create one)",
+                pb
+            ),
             EvsError::RepositoryLocked(err, pb) => {
                 write!(f, "The repository at {:?} could not be locked: {}", pb, err)
             }
             EvsError::ObjectNotInStore(hash) => {
                 write!(f, "Could not find object \"{}\" in store", hash)
             }
-            EvsError::AmbiguousObject(hash, target) => {
-                write!(
-                    f,
-                    "Name \"{}\" matches more than one object (e.g. {:?})",
-                    hash, target
-                )
+            EvsError::AmbiguousObject(hash, candidates) => {
+                write!(f, "Name \"{}\" matches more than one object:", hash)?;
+
+                for candidate in candidates {
+                    write!(f, "\n  {}", candidate)?;
+                }
+
+                Ok(())
             }
             EvsError::RepositoryInfoCorrupt(err) => write!(f, "Repository info corrupt: {}", err),
             EvsError::PathOutsideOfRepo(err) => {
                 write!(f, "Path {:?} is outside of the repository.", err)
             }
             EvsError::PathNotInStage(err) => write!(f, "Path {:?} is not in the stage.", err),
+            EvsError::NotATextFile(path) => write!(f, "Path {:?} is not a UTF-8 text file", path),
+            EvsError::PathNotInTree(path, tree) => {
+                write!(
+                    f,
+                    "Path {:?} is not in tree \"{}\"",
+                    path,
+                    HashDisplay(tree)
+                )
+            }
             EvsError::IntegerParseError(err) => write!(f, "Could not parse integer: {}", err),
             EvsError::NotACommit(hash) => {
                 write!(f, "Object \"{}\" is not a commit", HashDisplay(hash))
@@ -76,18 +191,138 @@ impl Display for EvsError {
                 write!(f, "Object \"{}\" is not a tree", HashDisplay(hash))
             }
             EvsError::NoPreviousCommit => write!(f, "NULL object does not have a previous commit"),
+            EvsError::NothingToAmend => write!(f, "There is no commit to amend yet"),
+            EvsError::EmptyCommitMessage => write!(f, "Aborting commit due to an empty message"),
+            EvsError::EditorFailed(status) => write!(f, "The editor failed with {}", status),
+            EvsError::NothingToCommit => {
+                write!(f, "Nothing to commit (use --allow-empty to commit anyway)")
+            }
             EvsError::PatternError(err) => write!(f, "{}", err),
+            EvsError::NoGlobMatches(pattern) => {
+                write!(f, "Pattern {:?} does not match any path", pattern)
+            }
             EvsError::PathError(e, bts) => {
                 write!(f, "Path \"{}\" is not valid: {}", bts.escape_ascii(), e)
             }
             EvsError::UncommittedChanges => write!(f, "There are uncommitted changes"),
             EvsError::EncoderFailed(err) => unreachable!("The encoder failed: {}", err),
             EvsError::MissingCommitInfo(info) => write!(f, "Missing {} to create a commit", info),
+            EvsError::NotAnAncestor(from, to) => write!(
+                f,
+                "Object \"{}\" is not an ancestor of \"{}\"",
+                HashDisplay(from),
+                HashDisplay(to)
+            ),
+            EvsError::MissingWorkspace(pb) => write!(
+                f,
+                "Workspace {:?} does not exist (use --parents to create it)",
+                pb
+            ),
+            EvsError::FieldTooLong(field, len, limit) => write!(
+                f,
+                "The {} is {} bytes long, but the limit is {} bytes",
+                field, len, limit
+            ),
+            EvsError::DirectoryLoop(pb) => write!(
+                f,
+                "Directory {:?} is reached again while hashing, it is part of a loop",
+                pb
+            ),
+            EvsError::TreeTooDeep(pb, limit) => write!(
+                f,
+                "Path {:?} is nested more than {} levels deep (use --max-tree-depth to raise the limit)",
+                pb, limit
+            ),
+            EvsError::InvalidEmail(email) => write!(f, "Email {:?} is not valid", email),
+            EvsError::MissingStage(hash) => write!(
+                f,
+                "Stage \"{}\" is missing or corrupt, run `evs reset-stage --from-head` to recover it",
+                HashDisplay(hash)
+            ),
+            EvsError::IndexCorrupt(err) => write!(f, "Index corrupt: {}", err),
+            EvsError::ConfigInvalid(pb, err) => {
+                write!(f, "Config file {:?} is invalid: {}", pb, err)
+            }
+            EvsError::ThreadPoolFailed(err) => write!(f, "Could not start the threads: {}", err),
+            EvsError::DuplicateTreeEntry(name) => {
+                write!(f, "Tree contains more than one entry named {:?}", name)
+            }
+            EvsError::HookFailed(hook, status) => {
+                write!(f, "The {} hook failed with {}", hook, status)
+            }
+            EvsError::InvalidBranchName(name) => write!(f, "{:?} is not a valid branch name", name),
+            EvsError::BranchExists(name) => write!(f, "Branch {:?} already exists", name),
+            EvsError::BranchDiverged(name) => write!(
+                f,
+                "Branch {:?} has diverged, moving it would not be a fast-forward",
+                name
+            ),
+            EvsError::InvalidTagName(name) => write!(f, "{:?} is not a valid tag name", name),
+            EvsError::TagExists(name) => write!(f, "Tag {:?} already exists", name),
+            EvsError::InvalidAncestry(steps) => {
+                write!(
+                    f,
+                    "Invalid ancestry {:?}, expected steps like ~2 or ^",
+                    steps
+                )
+            }
+            EvsError::MergeConflict(path) => write!(
+                f,
+                "Merge conflict in {:?}, both sides changed it differently",
+                path
+            ),
+            EvsError::MissingSigningKey(path) => write!(
+                f,
+                "No signing key at {:?} (write a 32 byte Ed25519 secret key to it to sign commits)",
+                path
+            ),
+            EvsError::InvalidSigningKey(path) => {
+                write!(
+                    f,
+                    "Signing key {:?} is not a 32 byte Ed25519 secret key",
+                    path
+                )
+            }
+            EvsError::InvalidSignature(hash) => {
+                write!(
+                    f,
+                    "Commit \"{}\" has an invalid signature",
+                    HashDisplay(hash)
+                )
+            }
+            EvsError::InvalidBundle(path, reason) => {
+                write!(f, "Bundle {:?} is invalid, {}", path, reason)
+            }
+            EvsError::ProtectedPath(path) => {
+                write!(
+                    f,
+                    "Refusing to write {:?} outside of the workspace or into .evs",
+                    path
+                )
+            }
         }
     }
 }
 
-impl Error for EvsError {}
+impl Error for EvsError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EvsError::IOError(err, _) => Some(err),
+            EvsError::RepositoryLocked(err, _) => Some(err),
+            EvsError::RepositoryInfoCorrupt(err) | EvsError::IndexCorrupt(err) => Some(err),
+            EvsError::ConfigInvalid(_, err) => Some(err),
+            EvsError::ThreadPoolFailed(err) => Some(err),
+            EvsError::PatternError(err) => Some(err),
+            EvsError::IntegerParseError(err) => Some(err),
+            EvsError::PathError(err, _) => Some(err),
+            EvsError::EncoderFailed(err) => Some(err),
+            // The corrupt state itself is already part of the message, so skip to its cause
+            EvsError::CorruptStateDetected(cs) => cs.source(),
+            _ => None,
+        }
+    }
+}
 
 impl From<(io::Error, PathBuf)> for EvsError {
     #[inline]
@@ -117,6 +352,13 @@ impl From<PatternError> for EvsError {
     }
 }
 
+impl From<ThreadPoolBuildError> for EvsError {
+    #[inline]
+    fn from(value: ThreadPoolBuildError) -> Self {
+        EvsError::ThreadPoolFailed(value)
+    }
+}
+
 impl From<encode::Error> for EvsError {
     #[inline]
     fn from(value: encode::Error) -> Self {
@@ -139,6 +381,12 @@ pub enum CorruptState {
     InvalidObjectContent(Hash, decode::Error),
     NonContentInTree(Hash, Hash, &'static str),
     HeadIsNotACommit,
+    TypeMismatch(Hash, Hash, &'static str, &'static str),
+    InvalidRef(PathBuf),
+    CommitWithoutParent(Hash),
+    InvalidDelta(Hash, &'static str),
+    InvalidPack(PathBuf, &'static str),
+    InvalidReflog(PathBuf, usize),
 }
 
 impl Display for CorruptState {
@@ -186,6 +434,79 @@ impl Display for CorruptState {
                 )
             }
             CorruptState::HeadIsNotACommit => write!(f, "HEAD is not a commit"),
+            CorruptState::TypeMismatch(referrer, referenced, kind, expected) => write!(
+                f,
+                "Object \"{}\" referenced by \"{}\" is a {} instead of {}",
+                HashDisplay(referenced),
+                HashDisplay(referrer),
+                kind,
+                expected
+            ),
+            CorruptState::InvalidRef(pb) => write!(f, "Ref {:?} does not contain a hash", pb),
+            CorruptState::InvalidDelta(hash, reason) => write!(
+                f,
+                "Object \"{}\" cannot be rebuilt from its delta, {}",
+                HashDisplay(hash),
+                reason
+            ),
+            CorruptState::InvalidPack(pb, reason) => {
+                write!(f, "Pack {:?} cannot be read, {}", pb, reason)
+            }
+            CorruptState::InvalidReflog(pb, line) => {
+                write!(f, "Reflog {:?} has an invalid entry on line {}", pb, line)
+            }
+            CorruptState::CommitWithoutParent(hash) => write!(
+                f,
+                "Commit \"{}\" has no parent, not even the null object",
+                HashDisplay(hash)
+            ),
+        }
+    }
+}
+
+impl Error for CorruptState {
+    #[inline]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CorruptState::InvalidCompression(_, err) => Some(err),
+            CorruptState::InvalidObjectContent(_, err) => Some(err),
+            _ => None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error as _, io, path::PathBuf};
+
+    use super::{CorruptState, EvsError};
+
+    #[test]
+    fn io_errors_keep_their_cause() {
+        let err = EvsError::from((io::Error::other("disk gone"), PathBuf::from("objects")));
+
+        assert!(
+            err.source()
+                .is_some_and(|source| source.to_string() == "disk gone"),
+            "the io error is the source"
+        );
+    }
+
+    #[test]
+    fn corrupt_states_skip_to_their_cause() {
+        let err = EvsError::CorruptStateDetected(CorruptState::InvalidCompression(
+            PathBuf::from("object"),
+            io::Error::other("bad gzip"),
+        ));
+
+        assert!(
+            err.source()
+                .is_some_and(|source| source.to_string() == "bad gzip"),
+            "the compression error is the source"
+        );
+        assert!(
+            EvsError::NoPreviousCommit.source().is_none(),
+            "plain errors have no source"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use std::{fs, iter::once, path::Path};
+
+use glob::Pattern;
+use tracing::{Span, debug, instrument, trace};
+
+use crate::{error::EvsError, util::matches_any};
+
+/// The name of the ignores file in the workspace root.
+pub const IGNORES_FILE: &str = ".evsignore";
+
+/// The patterns of the ignores file, which always ignore `.evs` as well.
+#[derive(Debug, Clone, Default, Hash)]
+pub struct IgnoreMatcher {
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreMatcher {
+    /// Reads the ignores file of `workspace`, a missing one ignores only `.evs`.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn load(
+        parent: &Span,
+        workspace: &Path,
+    ) -> Result<Self, EvsError> {
+        debug!("IgnoreMatcher::load({:?})", workspace);
+
+        let ignores_file = workspace.join(IGNORES_FILE);
+
+        let content = if ignores_file.exists() {
+            trace!("Ignores file exists, trying to read...");
+
+            fs::read_to_string(&ignores_file).map_err(|e| (e, ignores_file))?
+        } else {
+            trace!("Missing ignores file substituted with \"\".");
+
+            String::new()
+        };
+
+        trace!("Read ignores file successfully.");
+
+        Self::parse(&content)
+    }
+
+    /// One glob per line, blank lines and lines starting with `#` are skipped.
+    #[inline]
+    pub fn parse(content: &str) -> Result<Self, EvsError> {
+        let patterns = content
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .chain(once(".evs"))
+            .map(Pattern::new)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { patterns })
+    }
+
+    /// Whether the workspace relative path or any of its ancestors is ignored.
+    #[inline]
+    #[must_use]
+    pub fn matches(
+        &self,
+        relative: &Path,
+    ) -> bool {
+        matches_any(&self.patterns, relative)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+}
+
+impl AsRef<[Pattern]> for IgnoreMatcher {
+    #[inline]
+    fn as_ref(&self) -> &[Pattern] {
+        &self.patterns
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    fs::{self, Metadata},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+use tracing::{Span, debug, instrument, trace};
+
+use crate::{
+    error::EvsError,
+    store::{Hash, HashDisplay},
+};
+
+/// Files modified this recently are not cached, because a later modification might not change the mtime.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub mtime: SystemTime,
+    pub size: u64,
+    pub hash: Hash,
+}
+
+/// Caches the blob hashes of workspace files by their mtime and size.
+#[derive(Debug)]
+pub struct Index {
+    path: PathBuf,
+    entries: Mutex<AHashMap<String, IndexEntry>>,
+    modified: AtomicBool,
+}
+
+impl Index {
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn load(
+        parent: &Span,
+        path: PathBuf,
+    ) -> Result<Index, EvsError> {
+        debug!("Index::load({:?})", path);
+
+        let entries = if path.exists() {
+            trace!("Index file exists, trying to read...");
+
+            let content = fs::read(&path).map_err(|e| (e, path.clone()))?;
+
+            rmp_serde::from_slice(&content).map_err(EvsError::IndexCorrupt)?
+        } else {
+            trace!("Missing index substituted with an empty one.");
+
+            AHashMap::new()
+        };
+
+        trace!("Loaded index with {} entries.", entries.len());
+
+        Ok(Index {
+            path,
+            entries: Mutex::new(entries),
+            modified: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns the cached hash if the metadata still matches.
+    #[inline]
+    #[must_use]
+    pub fn get(
+        &self,
+        relative: &Path,
+        metadata: &Metadata,
+    ) -> Option<Hash> {
+        let mtime = metadata.modified().ok()?;
+
+        let entries = self.entries.lock().unwrap();
+
+        let entry = entries.get(relative.to_str()?)?;
+
+        (entry.mtime == mtime && entry.size == metadata.len()).then_some(entry.hash)
+    }
+
+    #[inline]
+    pub fn update(
+        &self,
+        relative: &Path,
+        metadata: &Metadata,
+        hash: Hash,
+    ) {
+        let (Ok(mtime), Some(relative)) = (metadata.modified(), relative.to_str()) else {
+            return;
+        };
+
+        if SystemTime::now()
+            .duration_since(mtime)
+            .is_ok_and(|age| age < RACY_WINDOW)
+        {
+            trace!("Not caching recently modified {:?}.", relative);
+
+            if self.entries.lock().unwrap().remove(relative).is_some() {
+                self.modified.store(true, Ordering::Relaxed);
+            }
+
+            return;
+        }
+
+        let entry = IndexEntry {
+            mtime,
+            size: metadata.len(),
+            hash,
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.get(relative) != Some(&entry) {
+            trace!("Caching \"{}\" for {:?}.", HashDisplay(&hash), relative);
+
+            entries.insert(relative.to_owned(), entry);
+
+            self.modified.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn save(
+        &self,
+        parent: &Span,
+    ) -> Result<(), EvsError> {
+        debug!("Index::save(self)");
+
+        if !self.modified.load(Ordering::Relaxed) {
+            trace!("Index unchanged.");
+
+            return Ok(());
+        }
+
+        let content = rmp_serde::to_vec(&*self.entries.lock().unwrap())?;
+
+        fs::write(&self.path, content).map_err(|e| (e, self.path.clone()))?;
+
+        self.modified.store(false, Ordering::Relaxed);
+
+        trace!("Wrote index.");
+
+        Ok(())
+    }
+}
@@ -96,9 +96,15 @@
 compile_error!("32 bit is not supported.");
 
 pub mod cli;
+pub mod config;
+pub mod delta;
 pub mod diff;
 pub mod error;
+pub mod ignore;
+pub mod index;
 pub mod objects;
 pub mod repo;
 pub mod store;
+#[cfg(test)]
+mod testing;
 pub mod util;
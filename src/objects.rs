@@ -1,37 +1,396 @@
 use std::{
     fmt::{self, Display, Formatter},
     ops::Deref as _,
-    time::SystemTime,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use serde::{Deserialize, Serialize};
-use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use rmp_serde::encode;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Value, json};
+use time::{OffsetDateTime, UtcOffset, format_description::well_known::Rfc3339};
 
 use crate::store::{Hash, HashDisplay};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Mode of a regular file, also assumed for entries of trees written before modes were stored.
+pub const MODE_FILE: u32 = 0o644;
+/// Mode of a file with any executable bit set.
+pub const MODE_EXECUTABLE: u32 = 0o755;
+/// Mode of an entry pointing at a subtree.
+pub const MODE_DIR: u32 = 0o40_000;
+/// Mode of an entry pointing at a symlink object.
+pub const MODE_SYMLINK: u32 = 0o120_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TreeEntry {
     pub name: String,
-    // Maybe mode?
     pub content: Hash,
+    #[serde(default = "default_mode")]
+    pub mode: u32,
+}
+
+const fn default_mode() -> u32 {
+    MODE_FILE
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The name, email and date are of the committer, the author fields of who wrote the change.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "StoredCommit")]
 pub struct Commit {
-    pub parent: Hash,
+    /// The commit this one was made on top of comes first, a merge adds the merged commits.
+    pub parents: Vec<Hash>,
     pub name: String,
     pub email: String,
     pub tree: Hash,
     pub msg: String,
     pub date: SystemTime,
+    /// The UTC offset of the committer in seconds.
+    pub offset: i32,
+    pub author_name: String,
+    pub author_email: String,
+    pub author_date: SystemTime,
+    pub author_offset: i32,
+    /// The Ed25519 key that signed the commit, it is covered by the signature.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signer_pubkey: Option<Vec<u8>>,
+    /// Skipped when absent so unsigned commits keep their encoding.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Vec<u8>>,
+}
+
+/// Commits written before the offset was recorded are in UTC,
+/// the ones written before authors were recorded are authored by their committer.
+#[derive(Deserialize)]
+struct StoredCommit {
+    #[serde(deserialize_with = "deserialize_parents")]
+    parents: Vec<Hash>,
+    name: String,
+    email: String,
+    tree: Hash,
+    msg: String,
+    date: SystemTime,
+    #[serde(default)]
+    offset: i32,
+    #[serde(default)]
+    author_name: Option<String>,
+    #[serde(default)]
+    author_email: Option<String>,
+    #[serde(default)]
+    author_date: Option<SystemTime>,
+    #[serde(default)]
+    author_offset: Option<i32>,
+    #[serde(default)]
+    signer_pubkey: Option<Vec<u8>>,
+    #[serde(default)]
+    signature: Option<Vec<u8>>,
+}
+
+impl From<StoredCommit> for Commit {
+    #[inline]
+    fn from(value: StoredCommit) -> Self {
+        Commit {
+            author_name: value.author_name.unwrap_or_else(|| value.name.clone()),
+            author_email: value.author_email.unwrap_or_else(|| value.email.clone()),
+            author_date: value.author_date.unwrap_or(value.date),
+            author_offset: value.author_offset.unwrap_or(value.offset),
+            parents: value.parents,
+            name: value.name,
+            email: value.email,
+            tree: value.tree,
+            msg: value.msg,
+            date: value.date,
+            offset: value.offset,
+            signer_pubkey: value.signer_pubkey,
+            signature: value.signature,
+        }
+    }
+}
+
+/// An annotated tag, lightweight tags point at their target directly.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tag {
+    pub target: Hash,
+    pub name: String,
+    pub message: String,
+    pub tagger: String,
+    pub date: SystemTime,
+}
+
+impl Commit {
+    /// The parent that history walks follow by default.
+    #[inline]
+    #[must_use]
+    pub fn first_parent(&self) -> Option<Hash> {
+        self.parents.first().copied()
+    }
+
+    /// The date in the offset of the committer, if it is representable.
+    #[inline]
+    #[must_use]
+    pub fn offset_date(&self) -> Option<OffsetDateTime> {
+        offset_date(&self.date, self.offset)
+    }
+
+    /// Like `offset_date`, but of the author.
+    #[inline]
+    #[must_use]
+    pub fn author_offset_date(&self) -> Option<OffsetDateTime> {
+        offset_date(&self.author_date, self.author_offset)
+    }
+
+    /// Whether the author differs from the committer in name, email or date.
+    #[inline]
+    #[must_use]
+    pub fn has_separate_author(&self) -> bool {
+        self.author_name != self.name
+            || self.author_email != self.email
+            || self.author_date != self.date
+            || self.author_offset != self.offset
+    }
+
+    /// The bytes a signature covers, the encoded commit without its signature.
+    #[inline]
+    pub fn signable(&self) -> Result<Vec<u8>, encode::Error> {
+        rmp_serde::to_vec(&Commit {
+            signature: None,
+            ..self.clone()
+        })
+    }
+}
+
+/// Commits written before merges existed store a single parent.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredParents {
+    Single(Hash),
+    Multiple(Vec<Hash>),
+}
+
+fn deserialize_parents<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Hash>, D::Error> {
+    Ok(match StoredParents::deserialize(deserializer)? {
+        StoredParents::Single(parent) => vec![parent],
+        StoredParents::Multiple(parents) => parents,
+    })
+}
+
+/// Displays a name and email, substituting `unknown` for empty parts.
+pub struct IdentityDisplay<'a>(pub &'a str, pub &'a str);
+
+impl Display for IdentityDisplay<'_> {
+    #[inline]
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> fmt::Result {
+        let name = if self.0.is_empty() { "unknown" } else { self.0 };
+        let email = if self.1.is_empty() { "unknown" } else { self.1 };
+
+        write!(f, "{} <{}>", name, email)
+    }
+}
+
+/// `date` at the UTC offset of `offset` seconds, if both are representable.
+fn offset_date(
+    date: &SystemTime,
+    offset: i32,
+) -> Option<OffsetDateTime> {
+    let (since, sign) = match date.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since, 1),
+        Err(err) => (err.duration(), -1),
+    };
+
+    let offset = UtcOffset::from_whole_seconds(offset).ok()?;
+
+    let nanos = i128::try_from(since.as_nanos()).ok()?.checked_mul(sign)?;
+
+    OffsetDateTime::from_unix_timestamp_nanos(nanos)
+        .ok()?
+        .checked_to_offset(offset)
+}
+
+/// Displays a time in RFC3339 or as the raw offset from the epoch if it is not representable.
+pub struct DateDisplay<'a>(pub &'a SystemTime);
+
+impl Display for DateDisplay<'_> {
+    #[inline]
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> fmt::Result {
+        OffsetDateDisplay(self.0, 0).fmt(f)
+    }
+}
+
+/// Like `DateDisplay`, but at a UTC offset in seconds.
+pub struct OffsetDateDisplay<'a>(pub &'a SystemTime, pub i32);
+
+impl Display for OffsetDateDisplay<'_> {
+    #[inline]
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> fmt::Result {
+        match offset_date(self.0, self.1).and_then(|date| date.format(&Rfc3339).ok()) {
+            Some(formatted) => write!(f, "{}", formatted),
+            None => match self.0.duration_since(UNIX_EPOCH) {
+                Ok(since) => write!(f, "{}s after the epoch", since.as_secs()),
+                Err(err) => write!(f, "{}s before the epoch", err.duration().as_secs()),
+            },
+        }
+    }
+}
+
+/// Serializes an object for tooling, with hashes as hex and raw bytes as base64.
+pub struct ObjectJson<'a>(pub &'a Object);
+
+impl ObjectJson<'_> {
+    fn value(&self) -> Value {
+        let hex = |hash: &Hash| HashDisplay(hash).to_string();
+        let date = |date: &SystemTime| DateDisplay(date).to_string();
+
+        match self.0 {
+            Object::Null => json!({ "kind": "null" }),
+            Object::Blob(content) => json!({ "kind": "blob", "content": STANDARD.encode(content) }),
+            Object::Tree(entries) => json!({
+                "kind": "tree",
+                "entries": entries
+                    .iter()
+                    .map(|entry| json!({
+                        "name": entry.name,
+                        "content": hex(&entry.content),
+                        "mode": format!("{:06o}", entry.mode),
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+            Object::Commit(commit) => json!({
+                "kind": "commit",
+                "parents": commit.parents.iter().map(hex).collect::<Vec<_>>(),
+                "name": commit.name,
+                "email": commit.email,
+                "tree": hex(&commit.tree),
+                "message": commit.msg,
+                "date": OffsetDateDisplay(&commit.date, commit.offset).to_string(),
+                "author_name": commit.author_name,
+                "author_email": commit.author_email,
+                "author_date": OffsetDateDisplay(&commit.author_date, commit.author_offset).to_string(),
+                "signer_pubkey": commit.signer_pubkey.as_ref().map(|key| STANDARD.encode(key)),
+                "signature": commit.signature.as_ref().map(|signature| STANDARD.encode(signature)),
+            }),
+            Object::Symlink(target) => {
+                json!({ "kind": "symlink", "target": STANDARD.encode(target) })
+            }
+            Object::ChunkList(chunks) => json!({
+                "kind": "chunk list",
+                "chunks": chunks.iter().map(hex).collect::<Vec<_>>(),
+            }),
+            Object::Tag(tag) => json!({
+                "kind": "tag",
+                "target": hex(&tag.target),
+                "name": tag.name,
+                "message": tag.message,
+                "tagger": tag.tagger,
+                "date": date(&tag.date),
+            }),
+            Object::Delta { base, patch } => json!({
+                "kind": "delta",
+                "base": hex(base),
+                "patch": STANDARD.encode(patch),
+            }),
+        }
+    }
+}
+
+impl Serialize for ObjectJson<'_> {
+    #[inline]
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        self.value().serialize(serializer)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Object {
     Null,
     Blob(Vec<u8>),
     Tree(Vec<TreeEntry>),
     Commit(Commit),
+    /// The target of a symbolic link as raw bytes.
+    Symlink(Vec<u8>),
+    /// The blobs a large file was split into, in order.
+    ChunkList(Vec<Hash>),
+    Tag(Tag),
+    /// A stored object rebuilt by applying `patch` to the serialized `base`, see `delta::apply`.
+    /// Lookups never return it, only the object it stands for.
+    Delta {
+        base: Hash,
+        patch: Vec<u8>,
+    },
+}
+
+impl Object {
+    /// The name of the variant, e.g. for error messages.
+    #[inline]
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Object::Null => "null",
+            Object::Blob(_) => "blob",
+            Object::Tree(_) => "tree",
+            Object::Commit(_) => "commit",
+            Object::Symlink(_) => "symlink",
+            Object::ChunkList(_) => "chunk list",
+            Object::Tag(_) => "tag",
+            Object::Delta { .. } => "delta",
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn info(&self) -> ObjectInfo {
+        let (bytes, entries) = match self {
+            Object::Blob(content) | Object::Symlink(content) => (Some(content.len()), None),
+            Object::Tree(entries) => (None, Some(entries.len())),
+            Object::ChunkList(chunks) => (None, Some(chunks.len())),
+            Object::Delta { patch, .. } => (Some(patch.len()), None),
+            Object::Null | Object::Commit(_) | Object::Tag(_) => (None, None),
+        };
+
+        ObjectInfo {
+            kind: self.kind(),
+            bytes,
+            entries,
+        }
+    }
+}
+
+/// The kind of an object with the length of blobs and symlinks or the entry count of trees and chunk lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub kind: &'static str,
+    pub bytes: Option<usize>,
+    pub entries: Option<usize>,
+}
+
+impl Display for ObjectInfo {
+    #[inline]
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+
+        if let Some(bytes) = self.bytes {
+            write!(f, " {} byte(s)", bytes)?;
+        }
+
+        if let Some(entries) = self.entries {
+            write!(f, " {} entry(s)", entries)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Display for Object {
@@ -43,6 +402,40 @@ impl Display for Object {
         match self {
             Object::Null => write!(f, "Null object :)"),
             Object::Blob(items) => write!(f, "Blob:\n{}", items.deref().escape_ascii()),
+            Object::Symlink(target) => write!(f, "Symlink to {}", target.deref().escape_ascii()),
+            Object::Tag(tag) => write!(
+                f,
+                "  Tag {:?} by {} at {}\n  - \"{}\" target\n\n{}",
+                tag.name,
+                if tag.tagger.is_empty() {
+                    "unknown"
+                } else {
+                    &tag.tagger
+                },
+                DateDisplay(&tag.date),
+                HashDisplay(&tag.target),
+                tag.message.lines().fold(String::new(), |mut acc, l| {
+                    acc += "    ";
+                    acc += l;
+                    acc += "\n";
+                    acc
+                }),
+            ),
+            Object::Delta { base, patch } => write!(
+                f,
+                "Delta of {} byte(s) against \"{}\"",
+                patch.len(),
+                HashDisplay(base)
+            ),
+            Object::ChunkList(chunks) => {
+                write!(f, "Chunk list:")?;
+
+                for chunk in chunks {
+                    write!(f, "\n- \"{}\"", HashDisplay(chunk))?;
+                }
+
+                Ok(())
+            }
             Object::Tree(items) => {
                 if items.is_empty() {
                     write!(f, "Empty tree :)")
@@ -50,27 +443,86 @@ impl Display for Object {
                     write!(f, "Tree:")?;
 
                     for item in items {
-                        write!(f, "\n- \"{}\" {}", HashDisplay(&item.content), item.name)?;
+                        write!(
+                            f,
+                            "\n- {:06o} \"{}\" {}",
+                            item.mode,
+                            HashDisplay(&item.content),
+                            item.name
+                        )?;
                     }
 
                     Ok(())
                 }
             }
-            Object::Commit(commit) => write!(
-                f,
-                "  Commit by {} <{}> at {}\n  - \"{}\" state\n  - \"{}\" parent\n\n{}",
-                commit.name,
-                commit.email,
-                OffsetDateTime::from(commit.date).format(&Rfc3339).unwrap(), // This can't fail I think
-                HashDisplay(&commit.tree),
-                HashDisplay(&commit.parent),
-                commit.msg.lines().fold(String::new(), |mut acc, l| {
-                    acc += "    ";
-                    acc += l;
-                    acc += "\n";
-                    acc
-                }),
-            ),
+            Object::Commit(commit) => {
+                write!(
+                    f,
+                    "  Commit by {} at {}\n  - \"{}\" state",
+                    IdentityDisplay(&commit.name, &commit.email),
+                    OffsetDateDisplay(&commit.date, commit.offset),
+                    HashDisplay(&commit.tree),
+                )?;
+
+                if commit.has_separate_author() {
+                    write!(
+                        f,
+                        "\n  - authored by {} at {}",
+                        IdentityDisplay(&commit.author_name, &commit.author_email),
+                        OffsetDateDisplay(&commit.author_date, commit.author_offset),
+                    )?;
+                }
+
+                if let Some(key) = &commit.signer_pubkey {
+                    write!(f, "\n  - signed by key {}", HashDisplay(key))?;
+                }
+
+                for parent in &commit.parents {
+                    write!(f, "\n  - \"{}\" parent", HashDisplay(parent))?;
+                }
+
+                write!(
+                    f,
+                    "\n\n{}",
+                    commit.msg.lines().fold(String::new(), |mut acc, l| {
+                        acc += "    ";
+                        acc += l;
+                        acc += "\n";
+                        acc
+                    })
+                )
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{DateDisplay, OffsetDateDisplay};
+
+    #[test]
+    fn unrepresentable_dates_fall_back_to_seconds() {
+        let secs = u64::MAX / 2u64;
+
+        let after = UNIX_EPOCH.checked_add(Duration::from_secs(secs)).unwrap();
+        let before = UNIX_EPOCH.checked_sub(Duration::from_secs(secs)).unwrap();
+
+        assert_eq!(
+            DateDisplay(&after).to_string(),
+            format!("{secs}s after the epoch"),
+            "far future dates are shown as seconds"
+        );
+        assert_eq!(
+            OffsetDateDisplay(&before, 3_600i32).to_string(),
+            format!("{secs}s before the epoch"),
+            "far past dates are shown as seconds"
+        );
+        assert_eq!(
+            OffsetDateDisplay(&UNIX_EPOCH, 3_600i32).to_string(),
+            "1970-01-01T01:00:00+01:00",
+            "representable dates are RFC3339"
+        );
+    }
+}
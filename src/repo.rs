@@ -1,37 +1,106 @@
 use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    fmt::{self, Display, Formatter, Write as _},
     fs::{self, DirBuilder, File, OpenOptions},
-    io::{self, ErrorKind, Read as _, Seek as _, SeekFrom, Write as _, stdout},
-    iter::{Peekable, once},
-    path::{Components, Path, PathBuf},
-    time::SystemTime,
+    io::{
+        self, BufReader, BufWriter, ErrorKind, Read as _, Seek as _, SeekFrom, Write as _, stdout,
+    },
+    iter::Peekable,
+    path::{Component, Components, Path, PathBuf},
+    process::{Command, ExitStatus},
+    str,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+    vec,
 };
 
-use ahash::AHashSet;
+use ahash::{AHashMap, AHashSet, RandomState};
+use ed25519_dalek::{Signature, Signer as _, SigningKey, VerifyingKey};
 use glob::Pattern;
 use rayon::iter::{ParallelBridge as _, ParallelIterator as _};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use similar::{ChangeTag, TextDiff};
+use time::OffsetDateTime;
 use tracing::{Span, debug, error, instrument, trace, warn};
 
 use crate::{
     cli::Cli,
     confirmation,
-    diff::DiffSide,
+    diff::{DiffMode, DiffSide, SideContent},
     error::{CorruptState, EvsError},
-    objects::{Commit, Object, TreeEntry},
-    store::{Hash, HashDisplay, Store},
+    ignore::IgnoreMatcher,
+    index::Index,
+    objects::{
+        Commit, DateDisplay, MODE_DIR, MODE_EXECUTABLE, MODE_SYMLINK, Object, ObjectInfo,
+        OffsetDateDisplay, Tag, TreeEntry,
+    },
+    store::{Hash, HashAlgo, HashDisplay, ObjectStore, RawObject, Store, parse_hash},
+    summary,
     util::{
-        ADD_COLOR, INFO_COLOR, MOD_COLOR, NONE_COLOR, SUB_COLOR, SizeDisplay, get_color,
-        partial_canonicalize,
+        ADD_COLOR, INFO_COLOR, MOD_COLOR, NONE_COLOR, Progress, SUB_COLOR, SizeDisplay,
+        check_depth, create_symlink, expand_glob, file_mode, get_color, matches_any,
+        partial_canonicalize, set_file_mode, set_shared_mode,
     },
 };
 
+/// Soft limit for the committer name length in bytes.
+pub const MAX_NAME_LENGTH: usize = 256;
+/// Soft limit for the committer email length in bytes.
+pub const MAX_EMAIL_LENGTH: usize = 256;
+/// Soft limit for the commit message length in bytes.
+pub const MAX_MESSAGE_LENGTH: usize = 64 * 1024;
+/// How deeply directories and trees may nest before walking them fails, see `--max-tree-depth`.
+pub const DEFAULT_MAX_TREE_DEPTH: usize = 256;
+/// The branch HEAD is attached to in new repositories.
+pub const DEFAULT_BRANCH: &str = "main";
+/// The version of the on-disk repository format, 2 introduced the sharded store.
+pub const FORMAT_VERSION: u32 = 2;
+/// The directory of `refs` holding the branches.
+const HEADS_DIR: &str = "heads";
+/// The directory of `refs` holding the tags.
+const TAGS_DIR: &str = "tags";
+/// The start of every bundle file.
+const BUNDLE_MAGIC: &[u8; 8] = b"EVSBUNDL";
+/// The version of the bundle format written, others are refused.
+const BUNDLE_VERSION: u32 = 1;
+/// The directory in the repository that `check --repair` moves unreadable objects into.
+const CORRUPT_DIR: &str = "corrupt";
+/// The file in the repository holding the 32 byte Ed25519 secret key commits are signed with.
+const SIGNING_KEY_FILE: &str = "signing_key";
+
+const REFLOG_FILE: &str = "reflog";
+
+/// A move of HEAD from the first to the second hash, at the time in seconds since the epoch.
+type ReflogEntry = (Hash, Hash, u64);
+
+/// A workspace with its `.evs` directory, keeping objects in `S`.
 #[derive(Debug)]
-pub struct Repository {
+pub struct Repository<S: ObjectStore = Store> {
     pub workspace: PathBuf,
     pub repository: PathBuf,
     pub lockfile: File,
-    pub store: Store,
+    pub store: S,
     pub info: RepositoryInfo,
+    pub index: Option<Index>,
+    /// Paths below the workspace or a tree may have at most this many components.
+    pub max_depth: usize,
+}
+
+/// Directories hashed by one `add` command, by canonical path and the `filter_key` they were hashed with.
+/// The workspace must not change while it is in use.
+pub type Subtrees = Mutex<AHashMap<(PathBuf, u64), HashedDir>>;
+
+/// A memoized directory hash, with the paths below it if nothing was filtered out.
+/// Copies of a directory elsewhere are walked again, only their objects are shared through the store.
+#[derive(Debug)]
+pub struct HashedDir {
+    hash: Hash,
+    descendants: Option<Vec<PathBuf>>,
 }
 
 impl Repository {
@@ -40,17 +109,22 @@ impl Repository {
     pub fn open<T: AsRef<Path>>(
         parent: &Span,
         path: T,
+        mode: OpenMode,
         options: &Cli,
     ) -> Result<Repository, EvsError> {
-        debug!("Repository::open({:?})", path.as_ref());
+        debug!("Repository::open({:?}, {:?})", path.as_ref(), mode);
 
-        Self::open_(path.as_ref(), options)
+        Self::open_(path.as_ref(), mode, options)
     }
 
+    #[allow(clippy::too_many_lines, reason = "This is fine.")]
     fn open_(
         path: &Path,
-        _options: &Cli,
+        mode: OpenMode,
+        options: &Cli,
     ) -> Result<Repository, EvsError> {
+        let current = Span::current();
+
         let _ = path.read_dir().map_err(|e| (e, path.to_path_buf()))?;
 
         trace!("Workspace exists and is a directory.");
@@ -58,7 +132,7 @@ impl Repository {
         let repo = path.join(".evs");
 
         if !repo.exists() {
-            return Err(EvsError::MissingRepository(repo));
+            return Err(EvsError::MissingRepository(path.to_path_buf()));
         }
 
         if !repo.is_dir() {
@@ -75,13 +149,13 @@ impl Repository {
 
         let store = repo.join("store");
 
-        if !store.exists() {
+        if !store.exists() && mode != OpenMode::RepairStore {
             return Err(EvsError::CorruptStateDetected(CorruptState::MissingPath(
                 store,
             )));
         }
 
-        if !store.is_dir() {
+        if store.exists() && !store.is_dir() {
             return Err(EvsError::CorruptStateDetected(
                 CorruptState::DirectoryIsFile(store),
             ));
@@ -123,19 +197,57 @@ impl Repository {
             .read_to_end(&mut repo_info)
             .map_err(|e| (e, lockfile_path.clone()))?;
 
-        let repo_info =
+        let repo_info: RepositoryInfo =
             rmp_serde::from_slice(&repo_info).map_err(EvsError::RepositoryInfoCorrupt)?;
 
         trace!("Read repository info successfully.");
 
-        let repository = Repository {
+        let missing_store = !store.exists();
+
+        let mut repository = Repository {
             workspace: path.to_path_buf(),
             repository: repo,
             lockfile,
-            store: Store::new(store),
+            store: Store::new(
+                store,
+                repo_info.shared(),
+                repo_info.hash_algo(),
+                options.compression,
+                options.chunk_size,
+                !options.no_cache,
+            ),
             info: repo_info,
+            index: None,
+            max_depth: options.max_tree_depth,
         };
 
+        if missing_store {
+            warn!("Recreating missing store {:?}.", repository.store.path());
+
+            repository.store.create_layout(&current)?;
+        }
+
+        repository.store.migrate(&current)?;
+
+        repository.store.load_packs(&current)?;
+
+        repository.info.head_hash = repository.resolve_head()?;
+
+        trace!(
+            "HEAD resolved to \"{}\".",
+            HashDisplay(&repository.info.head())
+        );
+
+        if mode == OpenMode::Normal {
+            let stage = repository.info.stage();
+
+            if !repository.store.contains(&stage) {
+                return Err(EvsError::MissingStage(stage));
+            }
+
+            trace!("Staged tree exists.");
+        }
+
         trace!("Created repository.");
 
         Ok(repository)
@@ -146,89 +258,47 @@ impl Repository {
     pub fn create<T: AsRef<Path>>(
         parent: &Span,
         path: T,
+        parents: bool,
+        shared: bool,
+        algo: HashAlgo,
         options: &Cli,
     ) -> Result<Repository, EvsError> {
-        debug!("Repository::create(self, {:?})", path.as_ref());
+        debug!(
+            "Repository::create(self, {:?}, {}, {}, {:?})",
+            path.as_ref(),
+            parents,
+            shared,
+            algo
+        );
 
-        Self::create_(path.as_ref(), options)
+        Self::create_(path.as_ref(), parents, shared, algo, options)
     }
 
     fn create_(
         path: &Path,
-        _options: &Cli,
+        parents: bool,
+        shared: bool,
+        algo: HashAlgo,
+        options: &Cli,
     ) -> Result<Repository, EvsError> {
         let current = Span::current();
 
-        let _ = path.read_dir().map_err(|e| (e, path.to_path_buf()))?;
-
-        trace!("Workspace exists and is a directory.");
-
-        let repo = path.join(".evs");
-
-        DirBuilder::new()
-            .create(&repo)
-            .map_err(|e| (e, repo.clone()))?;
-
-        trace!("Created repository directory.");
-
-        let repo = repo.canonicalize().map_err(|e| (e, repo))?;
-
-        trace!("Repository directory was canonicalized.");
-
-        let store = repo.join("store");
-
-        DirBuilder::new()
-            .create(&store)
-            .map_err(|e| (e, store.clone()))?;
-
-        trace!("Created store directory.");
-
-        let store = Store::new(store);
-
-        let root = store.insert(&current, Object::Null)?;
-
-        trace!("Inserted null object.");
-
-        let empty_stage = store.insert(&current, Object::Tree(vec![]))?;
-
-        trace!("Inserted empty tree.");
-
-        let lockfile_path = repo.join("lock");
-
-        let mut lockfile = OpenOptions::new()
-            .create_new(true)
-            .read(true)
-            .write(true)
-            .open(&lockfile_path)
-            .map_err(|e| (e, lockfile_path.clone()))?;
-
-        lockfile.try_lock().map_err(|e| (e, repo.clone()))?;
-
-        trace!("Created and locked lockfile.");
-
-        let repo_info = RepositoryInfo {
-            head: root,
-            stage: empty_stage,
-            modified: false,
-        };
-
-        lockfile
-            .write_all(&rmp_serde::to_vec(&repo_info)?)
-            .map_err(|e| (e, lockfile_path.clone()))?;
-
-        trace!("Wrote repository info into the lockfile.");
+        Self::create_with_(path, parents, shared, options, |repo| {
+            let store = Store::new(
+                repo.join("store"),
+                shared,
+                algo,
+                options.compression,
+                options.chunk_size,
+                !options.no_cache,
+            );
 
-        let repository = Repository {
-            workspace: path.to_path_buf(),
-            repository: repo,
-            lockfile,
-            store,
-            info: repo_info,
-        };
+            store.create_layout(&current)?;
 
-        trace!("Created repository.");
+            trace!("Created store directories.");
 
-        Ok(repository)
+            Ok(store)
+        })
     }
 
     #[inline]
@@ -236,27 +306,31 @@ impl Repository {
     pub fn find<T: AsRef<Path>>(
         parent: &Span,
         path: T,
+        mode: OpenMode,
         options: &Cli,
     ) -> Result<Repository, EvsError> {
-        debug!("Repository::find({:?})", path.as_ref());
+        debug!("Repository::find({:?}, {:?})", path.as_ref(), mode);
 
-        Self::find_(path.as_ref(), options)
+        Self::find_(path.as_ref(), mode, options)
     }
 
     fn find_(
         path: &Path,
+        mode: OpenMode,
         options: &Cli,
     ) -> Result<Repository, EvsError> {
         let current = Span::current();
 
-        let mut path = path.canonicalize().map_err(|e| (e, path.to_path_buf()))?;
+        let start = path.canonicalize().map_err(|e| (e, path.to_path_buf()))?;
 
         trace!("Canonicalized path.");
 
+        let mut path = start.clone();
+
         loop {
             trace!("Trying path {:?}:", path);
 
-            match Self::open(&current, &path, options) {
+            match Self::open(&current, &path, mode, options) {
                 Ok(repo) => {
                     trace!("Found repository in {:?}.", path);
 
@@ -269,453 +343,388 @@ impl Repository {
             }
 
             if !path.pop() {
-                return Err(EvsError::RepositoryNotFound);
+                return Err(EvsError::RepositoryNotFound(start));
             }
         }
     }
 
+    /// With `fast`, the check is skipped if nothing changed since the last successful one, which is then returned.
+    /// Unless `full`, blobs recorded as verified in `.evs/check-cache` are only checked for existence.
     #[inline]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
     pub fn check(
         &self,
         parent: &Span,
         all: bool,
-    ) -> Result<(), EvsError> {
-        debug!("Repository::check(self)");
+        fast: bool,
+        full: bool,
+    ) -> Result<Option<SystemTime>, EvsError> {
+        debug!("Repository::check(self, {}, {}, {})", all, fast, full);
 
-        self.store
-            .check::<&[Hash]>(&Span::current(), AHashSet::new(), &self.gc_roots(), all)
-            .map(|_| ())
-    }
+        let current = Span::current();
 
-    #[inline]
-    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn add<T: AsRef<Path>>(
-        &mut self,
-        parent: &Span,
-        path: T,
-        overrides: &AHashSet<PathBuf>,
-        options: &Cli,
-    ) -> Result<(), EvsError> {
-        debug!(
-            "Repository::add(self, {:?}, {:?})",
-            path.as_ref(),
-            overrides
-        );
+        let marker = self.repository.join("checked");
 
-        self.add_(path.as_ref(), overrides, options)
-    }
+        let mut hasher = Sha256::new();
 
-    fn add_(
-        &mut self,
-        path: &Path,
-        overrides: &AHashSet<PathBuf>,
-        options: &Cli,
-    ) -> Result<(), EvsError> {
-        let current = Span::current();
+        hasher.update(self.store.fingerprint(&current)?);
+        hasher.update(self.info.head());
+        hasher.update(self.info.stage());
+        hasher.update([u8::from(all)]);
 
-        let canon = path.canonicalize().map_err(|e| (e, path.to_path_buf()))?;
+        let fingerprint: Hash = hasher.finalize().into();
 
-        trace!("Canonicalized path to {:?}", canon);
+        if fast
+            && fs::read(&marker).is_ok_and(|previous| previous == fingerprint)
+            && let Ok(checked) = fs::metadata(&marker).and_then(|m| m.modified())
+        {
+            trace!("Fingerprint matches the last check.");
 
-        if !canon.starts_with(&self.workspace) {
-            return Err(EvsError::PathOutsideOfRepo(canon));
+            return Ok(Some(checked));
         }
 
-        let relative = canon.strip_prefix(&self.workspace).unwrap();
+        let cache_path = self.repository.join("check-cache");
 
-        let ignores = self.get_ignores(&current, options)?;
+        let (count, _) = self.store.status(&current)?;
 
-        trace!("Using ignores: {:?}.", ignores);
+        let blobs = if full {
+            AHashSet::new()
+        } else {
+            fs::read(&cache_path)
+                .ok()
+                .and_then(|content| rmp_serde::from_slice::<CheckCache>(&content).ok())
+                // Deleted objects could be missing now, so a smaller store invalidates the cache
+                .filter(|cache| cache.count <= count)
+                .map(|cache| cache.blobs)
+                .unwrap_or_default()
+        };
 
-        let is_ignored = ignores
-            .iter()
-            .any(|i| relative.ancestors().any(|a| i.matches_path(a)));
+        trace!("Skipping {} already verified blob(s).", blobs.len());
 
-        if is_ignored
-            && !overrides.contains(relative)
-            && (relative.starts_with(".evs")
-                || !confirmation!(false, "{:?} is ignored, add anyway?", relative)?)
-        {
-            trace!("Filtered path {:?}.", relative);
+        let blobs = self
+            .store
+            .check(&current, blobs, &self.gc_roots()?, all)?
+            .blobs;
 
-            return Ok(());
-        }
+        let content = rmp_serde::to_vec(&CheckCache { count, blobs })?;
 
-        let hash = if canon.is_dir() {
-            let ignores = if is_ignored { &vec![] } else { &ignores };
+        fs::write(&cache_path, content).map_err(|e| (e, cache_path))?;
 
-            let hash = self.hash_dir(&current, &canon, ignores, overrides)?;
+        fs::write(&marker, fingerprint).map_err(|e| (e, marker))?;
 
-            if relative == "" {
-                trace!("Hashed contents of path.");
+        trace!("Recorded fingerprint of successful check.");
 
-                trace!("Recomputed stage.");
+        Ok(None)
+    }
 
-                if self.info.stage() == hash {
-                    trace!("New stage is equal to old stage.");
-                } else {
-                    self.info.set_stage(hash);
-                }
+    /// Like `check` with `full`, but moves unreadable objects into `.evs/corrupt` instead of failing on the first one.
+    /// Returns them with why they could not be read and the required objects that are missing afterwards.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn check_repair(
+        &self,
+        parent: &Span,
+        all: bool,
+    ) -> Result<CheckRepair, EvsError> {
+        debug!("Repository::check_repair(self, {})", all);
 
-                return Ok(());
-            }
+        let current = Span::current();
 
-            hash
-        } else {
-            self.store.insert(
-                &current,
-                Object::Blob(fs::read(&canon).map_err(|e| (e, canon.clone()))?),
-            )?
-        };
+        let report = self.store.check_report(&current, &self.gc_roots()?, all)?;
 
-        trace!("Hashed contents of path.");
+        trace!(
+            "Found {} unreadable and {} missing object(s).",
+            report.corrupt.len(),
+            report.missing.len()
+        );
 
-        let new_stage = match self.update_stage(
-            &current,
-            relative.components().peekable(),
-            relative,
-            Some(hash),
-            self.info.stage(),
-        )? {
-            Some(stage) => stage,
-            None => self.store.insert(&current, Object::Tree(vec![]))?,
-        };
+        if !report.corrupt.is_empty() {
+            let names = report
+                .corrupt
+                .iter()
+                .map(|(name, _)| name.as_os_str())
+                .collect::<Vec<_>>();
 
-        trace!("Recomputed stage.");
+            let moved =
+                self.store
+                    .quarantine(&current, &names, &self.repository.join(CORRUPT_DIR))?;
 
-        if self.info.stage() == new_stage {
-            trace!("New stage is equal to old stage.");
-        } else {
-            self.info.set_stage(new_stage);
+            trace!("Quarantined {} object file(s).", moved.len());
         }
 
-        Ok(())
+        Ok(CheckRepair {
+            quarantined: report.corrupt,
+            missing: report.missing,
+        })
     }
 
+    /// Writes every object reachable from `ref` as stored to `output`, so `unbundle` can import them elsewhere.
+    /// The file is the magic, the version, the length of the header and the header,
+    /// followed by the hash, the length and the content of each object, all integers in little endian.
+    /// Returns the bundled object and the number of objects.
     #[inline]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn sub<T: AsRef<Path>>(
-        &mut self,
+    pub fn bundle<T: AsRef<str>>(
+        &self,
         parent: &Span,
-        path: T,
-        delete: bool,
-    ) -> Result<(), EvsError> {
-        debug!("Repository::sub(self, {:?})", path.as_ref());
+        r#ref: T,
+        output: &Path,
+    ) -> Result<(Hash, usize), EvsError> {
+        debug!(
+            "Repository::bundle(self, \"{}\", {:?})",
+            r#ref.as_ref(),
+            output
+        );
 
-        self.sub_(path.as_ref(), delete)
+        self.bundle_(r#ref.as_ref(), output)
     }
 
-    fn sub_(
-        &mut self,
-        path: &Path,
-        delete: bool,
-    ) -> Result<(), EvsError> {
+    fn bundle_(
+        &self,
+        r#ref: &str,
+        output: &Path,
+    ) -> Result<(Hash, usize), EvsError> {
         let current = Span::current();
 
-        let canon = partial_canonicalize(&current, path).map_err(|e| (e, path.to_path_buf()))?;
+        let resolved = self.resolve(&current, r#ref)?;
 
-        trace!("Canonicalized path to {:?}", canon);
+        let (target, _) = self.store.lookup_uncached(&current, resolved.as_str())?;
 
-        if !canon.starts_with(&self.workspace) {
-            return Err(EvsError::PathOutsideOfRepo(canon));
-        }
+        let branch = if r#ref == "HEAD" {
+            self.info.branch().map(ToOwned::to_owned)
+        } else if is_valid_ref_name(r#ref) && self.read_branch(r#ref)?.is_some() {
+            Some(r#ref.to_owned())
+        } else {
+            None
+        };
 
-        if canon.starts_with(&self.repository) {
-            return Err(EvsError::PathOutsideOfRepo(canon));
-        }
+        trace!(
+            "Bundling \"{}\" for branch {:?}.",
+            HashDisplay(&target),
+            branch
+        );
 
-        let relative = canon
-            .strip_prefix(self.repository.parent().unwrap())
-            .unwrap();
+        let found = self
+            .store
+            .check(&current, AHashSet::new(), &[target], false)?
+            .found;
 
-        let new_stage = if relative == "" {
-            if delete {
-                self.store.insert(&current, Object::Tree(vec![]))?
-            } else {
-                self.get_tree(&current, self.info.head())?
-            }
-        } else {
-            match self.update_stage(
-                &current,
-                relative.components().peekable(),
-                relative,
-                (!delete)
-                    .then(|| {
-                        self.tree_lookup(
-                            &current,
-                            self.get_tree(&current, self.info.head())?,
-                            path.components(),
-                        )
-                    })
-                    .transpose()?
-                    .flatten(),
-                self.info.stage(),
-            )? {
-                Some(stage) => stage,
-                None => self.store.insert(&current, Object::Tree(vec![]))?,
-            }
-        };
+        let mut objects = found.into_iter().collect::<Vec<_>>();
 
-        trace!("Recomputed stage.");
+        // Bundles of the same history are identical
+        objects.sort_unstable();
 
-        if self.info.stage() == new_stage {
-            trace!("New stage is equal to old stage.");
-        } else {
-            self.info.set_stage(new_stage);
+        let header = rmp_serde::to_vec(&BundleHeader {
+            algo: self.store.algo(),
+            branch,
+            target,
+            count: u64::try_from(objects.len()).unwrap_or(u64::MAX),
+        })?;
+
+        let io_error = |e| EvsError::IOError(e, output.to_path_buf());
+
+        let mut writer = BufWriter::new(File::create(output).map_err(io_error)?);
+
+        writer.write_all(BUNDLE_MAGIC).map_err(io_error)?;
+        writer
+            .write_all(&BUNDLE_VERSION.to_le_bytes())
+            .map_err(io_error)?;
+        write_bundle_chunk(&mut writer, &header).map_err(io_error)?;
+
+        for hash in &objects {
+            let stored = self.store.read_stored(&current, *hash)?;
+
+            writer.write_all(hash).map_err(io_error)?;
+            write_bundle_chunk(&mut writer, &stored).map_err(io_error)?;
         }
 
-        Ok(())
+        writer.flush().map_err(io_error)?;
+
+        trace!("Wrote {} object(s).", objects.len());
+
+        Ok((target, objects.len()))
     }
 
+    /// Imports the objects of a bundle written by `bundle`, verifying each of them,
+    /// and points the branch it was made from, or `branch` if given, at the bundled object.
+    /// An existing branch is only moved if that is a fast-forward, the checked out one along with the workspace.
+    /// Whether it is one is decided before any object is imported.
+    /// Returns the bundled object, the branch if there is one and the number of objects.
+    #[inline]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    fn update_stage(
+    pub fn unbundle(
         &mut self,
         parent: &Span,
-        mut components: Peekable<Components>,
-        path: impl AsRef<Path>,
-        obj: Option<Hash>,
-        tree: Hash,
-    ) -> Result<Option<Hash>, EvsError> {
-        let current = Span::current();
+        input: &Path,
+        branch: Option<&str>,
+        options: &Cli,
+    ) -> Result<(Hash, Option<String>, usize), EvsError> {
+        debug!("Repository::unbundle(self, {:?}, {:?})", input, branch);
 
-        let next = components.next().unwrap();
+        self.unbundle_(input, branch, options)
+    }
 
-        debug!(
-            "Repository::update_stage(self, {:?}, {:?}..., {}, {})",
-            path.as_ref(),
-            next,
-            obj.map_or("deleting", |_| "inserting"),
-            HashDisplay(&tree)
-        );
+    fn unbundle_(
+        &mut self,
+        input: &Path,
+        branch: Option<&str>,
+        options: &Cli,
+    ) -> Result<(Hash, Option<String>, usize), EvsError> {
+        let current = Span::current();
 
-        let path = path.as_ref();
+        // The first pass only verifies the bundle and collects the parents of its commits
+        let mut bundled = AHashMap::new();
 
-        let next_bytes = AsRef::<Path>::as_ref(&next).as_os_str().as_encoded_bytes();
-
-        let mut items = match self
-            .store
-            .lookup(&current, &format!("{}", HashDisplay(&tree)))
-        {
-            Ok((_, Object::Tree(items))) => items,
-            Ok((hash, _)) => {
-                trace!("Replacing object \"{}\" with new tree.", HashDisplay(&hash));
-
-                vec![]
-            }
-            Err(e) => return Err(e),
-        };
-
-        trace!("Obtained {} tree item(s).", items.len());
-
-        let hash = if components.peek().is_none() {
-            obj
-        } else {
-            let next = if let Some(next) = items.iter().find(|e| e.name.as_bytes() == next_bytes) {
-                next.content
-            } else {
-                if obj.is_none() {
-                    return Err(EvsError::PathNotInStage(path.to_path_buf()));
-                }
-                self.store.insert(&current, Object::Tree(vec![]))?
+        let header = self.read_bundle(input, |hash, data| {
+            let parents = match rmp_serde::from_slice::<Object>(data) {
+                Ok(Object::Commit(commit)) => Some(commit.parents),
+                _ => None,
             };
 
-            self.update_stage(&current, components, path, obj, next)?
-        };
-
-        trace!("Obtained hash or lack thereof of later component(s).");
+            bundled.insert(hash, parents);
 
-        let hash = if let Some(obj) = hash {
-            #[allow(clippy::indexing_slicing, reason = "The index comes from enumerate.")]
-            if let Some(index) = items
-                .iter()
-                .enumerate()
-                .find_map(|(i, e)| (e.name.as_bytes() == next_bytes).then_some(i))
-            {
-                if items[index].content == obj {
-                    trace!("Object unchanged.");
+            Ok(())
+        })?;
 
-                    Some(tree)
-                } else {
-                    items[index].content = obj;
+        let branch = branch.map(ToOwned::to_owned).or(header.branch.clone());
 
-                    trace!("Object changed, adding new tree to store...");
+        let mut fast_forward = false;
 
-                    Some(self.store.insert(&current, Object::Tree(items))?)
+        if let Some(name) = &branch {
+            match self.read_branch(name)? {
+                Some(existing) if existing == header.target => {
+                    trace!("Branch {:?} is already up to date.", name);
                 }
-            } else {
-                items.push(TreeEntry {
-                    name: String::from_utf8(next_bytes.to_owned())
-                        .map_err(|e| EvsError::PathError(e.utf8_error(), e.into_bytes()))?,
-                    content: obj,
-                });
-
-                trace!("Tree changed, adding tree to store...");
+                Some(existing) if !self.bundle_descends(&bundled, header.target, existing)? => {
+                    return Err(EvsError::BranchDiverged(name.clone()));
+                }
+                _ if self.info.branch() == Some(name) => {
+                    if self.info.stage() != self.get_tree(&current, self.info.head())? {
+                        return Err(EvsError::UncommittedChanges);
+                    }
 
-                Some(self.store.insert(&current, Object::Tree(items))?)
+                    fast_forward = true;
+                }
+                _ => {}
             }
-        } else {
-            if let Some(index) = items
-                .iter()
-                .enumerate()
-                .find_map(|(i, e)| (e.name.as_bytes() == next_bytes).then_some(i))
-            {
-                items.remove(index);
+        }
 
-                trace!("Deleted object.");
+        drop(bundled);
 
-                if items.is_empty() {
-                    trace!("Empty tree pruned.");
+        trace!(
+            "Importing {} object(s) for \"{}\".",
+            header.count,
+            HashDisplay(&header.target)
+        );
 
-                    None
-                } else {
-                    trace!("Tree changed, adding tree to store...");
+        let mut count = 0usize;
 
-                    Some(self.store.insert(&current, Object::Tree(items))?)
-                }
-            } else {
-                return Err(EvsError::PathNotInStage(path.to_path_buf()));
-            }
-        };
+        self.read_bundle(input, |_, data| {
+            self.store.insert_serialized(&current, data)?;
 
-        trace!("Obtained hash or lack thereof of this component.");
+            count = count.saturating_add(1);
 
-        Ok(hash)
-    }
+            Ok(())
+        })?;
 
-    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    fn hash_dir(
-        &self,
-        parent: &Span,
-        path: &PathBuf,
-        ignores: impl AsRef<[Pattern]>,
-        overrides: &AHashSet<PathBuf>,
-    ) -> Result<Hash, EvsError> {
-        debug!(
-            "Repository::hash_dir(self, {:?}, {} ignores, {:?})",
-            path,
-            ignores.as_ref().len(),
-            overrides
-        );
+        trace!("Imported {} object(s), checking completeness...", count);
 
-        let current = Span::current();
+        self.store
+            .check(&current, AHashSet::new(), &[header.target], false)?;
 
-        let ignores = ignores.as_ref();
+        if let Some(name) = &branch
+            && self.read_branch(name)? != Some(header.target)
+        {
+            if fast_forward {
+                trace!("Fast-forwarding the checked out branch {:?}.", name);
 
-        let res = if !path.is_dir() {
-            let content = fs::read(path).map_err(|e| (e, path.to_owned()))?;
+                let head_tree = self.get_tree(&current, self.info.head())?;
 
-            trace!("Read blob, inserting...");
+                let target_tree = self.get_tree(&current, header.target)?;
 
-            self.store.insert(&current, Object::Blob(content))?
-        } else {
-            let items = path
-                .read_dir()
-                .map_err(|e| (e, path.to_owned()))?
-                .par_bridge()
-                .filter_map(|child| {
-                    let _entered = current.enter();
+                self.merge_trees(&current, head_tree, head_tree, target_tree, options)?;
 
-                    let name = match child {
-                        Ok(child) => child.file_name(),
-                        Err(e) => return Some(Err((e, path.clone()).into())),
-                    };
+                self.set_head(header.target)?;
+            } else {
+                self.write_branch(name, header.target)?;
+            }
+        }
 
-                    let name_bytes = name.as_encoded_bytes().to_owned();
+        Ok((header.target, branch, count))
+    }
 
-                    let next = path.join(&name);
+    /// Reads and verifies the bundle at `input`, passing the hash and serialized data of each object to `visit`.
+    fn read_bundle<F: FnMut(Hash, &[u8]) -> Result<(), EvsError>>(
+        &self,
+        input: &Path,
+        mut visit: F,
+    ) -> Result<BundleHeader, EvsError> {
+        let invalid = |reason| EvsError::InvalidBundle(input.to_path_buf(), reason);
+
+        let read_error = |e: io::Error| {
+            if e.kind() == ErrorKind::UnexpectedEof {
+                invalid("it is truncated")
+            } else {
+                EvsError::IOError(e, input.to_path_buf())
+            }
+        };
 
-                    let relative = next.strip_prefix(&self.workspace).unwrap();
+        let mut reader = BufReader::new(File::open(input).map_err(read_error)?);
 
-                    if ignores
-                        .iter()
-                        .any(|i| relative.ancestors().any(|a| i.matches_path(a)))
-                        && !overrides.iter().any(|o| o.starts_with(relative))
-                    {
-                        trace!("Filtered child {:?}.", name);
+        let mut magic = [0; BUNDLE_MAGIC.len()];
 
-                        return None;
-                    }
+        reader.read_exact(&mut magic).map_err(read_error)?;
 
-                    let hash = match self.hash_dir(&current, &next, ignores, overrides) {
-                        Ok(hash) => hash,
-                        Err(e) => return Some(Err(e)),
-                    };
+        if magic != *BUNDLE_MAGIC {
+            return Err(invalid("it is not a bundle"));
+        }
 
-                    trace!("Hashed child {:?}.", name);
+        let mut version = [0; size_of::<u32>()];
 
-                    let name = match String::from_utf8(name_bytes) {
-                        Ok(name) => name,
-                        Err(e) => {
-                            return Some(Err(EvsError::PathError(e.utf8_error(), e.into_bytes())));
-                        }
-                    };
+        reader.read_exact(&mut version).map_err(read_error)?;
 
-                    Some(Ok(TreeEntry {
-                        name,
-                        content: hash,
-                    }))
-                })
-                .collect::<Result<Vec<_>, _>>()?;
+        if u32::from_le_bytes(version) != BUNDLE_VERSION {
+            return Err(invalid("its version is not supported"));
+        }
 
-            trace!("Inserting resulting tree...");
+        let header = read_bundle_chunk(&mut reader).map_err(read_error)?;
 
-            self.store.insert(&current, Object::Tree(items))?
-        };
+        let header = rmp_serde::from_slice::<BundleHeader>(&header)
+            .map_err(|_e| invalid("its header is corrupt"))?;
 
-        Ok(res)
-    }
+        if header.algo != self.store.algo() {
+            return Err(invalid("it uses a different hash algorithm"));
+        }
 
-    #[allow(clippy::too_many_arguments, reason = "Reasonable here.")]
-    #[inline]
-    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn commit(
-        &mut self,
-        parent: &Span,
-        amend_parent: Option<Hash>,
-        message: String,
-        name: String,
-        email: String,
-        time: SystemTime,
-        _options: &Cli,
-    ) -> Result<Hash, EvsError> {
-        debug!(
-            "Repository::commit(self, \"{}\", {}, {}, {:?})",
-            message.as_bytes().escape_ascii(),
-            name,
-            email,
-            time
-        );
+        for _ in 0..header.count {
+            let mut hash = Hash::default();
 
-        let commit = self.store.insert(
-            &Span::current(),
-            Object::Commit(Commit {
-                parent: amend_parent.unwrap_or(self.info.head()),
-                name,
-                email,
-                tree: self.info.stage(),
-                msg: message,
-                date: time,
-            }),
-        )?;
+            reader.read_exact(&mut hash).map_err(read_error)?;
 
-        trace!("Created and inserted commit object.");
+            let stored = read_bundle_chunk(&mut reader).map_err(read_error)?;
 
-        self.info.set_head(commit);
+            let data = Store::decompress(&stored)
+                .map_err(|_e| invalid("an object is compressed incorrectly"))?;
 
-        trace!("Moved head to \"{}\".", HashDisplay(&commit));
+            if self.store.algo().digest(&data) != hash {
+                return Err(invalid("an object does not match its hash"));
+            }
 
-        Ok(commit)
+            visit(hash, &data)?;
+        }
+
+        Ok(header)
     }
 
+    /// Streams the serialized object as stored, without reassembling chunk lists, see `Store::read_raw`.
     #[inline]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn lookup<T: AsRef<str>>(
+    pub fn read_raw<T: AsRef<str>>(
         &self,
         parent: &Span,
         r#ref: T,
-    ) -> Result<(Hash, Object), EvsError> {
-        debug!("Repository::lookup(self, \"{}\")", r#ref.as_ref());
+    ) -> Result<(Hash, RawObject), EvsError> {
+        debug!("Repository::read_raw(self, \"{}\")", r#ref.as_ref());
 
         let current = Span::current();
 
@@ -723,720 +732,6304 @@ impl Repository {
 
         trace!("Resolved to \"{}\".", resolved);
 
-        self.store.lookup(&current, resolved.as_str())
+        self.store.read_raw(&current, resolved.as_str())
     }
 
+    /// Unreferenced objects written within `grace` are kept, as another process might be about to reference them.
+    /// Everything HEAD pointed at within `keep_for` is kept as well, older reflog entries are dropped.
     #[inline]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn log<T: AsRef<str>>(
+    pub fn gc(
         &self,
         parent: &Span,
-        r#ref: T,
-        limit: usize,
-        oneline: bool,
-        options: &Cli,
-    ) -> Result<(), EvsError> {
-        debug!(
-            "Repository::log(self, \"{}\", {}, {})",
-            r#ref.as_ref(),
-            limit,
-            oneline
-        );
-
-        self.log_(r#ref.as_ref(), limit, oneline, options)
-    }
+        grace: Duration,
+        keep_for: Duration,
+        yes: bool,
+        _options: &Cli,
+    ) -> Result<(usize, usize), EvsError> {
+        debug!("Repository::gc(self, {:?}, {:?}, {})", grace, keep_for, yes);
 
-    fn log_(
-        &self,
-        r#ref: &str,
-        limit: usize,
-        oneline: bool,
-        options: &Cli,
-    ) -> Result<(), EvsError> {
         let current = Span::current();
 
-        let print_color = get_color(options);
+        let now = SystemTime::now();
 
-        let mod_color = if print_color { MOD_COLOR } else { "" };
-        let info_color = if print_color { INFO_COLOR } else { "" };
-        let none_color = if print_color { NONE_COLOR } else { "" };
+        let cutoff = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs())
+            .saturating_sub(keep_for.as_secs());
 
-        let mut resolved = self.resolve(&current, r#ref)?;
+        let (kept, expired): (Vec<_>, Vec<_>) = self
+            .read_reflog()?
+            .into_iter()
+            .partition(|(_, _, time)| *time >= cutoff);
 
-        trace!("Resolved to \"{}\".", resolved);
+        if !expired.is_empty() {
+            self.write_reflog(&kept)?;
 
-        for _ in 0..limit {
-            let (hash, commit) = self.store.lookup(&current, &resolved)?;
-
-            match commit {
-                Object::Null => return Ok(()),
-                Object::Commit(Commit {
-                    parent, ref msg, ..
-                }) => {
-                    if oneline {
-                        println!(
-                            "{}{}{}: {}{}{}",
-                            info_color,
-                            HashDisplay(&hash),
-                            none_color,
-                            mod_color,
-                            msg.lines().next().unwrap_or(""),
-                            none_color
-                        );
-                    } else {
-                        println!(
-                            "{}{}{}:\n{}{}{}",
-                            info_color,
-                            HashDisplay(&hash),
-                            none_color,
-                            mod_color,
-                            commit,
-                            none_color
-                        );
-                    }
+            trace!("Dropped {} expired reflog entries.", expired.len());
+        }
 
-                    resolved = format!("{}", HashDisplay(&parent));
-                }
-                _ => return Err(EvsError::NotACommit(hash)),
-            }
+        let mut roots = self.gc_roots()?;
 
-            trace!("Continuing with \"{}\"", resolved);
-        }
+        // Objects an earlier gc already deleted cannot be kept anymore.
+        roots.extend(
+            kept.iter()
+                .flat_map(|(old, new, _)| [*old, *new])
+                .filter(|hash| self.store.contains(hash)),
+        );
 
-        println!("{}...{}", info_color, none_color);
+        trace!("Keeping {} recent reflog entries.", kept.len());
 
-        Ok(())
-    }
+        let mut extra = self
+            .store
+            .check(&current, AHashSet::new(), &roots, true)?
+            .extra;
 
-    #[inline]
-    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn resolve<T: AsRef<str>>(
-        &self,
-        parent: &Span,
-        r#ref: T,
-    ) -> Result<String, EvsError> {
-        debug!("Repository::resolve(self, \"{}\")", r#ref.as_ref());
+        trace!("Checked store and obtained {} extras.", extra.len());
 
-        self.resolve_(r#ref.as_ref())
-    }
+        let mut recent = 0usize;
 
-    fn resolve_(
-        &self,
-        r#ref: &str,
-    ) -> Result<String, EvsError> {
-        let current = Span::current();
+        for item in extra.clone() {
+            let modified = self.store.modified(&current, item)?;
 
-        let (first, back_count) = r#ref.split_once('~').unwrap_or((r#ref, "0"));
+            if now.duration_since(modified).is_ok_and(|age| age >= grace) {
+                continue;
+            }
 
-        let back_count = back_count
-            .parse::<usize>()
-            .map_err(EvsError::IntegerParseError)?;
+            trace!("Keeping recent \"{}\".", HashDisplay(&item));
 
-        let first = match first {
-            "HEAD" => format!("{}", HashDisplay(&self.info.head())),
-            first => first.to_owned(),
-        };
+            extra.remove(&item);
 
-        trace!("Starting at \"{}\".", first);
+            recent = recent.saturating_add(1);
+        }
 
-        let mut resolved = first;
+        if recent > 0 {
+            summary!(
+                "Keeping {} unreferenced object(s) written within the last {}s",
+                recent,
+                grace.as_secs()
+            );
+        }
 
-        for _ in 0..back_count {
-            let (hash, commit) = self.store.lookup(&current, resolved.as_str())?;
+        let mut reclaimed = (0usize, 0usize);
 
-            resolved = match commit {
-                Object::Commit(Commit { parent, .. }) => format!("{}", HashDisplay(&parent)),
-                Object::Null => return Err(EvsError::NoPreviousCommit),
-                _ => return Err(EvsError::NotACommit(hash)),
-            };
+        if !extra.is_empty() {
+            summary!("This will delete {} object(s)", extra.len());
 
-            trace!("Gone back to \"{}\".", resolved);
+            if yes || confirmation!(true, "Are you sure?")? {
+                reclaimed = self.remove_objects(&current, extra)?;
+            }
         }
 
-        self.store.resolve_rest(&current, resolved)
+        Ok(reclaimed)
     }
 
-    #[inline]
-    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn gc(
+    /// Deletes `objects`, loose or packed, first expanding the deltas against them.
+    /// Returns the number and the stored size of the deleted objects.
+    fn remove_objects(
         &self,
         parent: &Span,
-        _options: &Cli,
-    ) -> Result<(), EvsError> {
-        debug!("Repository::gc(self)");
-
-        let current = Span::current();
+        objects: AHashSet<Hash>,
+    ) -> Result<(usize, usize), EvsError> {
+        if objects.is_empty() {
+            return Ok((0, 0));
+        }
 
-        let (_, extra) =
-            self.store
-                .check::<&[Hash]>(&current, AHashSet::new(), &self.gc_roots(), true)?;
+        let expanded = self.store.expand_deltas(parent, &objects)?;
 
-        trace!("Checked store and obtained {} extras.", extra.len());
+        if expanded > 0 {
+            trace!("Expanded {} delta(s) against deleted objects.", expanded);
+        }
 
-        if !extra.is_empty() {
-            println!("This will delete {} object(s)", extra.len(),);
+        warn!("Deleting {} object(s)...", objects.len());
 
-            if confirmation!(true, "Are you sure?")? {
-                warn!("Deleting {} object(s)...", extra.len());
+        let mut reclaimed = self.store.drop_packed(parent, &objects)?;
 
-                for item in extra {
-                    trace!("Deleting {}", HashDisplay(&item));
+        for item in objects {
+            if !self.store.contains(&item) {
+                trace!("Dropped \"{}\" from its pack.", HashDisplay(&item));
 
-                    self.store.remove(&current, item)?;
-                }
+                continue;
             }
+
+            trace!("Deleting {}", HashDisplay(&item));
+
+            let size = self.store.remove(parent, item)?;
+
+            reclaimed = (
+                reclaimed.0.saturating_add(1),
+                reclaimed.1.saturating_add(size),
+            );
         }
 
-        Ok(())
+        Ok(reclaimed)
     }
 
+    /// Stores each older version of a file reachable from the `gc` roots as a delta against the next newer one,
+    /// then moves all reachable loose objects into a pack file.
+    /// Returns the number of objects stored as deltas, the bytes saved by them and the number of packed objects.
     #[inline]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn get_tree(
+    pub fn pack(
         &self,
         parent: &Span,
-        commit: Hash,
-    ) -> Result<Hash, EvsError> {
-        debug!("Repository::get_tree(self, \"{}\")", HashDisplay(&commit));
+        _options: &Cli,
+    ) -> Result<(usize, usize, usize), EvsError> {
+        debug!("Repository::pack(self)");
 
         let current = Span::current();
 
-        let (hash, commit) = self
-            .store
-            .lookup(&current, &format!("{}", HashDisplay(&commit)))?;
+        let mut pending = VecDeque::from(self.gc_roots()?);
+        let mut seen = AHashSet::new();
 
-        trace!("Found referenced object.");
+        // The contents of each path, newest first
+        let mut versions = AHashMap::<PathBuf, Vec<Hash>>::new();
 
-        Ok(match commit {
-            Object::Null => self.store.insert(&current, Object::Tree(vec![]))?,
-            Object::Commit(commit) => commit.tree,
-            _ => return Err(EvsError::NotACommit(hash)),
-        })
-    }
+        while let Some(hash) = pending.pop_front() {
+            if !seen.insert(hash) {
+                continue;
+            }
 
-    #[inline]
-    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn get_ignores(
-        &self,
-        parent: &Span,
-        _options: &Cli,
-    ) -> Result<Vec<Pattern>, EvsError> {
-        debug!("Repository::get_ignores(self)");
+            let tree = match self
+                .store
+                .lookup_raw(&current, &format!("{}", HashDisplay(&hash)))?
+                .1
+            {
+                Object::Commit(commit) => {
+                    pending.extend(commit.parents);
 
-        let ignores_file = self.workspace.join(".evsignore");
+                    commit.tree
+                }
+                Object::Tag(tag) => {
+                    pending.push_back(tag.target);
 
-        let content = if ignores_file.exists() {
-            trace!("Ignores file exists, trying to read...");
+                    continue;
+                }
+                Object::Tree(_) => hash,
+                _ => continue,
+            };
 
-            fs::read_to_string(&ignores_file).map_err(|e| (e, ignores_file))?
-        } else {
-            trace!("Missing ignores file substituted with \"\".");
+            if tree != hash && !seen.insert(tree) {
+                continue;
+            }
 
-            String::new()
-        };
+            let mut entries = AHashMap::new();
 
-        trace!("Read ignores file successfully.");
+            self.tree_entries(&current, tree, Path::new(""), &mut entries)?;
 
-        content
-            .lines()
-            .map(str::trim)
-            .filter(|l| !l.is_empty())
-            .chain(once(".evs"))
-            .map(Pattern::new)
-            .collect::<Result<_, _>>()
-            .map_err(Into::into)
+            for (path, (content, mode)) in entries {
+                if mode == MODE_SYMLINK {
+                    continue;
+                }
+
+                let contents = versions.entry(path).or_default();
+
+                if contents.last() != Some(&content) {
+                    contents.push(content);
+                }
+            }
+        }
+
+        let mut versions = versions.into_iter().collect::<Vec<_>>();
+
+        versions.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        trace!("Found {} path(s) to pack.", versions.len());
+
+        let mut packed = (0usize, 0usize);
+
+        for (path, contents) in versions {
+            for pair in contents.windows(2) {
+                let &[base, target] = pair else {
+                    continue;
+                };
+
+                if let Some((before, after)) = self.store.pack_delta(&current, target, base)? {
+                    trace!("Packed a version of {:?}.", path);
+
+                    packed = (
+                        packed.0.saturating_add(1),
+                        packed.1.saturating_add(before.saturating_sub(after)),
+                    );
+                }
+            }
+        }
+
+        let reachable = self
+            .store
+            .check(&current, AHashSet::new(), &self.gc_roots()?, false)?
+            .found;
+
+        let reachable = reachable.into_iter().collect::<Vec<_>>();
+
+        let loose = reachable
+            .iter()
+            .filter(|hash| !self.store.is_packed(hash))
+            .count();
+
+        self.store.pack(&current, &reachable)?;
+
+        Ok((packed.0, packed.1, loose))
     }
 
     #[inline]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn status(
+    pub fn info(
         &self,
         parent: &Span,
         options: &Cli,
     ) -> Result<(), EvsError> {
-        debug!("Repository::status(self)");
+        debug!("Repository::info(self)");
 
-        let current = Span::current();
+        self.info_(&mut stdout().lock(), options)
+    }
 
-        let (store_count, store_size) = self.store.status(&current)?;
+    fn info_<W: io::Write>(
+        &self,
+        out: &mut W,
+        options: &Cli,
+    ) -> Result<(), EvsError> {
+        let (store_count, store_size) = self.store.status(&Span::current())?;
 
         trace!(
             "Store reported {} objects with a collective {} bytes.",
             store_count, store_size
         );
 
-        let (repo_head, repo_stage) = (self.info.head(), self.info.stage());
+        let write = |out: &mut W| -> io::Result<()> {
+            match self.info.created() {
+                Some(created) => writeln!(out, "  Created at {}", DateDisplay(&created))?,
+                None => writeln!(out, "  Created at an unknown time")?,
+            }
+            writeln!(out, "  Format version is {}", FORMAT_VERSION)?;
+            writeln!(out, "  Objects are named by {}", self.store.algo())?;
+            writeln!(out, "  Head is at \"{}\"", HashDisplay(&self.info.head()))?;
 
-        let commit_diffside = DiffSide::Tree(self.get_tree(&current, repo_head)?);
+            match self.info.branch() {
+                Some(branch) => writeln!(out, "  on branch {:?}", branch)?,
+                None => writeln!(out, "  detached from any branch")?,
+            }
 
-        let stage_diffside = DiffSide::Tree(repo_stage);
+            writeln!(
+                out,
+                "  and stage is \"{}\"",
+                HashDisplay(&self.info.stage())
+            )?;
+            writeln!(
+                out,
+                "  Store has {} objects with size {}",
+                store_count,
+                SizeDisplay(store_size, get_color(options))
+            )
+        };
 
-        let local_diffside = DiffSide::Local(self.workspace.clone());
+        write(out).map_err(|e| (e, "-".to_owned().into()).into())
+    }
 
-        trace!("Prepared diffsides.");
+    /// Reinserts the bootstrap objects and returns whether HEAD and the stage are present afterwards.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn repair_store(
+        &self,
+        parent: &Span,
+        _options: &Cli,
+    ) -> Result<(bool, bool), EvsError> {
+        debug!("Repository::repair_store(self)");
 
-        let ignores = self.get_ignores(&current, options)?;
+        let current = Span::current();
 
-        let global_filter = [AsRef::<Path>::as_ref("").to_path_buf()];
+        self.store.create_layout(&current)?;
 
-        let empty_set = AHashSet::new();
+        let root = self.store.insert(&current, Object::Null)?;
 
-        let cds = commit_diffside.read(
-            &current,
-            "",
-            &self.store,
-            &global_filter,
-            &ignores,
-            &empty_set,
-        )?;
+        trace!("Reinserted root \"{}\".", HashDisplay(&root));
 
-        let sds = stage_diffside.read(
-            &current,
-            "",
-            &self.store,
-            &global_filter,
-            &ignores,
-            &empty_set,
-        )?;
+        let empty_tree = self.store.insert(&current, Object::Tree(vec![]))?;
 
-        let lds =
-            local_diffside.read(&current, "", &self.store, &global_filter, &ignores, &sds.0)?;
+        trace!("Reinserted empty tree \"{}\".", HashDisplay(&empty_tree));
 
-        trace!("Read diffsides: {:?} -> {:?} -> {:?}.", cds.0, sds.0, lds.0);
+        Ok((
+            self.store.contains(&self.info.head()),
+            self.store.contains(&self.info.stage()),
+        ))
+    }
 
-        #[allow(clippy::indexing_slicing, reason = "The keys are in the map as well.")]
-        let (stage_added, stage_modified, stage_removed) = (
-            sds.0.difference(&cds.0).collect(),
-            sds.0
-                .intersection(&cds.0)
-                .filter(|k| cds.1[*k] != sds.1[*k])
-                .collect(),
-            cds.0.difference(&sds.0).collect(),
-        );
+    /// Deletes the objects unreachable from HEAD, the stage, the branches and the tags right away,
+    /// without the grace period and the reflog of `gc`.
+    /// With `dry_run`, only prints their names instead.
+    /// Returns the number and the stored size of the deleted objects, the size is 0 with `dry_run`.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn prune(
+        &self,
+        parent: &Span,
+        dry_run: bool,
+        _options: &Cli,
+    ) -> Result<(usize, usize), EvsError> {
+        debug!("Repository::prune(self, {})", dry_run);
 
-        trace!("Generated stage diff.");
+        let current = Span::current();
 
-        #[allow(clippy::indexing_slicing, reason = "The keys are in the map as well.")]
-        let (local_added, local_modified, local_removed) = (
-            lds.0.difference(&sds.0).collect(),
-            lds.0
-                .intersection(&sds.0)
-                .filter(|k| sds.1[*k] != lds.1[*k])
-                .collect(),
-            sds.0.difference(&lds.0).collect(),
-        );
+        let extra = self
+            .store
+            .check(&current, AHashSet::new(), &self.gc_roots()?, true)?
+            .extra;
 
-        trace!("Generated local diff.");
+        trace!("Checked store and obtained {} extras.", extra.len());
 
-        self.print_info(
-            store_count,
-            store_size,
-            repo_head,
-            repo_stage,
-            stage_added,
-            stage_modified,
-            stage_removed,
-            local_added,
-            local_modified,
-            local_removed,
-            get_color(options),
-        );
+        if dry_run {
+            let mut names = extra.iter().collect::<Vec<_>>();
 
-        Ok(())
+            names.sort_unstable();
+
+            for name in names {
+                println!("{}", HashDisplay(name));
+            }
+
+            return Ok((extra.len(), 0));
+        }
+
+        self.remove_objects(&current, extra)
     }
+}
 
+impl<S: ObjectStore> Repository<S> {
+    /// Like `Repository::create`, but keeps the objects in the store `make_store` returns for the new `.evs` directory.
     #[inline]
-    #[allow(clippy::too_many_arguments, reason = "This is fine.")]
-    pub fn print_info(
-        &self,
-        store_count: usize,
-        store_size: usize,
-        repo_head: Hash,
-        repo_stage: Hash,
-        stage_added: Vec<&PathBuf>,
-        stage_modified: Vec<&PathBuf>,
-        stage_removed: Vec<&PathBuf>,
-        local_added: Vec<&PathBuf>,
-        local_modified: Vec<&PathBuf>,
-        local_removed: Vec<&PathBuf>,
-        print_color: bool,
-    ) {
-        let add_color = if print_color { ADD_COLOR } else { "" };
-        let sub_color = if print_color { SUB_COLOR } else { "" };
-        let mod_color = if print_color { MOD_COLOR } else { "" };
-        let none_color = if print_color { NONE_COLOR } else { "" };
-
-        println!("  Head is at \"{}\"", HashDisplay(&repo_head));
-        println!("  and stage is \"{}\"", HashDisplay(&repo_stage));
-        println!(
-            "  Store has {} objects with size {}",
-            store_count,
-            SizeDisplay(store_size, print_color)
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn create_with<T: AsRef<Path>, F: FnOnce(&Path) -> Result<S, EvsError>>(
+        parent: &Span,
+        path: T,
+        parents: bool,
+        shared: bool,
+        options: &Cli,
+        make_store: F,
+    ) -> Result<Self, EvsError> {
+        debug!(
+            "Repository::create_with({:?}, {}, {})",
+            path.as_ref(),
+            parents,
+            shared
         );
-        if !stage_added.is_empty() || !stage_modified.is_empty() || !stage_removed.is_empty() {
-            println!();
-            println!("  Staged changes:");
-            print!("{}", add_color);
-            for addition in stage_added {
-                println!("    added {:?}", addition);
-            }
-            print!("{}", mod_color);
-            for modification in stage_modified {
-                println!("    modified {:?}", modification);
-            }
-            print!("{}", sub_color);
-            for deletion in stage_removed {
-                println!("    removed {:?}", deletion);
+
+        Self::create_with_(path.as_ref(), parents, shared, options, make_store)
+    }
+
+    fn create_with_<F: FnOnce(&Path) -> Result<S, EvsError>>(
+        path: &Path,
+        parents: bool,
+        shared: bool,
+        options: &Cli,
+        make_store: F,
+    ) -> Result<Self, EvsError> {
+        let current = Span::current();
+
+        if !path.exists() {
+            if !parents {
+                return Err(EvsError::MissingWorkspace(path.to_path_buf()));
             }
+
+            DirBuilder::new()
+                .recursive(true)
+                .create(path)
+                .map_err(|e| (e, path.to_path_buf()))?;
+
+            trace!("Created workspace directory.");
         }
-        if !local_added.is_empty() || !local_modified.is_empty() || !local_removed.is_empty() {
-            println!("{}", none_color);
-            println!("  Unstaged changes:");
-            print!("{}", add_color);
-            for addition in local_added {
-                println!("    added {:?}", addition);
+
+        let _ = path.read_dir().map_err(|e| (e, path.to_path_buf()))?;
+
+        trace!("Workspace exists and is a directory.");
+
+        let repo = path.join(".evs");
+
+        DirBuilder::new()
+            .create(&repo)
+            .map_err(|e| (e, repo.clone()))?;
+
+        trace!("Created repository directory.");
+
+        let repo = repo.canonicalize().map_err(|e| (e, repo))?;
+
+        trace!("Repository directory was canonicalized.");
+
+        if shared {
+            set_shared_mode(&repo)?;
+
+            trace!("Made repository directory group-writable.");
+        }
+
+        let store = make_store(&repo)?;
+
+        let root = store.insert(&current, Object::Null)?;
+
+        debug_assert_eq!(root, store.null_hash(), "The null hash is outdated");
+
+        trace!("Inserted null object.");
+
+        let empty_stage = store.insert(&current, Object::Tree(vec![]))?;
+
+        debug_assert_eq!(
+            empty_stage,
+            store.empty_tree_hash(),
+            "The empty tree hash is outdated"
+        );
+
+        trace!("Inserted empty tree.");
+
+        let lockfile_path = repo.join("lock");
+
+        let mut lockfile = OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(&lockfile_path)
+            .map_err(|e| (e, lockfile_path.clone()))?;
+
+        lockfile.try_lock().map_err(|e| (e, repo.clone()))?;
+
+        trace!("Created and locked lockfile.");
+
+        if shared {
+            set_shared_mode(&lockfile_path)?;
+        }
+
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|d| i64::try_from(d.as_secs()).ok())
+            .unwrap_or(0);
+
+        let repo_info = RepositoryInfo {
+            head: Head::Symbolic(DEFAULT_BRANCH.to_owned()),
+            stage: empty_stage,
+            modified: false,
+            created,
+            shared,
+            head_hash: root,
+            hash_algo: store.algo(),
+        };
+
+        lockfile
+            .write_all(&rmp_serde::to_vec(&repo_info)?)
+            .map_err(|e| (e, lockfile_path.clone()))?;
+
+        trace!("Wrote repository info into the lockfile.");
+
+        let repository = Repository {
+            workspace: path.to_path_buf(),
+            repository: repo,
+            lockfile,
+            store,
+            info: repo_info,
+            index: None,
+            max_depth: options.max_tree_depth,
+        };
+
+        repository.write_branch(DEFAULT_BRANCH, root)?;
+
+        trace!("Created repository.");
+
+        Ok(repository)
+    }
+
+    /// Whether `ancestor` is reachable from `target`, following the parents of the `bundled` commits
+    /// before those already in the store.
+    fn bundle_descends(
+        &self,
+        bundled: &AHashMap<Hash, Option<Vec<Hash>>>,
+        target: Hash,
+        ancestor: Hash,
+    ) -> Result<bool, EvsError> {
+        let mut seen = AHashSet::new();
+
+        let mut pending = vec![target];
+
+        while let Some(next) = pending.pop() {
+            if next == ancestor {
+                return Ok(true);
             }
-            print!("{}", mod_color);
-            for modification in local_modified {
-                println!("    modified {:?}", modification);
+
+            if next == self.store.null_hash() || !seen.insert(next) {
+                continue;
             }
-            print!("{}", sub_color);
-            for deletion in local_removed {
-                println!("    removed {:?}", deletion);
+
+            match bundled.get(&next) {
+                Some(Some(parents)) => pending.extend(parents),
+                Some(None) => return Err(EvsError::NotACommit(next)),
+                None => pending.extend(self.commit_parents(next)?),
             }
         }
-        print!("{}", none_color);
 
-        let _ = stdout().flush();
+        Ok(false)
     }
 
+    /// Like `check`, but only validates the objects reachable from `ref` and returns their count.
     #[inline]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn show<T: AsRef<str>>(
+    pub fn verify<T: AsRef<str>>(
         &self,
         parent: &Span,
         r#ref: T,
-        options: &Cli,
-    ) -> Result<(), EvsError> {
-        debug!("Repository::show(self, \"{}\")", r#ref.as_ref());
+    ) -> Result<(Hash, usize), EvsError> {
+        debug!("Repository::verify(self, \"{}\")", r#ref.as_ref());
+
+        let current = Span::current();
+
+        let resolved = self.resolve(&current, r#ref)?;
+
+        trace!("Resolved to \"{}\".", resolved);
+
+        let (hash, _) = self.store.lookup_uncached(&current, resolved.as_str())?;
+
+        let found = self
+            .store
+            .check(&current, AHashSet::new(), &[hash], false)?
+            .found;
+
+        Ok((hash, found.len()))
+    }
+
+    /// Unreadable paths are collected in `skipped` instead of failing if it is given.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn add<T: AsRef<Path>>(
+        &mut self,
+        parent: &Span,
+        path: T,
+        overrides: &AHashSet<PathBuf>,
+        skipped: Option<&Mutex<Vec<PathBuf>>>,
+        subtrees: &Subtrees,
+        options: &Cli,
+    ) -> Result<(), EvsError> {
+        debug!(
+            "Repository::add(self, {:?}, {:?}, {})",
+            path.as_ref(),
+            overrides,
+            skipped.is_some()
+        );
+
+        let Some(matches) = expand_glob(&Span::current(), path.as_ref())? else {
+            return self.add_(path.as_ref(), overrides, skipped, subtrees, options);
+        };
+
+        for path in matches {
+            self.add_(&path, overrides, skipped, subtrees, options)?;
+        }
+
+        Ok(())
+    }
+
+    fn add_(
+        &mut self,
+        path: &Path,
+        overrides: &AHashSet<PathBuf>,
+        skipped: Option<&Mutex<Vec<PathBuf>>>,
+        subtrees: &Subtrees,
+        options: &Cli,
+    ) -> Result<(), EvsError> {
+        let current = Span::current();
+
+        let metadata = fs::symlink_metadata(path).map_err(|e| (e, path.to_path_buf()))?;
+
+        // Symlinks are added themselves, so only their parent is canonicalized.
+        let canon = match (metadata.is_symlink(), path.parent(), path.file_name()) {
+            (true, Some(parent), Some(name)) => partial_canonicalize(&current, parent)
+                .map_err(|e| (e, path.to_path_buf()))?
+                .join(name),
+            _ => path.canonicalize().map_err(|e| (e, path.to_path_buf()))?,
+        };
+
+        trace!("Canonicalized path to {:?}", canon);
+
+        if !canon.starts_with(&self.workspace) {
+            return Err(EvsError::PathOutsideOfRepo(canon));
+        }
+
+        let relative = canon.strip_prefix(&self.workspace).unwrap();
+
+        if self.index.is_none() {
+            self.index = Some(Index::load(&current, self.repository.join("index"))?);
+        }
+
+        let ignores = self.get_ignores(&current, options)?;
+
+        trace!("Using ignores: {:?}.", ignores);
+
+        let is_ignored = ignores.matches(relative);
+
+        if is_ignored
+            && !overrides.contains(relative)
+            && (relative.starts_with(".evs")
+                || !confirmation!(false, "{:?} is ignored, add anyway?", relative)?)
+        {
+            trace!("Filtered path {:?}.", relative);
+
+            return Ok(());
+        }
+
+        let mode = file_mode(&metadata);
+
+        let hash = if metadata.is_symlink() {
+            self.hash_symlink(&current, &canon)?
+        } else if metadata.is_dir() {
+            let ignores = if is_ignored {
+                &IgnoreMatcher::default()
+            } else {
+                &ignores
+            };
+
+            let progress = Progress::new("hashing", None);
+
+            let (hash, _) = self.hash_dir(
+                &current,
+                &canon,
+                ignores,
+                overrides,
+                skipped,
+                &progress,
+                &Mutex::new(AHashSet::new()),
+                subtrees,
+                filter_key(ignores, overrides),
+            )?;
+
+            progress.finish();
+
+            if relative == "" {
+                trace!("Hashed contents of path.");
+
+                trace!("Recomputed stage.");
+
+                if self.info.stage() == hash {
+                    trace!("New stage is equal to old stage.");
+                } else {
+                    self.info.set_stage(hash);
+                }
+
+                return Ok(());
+            }
+
+            hash
+        } else {
+            self.hash_file(&current, &canon)?
+        };
+
+        trace!("Hashed contents of path.");
+
+        let new_stage = match self.update_stage(
+            &current,
+            relative.components().peekable(),
+            relative,
+            Some((hash, mode)),
+            self.info.stage(),
+        )? {
+            Some(stage) => stage,
+            None => self.empty_tree()?,
+        };
+
+        trace!("Recomputed stage.");
+
+        if self.info.stage() == new_stage {
+            trace!("New stage is equal to old stage.");
+        } else {
+            self.info.set_stage(new_stage);
+        }
+
+        Ok(())
+    }
+
+    /// With `delete`, the previously staged files are deleted from the workspace as well unless `cached`.
+    /// Deleting files is confirmed once for all `paths` before the stage changes.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn sub<T: AsRef<Path>>(
+        &mut self,
+        parent: &Span,
+        paths: &[T],
+        delete: bool,
+        cached: bool,
+        yes: bool,
+    ) -> Result<(), EvsError> {
+        debug!(
+            "Repository::sub(self, <{} path(s)>, {}, {}, {})",
+            paths.len(),
+            delete,
+            cached,
+            yes
+        );
+
+        let current = Span::current();
+
+        let mut stage = self.info.stage();
+
+        let mut staged = Vec::new();
+
+        for path in paths {
+            let Some(matches) = expand_glob(&current, path.as_ref())? else {
+                stage = self.sub_(path.as_ref(), stage, delete, cached, &mut staged)?;
+
+                continue;
+            };
+
+            for path in matches {
+                stage = self.sub_(&path, stage, delete, cached, &mut staged)?;
+            }
+        }
+
+        staged.sort_unstable();
+        staged.dedup();
+
+        if !staged.is_empty()
+            && !yes
+            && !confirmation!(
+                false,
+                "This will delete {} file(s) from the workspace, continue?",
+                staged.len()
+            )?
+        {
+            return Err(EvsError::UncommittedChanges);
+        }
+
+        trace!("Recomputed stage.");
+
+        if self.info.stage() == stage {
+            trace!("New stage is equal to old stage.");
+        } else {
+            self.info.set_stage(stage);
+        }
+
+        for file in &staged {
+            self.remove_and_prune(file)?;
+        }
+
+        if !staged.is_empty() {
+            trace!("Deleted files from the workspace.");
+        }
+
+        Ok(())
+    }
+
+    /// Returns `stage` without `path` and collects the staged files below it that would be deleted.
+    fn sub_(
+        &mut self,
+        path: &Path,
+        stage: Hash,
+        delete: bool,
+        cached: bool,
+        staged: &mut Vec<PathBuf>,
+    ) -> Result<Hash, EvsError> {
+        let current = Span::current();
+
+        let canon = partial_canonicalize(&current, path).map_err(|e| (e, path.to_path_buf()))?;
+
+        trace!("Canonicalized path to {:?}", canon);
+
+        if !canon.starts_with(&self.workspace) {
+            return Err(EvsError::PathOutsideOfRepo(canon));
+        }
+
+        if canon.starts_with(&self.repository) {
+            return Err(EvsError::PathOutsideOfRepo(canon));
+        }
+
+        let relative = canon
+            .strip_prefix(self.repository.parent().unwrap())
+            .unwrap();
+
+        if delete && !cached {
+            let mut entries = AHashMap::new();
+
+            self.tree_entries(&current, self.info.stage(), Path::new(""), &mut entries)?;
+
+            let before = staged.len();
+
+            staged.extend(entries.into_keys().filter(|file| {
+                file.starts_with(relative)
+                    && !file.starts_with(".evs")
+                    && fs::symlink_metadata(self.workspace.join(file)).is_ok()
+            }));
+
+            trace!(
+                "Found {} staged file(s) to delete.",
+                staged.len().saturating_sub(before)
+            );
+        }
+
+        if relative == "" {
+            return if delete {
+                self.empty_tree()
+            } else {
+                self.get_tree(&current, self.info.head())
+            };
+        }
+
+        let head = (!delete)
+            .then(|| {
+                self.tree_lookup(
+                    &current,
+                    self.get_tree(&current, self.info.head())?,
+                    path.components(),
+                )
+            })
+            .transpose()?
+            .flatten();
+
+        match self.update_stage(
+            &current,
+            relative.components().peekable(),
+            relative,
+            head,
+            stage,
+        )? {
+            Some(stage) => Ok(stage),
+            None => self.empty_tree(),
+        }
+    }
+
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn update_stage(
+        &mut self,
+        parent: &Span,
+        mut components: Peekable<Components>,
+        path: impl AsRef<Path>,
+        obj: Option<(Hash, u32)>,
+        tree: Hash,
+    ) -> Result<Option<Hash>, EvsError> {
+        let current = Span::current();
+
+        let next = components.next().unwrap();
+
+        debug!(
+            "Repository::update_stage(self, {:?}, {:?}..., {}, {})",
+            path.as_ref(),
+            next,
+            obj.map_or("deleting", |_| "inserting"),
+            HashDisplay(&tree)
+        );
+
+        let path = path.as_ref();
+
+        check_depth(path, self.max_depth)?;
+
+        let next_bytes = AsRef::<Path>::as_ref(&next).as_os_str().as_encoded_bytes();
+
+        let mut items = if tree == self.store.empty_tree_hash() {
+            vec![]
+        } else {
+            match self
+                .store
+                .lookup(&current, &format!("{}", HashDisplay(&tree)))
+            {
+                Ok((_, Object::Tree(items))) => items,
+                Ok((hash, _)) => {
+                    trace!("Replacing object \"{}\" with new tree.", HashDisplay(&hash));
+
+                    vec![]
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        trace!("Obtained {} tree item(s).", items.len());
+
+        check_duplicates(&mut items)?;
+
+        let hash = if components.peek().is_none() {
+            obj
+        } else {
+            let next = if let Some(next) = items.iter().find(|e| e.name.as_bytes() == next_bytes) {
+                next.content
+            } else {
+                if obj.is_none() {
+                    return Err(EvsError::PathNotInStage(path.to_path_buf()));
+                }
+                self.store.empty_tree_hash()
+            };
+
+            self.update_stage(&current, components, path, obj, next)?
+                .map(|hash| (hash, MODE_DIR))
+        };
+
+        trace!("Obtained hash or lack thereof of later component(s).");
+
+        let hash = if let Some((obj, mode)) = hash {
+            #[allow(clippy::indexing_slicing, reason = "The index comes from enumerate.")]
+            if let Some(index) = items
+                .iter()
+                .enumerate()
+                .find_map(|(i, e)| (e.name.as_bytes() == next_bytes).then_some(i))
+            {
+                if items[index].content == obj && items[index].mode == mode {
+                    trace!("Object unchanged.");
+
+                    Some(tree)
+                } else {
+                    items[index].content = obj;
+                    items[index].mode = mode;
+
+                    trace!("Object changed, adding new tree to store...");
+
+                    Some(self.store.insert(&current, Object::Tree(items))?)
+                }
+            } else {
+                items.push(TreeEntry {
+                    name: String::from_utf8(next_bytes.to_owned())
+                        .map_err(|e| EvsError::PathError(e.utf8_error(), e.into_bytes()))?,
+                    content: obj,
+                    mode,
+                });
+
+                trace!("Tree changed, adding tree to store...");
+
+                Some(self.store.insert(&current, Object::Tree(items))?)
+            }
+        } else {
+            if let Some(index) = items
+                .iter()
+                .enumerate()
+                .find_map(|(i, e)| (e.name.as_bytes() == next_bytes).then_some(i))
+            {
+                items.remove(index);
+
+                trace!("Deleted object.");
+
+                if items.is_empty() {
+                    trace!("Empty tree pruned.");
+
+                    None
+                } else {
+                    trace!("Tree changed, adding tree to store...");
+
+                    Some(self.store.insert(&current, Object::Tree(items))?)
+                }
+            } else {
+                return Err(EvsError::PathNotInStage(path.to_path_buf()));
+            }
+        };
+
+        trace!("Obtained hash or lack thereof of this component.");
+
+        Ok(hash)
+    }
+
+    /// Also returns the paths below `path`, unless some of them were filtered out.
+    /// Symlinks are hashed themselves instead of followed, directories already in `visited` are rejected as loops
+    /// unless they were hashed completely with the same `filters`, see `filter_key`.
+    #[allow(clippy::too_many_lines, reason = "This is fine.")]
+    #[allow(clippy::too_many_arguments, reason = "Reasonable here.")]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn hash_dir(
+        &self,
+        parent: &Span,
+        path: &PathBuf,
+        ignores: &IgnoreMatcher,
+        overrides: &AHashSet<PathBuf>,
+        skipped: Option<&Mutex<Vec<PathBuf>>>,
+        progress: &Progress,
+        visited: &Mutex<AHashSet<PathBuf>>,
+        subtrees: &Subtrees,
+        filters: u64,
+    ) -> Result<(Hash, Option<Vec<PathBuf>>), EvsError> {
+        debug!(
+            "Repository::hash_dir(self, {:?}, {} ignores, {:?}, {})",
+            path,
+            ignores.len(),
+            overrides,
+            skipped.is_some()
+        );
+
+        let current = Span::current();
+
+        let metadata = fs::symlink_metadata(path).map_err(|e| (e, path.to_owned()))?;
+
+        if metadata.is_symlink() {
+            let hash = self.hash_symlink(&current, path)?;
+
+            progress.step();
+
+            return Ok((hash, Some(Vec::new())));
+        }
+
+        if !metadata.is_dir() {
+            let hash = self.hash_file(&current, path)?;
+
+            progress.step();
+
+            return Ok((hash, Some(Vec::new())));
+        }
+
+        let relative = path.strip_prefix(&self.workspace).unwrap();
+
+        check_depth(relative, self.max_depth)?;
+
+        let key = (
+            path.canonicalize().map_err(|e| (e, path.to_owned()))?,
+            filters,
+        );
+
+        if let Some(HashedDir { hash, descendants }) = subtrees.lock().unwrap().get(&key) {
+            trace!(
+                "Reusing \"{}\" hashed earlier for {:?}.",
+                HashDisplay(hash),
+                key.0
+            );
+
+            return Ok((*hash, descendants.clone()));
+        }
+
+        if !visited.lock().unwrap().insert(key.0.clone()) {
+            return Err(EvsError::DirectoryLoop(path.to_owned()));
+        }
+
+        let complete = AtomicBool::new(true);
+
+        let children = path
+            .read_dir()
+            .map_err(|e| (e, path.to_owned()))?
+            .par_bridge()
+            .filter_map(|child| {
+                let _entered = current.enter();
+
+                let name = match child {
+                    Ok(child) => child.file_name(),
+                    Err(e) => return Some(Err((e, path.clone()).into())),
+                };
+
+                let name_bytes = name.as_encoded_bytes().to_owned();
+
+                let next = path.join(&name);
+
+                let relative = next.strip_prefix(&self.workspace).unwrap();
+
+                if ignores.matches(relative) && !overrides.iter().any(|o| o.starts_with(relative)) {
+                    trace!("Filtered child {:?}.", name);
+
+                    complete.store(false, Ordering::Relaxed);
+
+                    return None;
+                }
+
+                let mode = match fs::symlink_metadata(&next) {
+                    Ok(metadata) => file_mode(&metadata),
+                    Err(e) => return Some(Err((e, next).into())),
+                };
+
+                let (hash, descendants) = match self.hash_dir(
+                    &current, &next, ignores, overrides, skipped, progress, visited, subtrees,
+                    filters,
+                ) {
+                    Ok(res) => res,
+                    Err(EvsError::IOError(e, pb))
+                        if e.kind() == ErrorKind::PermissionDenied
+                            && let Some(skipped) = skipped =>
+                    {
+                        warn!("Skipping unreadable {:?}: {}", pb, e);
+
+                        skipped.lock().unwrap().push(pb);
+
+                        complete.store(false, Ordering::Relaxed);
+
+                        return None;
+                    }
+                    Err(e) => return Some(Err(e)),
+                };
+
+                trace!("Hashed child {:?}.", name);
+
+                let name = match String::from_utf8(name_bytes) {
+                    Ok(name) => name,
+                    Err(e) => {
+                        return Some(Err(EvsError::PathError(e.utf8_error(), e.into_bytes())));
+                    }
+                };
+
+                Some(Ok((
+                    TreeEntry {
+                        name,
+                        content: hash,
+                        mode,
+                    },
+                    descendants,
+                )))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut descendants = complete.load(Ordering::Relaxed).then(Vec::new);
+
+        let mut items = Vec::with_capacity(children.len());
+
+        for (entry, below) in children {
+            if let Some(list) = &mut descendants {
+                if let Some(below) = below {
+                    let name = Path::new(&entry.name);
+
+                    list.push(name.to_path_buf());
+                    list.extend(below.iter().map(|b| name.join(b)));
+                } else {
+                    descendants = None;
+                }
+            }
+
+            items.push(entry);
+        }
+
+        check_duplicates(&mut items)?;
+
+        trace!("Inserting resulting tree...");
+
+        let hash = self.store.insert(&current, Object::Tree(items))?;
+
+        subtrees.lock().unwrap().insert(
+            key,
+            HashedDir {
+                hash,
+                descendants: descendants.clone(),
+            },
+        );
+
+        Ok((hash, descendants))
+    }
+
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn hash_file(
+        &self,
+        parent: &Span,
+        path: &Path,
+    ) -> Result<Hash, EvsError> {
+        debug!("Repository::hash_file(self, {:?})", path);
+
+        let current = Span::current();
+
+        let relative = path.strip_prefix(&self.workspace).unwrap();
+
+        let metadata = fs::metadata(path).map_err(|e| (e, path.to_owned()))?;
+
+        if let Some(index) = &self.index
+            && let Some(hash) = index.get(relative, &metadata)
+            && self.store.contains(&hash)
+        {
+            trace!("Reusing cached hash \"{}\".", HashDisplay(&hash));
+
+            return Ok(hash);
+        }
+
+        let hash = self.store.insert_file(&current, path, metadata.len())?;
+
+        if let Some(index) = &self.index {
+            index.update(relative, &metadata, hash);
+        }
+
+        Ok(hash)
+    }
+
+    /// Stores the target of a symlink instead of following it.
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn hash_symlink(
+        &self,
+        parent: &Span,
+        path: &Path,
+    ) -> Result<Hash, EvsError> {
+        debug!("Repository::hash_symlink(self, {:?})", path);
+
+        let current = Span::current();
+
+        let target = fs::read_link(path).map_err(|e| (e, path.to_owned()))?;
+
+        trace!("Read symlink to {:?}, inserting...", target);
+
+        self.store.insert(
+            &current,
+            Object::Symlink(target.into_os_string().into_encoded_bytes()),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments, reason = "Reasonable here.")]
+    #[allow(clippy::too_many_lines, reason = "This is fine.")]
+    #[allow(
+        clippy::fn_params_excessive_bools,
+        reason = "These are independent flags."
+    )]
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn commit(
+        &mut self,
+        parent: &Span,
+        parents: Option<Vec<Hash>>,
+        message: String,
+        name: String,
+        email: String,
+        time: OffsetDateTime,
+        author: Option<Author>,
+        unlimited: bool,
+        verify: bool,
+        allow_empty: bool,
+        sign: bool,
+        _options: &Cli,
+    ) -> Result<Hash, EvsError> {
+        debug!(
+            "Repository::commit(self, \"{}\", {}, {}, {:?}, {:?}, {}, {}, {}, {})",
+            message.as_bytes().escape_ascii(),
+            name,
+            email,
+            time,
+            author,
+            unlimited,
+            verify,
+            allow_empty,
+            sign
+        );
+
+        let author = author.unwrap_or_else(|| Author {
+            name: name.clone(),
+            email: email.clone(),
+            date: time,
+        });
+
+        if !unlimited {
+            for (field, len, limit) in [
+                ("committer name", name.len(), MAX_NAME_LENGTH),
+                ("committer email", email.len(), MAX_EMAIL_LENGTH),
+                ("author name", author.name.len(), MAX_NAME_LENGTH),
+                ("author email", author.email.len(), MAX_EMAIL_LENGTH),
+                ("commit message", message.len(), MAX_MESSAGE_LENGTH),
+            ] {
+                if len > limit {
+                    return Err(EvsError::FieldTooLong(field, len, limit));
+                }
+            }
+
+            trace!("Commit info is within limits.");
+        }
+
+        for email in [&email, &author.email] {
+            if !email.is_empty() && !email.contains('@') {
+                return Err(EvsError::InvalidEmail(email.clone()));
+            }
+        }
+
+        let current = Span::current();
+
+        let defaulted = parents.is_none();
+
+        let mut parents = parents.unwrap_or_else(|| vec![self.info.head()]);
+
+        let mut tree = self.info.stage();
+
+        // Merges record the joining of histories even if the tree is unchanged.
+        if !allow_empty
+            && let [first] = parents.as_slice()
+            && self.get_tree(&current, *first)? == tree
+        {
+            return Err(EvsError::NothingToCommit);
+        }
+
+        let env = [
+            (
+                "EVS_PARENT",
+                parents
+                    .first()
+                    .map(|p| HashDisplay(p).to_string())
+                    .unwrap_or_default(),
+            ),
+            ("EVS_TREE", HashDisplay(&tree).to_string()),
+            ("EVS_MESSAGE", message.clone()),
+        ];
+
+        if verify && let Some(status) = self.run_hook(&current, "pre-commit", &env)? {
+            if !status.success() {
+                return Err(EvsError::HookFailed("pre-commit", status));
+            }
+
+            trace!("Taking the stage again after the pre-commit hook.");
+
+            tree = self.info.stage();
+
+            if defaulted {
+                parents = vec![self.info.head()];
+            }
+        }
+
+        let mut commit = Commit {
+            parents,
+            name,
+            email,
+            tree,
+            msg: message,
+            date: time.into(),
+            offset: time.offset().whole_seconds(),
+            author_name: author.name,
+            author_email: author.email,
+            author_date: author.date.into(),
+            author_offset: author.date.offset().whole_seconds(),
+            signer_pubkey: None,
+            signature: None,
+        };
+
+        if sign {
+            let key = self.signing_key()?;
+
+            commit.signer_pubkey = Some(key.verifying_key().to_bytes().to_vec());
+
+            commit.signature = Some(key.sign(&commit.signable()?).to_bytes().to_vec());
+
+            trace!("Signed commit.");
+        }
+
+        let commit = self.store.insert(&current, Object::Commit(commit))?;
+
+        trace!("Created and inserted commit object.");
+
+        self.set_head(commit)?;
+
+        trace!("Moved head to \"{}\".", HashDisplay(&commit));
+
+        if verify {
+            match self.run_hook(&current, "post-commit", &env) {
+                Ok(Some(status)) if !status.success() => {
+                    warn!("The post-commit hook failed with {}.", status);
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Could not run the post-commit hook: {}.", e),
+            }
+        }
+
+        Ok(commit)
+    }
+
+    /// Reads the key from `.evs/signing_key`.
+    fn signing_key(&self) -> Result<SigningKey, EvsError> {
+        let path = self.repository.join(SIGNING_KEY_FILE);
+
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(EvsError::MissingSigningKey(path));
+            }
+            Err(e) => return Err((e, path).into()),
+        };
+
+        let secret = content
+            .try_into()
+            .map_err(|_e| EvsError::InvalidSigningKey(path))?;
+
+        Ok(SigningKey::from_bytes(&secret))
+    }
+
+    /// Checks the signature of the commit `ref` resolves to against the key it records.
+    /// Returns the commit and the key, which is `None` if the commit is unsigned.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn verify_commit<T: AsRef<str>>(
+        &self,
+        parent: &Span,
+        r#ref: T,
+    ) -> Result<(Hash, Option<Vec<u8>>), EvsError> {
+        debug!("Repository::verify_commit(self, \"{}\")", r#ref.as_ref());
+
+        let (hash, obj) = self.lookup_peeled(&Span::current(), r#ref)?;
+
+        let Object::Commit(commit) = obj else {
+            return Err(EvsError::NotACommit(hash));
+        };
+
+        let (key, signature) = match (&commit.signer_pubkey, &commit.signature) {
+            (None, None) => {
+                trace!("Commit is unsigned.");
+
+                return Ok((hash, None));
+            }
+            (Some(key), Some(signature)) => (key, signature),
+            _ => return Err(EvsError::InvalidSignature(hash)),
+        };
+
+        let key = <&[u8; 32]>::try_from(key.as_slice())
+            .ok()
+            .and_then(|key| VerifyingKey::from_bytes(key).ok())
+            .ok_or(EvsError::InvalidSignature(hash))?;
+
+        let signature =
+            Signature::from_slice(signature).map_err(|_e| EvsError::InvalidSignature(hash))?;
+
+        key.verify_strict(&commit.signable()?, &signature)
+            .map_err(|_e| EvsError::InvalidSignature(hash))?;
+
+        trace!("Signature is valid.");
+
+        Ok((hash, commit.signer_pubkey))
+    }
+
+    /// Runs the hook script of the given name if it is an executable file, with the workspace as the working directory.
+    /// The repository is written back and unlocked while the hook runs, so the hook can run `evs` itself.
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn run_hook(
+        &mut self,
+        parent: &Span,
+        name: &'static str,
+        env: &[(&str, String)],
+    ) -> Result<Option<ExitStatus>, EvsError> {
+        debug!("Repository::run_hook(self, {})", name);
+
+        let current = Span::current();
+
+        let path = self.repository.join("hooks").join(name);
+
+        let Ok(metadata) = fs::metadata(&path) else {
+            trace!("No {} hook installed.", name);
+
+            return Ok(None);
+        };
+
+        if !metadata.is_file() || (cfg!(unix) && file_mode(&metadata) != MODE_EXECUTABLE) {
+            warn!(
+                "Skipping the {} hook {:?}, it is not an executable file.",
+                name, path
+            );
+
+            return Ok(None);
+        }
+
+        let lockfile_path = self.repository.join("lock");
+
+        self.write_info().map_err(|e| (e, lockfile_path.clone()))?;
+
+        if let Some(index) = self.index.take() {
+            index.save(&current)?;
+        }
+
+        self.lockfile
+            .unlock()
+            .map_err(|e| (e, lockfile_path.clone()))?;
+
+        trace!("Running {:?}...", path);
+
+        let status = Command::new(&path)
+            .current_dir(&self.workspace)
+            .envs(env.iter().map(|(k, v)| (k, v)))
+            .status()
+            .map_err(|e| (e, path));
+
+        self.lockfile
+            .try_lock()
+            .map_err(|e| (e, self.repository.clone()))?;
+
+        trace!("Relocked the repository.");
+
+        self.reload_info()?;
+
+        let status = status?;
+
+        trace!("Hook {} exited with {}.", name, status);
+
+        Ok(Some(status))
+    }
+
+    /// Writes the repository info back to the lockfile if it was modified.
+    fn write_info(&mut self) -> io::Result<()> {
+        if self.info.modified {
+            self.lockfile.set_len(0)?;
+            self.lockfile.seek(SeekFrom::Start(0))?;
+            self.lockfile.write_all(
+                &rmp_serde::to_vec(&self.info)
+                    .map_err(|_e| io::Error::new(ErrorKind::InvalidData, "encoder failed"))?,
+            )?;
+
+            self.info.modified = false;
+        }
+
+        Ok(())
+    }
+
+    /// Rereads everything another process might have changed while the repository was unlocked.
+    fn reload_info(&mut self) -> Result<(), EvsError> {
+        let current = Span::current();
+
+        let lockfile_path = self.repository.join("lock");
+
+        let mut repo_info = vec![];
+
+        self.lockfile
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| self.lockfile.read_to_end(&mut repo_info))
+            .map_err(|e| (e, lockfile_path))?;
+
+        self.info = rmp_serde::from_slice(&repo_info).map_err(EvsError::RepositoryInfoCorrupt)?;
+
+        self.info.head_hash = self.resolve_head()?;
+
+        self.store.load_packs(&current)?;
+
+        trace!("Reloaded repository info.");
+
+        Ok(())
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn lookup<T: AsRef<str>>(
+        &self,
+        parent: &Span,
+        r#ref: T,
+    ) -> Result<(Hash, Object), EvsError> {
+        debug!("Repository::lookup(self, \"{}\")", r#ref.as_ref());
+
+        let current = Span::current();
+
+        let resolved = self.resolve(&current, r#ref)?;
+
+        trace!("Resolved to \"{}\".", resolved);
+
+        self.store.lookup(&current, resolved.as_str())
+    }
+
+    /// Like `lookup`, but returns chunk lists as they are stored, see `chunk_reader`.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn lookup_raw<T: AsRef<str>>(
+        &self,
+        parent: &Span,
+        r#ref: T,
+    ) -> Result<(Hash, Object), EvsError> {
+        debug!("Repository::lookup_raw(self, \"{}\")", r#ref.as_ref());
+
+        let current = Span::current();
+
+        let resolved = self.resolve(&current, r#ref)?;
+
+        trace!("Resolved to \"{}\".", resolved);
+
+        self.store.lookup_raw(&current, resolved.as_str())
+    }
+
+    /// Streams the blob the chunk list `list` with `chunks` was split from, one chunk at a time.
+    #[inline]
+    #[must_use]
+    pub fn chunk_reader(
+        &self,
+        list: Hash,
+        chunks: Vec<Hash>,
+    ) -> ChunkReader<'_, S> {
+        ChunkReader {
+            store: &self.store,
+            list,
+            chunks: chunks.into_iter(),
+            current: io::Cursor::new(Vec::new()),
+        }
+    }
+
+    /// Like `lookup`, but only returns the kind and size, without reassembling chunk lists.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn object_info<T: AsRef<str>>(
+        &self,
+        parent: &Span,
+        r#ref: T,
+    ) -> Result<(Hash, ObjectInfo), EvsError> {
+        debug!("Repository::object_info(self, \"{}\")", r#ref.as_ref());
+
+        let current = Span::current();
+
+        let resolved = self.resolve(&current, r#ref)?;
+
+        trace!("Resolved to \"{}\".", resolved);
+
+        let (hash, obj) = self.store.lookup_raw(&current, resolved.as_str())?;
+
+        Ok((hash, obj.info()))
+    }
+
+    /// Like `lookup`, but follows annotated tags to the object they point at.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn lookup_peeled<T: AsRef<str>>(
+        &self,
+        parent: &Span,
+        r#ref: T,
+    ) -> Result<(Hash, Object), EvsError> {
+        debug!("Repository::lookup_peeled(self, \"{}\")", r#ref.as_ref());
+
+        let found = self.lookup(&Span::current(), r#ref)?;
+
+        self.peel(found)
+    }
+
+    /// Follows annotated tags until reaching an object that is not one.
+    fn peel(
+        &self,
+        mut found: (Hash, Object),
+    ) -> Result<(Hash, Object), EvsError> {
+        while let (hash, Object::Tag(tag)) = &found {
+            trace!(
+                "Following tag \"{}\" to \"{}\".",
+                HashDisplay(hash),
+                HashDisplay(&tag.target)
+            );
+
+            found = self
+                .store
+                .lookup(&Span::current(), &format!("{}", HashDisplay(&tag.target)))?;
+        }
+
+        Ok(found)
+    }
+
+    /// `depth` limits the number of generations followed along all parents, independently of `limit`.
+    /// `format` replaces the output of each commit, see `format_commit`.
+    /// With a `path`, only the commits that changed it are printed, but all of them are followed.
+    ///
+    /// With `porcelain`, each commit is printed as one record and nothing else is printed:
+    ///
+    /// ```text
+    /// record  = hash NUL parents NUL author NUL committer NUL subject LF
+    /// parents = hash *(SP hash)
+    /// author  = name NUL email NUL date
+    /// date    = RFC 3339 date in the UTC offset of the commit
+    /// ```
+    ///
+    /// Hashes are full lowercase hex, the first commit has the null object as its parent.
+    /// The subject is the first line of the message.
+    #[allow(clippy::too_many_arguments, reason = "Reasonable here.")]
+    #[allow(
+        clippy::fn_params_excessive_bools,
+        reason = "These are independent flags."
+    )]
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn log<T: AsRef<str>>(
+        &self,
+        parent: &Span,
+        r#ref: T,
+        limit: usize,
+        depth: Option<usize>,
+        oneline: bool,
+        format: Option<&str>,
+        porcelain: bool,
+        path: Option<&Path>,
+        options: &Cli,
+    ) -> Result<(), EvsError> {
+        debug!(
+            "Repository::log(self, \"{}\", {}, {:?}, {}, {:?}, {}, {:?})",
+            r#ref.as_ref(),
+            limit,
+            depth,
+            oneline,
+            format,
+            porcelain,
+            path
+        );
+
+        self.log_(
+            r#ref.as_ref(),
+            limit,
+            depth,
+            oneline,
+            format,
+            porcelain,
+            path,
+            options,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments, reason = "Reasonable here.")]
+    #[allow(
+        clippy::fn_params_excessive_bools,
+        reason = "These are independent flags."
+    )]
+    fn log_(
+        &self,
+        r#ref: &str,
+        limit: usize,
+        depth: Option<usize>,
+        oneline: bool,
+        format: Option<&str>,
+        porcelain: bool,
+        path: Option<&Path>,
+        options: &Cli,
+    ) -> Result<(), EvsError> {
+        let current = Span::current();
+
+        let route = path
+            .map(|path| self.workspace_route(&current, path))
+            .transpose()?;
+
+        trace!("Filtering by {:?}.", route);
+
+        let print_color = get_color(options);
+
+        let mod_color = if print_color { MOD_COLOR } else { "" };
+        let info_color = if print_color { INFO_COLOR } else { "" };
+        let none_color = if print_color { NONE_COLOR } else { "" };
+
+        let truncated = self.walk_log(
+            &current,
+            r#ref,
+            limit,
+            depth,
+            route.as_deref(),
+            |hash, commit, inner| {
+                if porcelain {
+                    println!("{}", porcelain_commit(hash, inner));
+                } else if let Some(format) = format {
+                    println!("{}", format_commit(format, hash, inner));
+                } else if oneline {
+                    println!(
+                        "{}{:.7}{}: {}{}{}",
+                        info_color,
+                        HashDisplay(&hash[..4]).to_string(),
+                        none_color,
+                        mod_color,
+                        inner.msg.lines().next().unwrap_or(""),
+                        none_color
+                    );
+                } else {
+                    println!(
+                        "{}{}{}:\n{}{}{}",
+                        info_color,
+                        HashDisplay(hash),
+                        none_color,
+                        mod_color,
+                        commit,
+                        none_color
+                    );
+                }
+            },
+        )?;
+
+        if truncated && format.is_none() && !porcelain {
+            println!("{}...{}", info_color, none_color);
+        }
+
+        Ok(())
+    }
+
+    /// Visits the commits `log` shows, breadth first from `ref` along all parents,
+    /// and returns whether `limit` or `depth` cut the walk short.
+    fn walk_log<F: FnMut(&Hash, &Object, &Commit)>(
+        &self,
+        current: &Span,
+        r#ref: &str,
+        limit: usize,
+        depth: Option<usize>,
+        route: Option<&Path>,
+        mut visit: F,
+    ) -> Result<bool, EvsError> {
+        let start = self.resolve_commit(current, r#ref)?;
+
+        let mut queue = VecDeque::from([(format!("{}", HashDisplay(&start)), 0usize)]);
+        let mut seen = AHashSet::from([start]);
+        let mut printed = 0usize;
+
+        // The root commits have the null object as their parent, which never cuts the walk short,
+        // and commits reached again are only queued once so they do not either.
+        let null = self.store.null_hash();
+
+        while let Some((resolved, generation)) = queue.pop_front() {
+            if printed == limit || depth.is_some_and(|d| generation >= d) {
+                return Ok(true);
+            }
+
+            let (hash, commit) = self.peel(self.store.lookup(current, &resolved)?)?;
+
+            match &commit {
+                Object::Null => continue,
+                Object::Commit(inner)
+                    if route
+                        .map(|route| self.changes_path(current, inner, route))
+                        .transpose()?
+                        == Some(false) =>
+                {
+                    trace!(
+                        "Skipping \"{}\" which did not change the path.",
+                        HashDisplay(&hash)
+                    );
+
+                    for parent in inner
+                        .parents
+                        .iter()
+                        .filter(|&&p| p != null && seen.insert(p))
+                    {
+                        queue.push_back((
+                            format!("{}", HashDisplay(parent)),
+                            generation.saturating_add(1),
+                        ));
+                    }
+                }
+                Object::Commit(inner) => {
+                    visit(&hash, &commit, inner);
+
+                    printed = printed.saturating_add(1);
+
+                    for parent in inner
+                        .parents
+                        .iter()
+                        .filter(|&&p| p != null && seen.insert(p))
+                    {
+                        queue.push_back((
+                            format!("{}", HashDisplay(parent)),
+                            generation.saturating_add(1),
+                        ));
+                    }
+                }
+                _ => return Err(EvsError::NotACommit(hash)),
+            }
+
+            trace!("Continuing with {} queued commit(s).", queue.len());
+        }
+
+        Ok(false)
+    }
+
+    /// Prints each line of the file at `path` in `ref` with the commit that last changed it,
+    /// following only the first parents.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn blame<T: AsRef<str>>(
+        &self,
+        parent: &Span,
+        r#ref: T,
+        path: &Path,
+        options: &Cli,
+    ) -> Result<(), EvsError> {
+        debug!(
+            "Repository::blame(self, \"{}\", {:?})",
+            r#ref.as_ref(),
+            path
+        );
+
+        self.blame_(r#ref.as_ref(), path, options)
+    }
+
+    fn blame_(
+        &self,
+        r#ref: &str,
+        path: &Path,
+        options: &Cli,
+    ) -> Result<(), EvsError> {
+        let current = Span::current();
+
+        let route = self.workspace_route(&current, path)?;
+
+        let resolved = self.resolve(&current, r#ref)?;
+
+        trace!("Resolved to \"{}\".", resolved);
+
+        let (mut hash, mut obj) = self.peel(self.store.lookup(&current, &resolved)?)?;
+
+        // Newest first, the commit and the content of the file in it
+        let mut versions = Vec::new();
+
+        loop {
+            let commit = match obj {
+                Object::Null => break,
+                Object::Commit(commit) => commit,
+                _ => return Err(EvsError::NotACommit(hash)),
+            };
+
+            let Some((content, _)) =
+                self.tree_lookup_(commit.tree, MODE_DIR, route.components())?
+            else {
+                if versions.is_empty() {
+                    return Err(EvsError::PathNotInTree(route, commit.tree));
+                }
+
+                break;
+            };
+
+            let Some(first_parent) = commit.first_parent() else {
+                return Err(EvsError::CorruptStateDetected(
+                    CorruptState::CommitWithoutParent(hash),
+                ));
+            };
+
+            versions.push((hash, commit, content));
+
+            (hash, obj) = self.peel(
+                self.store
+                    .lookup(&current, &format!("{}", HashDisplay(&first_parent)))?,
+            )?;
+        }
+
+        trace!("Found {} version(s) of the file.", versions.len());
+
+        // The index into `versions` of the commit each line comes from
+        let mut owners = Vec::new();
+        let mut text = String::new();
+        let mut previous = None;
+
+        for (index, (_, _, content)) in versions.iter().enumerate().rev() {
+            if previous == Some(*content) {
+                continue;
+            }
+
+            previous = Some(*content);
+
+            let Object::Blob(data) = self
+                .store
+                .lookup(&current, &format!("{}", HashDisplay(content)))?
+                .1
+            else {
+                return Err(EvsError::NotATextFile(route));
+            };
+
+            let Ok(new_text) = String::from_utf8(data) else {
+                return Err(EvsError::NotATextFile(route));
+            };
+
+            let diff = TextDiff::from_lines(&text, &new_text);
+
+            owners = diff
+                .iter_all_changes()
+                .filter_map(|change| match change.tag() {
+                    ChangeTag::Equal => owners.get(change.old_index()?).copied(),
+                    ChangeTag::Insert => Some(index),
+                    ChangeTag::Delete => None,
+                })
+                .collect();
+
+            text = new_text;
+        }
+
+        let print_color = get_color(options);
+
+        let info_color = if print_color { INFO_COLOR } else { "" };
+        let none_color = if print_color { NONE_COLOR } else { "" };
+
+        let width = versions
+            .iter()
+            .map(|(_, commit, _)| commit.author_name.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        #[allow(clippy::indexing_slicing, reason = "Owners are indices into versions.")]
+        for (line, &owner) in text.lines().zip(&owners) {
+            let (hash, commit, _) = &versions[owner];
+
+            println!(
+                "{}{:.7}{} {:<width$} | {}",
+                info_color,
+                HashDisplay(&hash[..4]).to_string(),
+                none_color,
+                commit.author_name,
+                line,
+                width = width
+            );
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn count<F: AsRef<str>, T: AsRef<str>>(
+        &self,
+        parent: &Span,
+        from: F,
+        to: T,
+    ) -> Result<usize, EvsError> {
+        debug!(
+            "Repository::count(self, \"{}\", \"{}\")",
+            from.as_ref(),
+            to.as_ref()
+        );
+
+        self.count_(from.as_ref(), to.as_ref())
+    }
+
+    fn count_(
+        &self,
+        from: &str,
+        to: &str,
+    ) -> Result<usize, EvsError> {
+        let current = Span::current();
+
+        let from = self.resolve_commit(&current, from)?;
+        let to = self.resolve_commit(&current, to)?;
+
+        trace!(
+            "Counting from \"{}\" to \"{}\"...",
+            HashDisplay(&from),
+            HashDisplay(&to)
+        );
+
+        let mut ancestors = AHashSet::new();
+
+        ancestors.insert(self.store.null_hash());
+
+        let mut pending = vec![from];
+
+        while let Some(hash) = pending.pop() {
+            if ancestors.insert(hash) {
+                pending.extend(self.commit_parents(hash)?);
+            }
+        }
+
+        trace!("Collected {} ancestor(s) to exclude.", ancestors.len());
+
+        let mut pending = vec![to];
+        let mut visited = AHashSet::new();
+        let mut found = false;
+        let mut count = 0usize;
+
+        while let Some(hash) = pending.pop() {
+            if hash == from {
+                found = true;
+            }
+
+            if ancestors.contains(&hash) || !visited.insert(hash) {
+                continue;
+            }
+
+            count = count.saturating_add(1);
+
+            trace!("Counted \"{}\".", HashDisplay(&hash));
+
+            pending.extend(self.commit_parents(hash)?);
+        }
+
+        if !found {
+            return Err(EvsError::NotAnAncestor(from, to));
+        }
+
+        Ok(count)
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn resolve<T: AsRef<str>>(
+        &self,
+        parent: &Span,
+        r#ref: T,
+    ) -> Result<String, EvsError> {
+        debug!("Repository::resolve(self, \"{}\")", r#ref.as_ref());
+
+        self.resolve_(r#ref.as_ref())
+    }
+
+    fn resolve_(
+        &self,
+        r#ref: &str,
+    ) -> Result<String, EvsError> {
+        let current = Span::current();
+
+        let (first, steps) = r#ref.split_at(r#ref.find(['~', '^']).unwrap_or(r#ref.len()));
+
+        let back_count = parse_ancestry(steps)?;
+
+        let first = match first {
+            "HEAD" => format!("{}", HashDisplay(&self.info.head())),
+            first => match self.read_branch(first) {
+                Ok(Some(hash)) => {
+                    trace!("Found branch {:?}.", first);
+
+                    format!("{}", HashDisplay(&hash))
+                }
+                Ok(None) | Err(EvsError::InvalidBranchName(_)) => match self.read_tag(first) {
+                    Ok(Some(hash)) => {
+                        trace!("Found tag {:?}.", first);
+
+                        format!("{}", HashDisplay(&hash))
+                    }
+                    Ok(None) | Err(EvsError::InvalidTagName(_)) => first.to_owned(),
+                    Err(e) => return Err(e),
+                },
+                Err(e) => return Err(e),
+            },
+        };
+
+        trace!("Starting at \"{}\".", first);
+
+        let mut resolved = first;
+
+        for _ in 0..back_count {
+            let (hash, commit) = self.peel(self.store.lookup(&current, resolved.as_str())?)?;
+
+            resolved = match commit {
+                Object::Commit(commit) => match commit.first_parent() {
+                    Some(parent) => format!("{}", HashDisplay(&parent)),
+                    None => return Err(EvsError::NoPreviousCommit),
+                },
+                Object::Null => return Err(EvsError::NoPreviousCommit),
+                _ => return Err(EvsError::NotACommit(hash)),
+            };
+
+            trace!("Gone back to \"{}\".", resolved);
+        }
+
+        self.store.resolve_rest(&current, resolved)
+    }
+
+    /// Like `resolve`, but follows annotated tags and fails early unless the result is a commit or the null commit.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn resolve_commit<T: AsRef<str>>(
+        &self,
+        parent: &Span,
+        r#ref: T,
+    ) -> Result<Hash, EvsError> {
+        debug!("Repository::resolve_commit(self, \"{}\")", r#ref.as_ref());
+
+        let (hash, obj) = self.lookup_peeled(&Span::current(), r#ref)?;
+
+        if !matches!(obj, Object::Null | Object::Commit(_)) {
+            return Err(EvsError::NotACommit(hash));
+        }
+
+        trace!("Resolved to commit \"{}\".", HashDisplay(&hash));
+
+        Ok(hash)
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn reset_stage(
+        &mut self,
+        parent: &Span,
+        _options: &Cli,
+    ) -> Result<Hash, EvsError> {
+        debug!("Repository::reset_stage(self)");
+
+        let current = Span::current();
+
+        let tree = self.get_tree(&current, self.info.head())?;
+
+        trace!("Rebuilding stage from \"{}\".", HashDisplay(&tree));
+
+        self.info.set_stage(tree);
+
+        Ok(tree)
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn graph<T: AsRef<Path>>(
+        &self,
+        parent: &Span,
+        output: T,
+        options: &Cli,
+    ) -> Result<(), EvsError> {
+        debug!("Repository::graph(self, {:?})", output.as_ref());
+
+        self.graph_(output.as_ref(), options)
+    }
+
+    #[allow(clippy::too_many_lines, reason = "This is fine.")]
+    fn graph_(
+        &self,
+        output: &Path,
+        _options: &Cli,
+    ) -> Result<(), EvsError> {
+        let current = Span::current();
+
+        let mut dot = String::from("digraph evs {\n");
+
+        let _ = writeln!(dot, "    \"HEAD\" [shape=plaintext];");
+        let _ = writeln!(dot, "    \"stage\" [shape=plaintext];");
+        let _ = writeln!(
+            dot,
+            "    \"HEAD\" -> \"{}\";",
+            HashDisplay(&self.info.head())
+        );
+        let _ = writeln!(
+            dot,
+            "    \"stage\" -> \"{}\";",
+            HashDisplay(&self.info.stage())
+        );
+
+        let mut pending = self.gc_roots()?;
+        let mut visited = AHashSet::new();
+
+        while let Some(hash) = pending.pop() {
+            if !visited.insert(hash) {
+                continue;
+            }
+
+            let (hash, obj) = self
+                .store
+                .lookup_raw(&current, &format!("{}", HashDisplay(&hash)))?;
+
+            trace!("Visiting \"{}\".", HashDisplay(&hash));
+
+            let shape = match obj {
+                Object::Null => "doublecircle",
+                Object::Blob(_) => "note",
+                Object::Tree(_) => "folder",
+                Object::Commit(_) => "box",
+                Object::Symlink(_) => "cds",
+                Object::ChunkList(_) => "component",
+                Object::Tag(_) => "tab",
+                Object::Delta { .. } => "diamond",
+            };
+
+            let _ = writeln!(
+                dot,
+                "    \"{}\" [shape={}, label=\"{}\"];",
+                HashDisplay(&hash),
+                shape,
+                HashDisplay(&hash[..4])
+            );
+
+            match obj {
+                Object::Null | Object::Blob(_) | Object::Symlink(_) => (),
+                Object::Tag(tag) => {
+                    let _ = writeln!(
+                        dot,
+                        "    \"{}\" -> \"{}\" [label=\"target\"];",
+                        HashDisplay(&hash),
+                        HashDisplay(&tag.target)
+                    );
+
+                    pending.push(tag.target);
+                }
+                Object::Delta { base, .. } => {
+                    let _ = writeln!(
+                        dot,
+                        "    \"{}\" -> \"{}\" [label=\"base\"];",
+                        HashDisplay(&hash),
+                        HashDisplay(&base)
+                    );
+
+                    pending.push(base);
+                }
+                Object::ChunkList(chunks) => {
+                    for (index, chunk) in chunks.into_iter().enumerate() {
+                        let _ = writeln!(
+                            dot,
+                            "    \"{}\" -> \"{}\" [label=\"{}\"];",
+                            HashDisplay(&hash),
+                            HashDisplay(&chunk),
+                            index
+                        );
+
+                        pending.push(chunk);
+                    }
+                }
+                Object::Tree(entries) => {
+                    for entry in entries {
+                        let _ = writeln!(
+                            dot,
+                            "    \"{}\" -> \"{}\" [label={:?}];",
+                            HashDisplay(&hash),
+                            HashDisplay(&entry.content),
+                            entry.name
+                        );
+
+                        pending.push(entry.content);
+                    }
+                }
+                Object::Commit(commit) => {
+                    for parent in &commit.parents {
+                        let _ = writeln!(
+                            dot,
+                            "    \"{}\" -> \"{}\" [label=\"parent\"];",
+                            HashDisplay(&hash),
+                            HashDisplay(parent)
+                        );
+                    }
+                    let _ = writeln!(
+                        dot,
+                        "    \"{}\" -> \"{}\" [label=\"tree\"];",
+                        HashDisplay(&hash),
+                        HashDisplay(&commit.tree)
+                    );
+
+                    pending.extend(commit.parents);
+                    pending.push(commit.tree);
+                }
+            }
+        }
+
+        dot.push_str("}\n");
+
+        trace!("Generated graph of {} object(s).", visited.len());
+
+        if output == "-" {
+            let _ = stdout().write_all(dot.as_bytes());
+        } else {
+            fs::write(output, dot).map_err(|e| (e, output.to_path_buf()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Finds the closest ancestor of `b` that is also an ancestor of `a`, or the null hash for unrelated histories.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn merge_base(
+        &self,
+        parent: &Span,
+        a: &str,
+        b: &str,
+        _options: &Cli,
+    ) -> Result<Hash, EvsError> {
+        debug!("Repository::merge_base(self, \"{}\", \"{}\")", a, b);
+
+        let current = Span::current();
+
+        let a = self.resolve_commit(&current, a)?;
+        let b = self.resolve_commit(&current, b)?;
+
+        self.merge_base_(a, b)
+    }
+
+    fn merge_base_(
+        &self,
+        a: Hash,
+        b: Hash,
+    ) -> Result<Hash, EvsError> {
+        let mut ancestors = AHashSet::new();
+
+        let mut pending = vec![a];
+
+        while let Some(next) = pending.pop() {
+            if next != self.store.null_hash() && ancestors.insert(next) {
+                pending.extend(self.commit_parents(next)?);
+            }
+        }
+
+        trace!("Collected {} ancestor(s).", ancestors.len());
+
+        let mut queue = VecDeque::from([b]);
+        let mut seen = AHashSet::new();
+
+        while let Some(next) = queue.pop_front() {
+            if next == self.store.null_hash() || !seen.insert(next) {
+                continue;
+            }
+
+            if ancestors.contains(&next) {
+                trace!("Found merge base \"{}\".", HashDisplay(&next));
+
+                return Ok(next);
+            }
+
+            queue.extend(self.commit_parents(next)?);
+        }
+
+        trace!("Histories are unrelated.");
+
+        Ok(self.store.null_hash())
+    }
+
+    /// Merges `other` into HEAD per path against their merge base and commits the result with both as parents.
+    /// Returns `None` if `other` is already an ancestor of HEAD.
+    #[allow(clippy::too_many_arguments, reason = "Reasonable here.")]
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn merge(
+        &mut self,
+        parent: &Span,
+        other: &str,
+        message: Option<String>,
+        name: String,
+        email: String,
+        time: OffsetDateTime,
+        options: &Cli,
+    ) -> Result<Option<Hash>, EvsError> {
+        debug!(
+            "Repository::merge(self, \"{}\", {:?}, {}, {}, {:?})",
+            other, message, name, email, time
+        );
+
+        self.merge_(other, message, name, email, time, options)
+    }
+
+    fn merge_(
+        &mut self,
+        other: &str,
+        message: Option<String>,
+        name: String,
+        email: String,
+        time: OffsetDateTime,
+        options: &Cli,
+    ) -> Result<Option<Hash>, EvsError> {
+        let current = Span::current();
+
+        let head = self.info.head();
+
+        let head_tree = self.get_tree(&current, head)?;
+
+        if self.info.stage() != head_tree {
+            return Err(EvsError::UncommittedChanges);
+        }
+
+        let theirs = self.resolve_commit(&current, other)?;
+        let other_tree = self.read_commit(theirs)?.tree;
+
+        let base = self.merge_base_(head, theirs)?;
+
+        trace!("Merge base is \"{}\".", HashDisplay(&base));
+
+        if base == theirs {
+            trace!("Already up to date.");
+
+            return Ok(None);
+        }
+
+        let base_tree = self.get_tree(&current, base)?;
+
+        self.merge_trees(&current, base_tree, head_tree, other_tree, options)?;
+
+        let message = message.unwrap_or_else(|| format!("Merge {}", other));
+
+        self.commit(
+            &current,
+            Some(vec![head, theirs]),
+            message,
+            name,
+            email,
+            time,
+            None,
+            false,
+            true,
+            true,
+            false,
+            options,
+        )
+        .map(Some)
+    }
+
+    /// Commits the changes of the commit `ref` resolves to relative to its first parent on top of HEAD,
+    /// with the same message and author.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn cherry_pick<T: AsRef<str>>(
+        &mut self,
+        parent: &Span,
+        r#ref: T,
+        name: String,
+        email: String,
+        time: OffsetDateTime,
+        options: &Cli,
+    ) -> Result<Hash, EvsError> {
+        debug!(
+            "Repository::cherry_pick(self, \"{}\", {}, {}, {:?})",
+            r#ref.as_ref(),
+            name,
+            email,
+            time
+        );
+
+        self.cherry_pick_(r#ref.as_ref(), name, email, time, options)
+    }
+
+    fn cherry_pick_(
+        &mut self,
+        r#ref: &str,
+        name: String,
+        email: String,
+        time: OffsetDateTime,
+        options: &Cli,
+    ) -> Result<Hash, EvsError> {
+        let current = Span::current();
+
+        let (_, commit, base_tree, head_tree) = self.change_onto_head(&current, r#ref)?;
+
+        self.merge_trees(&current, base_tree, head_tree, commit.tree, options)?;
+
+        let author = commit.author_offset_date().map(|date| Author {
+            name: commit.author_name,
+            email: commit.author_email,
+            date,
+        });
+
+        self.commit(
+            &current, None, commit.msg, name, email, time, author, true, true, false, false,
+            options,
+        )
+    }
+
+    /// Commits the inverse of the changes of the commit `ref` resolves to relative to its first parent on top of HEAD.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn revert<T: AsRef<str>>(
+        &mut self,
+        parent: &Span,
+        r#ref: T,
+        name: String,
+        email: String,
+        time: OffsetDateTime,
+        options: &Cli,
+    ) -> Result<Hash, EvsError> {
+        debug!(
+            "Repository::revert(self, \"{}\", {}, {}, {:?})",
+            r#ref.as_ref(),
+            name,
+            email,
+            time
+        );
+
+        self.revert_(r#ref.as_ref(), name, email, time, options)
+    }
+
+    fn revert_(
+        &mut self,
+        r#ref: &str,
+        name: String,
+        email: String,
+        time: OffsetDateTime,
+        options: &Cli,
+    ) -> Result<Hash, EvsError> {
+        let current = Span::current();
+
+        let (reverted, commit, parent_tree, head_tree) = self.change_onto_head(&current, r#ref)?;
+
+        // The reverted commit is the base, so its changes are undone where HEAD still has them
+        self.merge_trees(&current, commit.tree, head_tree, parent_tree, options)?;
+
+        let message = format!(
+            "Revert \"{}\"\n\nThis reverts commit {}.",
+            commit.msg.lines().next().unwrap_or_default(),
+            HashDisplay(&reverted)
+        );
+
+        self.commit(
+            &current, None, message, name, email, time, None, true, true, false, false, options,
+        )
+    }
+
+    /// Resolves `ref` to a commit for `cherry_pick` or `revert`, which requires the stage to match HEAD.
+    /// Returns the commit with its hash, the tree of its first parent and the tree of HEAD.
+    fn change_onto_head(
+        &self,
+        parent: &Span,
+        r#ref: &str,
+    ) -> Result<(Hash, Commit, Hash, Hash), EvsError> {
+        let head_tree = self.get_tree(parent, self.info.head())?;
+
+        if self.info.stage() != head_tree {
+            return Err(EvsError::UncommittedChanges);
+        }
+
+        let hash = self.resolve_commit(parent, r#ref)?;
+        let commit = self.read_commit(hash)?;
+
+        let base = commit
+            .first_parent()
+            .unwrap_or_else(|| self.store.null_hash());
+
+        trace!(
+            "Applying \"{}\" relative to \"{}\".",
+            HashDisplay(&hash),
+            HashDisplay(&base)
+        );
+
+        let base_tree = self.get_tree(parent, base)?;
+
+        Ok((hash, commit, base_tree, head_tree))
+    }
+
+    /// Merges the changes from `base_tree` to `their_tree` into `our_tree`, which has to be the stage,
+    /// and writes the result to the workspace and the stage.
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn merge_trees(
+        &mut self,
+        parent: &Span,
+        base_tree: Hash,
+        our_tree: Hash,
+        their_tree: Hash,
+        options: &Cli,
+    ) -> Result<(), EvsError> {
+        debug!(
+            "Repository::merge_trees(self, \"{}\", \"{}\", \"{}\")",
+            HashDisplay(&base_tree),
+            HashDisplay(&our_tree),
+            HashDisplay(&their_tree)
+        );
+
+        let current = Span::current();
+
+        let [base_entries, our_entries, their_entries] =
+            [base_tree, our_tree, their_tree].map(|tree| {
+                let mut entries = AHashMap::new();
+
+                self.tree_entries(&current, tree, Path::new(""), &mut entries)
+                    .map(|()| entries)
+            });
+
+        let merged = merge_entries(&base_entries?, our_entries?, &their_entries?)?;
+
+        trace!("Merged into {} entries.", merged.len());
+
+        let merged_tree = self.insert_entries(&current, Path::new(""), merged)?;
+
+        trace!("Merged tree \"{}\".", HashDisplay(&merged_tree));
+
+        let global_filter = [AsRef::<Path>::as_ref("").to_path_buf()];
+
+        let empty_set = AHashSet::new();
+
+        let ignores = self.get_ignores(&current, options)?;
+
+        let ds = DiffSide::Tree(our_tree).read(
+            &current,
+            "",
+            &self.store,
+            &global_filter,
+            &ignores,
+            &empty_set,
+            self.max_depth,
+        )?;
+
+        let dl = DiffSide::Local(self.workspace.clone()).read(
+            &current,
+            "",
+            &self.store,
+            &global_filter,
+            &ignores,
+            &ds.0,
+            self.max_depth,
+        )?;
+
+        let dd = DiffSide::Tree(merged_tree).read(
+            &current,
+            "",
+            &self.store,
+            &global_filter,
+            &ignores,
+            &empty_set,
+            self.max_depth,
+        )?;
+
+        if has_local_changes(&ds, &dl, &dd) {
+            return Err(EvsError::UncommittedChanges);
+        }
+
+        check_protected(&dd)?;
+
+        self.write_transition(&current, our_tree, merged_tree, &ds, &dd)?;
+
+        self.info.set_stage(merged_tree);
+
+        trace!("Wrote merged tree to workspace and stage.");
+
+        Ok(())
+    }
+
+    /// Inserts the tree holding `entries`, whose paths are relative to `prefix`.
+    fn insert_entries(
+        &self,
+        parent: &Span,
+        prefix: &Path,
+        entries: AHashMap<PathBuf, (Hash, u32)>,
+    ) -> Result<Hash, EvsError> {
+        check_depth(prefix, self.max_depth)?;
+
+        let mut groups = AHashMap::<String, AHashMap<PathBuf, (Hash, u32)>>::new();
+
+        for (path, entry) in entries {
+            let mut components = path.components();
+
+            let Some(first) = components.next() else {
+                continue;
+            };
+
+            groups
+                .entry(first.as_os_str().to_string_lossy().into_owned())
+                .or_default()
+                .insert(components.as_path().to_path_buf(), entry);
+        }
+
+        let mut items = Vec::with_capacity(groups.len());
+
+        for (name, group) in groups {
+            let path = prefix.join(&name);
+
+            let (content, mode) = match group.get(Path::new("")) {
+                Some(_) if group.len() > 1 => return Err(EvsError::MergeConflict(path)),
+                Some(entry) => *entry,
+                None => (self.insert_entries(parent, &path, group)?, MODE_DIR),
+            };
+
+            items.push(TreeEntry {
+                name,
+                content,
+                mode,
+            });
+        }
+
+        self.store.insert(parent, Object::Tree(items))
+    }
+
+    /// The parents of the commit `hash`.
+    fn commit_parents(
+        &self,
+        hash: Hash,
+    ) -> Result<Vec<Hash>, EvsError> {
+        Ok(self.read_commit(hash)?.parents)
+    }
+
+    /// Reads the commit `hash`, which unlike in `resolve_commit` must not be the null commit.
+    fn read_commit(
+        &self,
+        hash: Hash,
+    ) -> Result<Commit, EvsError> {
+        match self
+            .store
+            .lookup(&Span::current(), &format!("{}", HashDisplay(&hash)))?
+        {
+            (_, Object::Commit(commit)) => Ok(commit),
+            (hash, _) => Err(EvsError::NotACommit(hash)),
+        }
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn get_tree(
+        &self,
+        parent: &Span,
+        commit: Hash,
+    ) -> Result<Hash, EvsError> {
+        debug!("Repository::get_tree(self, \"{}\")", HashDisplay(&commit));
+
+        let current = Span::current();
+
+        if commit == self.store.null_hash() {
+            return self.empty_tree();
+        }
+
+        let (hash, commit) = self.peel(
+            self.store
+                .lookup(&current, &format!("{}", HashDisplay(&commit)))?,
+        )?;
+
+        trace!("Found referenced object.");
+
+        Ok(match commit {
+            Object::Null => self.empty_tree()?,
+            Object::Commit(commit) => commit.tree,
+            _ => return Err(EvsError::NotACommit(hash)),
+        })
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn get_ignores(
+        &self,
+        parent: &Span,
+        _options: &Cli,
+    ) -> Result<IgnoreMatcher, EvsError> {
+        debug!("Repository::get_ignores(self)");
+
+        IgnoreMatcher::load(&Span::current(), &self.workspace)
+    }
+
+    /// With `porcelain`, each changed path is printed as one record, sorted by path, and nothing else is printed:
+    ///
+    /// ```text
+    /// record   = (stage worktree / "??") NUL path LF
+    /// stage    = "A" / "M" / "D" / "."
+    /// worktree = "M" / "D" / "."
+    /// ```
+    ///
+    /// `stage` is the change of the stage against HEAD and `worktree` the one of the workspace against the stage,
+    /// `A` for added, `M` for modified, `D` for deleted and `.` for unchanged. Untracked paths get `??`,
+    /// so a path deleted from the stage but still in the workspace gets a `D.` and a `??` record.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn status(
+        &self,
+        parent: &Span,
+        excludes: &[Pattern],
+        porcelain: bool,
+        options: &Cli,
+    ) -> Result<(), EvsError> {
+        debug!(
+            "Repository::status(self, {} excludes, {})",
+            excludes.len(),
+            porcelain
+        );
+
+        let current = Span::current();
+
+        let (store_count, store_size) = self.store.status(&current)?;
+
+        trace!(
+            "Store reported {} objects with a collective {} bytes.",
+            store_count, store_size
+        );
+
+        let (repo_head, repo_stage) = (self.info.head(), self.info.stage());
+
+        let [cds, sds, lds] = self.status_sides(&current, excludes, options)?;
+
+        #[allow(clippy::indexing_slicing, reason = "The keys are in the map as well.")]
+        let (stage_added, stage_modified, stage_removed): (Vec<_>, Vec<_>, Vec<_>) = (
+            sds.0.difference(&cds.0).collect(),
+            sds.0
+                .intersection(&cds.0)
+                .filter(|k| cds.1[*k] != sds.1[*k])
+                .collect(),
+            cds.0.difference(&sds.0).collect(),
+        );
+
+        trace!("Generated stage diff.");
+
+        #[allow(clippy::indexing_slicing, reason = "The keys are in the map as well.")]
+        let (untracked, local_modified, local_removed): (Vec<_>, Vec<_>, Vec<_>) = (
+            lds.0.difference(&sds.0).collect(),
+            lds.0
+                .intersection(&sds.0)
+                .filter(|k| sds.1[*k] != lds.1[*k])
+                .collect(),
+            sds.0.difference(&lds.0).collect(),
+        );
+
+        trace!("Generated local diff.");
+
+        if porcelain {
+            print_porcelain_status(
+                &stage_added,
+                &stage_modified,
+                &stage_removed,
+                &untracked,
+                &local_modified,
+                &local_removed,
+            );
+
+            return Ok(());
+        }
+
+        self.print_info(
+            store_count,
+            store_size,
+            repo_head,
+            repo_stage,
+            stage_added,
+            stage_modified,
+            stage_removed,
+            untracked,
+            local_modified,
+            local_removed,
+            get_color(options),
+        );
+
+        Ok(())
+    }
+
+    /// HEAD, the stage and the workspace as read by `status`, leaving out the paths matching any of `excludes`.
+    fn status_sides(
+        &self,
+        current: &Span,
+        excludes: &[Pattern],
+        options: &Cli,
+    ) -> Result<[SideContent; 3], EvsError> {
+        let commit_diffside = DiffSide::Tree(self.get_tree(current, self.info.head())?);
+
+        let stage_diffside = DiffSide::Tree(self.info.stage());
+
+        let local_diffside = DiffSide::Local(self.workspace.clone());
+
+        trace!("Prepared diffsides.");
+
+        let ignores = self.get_ignores(current, options)?;
+
+        let global_filter = [AsRef::<Path>::as_ref("").to_path_buf()];
+
+        let empty_set = AHashSet::new();
+
+        let mut cds = commit_diffside.read(
+            current,
+            "",
+            &self.store,
+            &global_filter,
+            &ignores,
+            &empty_set,
+            self.max_depth,
+        )?;
+
+        let mut sds = stage_diffside.read(
+            current,
+            "",
+            &self.store,
+            &global_filter,
+            &ignores,
+            &empty_set,
+            self.max_depth,
+        )?;
+
+        let mut lds = local_diffside.read(
+            current,
+            "",
+            &self.store,
+            &global_filter,
+            &ignores,
+            &sds.0,
+            self.max_depth,
+        )?;
+
+        trace!("Read diffsides: {:?} -> {:?} -> {:?}.", cds.0, sds.0, lds.0);
+
+        for set in [&mut cds.0, &mut sds.0, &mut lds.0] {
+            set.retain(|p| !matches_any(excludes, p));
+        }
+
+        trace!("Applied {} exclude(s).", excludes.len());
+
+        Ok([cds, sds, lds])
+    }
+
+    #[inline]
+    #[allow(clippy::too_many_arguments, reason = "This is fine.")]
+    pub fn print_info(
+        &self,
+        store_count: usize,
+        store_size: usize,
+        repo_head: Hash,
+        repo_stage: Hash,
+        stage_added: Vec<&PathBuf>,
+        stage_modified: Vec<&PathBuf>,
+        stage_removed: Vec<&PathBuf>,
+        untracked: Vec<&PathBuf>,
+        local_modified: Vec<&PathBuf>,
+        local_removed: Vec<&PathBuf>,
+        print_color: bool,
+    ) {
+        let add_color = if print_color { ADD_COLOR } else { "" };
+        let sub_color = if print_color { SUB_COLOR } else { "" };
+        let mod_color = if print_color { MOD_COLOR } else { "" };
+        let none_color = if print_color { NONE_COLOR } else { "" };
+
+        println!("  Head is at \"{}\"", HashDisplay(&repo_head));
+        println!("  and stage is \"{}\"", HashDisplay(&repo_stage));
+        println!(
+            "  Store has {} objects with size {}",
+            store_count,
+            SizeDisplay(store_size, print_color)
+        );
+        if !stage_added.is_empty() || !stage_modified.is_empty() || !stage_removed.is_empty() {
+            println!();
+            println!("  Staged changes:");
+            print!("{}", add_color);
+            for addition in stage_added {
+                println!("    added {:?}", addition);
+            }
+            print!("{}", mod_color);
+            for modification in stage_modified {
+                println!("    modified {:?}", modification);
+            }
+            print!("{}", sub_color);
+            for deletion in stage_removed {
+                println!("    removed {:?}", deletion);
+            }
+        }
+        if !local_modified.is_empty() || !local_removed.is_empty() {
+            println!("{}", none_color);
+            println!("  Unstaged changes:");
+            print!("{}", mod_color);
+            for modification in local_modified {
+                println!("    modified {:?}", modification);
+            }
+            print!("{}", sub_color);
+            for deletion in local_removed {
+                println!("    removed {:?}", deletion);
+            }
+        }
+        if !untracked.is_empty() {
+            println!("{}", none_color);
+            println!("  Untracked:");
+            print!("{}", add_color);
+            for file in untracked {
+                println!("    {:?}", file);
+            }
+        }
+        print!("{}", none_color);
+
+        let _ = stdout().flush();
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn show<T: AsRef<str>>(
+        &self,
+        parent: &Span,
+        r#ref: T,
+        mode: DiffMode,
+        options: &Cli,
+    ) -> Result<(), EvsError> {
+        debug!("Repository::show(self, \"{}\", {:?})", r#ref.as_ref(), mode);
+
+        self.show_(r#ref.as_ref(), mode, options)
+    }
+
+    fn show_(
+        &self,
+        r#ref: &str,
+        mode: DiffMode,
+        options: &Cli,
+    ) -> Result<(), EvsError> {
+        let current = Span::current();
+
+        let (hash, commit) = self.lookup_peeled(&current, r#ref)?;
+
+        trace!("Found commit \"{}\".", HashDisplay(&hash));
+
+        let (tree, parent) = match &commit {
+            Object::Null => return Ok(()),
+            Object::Commit(commit) => (
+                commit.tree,
+                commit.first_parent().unwrap_or(self.store.null_hash()),
+            ),
+            _ => return Err(EvsError::NotACommit(hash)),
+        };
+
+        println!("{}", commit);
+
+        let rhs = DiffSide::Tree(tree);
+
+        let lhs = DiffSide::Tree(self.get_tree(&current, parent)?);
+
+        trace!("Diffing...");
+
+        DiffSide::diff_with(
+            lhs,
+            rhs,
+            &current,
+            &self.store,
+            &[AsRef::<Path>::as_ref("").to_path_buf()],
+            &[],
+            &[],
+            mode,
+            options,
+        )
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn checkout<T: AsRef<str>>(
+        &mut self,
+        parent: &Span,
+        r#ref: T,
+        force: bool,
+        clean: bool,
+        options: &Cli,
+    ) -> Result<Hash, EvsError> {
+        debug!(
+            "Repository::checkout(self, \"{}\", {}, {})",
+            r#ref.as_ref(),
+            force,
+            clean
+        );
+
+        self.checkout_(r#ref.as_ref(), force, clean, options)
+    }
+
+    /// With `clean`, unignored files that are in neither the old nor the new tree are deleted as well.
+    #[allow(clippy::too_many_lines, reason = "This is fine.")]
+    fn checkout_(
+        &mut self,
+        r#ref: &str,
+        force: bool,
+        clean: bool,
+        options: &Cli,
+    ) -> Result<Hash, EvsError> {
+        let current = Span::current();
+
+        let (hash, _) = self.lookup_peeled(&current, r#ref)?;
+
+        trace!("Found commit \"{}\".", HashDisplay(&hash));
+
+        let dest_tree = self.get_tree(&current, hash)?;
+
+        trace!("Destination tree \"{}\".", HashDisplay(&dest_tree));
+
+        let mut src_tree = self.get_tree(&current, self.info.head())?;
+
+        trace!("Source tree \"{}\".", HashDisplay(&src_tree));
+
+        if src_tree != self.info.stage() {
+            if force
+                || confirmation!(
+                    false,
+                    "There are uncommitted changes, do you want to discard them?"
+                )?
+            {
+                warn!("Discarding current stage...");
+
+                src_tree = self.info.stage();
+            } else {
+                return Err(EvsError::UncommittedChanges);
+            }
+        }
+
+        let dd = DiffSide::Tree(dest_tree);
+
+        let ds = DiffSide::Tree(src_tree);
+
+        let dl = DiffSide::Local(self.workspace.clone());
+
+        trace!("Prepared diffsides.");
+
+        let global_filter = [AsRef::<Path>::as_ref("").to_path_buf()];
+
+        let empty_set = AHashSet::new();
+
+        let ignores = self.get_ignores(&current, options)?;
+
+        let ds = ds.read(
+            &current,
+            "",
+            &self.store,
+            &global_filter,
+            &ignores,
+            &empty_set,
+            self.max_depth,
+        )?;
+
+        let dl = dl.read(
+            &current,
+            "",
+            &self.store,
+            &global_filter,
+            &ignores,
+            &ds.0,
+            self.max_depth,
+        )?;
+
+        let dd = dd.read(
+            &current,
+            "",
+            &self.store,
+            &global_filter,
+            &ignores,
+            &empty_set,
+            self.max_depth,
+        )?;
+
+        trace!("Read diffsides.");
+
+        if has_local_changes(&ds, &dl, &dd) {
+            if force
+                || confirmation!(
+                    false,
+                    "There are unstaged changes, do you want to discard them?"
+                )?
+            {
+                warn!("Discarding local changes...");
+            } else {
+                return Err(EvsError::UncommittedChanges);
+            }
+        }
+
+        check_protected(&dd)?;
+
+        let branch = self
+            .read_branch(r#ref)
+            .ok()
+            .flatten()
+            .map(|_| r#ref.to_owned());
+
+        trace!("Attaching HEAD to {:?}.", branch);
+
+        self.info.set_branch(branch);
+
+        self.set_head(hash)?;
+
+        self.info.set_stage(dest_tree);
+
+        trace!("Modified repository info.");
+
+        if clean {
+            let untracked =
+                dl.0.iter()
+                    .filter(|k| !dd.0.contains(*k) && !ds.0.contains(*k))
+                    .collect::<Vec<_>>();
+
+            if !untracked.is_empty()
+                && (force
+                    || confirmation!(
+                        false,
+                        "This will delete {} untracked file(s), continue?",
+                        untracked.len()
+                    )?)
+            {
+                for file in untracked {
+                    self.remove_and_prune(file)?;
+                }
+
+                trace!("Deleted untracked files...");
+            }
+        }
+
+        self.write_transition(&current, src_tree, dest_tree, &ds, &dd)?;
+
+        trace!("Checkout complete.");
+
+        Ok(hash)
+    }
+
+    /// Moves HEAD and the branch it is attached to, without changing which branch that is.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn reset<T: AsRef<str>>(
+        &mut self,
+        parent: &Span,
+        r#ref: T,
+        mode: ResetMode,
+        force: bool,
+        options: &Cli,
+    ) -> Result<Hash, EvsError> {
+        debug!(
+            "Repository::reset(self, \"{}\", {:?}, {})",
+            r#ref.as_ref(),
+            mode,
+            force
+        );
+
+        self.reset_(r#ref.as_ref(), mode, force, options)
+    }
+
+    fn reset_(
+        &mut self,
+        r#ref: &str,
+        mode: ResetMode,
+        force: bool,
+        options: &Cli,
+    ) -> Result<Hash, EvsError> {
+        let current = Span::current();
+
+        let hash = self.resolve_commit(&current, r#ref)?;
+
+        trace!("Found commit \"{}\".", HashDisplay(&hash));
+
+        let dest_tree = self.get_tree(&current, hash)?;
+
+        if mode == ResetMode::Hard {
+            if !force
+                && !confirmation!(
+                    false,
+                    "This will discard all uncommitted changes, continue?"
+                )?
+            {
+                return Err(EvsError::UncommittedChanges);
+            }
+
+            let src_tree = self.info.stage();
+
+            let global_filter = [AsRef::<Path>::as_ref("").to_path_buf()];
+
+            let empty_set = AHashSet::new();
+
+            let ignores = self.get_ignores(&current, options)?;
+
+            let ds = DiffSide::Tree(src_tree).read(
+                &current,
+                "",
+                &self.store,
+                &global_filter,
+                &ignores,
+                &empty_set,
+                self.max_depth,
+            )?;
+
+            let dl = DiffSide::Local(self.workspace.clone()).read(
+                &current,
+                "",
+                &self.store,
+                &global_filter,
+                &ignores,
+                &ds.0,
+                self.max_depth,
+            )?;
+
+            let dd = DiffSide::Tree(dest_tree).read(
+                &current,
+                "",
+                &self.store,
+                &global_filter,
+                &ignores,
+                &empty_set,
+                self.max_depth,
+            )?;
+
+            trace!("Read diffsides.");
+
+            check_protected(&dd)?;
+
+            // The tracked files as they are on disk, so local changes are overwritten as well.
+            let tracked =
+                dl.0.into_iter()
+                    .filter(|path| ds.0.contains(path) && !path.starts_with(".evs"))
+                    .collect::<AHashSet<_>>();
+
+            let contents =
+                dl.1.into_iter()
+                    .filter(|(path, _)| tracked.contains(path))
+                    .collect::<AHashMap<_, _>>();
+
+            self.write_transition(&current, src_tree, dest_tree, &(tracked, contents), &dd)?;
+
+            trace!("Restored workspace.");
+        }
+
+        self.set_head(hash)?;
+
+        if mode != ResetMode::Soft {
+            self.info.set_stage(dest_tree);
+
+            trace!("Reset stage to \"{}\".", HashDisplay(&dest_tree));
+        }
+
+        Ok(hash)
+    }
+
+    /// Overwrites the given workspace files, or all files below given directories, with their content in
+    /// the stage or the tree of `source` and returns how many were written.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn restore<T: AsRef<Path>>(
+        &self,
+        parent: &Span,
+        paths: &[T],
+        source: Option<&str>,
+    ) -> Result<usize, EvsError> {
+        debug!(
+            "Repository::restore(self, <{} path(s)>, {:?})",
+            paths.len(),
+            source
+        );
+
+        let current = Span::current();
+
+        let tree = match source {
+            Some(source) => self.get_tree(&current, self.lookup(&current, source)?.0)?,
+            None => self.info.stage(),
+        };
+
+        trace!("Restoring from tree \"{}\".", HashDisplay(&tree));
+
+        let mut entries = AHashMap::new();
+
+        self.tree_entries(&current, tree, Path::new(""), &mut entries)?;
+
+        let mut restored = 0usize;
+
+        for path in paths {
+            let path = path.as_ref();
+
+            let canon =
+                partial_canonicalize(&current, path).map_err(|e| (e, path.to_path_buf()))?;
+
+            let Ok(relative) = canon.strip_prefix(&self.workspace) else {
+                return Err(EvsError::PathOutsideOfRepo(canon));
+            };
+
+            if relative.starts_with(".evs") {
+                return Err(EvsError::ProtectedPath(relative.to_path_buf()));
+            }
+
+            let mut matching = entries
+                .iter()
+                .filter(|(file, _)| file.starts_with(relative))
+                .peekable();
+
+            if matching.peek().is_none() {
+                return Err(EvsError::PathNotInTree(relative.to_path_buf(), tree));
+            }
+
+            for (file, &(content, mode)) in matching {
+                let file = self.workspace.join(file);
+
+                // workspace is parent
+                let parent = file.parent().unwrap();
+
+                fs::create_dir_all(parent).map_err(|e| (e, file.clone()))?;
+
+                trace!("Restoring file {:?}...", file);
+
+                self.write_workspace_object(&current, &file, tree, content, mode == MODE_SYMLINK)?;
+
+                if mode != MODE_SYMLINK {
+                    set_file_mode(&file, mode)?;
+                }
+
+                restored = restored.saturating_add(1);
+            }
+        }
+
+        Ok(restored)
+    }
+
+    /// Writes the blob, chunk list or symlink `content` of `tree` to `file`, streaming chunk lists instead of
+    /// reassembling them in memory.
+    fn write_workspace_object(
+        &self,
+        current: &Span,
+        file: &Path,
+        tree: Hash,
+        content: Hash,
+        symlink: bool,
+    ) -> Result<(), EvsError> {
+        match self
+            .store
+            .lookup_raw(current, &format!("{}", HashDisplay(&content)))?
+        {
+            (_, Object::Blob(data) | Object::Symlink(data)) => {
+                write_workspace_file(file, &data, symlink)
+            }
+            (_, Object::ChunkList(chunks)) if !symlink => {
+                if fs::symlink_metadata(file).is_ok_and(|m| m.is_symlink()) {
+                    trace!("Removing old symlink {:?}...", file);
+
+                    fs::remove_file(file).map_err(|e| (e, file.to_path_buf()))?;
+                }
+
+                let mut output = File::create(file).map_err(|e| (e, file.to_path_buf()))?;
+
+                trace!("Streaming {} chunk(s) to {:?}...", chunks.len(), file);
+
+                io::copy(&mut self.chunk_reader(content, chunks), &mut output)
+                    .map_err(|e| chunk_error(e, file))?;
+
+                Ok(())
+            }
+            (content, other) => Err(EvsError::CorruptStateDetected(
+                CorruptState::NonContentInTree(tree, content, other.kind()),
+            )),
+        }
+    }
+
+    /// Rewrites the workspace files of `src_tree` into those of `dest_tree`, given both as read diffsides.
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn write_transition(
+        &self,
+        parent: &Span,
+        src_tree: Hash,
+        dest_tree: Hash,
+        ds: &SideContent,
+        dd: &SideContent,
+    ) -> Result<(), EvsError> {
+        debug!(
+            "Repository::write_transition(self, \"{}\", \"{}\")",
+            HashDisplay(&src_tree),
+            HashDisplay(&dest_tree)
+        );
+
+        let current = Span::current();
+
+        for file in ds.0.difference(&dd.0) {
+            self.remove_and_prune(file)?;
+        }
+
+        trace!("Deleted files...");
+
+        let [src_entries, dest_entries] = [src_tree, dest_tree].map(|tree| {
+            let mut entries = AHashMap::new();
+
+            self.tree_entries(&current, tree, Path::new(""), &mut entries)
+                .map(|()| entries)
+        });
+
+        let (src_entries, dest_entries) = (src_entries?, dest_entries?);
+
+        let [src_modes, dest_modes] = [&src_entries, &dest_entries].map(|entries| {
+            entries
+                .iter()
+                .map(|(path, &(_, mode))| (path, mode))
+                .collect::<AHashMap<_, _>>()
+        });
+
+        let is_symlink = |modes: &AHashMap<&PathBuf, u32>, file: &PathBuf| {
+            modes.get(file) == Some(&MODE_SYMLINK)
+        };
+
+        // Every file of the destination diffside is in its tree
+        #[allow(clippy::indexing_slicing, reason = "The keys are in the map as well.")]
+        let write = |file: &PathBuf, path: &Path| {
+            self.write_workspace_object(
+                &current,
+                path,
+                dest_tree,
+                dest_entries[file].0,
+                is_symlink(&dest_modes, file),
+            )
+        };
+
+        for file in dd.0.difference(&ds.0) {
+            let path = self.workspace.join(file);
+
+            // workspace is parent
+            let parent = path.parent().unwrap();
+
+            trace!("Creating dir {:?}...", parent);
+
+            fs::create_dir_all(parent).map_err(|e| (e, path.clone()))?;
+
+            trace!("Creating file {:?}...", path);
+
+            write(file, &path)?;
+        }
+
+        trace!("Created new files...");
+
+        #[allow(clippy::indexing_slicing, reason = "The keys are in the map as well.")]
+        for file in ds.0.intersection(&dd.0).filter(|k| {
+            ds.1[*k] != dd.1[*k] || is_symlink(&src_modes, k) != is_symlink(&dest_modes, k)
+        }) {
+            let path = self.workspace.join(file);
+
+            trace!("Modifying file {:?}...", path);
+
+            write(file, &path)?;
+        }
+
+        trace!("Modified files...");
+
+        #[allow(clippy::indexing_slicing, reason = "The keys are in the map as well.")]
+        for (file, mode) in
+            dd.0.iter()
+                .filter_map(|k| dest_modes.get(k).map(|m| (k, *m)))
+        {
+            if mode == MODE_SYMLINK
+                || (ds.0.contains(file)
+                    && ds.1[file] == dd.1[file]
+                    && src_modes.get(file) == Some(&mode))
+            {
+                continue;
+            }
+
+            let file = self.workspace.join(file);
+
+            trace!("Setting mode {:o} of {:?}...", mode, file);
+
+            set_file_mode(&file, mode)?;
+        }
+
+        trace!("Applied file modes...");
+
+        Ok(())
+    }
+
+    /// Collects the contents and modes of all files below `tree`, descending only into entries stored as directories.
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn tree_entries(
+        &self,
+        parent: &Span,
+        tree: Hash,
+        prefix: &Path,
+        entries: &mut AHashMap<PathBuf, (Hash, u32)>,
+    ) -> Result<(), EvsError> {
+        debug!(
+            "Repository::tree_entries(self, \"{}\", {:?})",
+            HashDisplay(&tree),
+            prefix
+        );
+
+        let current = Span::current();
+
+        check_depth(prefix, self.max_depth)?;
+
+        if tree == self.store.empty_tree_hash() {
+            return Ok(());
+        }
+
+        let Object::Tree(items) = self
+            .store
+            .lookup(&current, &format!("{}", HashDisplay(&tree)))?
+            .1
+        else {
+            return Ok(());
+        };
+
+        for item in items {
+            let path = prefix.join(&item.name);
+
+            if item.mode == MODE_DIR {
+                self.tree_entries(&current, item.content, &path, entries)?;
+            } else {
+                entries.insert(path, (item.content, item.mode));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes a workspace file and then its ancestors until one is not empty.
+    fn remove_and_prune(
+        &self,
+        relative: &Path,
+    ) -> Result<(), EvsError> {
+        let file = self.workspace.join(relative);
+
+        trace!("Deleting file {:?}...", file);
+
+        fs::remove_file(&file).map_err(|e| (e, file.clone()))?;
+
+        for ancestor in file
+            .ancestors()
+            .skip(1)
+            .take_while(|a| *a != self.workspace)
+        {
+            if let Ok(dir) = ancestor.read_dir()
+                && dir.count() == 0
+            {
+                trace!("Pruning empty dir {:?}...", ancestor);
+
+                if fs::remove_dir(ancestor).is_err() {
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the commit HEAD points at, an unborn branch resolves to the null commit.
+    fn resolve_head(&self) -> Result<Hash, EvsError> {
+        match &self.info.head {
+            Head::Detached(hash) => Ok(*hash),
+            Head::Symbolic(name) => Ok(self.read_branch(name)?.unwrap_or_else(|| {
+                trace!("Branch {:?} is unborn.", name);
+
+                self.store.null_hash()
+            })),
+        }
+    }
+
+    /// Moves HEAD and, if HEAD is symbolic, the branch it points at.
+    #[inline]
+    pub fn set_head(
+        &mut self,
+        hash: Hash,
+    ) -> Result<(), EvsError> {
+        let old = self.info.head();
+
+        self.info.set_head(hash);
+
+        if let Some(branch) = self.info.branch() {
+            self.write_branch(branch, hash)?;
+        }
+
+        if old != hash {
+            self.append_reflog(old, hash)?;
+        }
+
+        Ok(())
+    }
+
+    /// Records a move of HEAD, so `gc` keeps what HEAD recently pointed at.
+    fn append_reflog(
+        &self,
+        old: Hash,
+        new: Hash,
+    ) -> Result<(), EvsError> {
+        let path = self.repository.join(REFLOG_FILE);
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| (e, path.clone()))?;
+
+        writeln!(file, "{} {} {}", HashDisplay(&old), HashDisplay(&new), time)
+            .map_err(|e| (e, path.clone()))?;
+
+        if self.info.shared() {
+            set_shared_mode(&path)?;
+        }
+
+        trace!(
+            "Recorded HEAD move from \"{}\" to \"{}\" in the reflog.",
+            HashDisplay(&old),
+            HashDisplay(&new)
+        );
+
+        Ok(())
+    }
+
+    /// The reflog entries, oldest first, a missing reflog has none.
+    fn read_reflog(&self) -> Result<Vec<ReflogEntry>, EvsError> {
+        let path = self.repository.join(REFLOG_FILE);
+
+        if !path.is_file() {
+            return Ok(Vec::new());
+        }
+
+        let content = fs::read_to_string(&path).map_err(|e| (e, path.clone()))?;
+
+        content
+            .lines()
+            .enumerate()
+            .map(|(index, line)| {
+                let mut fields = line.split(' ');
+
+                let entry = (
+                    fields.next().and_then(parse_hash),
+                    fields.next().and_then(parse_hash),
+                    fields.next().and_then(|time| time.parse().ok()),
+                    fields.next(),
+                );
+
+                let (Some(old), Some(new), Some(time), None) = entry else {
+                    return Err(EvsError::CorruptStateDetected(CorruptState::InvalidReflog(
+                        path.clone(),
+                        index.saturating_add(1),
+                    )));
+                };
+
+                Ok((old, new, time))
+            })
+            .collect()
+    }
+
+    /// Replaces the reflog with `entries`, see `read_reflog`.
+    fn write_reflog(
+        &self,
+        entries: &[ReflogEntry],
+    ) -> Result<(), EvsError> {
+        let path = self.repository.join(REFLOG_FILE);
+
+        let mut content = String::new();
+
+        for (old, new, time) in entries {
+            let _ = writeln!(
+                content,
+                "{} {} {}",
+                HashDisplay(old),
+                HashDisplay(new),
+                time
+            );
+        }
+
+        fs::write(&path, content).map_err(|e| (e, path.clone()))?;
+
+        if self.info.shared() {
+            set_shared_mode(&path)?;
+        }
+
+        Ok(())
+    }
+
+    /// The empty tree, inserted again in case it went missing, as anything pointing at it needs the object.
+    fn empty_tree(&self) -> Result<Hash, EvsError> {
+        self.store.insert(&Span::current(), Object::Tree(vec![]))
+    }
+
+    /// HEAD, the stage, every branch and every tag, and the bootstrap objects every repository contains.
+    #[inline]
+    pub fn gc_roots(&self) -> Result<Vec<Hash>, EvsError> {
+        let mut roots = vec![
+            self.info.head(),
+            self.info.stage(),
+            self.store.null_hash(),
+            self.store.empty_tree_hash(),
+        ];
+
+        roots.extend(
+            self.list_branches(&Span::current())?
+                .into_iter()
+                .chain(self.list_tags(&Span::current())?)
+                .map(|(_, hash)| hash),
+        );
+
+        Ok(roots)
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn create_branch(
+        &self,
+        parent: &Span,
+        name: &str,
+        target: Hash,
+    ) -> Result<(), EvsError> {
+        debug!(
+            "Repository::create_branch(self, {:?}, \"{}\")",
+            name,
+            HashDisplay(&target)
+        );
+
+        if self.read_branch(name)?.is_some() {
+            return Err(EvsError::BranchExists(name.to_owned()));
+        }
+
+        self.write_branch(name, target)
+    }
+
+    /// Returns all branches sorted by name.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn list_branches(
+        &self,
+        parent: &Span,
+    ) -> Result<Vec<(String, Hash)>, EvsError> {
+        debug!("Repository::list_branches(self)");
+
+        self.list_refs(HEADS_DIR)
+    }
+
+    /// Returns all tags sorted by name.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn list_tags(
+        &self,
+        parent: &Span,
+    ) -> Result<Vec<(String, Hash)>, EvsError> {
+        debug!("Repository::list_tags(self)");
+
+        self.list_refs(TAGS_DIR)
+    }
+
+    /// Points the new tag `name` at `target`, or at a new tag object if it has a message.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn create_tag(
+        &self,
+        parent: &Span,
+        name: &str,
+        target: Hash,
+        message: Option<String>,
+        tagger: String,
+        time: SystemTime,
+    ) -> Result<Hash, EvsError> {
+        debug!(
+            "Repository::create_tag(self, {:?}, \"{}\", {}, {:?})",
+            name,
+            HashDisplay(&target),
+            message.is_some(),
+            tagger
+        );
+
+        if self.read_tag(name)?.is_some() {
+            return Err(EvsError::TagExists(name.to_owned()));
+        }
+
+        let hash = match message {
+            Some(message) => {
+                trace!("Creating annotated tag...");
+
+                self.store.insert(
+                    &Span::current(),
+                    Object::Tag(Tag {
+                        target,
+                        name: name.to_owned(),
+                        message,
+                        tagger,
+                        date: time,
+                    }),
+                )?
+            }
+            None => target,
+        };
+
+        self.write_ref(TAGS_DIR, name, hash)?;
+
+        Ok(hash)
+    }
+
+    /// Returns the refs in `refs/<kind>` sorted by name.
+    fn list_refs(
+        &self,
+        kind: &str,
+    ) -> Result<Vec<(String, Hash)>, EvsError> {
+        let dir = self.repository.join("refs").join(kind);
+
+        if !dir.exists() {
+            trace!("No {} yet.", kind);
+
+            return Ok(Vec::new());
+        }
+
+        let mut refs = dir
+            .read_dir()
+            .map_err(|e| (e, dir.clone()))?
+            .map(|entry| {
+                let entry = entry.map_err(|e| (e, dir.clone()))?;
+
+                let name = entry.file_name().into_string().map_err(|_name| {
+                    EvsError::CorruptStateDetected(CorruptState::InvalidRef(entry.path()))
+                })?;
+
+                if !is_valid_ref_name(&name) {
+                    return Err(EvsError::CorruptStateDetected(CorruptState::InvalidRef(
+                        entry.path(),
+                    )));
+                }
+
+                let hash = self.read_ref(kind, &name)?.ok_or_else(|| {
+                    EvsError::CorruptStateDetected(CorruptState::InvalidRef(entry.path()))
+                })?;
+
+                Ok((name, hash))
+            })
+            .collect::<Result<Vec<_>, EvsError>>()?;
+
+        refs.sort_unstable();
+
+        trace!("Found {} ref(s) in {}.", refs.len(), kind);
+
+        Ok(refs)
+    }
+
+    /// Returns the target of a branch or `None` if it does not exist.
+    fn read_branch(
+        &self,
+        name: &str,
+    ) -> Result<Option<Hash>, EvsError> {
+        if !is_valid_ref_name(name) {
+            return Err(EvsError::InvalidBranchName(name.to_owned()));
+        }
+
+        self.read_ref(HEADS_DIR, name)
+    }
+
+    /// Returns the target of a tag or `None` if it does not exist.
+    fn read_tag(
+        &self,
+        name: &str,
+    ) -> Result<Option<Hash>, EvsError> {
+        if !is_valid_ref_name(name) {
+            return Err(EvsError::InvalidTagName(name.to_owned()));
+        }
+
+        self.read_ref(TAGS_DIR, name)
+    }
+
+    /// Expects a valid name.
+    fn read_ref(
+        &self,
+        kind: &str,
+        name: &str,
+    ) -> Result<Option<Hash>, EvsError> {
+        let path = self.repository.join("refs").join(kind).join(name);
+
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path).map_err(|e| (e, path.clone()))?;
+
+        let Some(hash) = parse_hash(content.trim()) else {
+            return Err(EvsError::CorruptStateDetected(CorruptState::InvalidRef(
+                path,
+            )));
+        };
+
+        Ok(Some(hash))
+    }
+
+    fn write_branch(
+        &self,
+        name: &str,
+        target: Hash,
+    ) -> Result<(), EvsError> {
+        if !is_valid_ref_name(name) {
+            return Err(EvsError::InvalidBranchName(name.to_owned()));
+        }
+
+        self.write_ref(HEADS_DIR, name, target)
+    }
+
+    /// Expects a valid name.
+    fn write_ref(
+        &self,
+        kind: &str,
+        name: &str,
+        target: Hash,
+    ) -> Result<(), EvsError> {
+        let refs = self.repository.join("refs");
+        let dir = refs.join(kind);
+
+        fs::create_dir_all(&dir).map_err(|e| (e, dir.clone()))?;
+
+        let path = dir.join(name);
+
+        fs::write(&path, format!("{}\n", HashDisplay(&target))).map_err(|e| (e, path.clone()))?;
+
+        if self.info.shared() {
+            set_shared_mode(&refs)?;
+            set_shared_mode(&dir)?;
+            set_shared_mode(&path)?;
+        }
+
+        trace!(
+            "Moved {} ref {:?} to \"{}\".",
+            kind,
+            name,
+            HashDisplay(&target)
+        );
+
+        Ok(())
+    }
+
+    /// Lists the entries of the tree `r#ref` resolves to, directly or through a commit.
+    /// With `recursive`, subtrees are replaced by their entries with the path prefixed.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn ls_tree(
+        &self,
+        parent: &Span,
+        r#ref: &str,
+        recursive: bool,
+    ) -> Result<Vec<(Hash, String)>, EvsError> {
+        debug!("Repository::ls_tree(self, \"{}\", {})", r#ref, recursive);
+
+        let current = Span::current();
+
+        let (hash, obj) = self.lookup_peeled(&current, r#ref)?;
+
+        let tree = match obj {
+            Object::Null => self.store.empty_tree_hash(),
+            Object::Commit(commit) => commit.tree,
+            Object::Tree(_) => hash,
+            _ => return Err(EvsError::NotATree(hash)),
+        };
+
+        trace!("Listing tree \"{}\".", HashDisplay(&tree));
+
+        let mut entries = Vec::new();
+
+        self.ls_tree_(tree, "", recursive, &mut entries)?;
+
+        Ok(entries)
+    }
+
+    fn ls_tree_(
+        &self,
+        tree: Hash,
+        prefix: &str,
+        recursive: bool,
+        entries: &mut Vec<(Hash, String)>,
+    ) -> Result<(), EvsError> {
+        let current = Span::current();
+
+        check_depth(Path::new(prefix), self.max_depth)?;
+
+        let (hash, obj) = self
+            .store
+            .lookup(&current, &format!("{}", HashDisplay(&tree)))?;
+
+        let Object::Tree(items) = obj else {
+            return Err(EvsError::NotATree(hash));
+        };
+
+        for item in items {
+            let name = format!("{}{}", prefix, item.name);
+
+            if recursive && item.mode == MODE_DIR {
+                self.ls_tree_(item.content, &format!("{}/", name), recursive, entries)?;
+            } else {
+                entries.push((item.content, name));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the tree of `r#ref` as a tar archive to `output`, or to stdout if it is `None`.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn archive(
+        &self,
+        parent: &Span,
+        r#ref: &str,
+        output: Option<&Path>,
+    ) -> Result<Hash, EvsError> {
+        debug!("Repository::archive(self, \"{}\", {:?})", r#ref, output);
+
+        let current = Span::current();
+
+        let (hash, obj) = self.lookup_peeled(&current, r#ref)?;
+
+        let (tree, mtime) = match obj {
+            Object::Null => (self.store.empty_tree_hash(), UNIX_EPOCH),
+            Object::Commit(commit) => (commit.tree, commit.date),
+            Object::Tree(_) => (hash, UNIX_EPOCH),
+            _ => return Err(EvsError::NotATree(hash)),
+        };
+
+        let mtime = mtime
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |offset| offset.as_secs());
+
+        trace!("Archiving tree \"{}\".", HashDisplay(&tree));
+
+        let output_path = output.map_or_else(|| PathBuf::from("-"), Path::to_path_buf);
+
+        let writer: Box<dyn io::Write> = match output {
+            Some(path) => Box::new(File::create(path).map_err(|e| (e, path.to_path_buf()))?),
+            None => Box::new(stdout().lock()),
+        };
+
+        let mut builder = tar::Builder::new(writer);
+
+        self.archive_(&mut builder, tree, Path::new(""), mtime)
+            .map_err(|e| match e {
+                EvsError::IOError(e, _) => EvsError::IOError(e, output_path.clone()),
+                e => e,
+            })?;
+
+        builder
+            .into_inner()
+            .and_then(|mut writer| writer.flush())
+            .map_err(|e| (e, output_path))?;
+
+        trace!("Finished archive.");
+
+        Ok(tree)
+    }
+
+    fn archive_<W: io::Write>(
+        &self,
+        builder: &mut tar::Builder<W>,
+        tree: Hash,
+        prefix: &Path,
+        mtime: u64,
+    ) -> Result<(), EvsError> {
+        let current = Span::current();
+
+        check_depth(prefix, self.max_depth)?;
+
+        let (hash, obj) = self
+            .store
+            .lookup(&current, &format!("{}", HashDisplay(&tree)))?;
+
+        let Object::Tree(items) = obj else {
+            return Err(EvsError::NotATree(hash));
+        };
+
+        for item in items {
+            let path = prefix.join(&item.name);
+
+            let mut header = tar::Header::new_gnu();
+
+            header.set_mtime(mtime);
+
+            // Looks at the object instead of the mode, as trees without modes store directories as files.
+            match self
+                .store
+                .lookup_raw(&current, &format!("{}", HashDisplay(&item.content)))?
+                .1
+            {
+                Object::Tree(_) => {
+                    trace!("Archiving directory {:?}...", path);
+
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_mode(MODE_EXECUTABLE);
+                    header.set_size(0);
+
+                    builder
+                        .append_data(&mut header, &path, io::empty())
+                        .map_err(|e| (e, path.clone()))?;
+
+                    self.archive_(builder, item.content, &path, mtime)?;
+                }
+                Object::Symlink(target) => {
+                    trace!("Archiving symlink {:?}...", path);
+
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_mode(0o777);
+                    header.set_size(0);
+
+                    let target = str::from_utf8(&target)
+                        .map_err(|e| EvsError::PathError(e, target.clone()))?;
+
+                    builder
+                        .append_link(&mut header, &path, target)
+                        .map_err(|e| (e, path.clone()))?;
+                }
+                Object::Blob(content) => {
+                    trace!("Archiving file {:?}...", path);
+
+                    header.set_mode(item.mode);
+                    header.set_size(u64::try_from(content.len()).unwrap_or(u64::MAX));
+
+                    builder
+                        .append_data(&mut header, &path, content.as_slice())
+                        .map_err(|e| (e, path.clone()))?;
+                }
+                Object::ChunkList(chunks) => {
+                    trace!("Archiving chunked file {:?}...", path);
+
+                    let mut size = 0u64;
+
+                    for chunk in &chunks {
+                        let content = match self
+                            .store
+                            .lookup_raw(&current, &format!("{}", HashDisplay(chunk)))?
+                            .1
+                        {
+                            Object::Blob(content) => content,
+                            obj => {
+                                return Err(EvsError::CorruptStateDetected(
+                                    CorruptState::TypeMismatch(
+                                        item.content,
+                                        *chunk,
+                                        obj.kind(),
+                                        "a blob",
+                                    ),
+                                ));
+                            }
+                        };
+
+                        size =
+                            size.saturating_add(u64::try_from(content.len()).unwrap_or(u64::MAX));
+                    }
+
+                    header.set_mode(item.mode);
+                    header.set_size(size);
+
+                    let reader = self.chunk_reader(item.content, chunks);
+
+                    builder
+                        .append_data(&mut header, &path, reader)
+                        .map_err(|e| (e, path.clone()))?;
+                }
+                obj => {
+                    return Err(EvsError::CorruptStateDetected(
+                        CorruptState::NonContentInTree(tree, item.content, obj.kind()),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn tree_lookup(
+        &self,
+        parent: &Span,
+        tree: Hash,
+        path: Components,
+    ) -> Result<Option<(Hash, u32)>, EvsError> {
+        debug!(
+            "Repository::tree_lookup(\"{}\", {:?})",
+            HashDisplay(&tree),
+            path
+        );
+
+        self.tree_lookup_(tree, MODE_DIR, path)
+    }
+
+    /// The workspace relative route of `path`, which has to be inside the workspace but not in `.evs`.
+    fn workspace_route(
+        &self,
+        parent: &Span,
+        path: &Path,
+    ) -> Result<PathBuf, EvsError> {
+        let canon = partial_canonicalize(parent, path).map_err(|e| (e, path.to_path_buf()))?;
+
+        if !canon.starts_with(&self.workspace) || canon.starts_with(&self.repository) {
+            return Err(EvsError::PathOutsideOfRepo(canon));
+        }
+
+        // Checked above
+        Ok(canon.strip_prefix(&self.workspace).unwrap().to_path_buf())
+    }
+
+    /// Whether the entry at the workspace relative `route` differs from the one in each parent of `commit`.
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn changes_path(
+        &self,
+        parent: &Span,
+        commit: &Commit,
+        route: &Path,
+    ) -> Result<bool, EvsError> {
+        debug!("Repository::changes_path(self, {:?})", route);
+
+        let current = Span::current();
+
+        let entry = self
+            .tree_lookup_(commit.tree, MODE_DIR, route.components())?
+            .map(|(hash, _)| hash);
+
+        for parent in &commit.parents {
+            let tree = self.get_tree(&current, *parent)?;
+
+            if self
+                .tree_lookup_(tree, MODE_DIR, route.components())?
+                .map(|(hash, _)| hash)
+                == entry
+            {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Also returns the mode of the entry that was found.
+    fn tree_lookup_(
+        &self,
+        tree: Hash,
+        mode: u32,
+        mut path: Components,
+    ) -> Result<Option<(Hash, u32)>, EvsError> {
+        let current = Span::current();
+
+        let Some(name) = path.next() else {
+            trace!("Found \"{}\".", HashDisplay(&tree));
+
+            return Ok(Some((tree, mode)));
+        };
+
+        let name = name.as_os_str();
+
+        trace!("Looking for {:?}...", name);
+
+        match self
+            .store
+            .lookup(&current, &format!("{}", HashDisplay(&tree)))?
+            .1
+        {
+            Object::Tree(entries) => Ok(entries
+                .iter()
+                .find(|entry| *entry.name == *name)
+                .map(|entry| self.tree_lookup_(entry.content, entry.mode, path.clone()))
+                .transpose()?
+                .flatten()),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Takes every path from the side that changed it since `base`, both changing it differently is a conflict.
+fn merge_entries(
+    base: &AHashMap<PathBuf, (Hash, u32)>,
+    mut ours: AHashMap<PathBuf, (Hash, u32)>,
+    theirs: &AHashMap<PathBuf, (Hash, u32)>,
+) -> Result<AHashMap<PathBuf, (Hash, u32)>, EvsError> {
+    let mut conflicts = Vec::new();
+
+    let paths = base
+        .keys()
+        .chain(theirs.keys())
+        .filter(|path| !ours.contains_key(*path))
+        .cloned()
+        .chain(ours.keys().cloned())
+        .collect::<AHashSet<_>>();
+
+    for path in paths {
+        let (old, mine, other) = (base.get(&path), ours.get(&path), theirs.get(&path));
+
+        if mine == other || other == old {
+            continue;
+        }
+
+        if mine == old {
+            match other {
+                Some(entry) => ours.insert(path, *entry),
+                None => ours.remove(&path),
+            };
+        } else {
+            conflicts.push(path);
+        }
+    }
+
+    conflicts.sort();
+
+    match conflicts.into_iter().next() {
+        Some(path) => Err(EvsError::MergeConflict(path)),
+        None => Ok(ours),
+    }
+}
+
+/// Whether the workspace differs from `ds`, or has untracked files that `dd` would overwrite.
+#[allow(clippy::indexing_slicing, reason = "The keys are in the map as well.")]
+fn has_local_changes(
+    ds: &SideContent,
+    dl: &SideContent,
+    dd: &SideContent,
+) -> bool {
+    ds.0.difference(&dl.0).count() > 0
+        || dl.0.difference(&ds.0).any(|k| dd.0.contains(k))
+        || ds.0.intersection(&dl.0).any(|k| ds.1[k] != dl.1[k])
+}
+
+/// Expands `%h` (short hash), `%H` (hash), `%an`, `%ae` and `%ad` (author name, email and date),
+/// `%cn`, `%ce` and `%cd` (committer name, email and date) and `%s` (subject) in `format`.
+/// Unknown tokens are kept as they are.
+fn format_commit(
+    format: &str,
+    hash: &Hash,
+    commit: &Commit,
+) -> String {
+    let mut out = String::with_capacity(format.len());
+    let mut rest = format;
+
+    while let Some(index) = rest.find('%') {
+        let (before, token) = rest.split_at(index);
+
+        out.push_str(before);
+
+        let (expansion, len) = if token.starts_with("%h") {
+            (format!("{:.7}", HashDisplay(&hash[..4]).to_string()), 2)
+        } else if token.starts_with("%H") {
+            (HashDisplay(hash).to_string(), 2)
+        } else if token.starts_with("%an") {
+            (commit.author_name.clone(), 3)
+        } else if token.starts_with("%ae") {
+            (commit.author_email.clone(), 3)
+        } else if token.starts_with("%ad") {
+            (
+                OffsetDateDisplay(&commit.author_date, commit.author_offset).to_string(),
+                3,
+            )
+        } else if token.starts_with("%cn") {
+            (commit.name.clone(), 3)
+        } else if token.starts_with("%ce") {
+            (commit.email.clone(), 3)
+        } else if token.starts_with("%cd") {
+            (
+                OffsetDateDisplay(&commit.date, commit.offset).to_string(),
+                3,
+            )
+        } else if token.starts_with("%s") {
+            (commit.msg.lines().next().unwrap_or("").to_owned(), 2)
+        } else {
+            ("%".to_owned(), 1)
+        };
+
+        out.push_str(&expansion);
+
+        // The token is at least as long as what it starts with
+        rest = token.get(len..).unwrap();
+    }
+
+    out.push_str(rest);
+
+    out
+}
+
+/// A record of `log --porcelain`, see `Repository::log`.
+fn porcelain_commit(
+    hash: &Hash,
+    commit: &Commit,
+) -> String {
+    format!(
+        "{}\0{}\0{}\0{}\0{}\0{}\0{}\0{}\0{}",
+        HashDisplay(hash),
+        commit
+            .parents
+            .iter()
+            .map(|parent| HashDisplay(parent).to_string())
+            .collect::<Vec<_>>()
+            .join(" "),
+        commit.author_name,
+        commit.author_email,
+        OffsetDateDisplay(&commit.author_date, commit.author_offset),
+        commit.name,
+        commit.email,
+        OffsetDateDisplay(&commit.date, commit.offset),
+        commit.msg.lines().next().unwrap_or("")
+    )
+}
+
+/// The records of `status --porcelain`, see `Repository::status`.
+fn print_porcelain_status(
+    stage_added: &[&PathBuf],
+    stage_modified: &[&PathBuf],
+    stage_removed: &[&PathBuf],
+    untracked: &[&PathBuf],
+    local_modified: &[&PathBuf],
+    local_removed: &[&PathBuf],
+) {
+    let mut codes = AHashMap::<&PathBuf, [char; 2]>::new();
+
+    for (paths, side, code) in [
+        (stage_added, 0, 'A'),
+        (stage_modified, 0, 'M'),
+        (stage_removed, 0, 'D'),
+        (local_modified, 1, 'M'),
+        (local_removed, 1, 'D'),
+    ] {
+        for &path in paths {
+            if let Some(slot) = codes.entry(path).or_insert(['.'; 2]).get_mut(side) {
+                *slot = code;
+            }
+        }
+    }
+
+    let mut records = codes
+        .into_iter()
+        .map(|(path, code)| (path, code.iter().collect::<String>()))
+        .chain(untracked.iter().map(|&path| (path, "??".to_owned())))
+        .collect::<Vec<_>>();
+
+    records.sort_unstable();
+
+    let mut stdout = stdout().lock();
+
+    for (path, code) in records {
+        let _ = writeln!(stdout, "{}\0{}", code, path.display());
+    }
+}
+
+/// Refuses trees that would write into `.evs` or outside of the workspace.
+fn check_protected(dd: &SideContent) -> Result<(), EvsError> {
+    if let Some(path) = dd.0.iter().find(|path| {
+        path.starts_with(".evs")
+            || !path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+    }) {
+        return Err(EvsError::ProtectedPath(path.clone()));
+    }
+
+    Ok(())
+}
+
+/// Writes a file or symlink of the workspace, replacing a symlink instead of writing through it.
+fn write_workspace_file(
+    file: &Path,
+    content: &[u8],
+    symlink: bool,
+) -> Result<(), EvsError> {
+    if fs::symlink_metadata(file).is_ok_and(|m| m.is_symlink()) {
+        trace!("Removing old symlink {:?}...", file);
+
+        fs::remove_file(file).map_err(|e| (e, file.to_path_buf()))?;
+    }
+
+    if symlink {
+        create_symlink(content, file)
+    } else {
+        fs::write(file, content).map_err(|e| (e, file.to_path_buf()).into())
+    }
+}
+
+/// Counts the steps of a suffix like `~2^~`, where `^` and a bare `~` go back one commit.
+fn parse_ancestry(steps: &str) -> Result<usize, EvsError> {
+    let mut back_count = 0usize;
+
+    let mut chars = steps.chars().peekable();
+
+    while let Some(step) = chars.next() {
+        let count = match step {
+            '^' => 1,
+            '~' => {
+                let mut digits = String::new();
+
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(digit);
+                }
+
+                if digits.is_empty() {
+                    1
+                } else {
+                    digits
+                        .parse::<usize>()
+                        .map_err(EvsError::IntegerParseError)?
+                }
+            }
+            _ => return Err(EvsError::InvalidAncestry(steps.to_owned())),
+        };
+
+        back_count = back_count.saturating_add(count);
+    }
+
+    trace!("Parsed {:?} as {} step(s) back.", steps, back_count);
+
+    Ok(back_count)
+}
+
+/// Branch and tag names are single path components that cannot be confused with other refs.
+fn is_valid_ref_name(name: &str) -> bool {
+    !(name.is_empty()
+        || name == "HEAD"
+        || name.starts_with('.')
+        || name.contains(['/', '\\', '~', '^', ':', ' '])
+        || name.chars().any(char::is_control))
+}
+
+/// Identifies the ignores and overrides a directory is hashed with, see `Subtrees`.
+fn filter_key(
+    ignores: &IgnoreMatcher,
+    overrides: &AHashSet<PathBuf>,
+) -> u64 {
+    let mut overrides = overrides.iter().collect::<Vec<_>>();
+
+    overrides.sort_unstable();
+
+    RandomState::with_seeds(0, 0, 0, 0).hash_one((ignores, overrides))
+}
+
+/// Sorts the entries by name and fails if two of them share a name.
+fn check_duplicates(items: &mut [TreeEntry]) -> Result<(), EvsError> {
+    items.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if let Some(name) = items.windows(2).find_map(|w| match w {
+        [a, b] if a.name == b.name => Some(a.name.clone()),
+        _ => None,
+    }) {
+        return Err(EvsError::DuplicateTreeEntry(name));
+    }
+
+    Ok(())
+}
+
+impl<S: ObjectStore> Drop for Repository<S> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Err(err) = self.write_info() {
+            error!("Writing back Repository Info failed: {}", err);
+        }
+
+        if let Some(index) = &self.index
+            && let Err(err) = index.save(&Span::current())
+        {
+            error!("Writing back the index failed: {}", err);
+        }
+    }
+}
+
+/// Follows the magic and the version of a bundle, see `Repository::bundle`.
+#[derive(Serialize, Deserialize, Debug)]
+struct BundleHeader {
+    algo: HashAlgo,
+    /// The branch the bundled ref named, if any.
+    branch: Option<String>,
+    target: Hash,
+    count: u64,
+}
+
+/// Writes `data` prefixed with its length.
+fn write_bundle_chunk<W: io::Write>(
+    writer: &mut W,
+    data: &[u8],
+) -> io::Result<()> {
+    writer.write_all(&u64::try_from(data.len()).unwrap_or(u64::MAX).to_le_bytes())?;
+
+    writer.write_all(data)
+}
+
+/// Reads data written by `write_bundle_chunk`, without trusting the length for the allocation.
+fn read_bundle_chunk<R: io::Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut length = [0; size_of::<u64>()];
+
+    reader.read_exact(&mut length)?;
+
+    let length = u64::from_le_bytes(length);
+
+    let mut data = Vec::new();
+
+    reader.by_ref().take(length).read_to_end(&mut data)?;
+
+    if u64::try_from(data.len()).unwrap_or(u64::MAX) != length {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(data)
+}
+
+/// The blobs verified by the last successful check, valid as long as the store does not shrink.
+/// Only blobs are recorded, because everything else has to be read again anyway to follow its references.
+#[derive(Serialize, Deserialize, Debug)]
+struct CheckCache {
+    count: usize,
+    blobs: AHashSet<Hash>,
+}
+
+/// Reads the blobs of a chunk list one after another, holding only one of them in memory.
+/// Errors of the store are wrapped in the returned `io::Error`.
+pub struct ChunkReader<'a, S: ObjectStore> {
+    store: &'a S,
+    list: Hash,
+    chunks: vec::IntoIter<Hash>,
+    current: io::Cursor<Vec<u8>>,
+}
+
+impl<S: ObjectStore> io::Read for ChunkReader<'_, S> {
+    #[inline]
+    fn read(
+        &mut self,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        loop {
+            let read = self.current.read(buf)?;
+
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+
+            let Some(chunk) = self.chunks.next() else {
+                return Ok(0);
+            };
+
+            match self
+                .store
+                .lookup_raw(&Span::current(), &format!("{}", HashDisplay(&chunk)))
+            {
+                Ok((_, Object::Blob(content))) => self.current = io::Cursor::new(content),
+                Ok((_, obj)) => {
+                    return Err(io::Error::other(EvsError::CorruptStateDetected(
+                        CorruptState::TypeMismatch(self.list, chunk, obj.kind(), "a blob"),
+                    )));
+                }
+                Err(e) => return Err(io::Error::other(e)),
+            }
+        }
+    }
+}
+
+/// The store error wrapped in `e` by a `ChunkReader`, or `e` itself as failing on `path`.
+fn chunk_error(
+    e: io::Error,
+    path: &Path,
+) -> EvsError {
+    e.downcast::<EvsError>()
+        .unwrap_or_else(|e| (e, path.to_path_buf()).into())
+}
+
+/// Where HEAD points, serialized untagged so repositories with a bare hash still load.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Head {
+    Detached(Hash),
+    /// The name of the branch, `refs/heads/<name>` in the repository.
+    Symbolic(String),
+}
+
+impl Display for Head {
+    #[inline]
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            Head::Detached(hash) => write!(f, "{}", HashDisplay(hash)),
+            Head::Symbolic(name) => write!(f, "ref: refs/heads/{name}"),
+        }
+    }
+}
+
+/// What `Repository::check_repair` did.
+#[derive(Debug)]
+pub struct CheckRepair {
+    /// The names of the objects moved into `.evs/corrupt`, with why they could not be read.
+    pub quarantined: Vec<(OsString, EvsError)>,
+    /// Required objects that are missing, including quarantined ones.
+    pub missing: AHashSet<Hash>,
+}
+
+/// Who wrote the change of a commit, if that is not the committer.
+#[derive(Debug, Clone)]
+pub struct Author {
+    pub name: String,
+    pub email: String,
+    pub date: OffsetDateTime,
+}
+
+/// What `Repository::open` tolerates, so the recovery commands can open a damaged repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Requires the store and the staged tree.
+    Normal,
+    /// Does not require the staged tree, for the commands checking or replacing it.
+    SkipStage,
+    /// Also recreates a missing store, for `repair-store`.
+    RepairStore,
+}
+
+/// What `Repository::reset` moves besides HEAD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Only HEAD.
+    Soft,
+    /// HEAD and the stage.
+    Mixed,
+    /// HEAD, the stage and the workspace.
+    Hard,
+}
+
+impl ResetMode {
+    /// Picks the mode from the mutually exclusive command line flags.
+    #[inline]
+    #[must_use]
+    pub fn from_flags(
+        soft: bool,
+        hard: bool,
+    ) -> ResetMode {
+        if soft {
+            ResetMode::Soft
+        } else if hard {
+            ResetMode::Hard
+        } else {
+            ResetMode::Mixed
+        }
+    }
+}
+
+/// All of the info about the repository.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RepositoryInfo {
+    head: Head,
+    stage: Hash,
+    #[serde(skip)]
+    modified: bool,
+    /// Seconds since the epoch, 0 for repositories created before this was recorded.
+    #[serde(default)]
+    created: i64,
+    /// Whether new files and directories are made group-writable.
+    #[serde(default)]
+    shared: bool,
+    /// The commit HEAD resolves to, read from the branch if HEAD is symbolic.
+    #[serde(skip)]
+    head_hash: Hash,
+    /// Older repositories always use SHA-256.
+    #[serde(default)]
+    hash_algo: HashAlgo,
+}
+
+impl RepositoryInfo {
+    #[inline]
+    #[must_use]
+    pub fn head_ref(&self) -> &Head {
+        &self.head
+    }
+
+    /// The branch HEAD points at, `None` if HEAD is detached.
+    #[inline]
+    #[must_use]
+    pub fn branch(&self) -> Option<&str> {
+        match &self.head {
+            Head::Detached(_) => None,
+            Head::Symbolic(name) => Some(name),
+        }
+    }
+
+    /// Attaches HEAD to a branch or detaches it at the current commit.
+    #[inline]
+    pub fn set_branch(
+        &mut self,
+        new_branch: Option<String>,
+    ) {
+        let new_head = match new_branch {
+            Some(name) => Head::Symbolic(name),
+            None => Head::Detached(self.head_hash),
+        };
+
+        self.modified |= self.head != new_head;
+        self.head = new_head;
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn shared(&self) -> bool {
+        self.shared
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn hash_algo(&self) -> HashAlgo {
+        self.hash_algo
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn created(&self) -> Option<SystemTime> {
+        let secs = u64::try_from(self.created).ok().filter(|s| *s != 0)?;
+
+        UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn head(&self) -> Hash {
+        self.head_hash
+    }
+
+    /// Only moves HEAD itself, the branch is moved by `Repository::set_head`.
+    #[inline]
+    pub fn set_head(
+        &mut self,
+        new_head: Hash,
+    ) {
+        if let Head::Detached(hash) = &mut self.head {
+            self.modified |= *hash != new_head;
+            *hash = new_head;
+        }
+
+        self.head_hash = new_head;
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn stage(&self) -> Hash {
+        self.stage
+    }
+
+    #[inline]
+    pub fn set_stage(
+        &mut self,
+        new_stage: Hash,
+    ) {
+        self.modified |= self.stage != new_stage;
+        self.stage = new_stage;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, File},
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
+    };
+
+    use ahash::{AHashMap, AHashSet};
+    use glob::Pattern;
+    use time::OffsetDateTime;
+    use tracing::Span;
+
+    use crate::{
+        diff::DiffSide,
+        error::{CorruptState, EvsError},
+        objects::{MODE_EXECUTABLE, MODE_FILE, MODE_SYMLINK, Object, TreeEntry},
+        store::{Hash, HashAlgo, HashDisplay, ObjectStore as _},
+        testing::{TempDir, add, cli, commit_file, mem_repo, repo},
+        util::set_file_mode,
+    };
+
+    use super::{
+        DEFAULT_BRANCH, FORMAT_VERSION, MAX_MESSAGE_LENGTH, OpenMode, Repository, RepositoryInfo,
+        Subtrees, check_duplicates,
+    };
+
+    fn name(hash: Hash) -> String {
+        format!("{}", HashDisplay(&hash))
+    }
+
+    #[test]
+    fn sub_of_an_unstaged_path_is_an_error() {
+        let (dir, mut repo) = repo();
+
+        commit_file(&dir, &mut repo, "a", "1");
+
+        let span = Span::current();
+
+        for path in ["missing", "missing/below", "a/below"] {
+            let path = repo.workspace.join(path);
+
+            assert!(
+                matches!(
+                    repo.sub(&span, &[&path], true, true, true),
+                    Err(EvsError::PathNotInStage(_))
+                ),
+                "{path:?} is not in the stage"
+            );
+        }
+    }
+
+    #[test]
+    fn history_over_a_mem_store() {
+        let (dir, mut repo) = mem_repo();
+
+        let first = commit_file(&dir, &mut repo, "a", "1");
+        let second = commit_file(&dir, &mut repo, "b", "2");
+
+        let span = Span::current();
+
+        assert_eq!(
+            repo.count(&span, name(first), name(second)).unwrap(),
+            1,
+            "the second commit follows the first"
+        );
+        assert!(
+            repo.store
+                .check(&span, AHashSet::new(), &[second], false)
+                .is_ok_and(|report| report.found.contains(&repo.info.stage())),
+            "the stage is in memory and reachable from HEAD"
+        );
+        assert!(
+            !repo.repository.join("store").exists(),
+            "no object touches the filesystem"
+        );
+    }
+
+    #[test]
+    fn graph_contains_the_edges_of_a_commit() {
+        let (dir, mut repo) = repo();
+
+        let span = Span::current();
+
+        let head = commit_file(&dir, &mut repo, "a", "1");
+        let tree = repo.get_tree(&span, head).unwrap();
+        let blob = repo
+            .store
+            .insert(&span, Object::Blob(b"1".to_vec()))
+            .unwrap();
+
+        let outside = TempDir::new();
+        let output = outside.path().join("graph.dot");
+
+        repo.graph(&span, &output, &cli(["status"])).unwrap();
+
+        let dot = fs::read_to_string(&output).unwrap();
+
+        for (edge, what) in [
+            (
+                format!(
+                    "\"{}\" -> \"{}\" [label=\"parent\"];",
+                    name(head),
+                    name(repo.store.null_hash())
+                ),
+                "commit to parent",
+            ),
+            (
+                format!("\"{}\" -> \"{}\" [label=\"tree\"];", name(head), name(tree)),
+                "commit to tree",
+            ),
+            (
+                format!("\"{}\" -> \"{}\" [label=\"a\"];", name(tree), name(blob)),
+                "tree to blob",
+            ),
+        ] {
+            assert!(dot.contains(&edge), "the {what} edge is in the graph");
+        }
+    }
+
+    #[test]
+    fn commit_rejects_long_messages_and_invalid_emails() {
+        let (dir, mut repo) = repo();
+
+        dir.write("a", "1");
+        add(&mut repo, "");
+
+        let head = repo.info.head();
+
+        let mut try_commit = |message: String, email: &str, unlimited: bool| {
+            repo.commit(
+                &Span::current(),
+                None,
+                message,
+                "Test".to_owned(),
+                email.to_owned(),
+                OffsetDateTime::UNIX_EPOCH,
+                None,
+                unlimited,
+                true,
+                false,
+                false,
+                &cli(["commit"]),
+            )
+        };
+
+        let long = "x".repeat(MAX_MESSAGE_LENGTH + 1);
+
+        assert!(
+            matches!(
+                try_commit(long.clone(), "test@example.com", false),
+                Err(EvsError::FieldTooLong("commit message", len, MAX_MESSAGE_LENGTH))
+                    if len == long.len()
+            ),
+            "the message is over the limit"
+        );
+        assert!(
+            matches!(
+                try_commit("short".to_owned(), "nobody", false),
+                Err(EvsError::InvalidEmail(email)) if email == "nobody"
+            ),
+            "the email has no @"
+        );
+        assert!(
+            try_commit(long, "test@example.com", true).is_ok(),
+            "--unlimited lifts the limit"
+        );
+        assert_ne!(repo.info.head(), head, "only the last commit moved HEAD");
+    }
+
+    #[test]
+    fn init_into_a_missing_nested_path_needs_parents() {
+        let dir = TempDir::new();
+        let nested = dir.path().join("a").join("b");
+
+        let create = |parents| {
+            Repository::create(
+                &Span::current(),
+                &nested,
+                parents,
+                false,
+                HashAlgo::Sha256,
+                &cli(["init"]),
+            )
+        };
+
+        assert!(
+            matches!(create(false), Err(EvsError::MissingWorkspace(path)) if path == nested),
+            "the workspace is missing"
+        );
+        assert!(!nested.exists(), "nothing was created");
+
+        let repo = create(true).unwrap();
+
+        assert_eq!(
+            repo.workspace,
+            nested.canonicalize().unwrap(),
+            "the workspace was created"
+        );
+        assert!(nested.join(".evs").is_dir(), "the repository was created");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_directories_fail_or_are_skipped() {
+        use std::{io::ErrorKind, os::unix::fs::PermissionsExt as _, sync::Mutex};
+
+        let (dir, mut repo) = repo();
+
+        dir.write("open/a", "1");
+        let locked = dir.write("locked/b", "2").parent().unwrap().to_path_buf();
+
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+        if fs::read_dir(&locked).is_ok() {
+            // Running with privileges that ignore permissions, nothing to observe.
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+            return;
+        }
+
+        let try_add = |repo: &mut Repository, skipped| {
+            repo.add(
+                &Span::current(),
+                repo.workspace.clone(),
+                &AHashSet::new(),
+                skipped,
+                &Subtrees::default(),
+                &cli(["add"]),
+            )
+        };
+
+        let strict = try_add(&mut repo, None);
+        let skipped = Mutex::new(vec![]);
+        let skipping = try_add(&mut repo, Some(&skipped));
+
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert!(
+            matches!(strict, Err(EvsError::IOError(e, _)) if e.kind() == ErrorKind::PermissionDenied),
+            "the strict policy fails on the unreadable directory"
+        );
+        assert!(skipping.is_ok(), "the skip policy carries on");
+        assert_eq!(
+            skipped.into_inner().unwrap(),
+            vec![locked],
+            "the unreadable directory is recorded"
+        );
+
+        let (_, Object::Tree(items)) = repo
+            .store
+            .lookup(&Span::current(), &name(repo.info.stage()))
+            .unwrap()
+        else {
+            unreachable!("the stage is a tree");
+        };
+
+        assert!(
+            items.iter().any(|item| item.name == "open")
+                && items.iter().all(|item| item.name != "locked"),
+            "only the readable directory is staged"
+        );
+    }
+
+    #[test]
+    fn excluded_directories_are_left_out_of_status_and_diff() {
+        let (dir, mut repo) = repo();
+
+        dir.write("dir/a", "1");
+        dir.write("dir/b", "1");
+        commit_file(&dir, &mut repo, "keep", "1");
+
+        dir.write("dir/b", "2");
+        add(&mut repo, "dir/b");
+        dir.write("dir/a", "2");
+        dir.write("dir/c", "2");
+        dir.write("keep", "2");
+
+        let excludes = [Pattern::new("dir").unwrap()];
+        let in_dir = |paths: &AHashSet<PathBuf>| paths.iter().any(|p| p.starts_with("dir"));
+
+        let [.., all] = repo
+            .status_sides(&Span::current(), &[], &cli(["status"]))
+            .unwrap();
+
+        assert!(in_dir(&all.0), "the directory is there without excludes");
+
+        let sides = repo
+            .status_sides(&Span::current(), &excludes, &cli(["status"]))
+            .unwrap();
+
+        assert!(
+            sides.iter().all(|side| !in_dir(&side.0)),
+            "status leaves out the excluded directory"
+        );
+        assert!(
+            sides.iter().all(|side| side.0.contains(Path::new("keep"))),
+            "status keeps the other files"
+        );
+
+        let (lhs, rhs) = DiffSide::read_both(
+            DiffSide::Tree(repo.get_tree(&Span::current(), repo.info.head()).unwrap()),
+            DiffSide::Local(repo.workspace.clone()),
+            &Span::current(),
+            &repo.store,
+            [PathBuf::new()],
+            [],
+            &excludes,
+            repo.max_depth,
+        )
+        .unwrap();
+
+        assert!(
+            !in_dir(&lhs.0) && !in_dir(&rhs.0),
+            "diff leaves out the excluded directory"
+        );
+        assert!(
+            lhs.1.get(Path::new("keep")) != rhs.1.get(Path::new("keep")),
+            "diff keeps the other changes"
+        );
+    }
+
+    fn walk_log(
+        repo: &Repository,
+        limit: usize,
+        depth: Option<usize>,
+    ) -> (Vec<Hash>, bool) {
+        let mut visited = vec![];
+
+        let truncated = repo
+            .walk_log(
+                &Span::current(),
+                "HEAD",
+                limit,
+                depth,
+                None,
+                |hash, _, _| {
+                    visited.push(*hash);
+                },
+            )
+            .unwrap();
+
+        (visited, truncated)
+    }
+
+    #[test]
+    fn log_depth_matches_limit_on_a_linear_chain() {
+        let (dir, mut repo) = repo();
+
+        let commits =
+            ["1", "2", "3", "4"].map(|content| commit_file(&dir, &mut repo, "a", content));
+
+        let (limited, limit_cut) = walk_log(&repo, 2, None);
+        let (deep, depth_cut) = walk_log(&repo, usize::MAX, Some(2));
+
+        assert_eq!(
+            limited,
+            [commits[3], commits[2]],
+            "the newest two commits are shown"
+        );
+        assert_eq!(deep, limited, "depth counts the same commits as limit");
+        assert!(limit_cut && depth_cut, "both cut the walk short");
+        assert_eq!(
+            walk_log(&repo, usize::MAX, Some(4)),
+            (commits.into_iter().rev().collect(), false),
+            "a depth of the whole chain shows everything"
+        );
+    }
+
+    #[test]
+    fn log_depth_follows_both_branches_of_a_merge() {
+        let (dir, mut repo) = repo();
+
+        let fork = commit_file(&dir, &mut repo, "a", "1");
+        let before = commit_file(&dir, &mut repo, "a", "2");
+        let main = commit_file(&dir, &mut repo, "a", "3");
+
+        dir.write("b", "1");
+        add(&mut repo, "b");
+
+        let side = try_commit(&mut repo, Some(vec![fork])).unwrap();
+        let merge = try_commit(&mut repo, Some(vec![main, side])).unwrap();
+
+        assert_eq!(
+            walk_log(&repo, 2, None),
+            (vec![merge, main], true),
+            "limit stops along the first parent"
+        );
+        assert_eq!(
+            walk_log(&repo, usize::MAX, Some(2)),
+            (vec![merge, main, side], true),
+            "depth reaches the head of both branches"
+        );
+        assert_eq!(
+            walk_log(&repo, usize::MAX, Some(3)),
+            (vec![merge, main, side, before, fork], false),
+            "the fork is one generation behind the side commit"
+        );
+    }
+
+    #[test]
+    fn chunked_files_are_streamed_into_the_workspace() {
+        let dir = TempDir::new();
+        let options = cli(["--chunk-size", "4", "status"]);
+        let span = Span::current();
+
+        let mut repo =
+            Repository::create(&span, dir.path(), false, false, HashAlgo::Sha256, &options)
+                .unwrap();
+
+        let first = commit_file(&dir, &mut repo, "big", "the first long content");
+        let second = commit_file(&dir, &mut repo, "big", "and then the second one");
+
+        let mut entries = AHashMap::new();
+
+        repo.tree_entries(
+            &span,
+            repo.get_tree(&span, second).unwrap(),
+            Path::new(""),
+            &mut entries,
+        )
+        .unwrap();
+
+        let &(content, _) = entries.get(Path::new("big")).unwrap();
+
+        assert!(
+            matches!(
+                repo.lookup_raw(&span, name(content)).unwrap(),
+                (_, Object::ChunkList(chunks)) if chunks.len() == 6
+            ),
+            "the file is stored in chunks"
+        );
+
+        repo.checkout(&span, name(first), true, false, &options)
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("big")).unwrap(),
+            "the first long content",
+            "checkout writes the chunks of the file"
+        );
+
+        dir.write("big", "changed");
+
+        assert_eq!(
+            repo.restore(&span, &[dir.path().join("big")], Some(&name(second)))
+                .unwrap(),
+            1,
+            "one file is restored"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("big")).unwrap(),
+            "and then the second one",
+            "restore writes the chunks of the file"
+        );
+    }
+
+    #[test]
+    fn count_linear_history() {
+        let (dir, mut repo) = repo();
+
+        let first = commit_file(&dir, &mut repo, "a", "1");
+        commit_file(&dir, &mut repo, "a", "2");
+        let third = commit_file(&dir, &mut repo, "a", "3");
+
+        let span = Span::current();
+
+        assert_eq!(
+            repo.count(&span, name(first), name(third)).unwrap(),
+            2,
+            "two commits follow the first"
+        );
+        assert_eq!(
+            repo.count(&span, name(repo.store.null_hash()), name(third))
+                .unwrap(),
+            3,
+            "the whole chain follows the root"
+        );
+        assert_eq!(
+            repo.count(&span, "HEAD", "HEAD").unwrap(),
+            0,
+            "a commit is no distance from itself"
+        );
+        assert!(
+            matches!(
+                repo.count(&span, name(third), name(first)),
+                Err(EvsError::NotAnAncestor(..))
+            ),
+            "the newest commit is not an ancestor of the first"
+        );
+    }
+
+    #[test]
+    fn count_merged_history() {
+        let (dir, mut repo) = repo();
+
+        let first = commit_file(&dir, &mut repo, "a", "1");
+        let main = commit_file(&dir, &mut repo, "a", "2");
+
+        dir.write("b", "1");
+        add(&mut repo, "b");
+
+        let side = try_commit(&mut repo, Some(vec![first])).unwrap();
+        let merge = try_commit(&mut repo, Some(vec![main, side])).unwrap();
+
+        let span = Span::current();
+
+        assert_eq!(
+            repo.count(&span, name(main), name(merge)).unwrap(),
+            2,
+            "the merge and the side commit are not reachable from main"
+        );
+        assert_eq!(
+            repo.count(&span, name(first), name(merge)).unwrap(),
+            3,
+            "both branches follow the fork"
+        );
+        assert!(
+            matches!(
+                repo.count(&span, name(side), name(main)),
+                Err(EvsError::NotAnAncestor(..))
+            ),
+            "the side branch is not an ancestor of main"
+        );
+    }
+
+    #[test]
+    fn gc_keeps_the_empty_tree_for_the_stage() {
+        let (dir, mut repo) = repo();
+
+        commit_file(&dir, &mut repo, "a", "1");
+
+        let span = Span::current();
+        let options = cli(["gc"]);
+
+        repo.gc(&span, Duration::ZERO, Duration::ZERO, true, &options)
+            .unwrap();
+
+        repo.sub(&span, &[repo.workspace.clone()], true, true, true)
+            .unwrap();
+
+        assert_eq!(
+            repo.info.stage(),
+            repo.store.empty_tree_hash(),
+            "the stage is empty"
+        );
+        assert!(
+            repo.store.contains(&repo.store.empty_tree_hash()),
+            "the empty tree survived gc"
+        );
+        assert!(
+            repo.store.contains(&repo.store.null_hash()),
+            "the null object survived gc"
+        );
+    }
+
+    #[test]
+    fn reset_stage_recovers_a_deleted_stage() {
+        let (dir, mut repo) = repo();
+
+        let head = commit_file(&dir, &mut repo, "a", "1");
+
+        dir.write("b", "2");
+        add(&mut repo, "b");
+
+        let span = Span::current();
+        let options = cli(["reset-stage", "--from-head"]);
+
+        let stage = repo.info.stage();
+
+        repo.store.remove(&span, stage).unwrap();
+
+        drop(repo);
+
+        assert!(
+            matches!(
+                Repository::open(&span, dir.path(), OpenMode::Normal, &options),
+                Err(EvsError::MissingStage(missing)) if missing == stage
+            ),
+            "the missing stage is detected on open"
+        );
+
+        let mut repo = Repository::open(&span, dir.path(), OpenMode::SkipStage, &options).unwrap();
+
+        let tree = repo.reset_stage(&span, &options).unwrap();
+
+        assert_eq!(
+            tree,
+            repo.get_tree(&span, head).unwrap(),
+            "the stage is rebuilt from HEAD"
+        );
+
+        drop(repo);
+
+        Repository::open(&span, dir.path(), OpenMode::Normal, &options).unwrap();
+    }
+
+    #[test]
+    fn reset_stage_reinserts_the_empty_tree_for_a_null_head() {
+        let (dir, mut repo) = repo();
+
+        let span = Span::current();
+        let options = cli(["reset-stage", "--from-head"]);
+
+        let empty = repo.store.empty_tree_hash();
+
+        repo.store.remove(&span, empty).unwrap();
+
+        assert_eq!(
+            repo.reset_stage(&span, &options).unwrap(),
+            empty,
+            "a null HEAD has the empty tree"
+        );
+        assert!(repo.store.contains(&empty), "the empty tree is back");
+
+        drop(repo);
+
+        Repository::open(&span, dir.path(), OpenMode::Normal, &options).unwrap();
+    }
+
+    #[test]
+    fn repair_store_recreates_a_deleted_store() {
+        let (dir, repo) = repo();
+
+        let span = Span::current();
+        let options = cli(["repair-store"]);
+
+        let store = repo.store.path().clone();
+
+        drop(repo);
+
+        fs::remove_dir_all(&store).unwrap();
+
+        assert!(
+            Repository::open(&span, dir.path(), OpenMode::Normal, &options).is_err(),
+            "a missing store is corrupt"
+        );
+
+        let repo = Repository::open(&span, dir.path(), OpenMode::RepairStore, &options).unwrap();
+
+        assert!(store.join("tmp").is_dir(), "the tmp directory is back");
+        assert!(store.join("pack").is_dir(), "the pack directory is back");
+
+        assert_eq!(
+            repo.repair_store(&span, &options).unwrap(),
+            (true, true),
+            "HEAD and the stage are bootstrap objects"
+        );
+
+        drop(repo);
+
+        Repository::open(&span, dir.path(), OpenMode::Normal, &options).unwrap();
+    }
+
+    #[test]
+    fn add_reuses_the_index_for_unchanged_files() {
+        let (dir, repo) = repo();
+
+        drop(repo);
+
+        let span = Span::current();
+        let options = cli(["add", "a"]);
+
+        let path = dir.write("a", "1");
+        let set_mtime = |age| {
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::now().checked_sub(age).unwrap())
+                .unwrap();
+        };
+
+        set_mtime(Duration::from_hours(1));
+
+        let mut reads = vec![];
+
+        for touch in [false, false, true] {
+            if touch {
+                set_mtime(Duration::from_mins(30));
+            }
+
+            let mut repo = Repository::open(&span, dir.path(), OpenMode::Normal, &options).unwrap();
+
+            add(&mut repo, "a");
+
+            reads.push(repo.store.files_read());
+        }
+
+        assert_eq!(reads, [1, 0, 1], "only new or touched files are read");
+    }
+
+    #[test]
+    fn check_duplicates_rejects_names_encoding_identically() {
+        // Workspace names are strict UTF-8 here, so no two directory entries can collide on disk
+        let entry = |name: &str, content| TreeEntry {
+            name: name.to_owned(),
+            content,
+            mode: MODE_FILE,
+        };
+
+        let (_dir, repo) = repo();
+
+        let null = repo.store.null_hash();
+        let empty = repo.store.empty_tree_hash();
+
+        let mut items = vec![entry("b", null), entry("a", empty)];
+
+        check_duplicates(&mut items).unwrap();
+
+        assert_eq!(
+            items.iter().map(|i| i.name.as_str()).collect::<Vec<_>>(),
+            ["a", "b"],
+            "entries are sorted by name"
+        );
+
+        let mut items = vec![entry("a", null), entry("b", null), entry("a", empty)];
+
+        assert!(
+            matches!(
+                check_duplicates(&mut items),
+                Err(EvsError::DuplicateTreeEntry(name)) if name == "a"
+            ),
+            "the duplicate name is reported"
+        );
+    }
+
+    #[test]
+    fn recompress_rewrites_each_object_once() {
+        let (dir, repo) = repo();
+
+        drop(repo);
+
+        let span = Span::current();
+        let content = "evs ".repeat(1024);
+
+        let head = {
+            let options = cli(["--compression", "0", "status"]);
+            let mut repo = Repository::open(&span, dir.path(), OpenMode::Normal, &options).unwrap();
+
+            commit_file(&dir, &mut repo, "a", &content)
+        };
+
+        let options = cli(["--compression", "9", "recompress"]);
+        let repo = Repository::open(&span, dir.path(), OpenMode::Normal, &options).unwrap();
+
+        let (count, before, after) = repo.store.recompress(&span).unwrap();
+
+        assert!(count > 0, "objects stored at level 0 are rewritten");
+        assert!(after < before, "level 9 is smaller than level 0");
+
+        assert_eq!(
+            repo.store.recompress(&span).unwrap(),
+            (0, after, after),
+            "objects at level 9 are skipped"
+        );
+
+        repo.check(&span, true, false, true).unwrap();
+
+        let (_, Object::Tree(items)) = repo
+            .store
+            .lookup(&span, &name(repo.get_tree(&span, head).unwrap()))
+            .unwrap()
+        else {
+            unreachable!("commits point at trees");
+        };
+
+        let blob = items.iter().find(|item| item.name == "a").unwrap().content;
+
+        assert!(
+            matches!(
+                repo.store.lookup(&span, &name(blob)).unwrap(),
+                (_, Object::Blob(data)) if data == content.as_bytes()
+            ),
+            "the blob still decodes"
+        );
+    }
+
+    #[test]
+    fn recompress_rewrites_packed_objects() {
+        let (dir, repo) = repo();
+
+        drop(repo);
+
+        let span = Span::current();
+        let content = "packed ".repeat(1024);
+
+        let blob = {
+            let options = cli(["--compression", "0", "status"]);
+            let repo = Repository::open(&span, dir.path(), OpenMode::Normal, &options).unwrap();
+
+            let blob = repo
+                .store
+                .insert(&span, Object::Blob(content.clone().into_bytes()))
+                .unwrap();
+
+            repo.store.pack(&span, &[blob]).unwrap();
+
+            blob
+        };
+
+        let options = cli(["--compression", "9", "recompress"]);
+        let repo = Repository::open(&span, dir.path(), OpenMode::Normal, &options).unwrap();
+
+        assert!(repo.store.is_packed(&blob), "the blob starts out packed");
+
+        let (count, before, after) = repo.store.recompress(&span).unwrap();
+
+        assert!(count > 0, "the packed blob is rewritten");
+        assert!(after < before, "level 9 is smaller than level 0");
+        assert!(repo.store.is_packed(&blob), "the blob stays packed");
+        assert_eq!(
+            fs::read_dir(repo.repository.join("store").join("pack"))
+                .unwrap()
+                .count(),
+            1,
+            "the old pack is replaced"
+        );
+        assert_eq!(
+            repo.store.recompress(&span).unwrap(),
+            (0, after, after),
+            "the rewritten pack is skipped"
+        );
+        assert!(
+            matches!(
+                repo.store.lookup(&span, &name(blob)).unwrap(),
+                (_, Object::Blob(data)) if data == content.as_bytes()
+            ),
+            "the blob still decodes"
+        );
+    }
+
+    /// Installs `script` as the hook `name`.
+    fn install_hook(
+        repo: &Repository,
+        name: &str,
+        script: &str,
+        executable: bool,
+    ) {
+        let path = repo.repository.join("hooks").join(name);
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, script).unwrap();
+
+        if executable {
+            set_file_mode(&path, MODE_EXECUTABLE).unwrap();
+        }
+    }
+
+    fn try_commit(
+        repo: &mut Repository,
+        parents: Option<Vec<Hash>>,
+    ) -> Result<Hash, EvsError> {
+        repo.commit(
+            &Span::current(),
+            parents,
+            "hooked".to_owned(),
+            "Test".to_owned(),
+            "test@example.com".to_owned(),
+            OffsetDateTime::UNIX_EPOCH,
+            None,
+            false,
+            true,
+            false,
+            false,
+            &cli(["commit"]),
+        )
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_pre_commit_hook_keeps_head() {
+        let (dir, mut repo) = repo();
+
+        let head = commit_file(&dir, &mut repo, "a", "1");
+
+        install_hook(&repo, "pre-commit", "#!/bin/sh\nexit 1\n", true);
+
+        dir.write("a", "2");
+        add(&mut repo, "a");
+
+        assert!(
+            matches!(
+                try_commit(&mut repo, None),
+                Err(EvsError::HookFailed("pre-commit", status)) if status.code() == Some(1i32)
+            ),
+            "the failing hook aborts the commit"
+        );
+        assert_eq!(repo.info.head(), head, "HEAD did not move");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_executable_hooks_are_skipped() {
+        let (dir, mut repo) = repo();
+
+        install_hook(&repo, "pre-commit", "#!/bin/sh\nexit 1\n", false);
+
+        dir.write("a", "1");
+        add(&mut repo, "a");
+
+        let commit = try_commit(&mut repo, None).unwrap();
+
+        assert_eq!(repo.info.head(), commit, "HEAD moved");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn hooks_run_unlocked_after_writing_back_the_info() {
+        let (dir, mut repo) = repo();
+
+        // The copy is only made if the hook can take the lock itself
+        install_hook(
+            &repo,
+            "pre-commit",
+            "#!/bin/sh\nexec flock -n .evs/lock cp .evs/lock hooked\n",
+            true,
+        );
+
+        dir.write("a", "1");
+        add(&mut repo, "a");
+
+        let stage = repo.info.stage();
 
-        self.show_(r#ref.as_ref(), options)
+        try_commit(&mut repo, None).unwrap();
+
+        let info: RepositoryInfo =
+            rmp_serde::from_slice(&fs::read(dir.path().join("hooked")).unwrap()).unwrap();
+
+        assert_eq!(info.stage(), stage, "the hook sees the staged tree");
+        assert_eq!(
+            repo.get_tree(&Span::current(), repo.info.head()).unwrap(),
+            stage,
+            "the commit has the staged tree"
+        );
     }
 
-    fn show_(
-        &self,
-        r#ref: &str,
-        options: &Cli,
-    ) -> Result<(), EvsError> {
-        let current = Span::current();
+    #[test]
+    fn add_reuses_directories_hashed_with_the_same_filters() {
+        let (dir, mut repo) = repo();
+
+        dir.write("d/a", "1");
+        dir.write("d/b", "2");
+        dir.write("c", "3");
+
+        let subtrees = Subtrees::default();
+        let add = |repo: &mut Repository, path: &str| {
+            repo.add(
+                &Span::current(),
+                repo.workspace.join(path),
+                &AHashSet::new(),
+                None,
+                &subtrees,
+                &cli(["add"]),
+            )
+            .unwrap();
+        };
 
-        let (hash, commit) = self.lookup(&current, r#ref)?;
+        add(&mut repo, "d");
 
-        trace!("Found commit \"{}\".", HashDisplay(&hash));
+        let reads = repo.store.files_read();
 
-        let commit = match commit {
-            Object::Null => return Ok(()),
-            Object::Commit(commit) => commit,
-            _ => return Err(EvsError::NotACommit(hash)),
+        add(&mut repo, "");
+
+        assert_eq!(
+            repo.store.files_read(),
+            reads.saturating_add(1),
+            "only the file outside of the hashed directory is read"
+        );
+
+        dir.write(".evsignore", "d/b\n");
+
+        add(&mut repo, "");
+
+        let (_, Object::Tree(items)) = repo
+            .store
+            .lookup(&Span::current(), &name(repo.info.stage()))
+            .unwrap()
+        else {
+            unreachable!("the stage is a tree");
         };
 
-        let rhs = DiffSide::Tree(commit.tree);
+        let d = items.iter().find(|item| item.name == "d").unwrap().content;
 
-        let lhs = DiffSide::Tree(self.get_tree(&current, commit.parent)?);
+        assert!(
+            matches!(
+                repo.store.lookup(&Span::current(), &name(d)).unwrap(),
+                (_, Object::Tree(items)) if items.iter().all(|item| item.name != "b")
+            ),
+            "new ignores are not answered from the memo"
+        );
+    }
 
-        trace!("Diffing...");
+    #[test]
+    fn gc_keeps_unreferenced_objects_within_the_grace_period() {
+        let (_dir, repo) = repo();
 
-        DiffSide::diff_with(
-            lhs,
-            rhs,
-            &current,
-            &self.store,
-            &[AsRef::<Path>::as_ref("").to_path_buf()],
-            &[],
-            options,
+        let span = Span::current();
+        let options = cli(["gc"]);
+
+        let blob = repo
+            .store
+            .insert(&span, Object::Blob(b"in flight".to_vec()))
+            .unwrap();
+
+        repo.gc(
+            &span,
+            Duration::from_hours(1),
+            Duration::ZERO,
+            true,
+            &options,
         )
-    }
+        .unwrap();
 
-    #[inline]
-    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn checkout<T: AsRef<str>>(
-        &mut self,
-        parent: &Span,
-        r#ref: T,
-        force: bool,
-        options: &Cli,
-    ) -> Result<Hash, EvsError> {
-        debug!("Repository::checkout(self, \"{}\")", r#ref.as_ref());
+        assert!(
+            repo.store.contains(&blob),
+            "a new object survives the grace period"
+        );
+
+        repo.gc(&span, Duration::ZERO, Duration::ZERO, true, &options)
+            .unwrap();
 
-        self.checkout_(r#ref.as_ref(), force, options)
+        assert!(
+            !repo.store.contains(&blob),
+            "an old unreferenced object is removed"
+        );
     }
 
-    #[allow(clippy::too_many_lines, reason = "This is fine.")]
-    fn checkout_(
-        &mut self,
-        r#ref: &str,
-        force: bool,
-        options: &Cli,
-    ) -> Result<Hash, EvsError> {
-        let current = Span::current();
+    #[test]
+    fn info_reports_a_fresh_repository() {
+        let (_dir, mut repo) = repo();
 
-        let (hash, _) = self.lookup(&current, r#ref)?;
+        let options = cli(["info"]);
 
-        trace!("Found commit \"{}\".", HashDisplay(&hash));
+        let mut out = vec![];
 
-        let dest_tree = self.get_tree(&current, hash)?;
+        repo.info_(&mut out, &options).unwrap();
 
-        trace!("Destination tree \"{}\".", HashDisplay(&dest_tree));
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().map(str::to_owned).collect::<Vec<_>>();
 
-        let mut src_tree = self.get_tree(&current, self.info.head())?;
+        assert!(
+            lines
+                .first()
+                .is_some_and(|l| l.starts_with("  Created at ") && !l.contains("unknown")),
+            "the creation time is known: {out}"
+        );
+        assert_eq!(
+            lines.get(1..6),
+            Some(
+                [
+                    format!("  Format version is {FORMAT_VERSION}"),
+                    "  Objects are named by SHA-256".to_owned(),
+                    format!("  Head is at \"{}\"", name(repo.store.null_hash())),
+                    format!("  on branch {DEFAULT_BRANCH:?}"),
+                    format!("  and stage is \"{}\"", name(repo.store.empty_tree_hash())),
+                ]
+                .as_slice()
+            ),
+            "the fields describe the fresh repository"
+        );
+        assert!(
+            lines
+                .get(6)
+                .is_some_and(|l| l.starts_with("  Store has 2 objects with size ")),
+            "the bootstrap objects are counted: {out}"
+        );
 
-        trace!("Source tree \"{}\".", HashDisplay(&src_tree));
+        repo.info.created = 0;
 
-        if src_tree != self.info.stage() {
-            if force
-                || confirmation!(
-                    false,
-                    "There are uncommitted changes, do you want to discard them?"
-                )?
-            {
-                warn!("Discarding current stage...");
+        let mut out = vec![];
 
-                src_tree = self.info.stage();
-            } else {
-                return Err(EvsError::UncommittedChanges);
-            }
-        }
+        repo.info_(&mut out, &options).unwrap();
 
-        let dd = DiffSide::Tree(dest_tree);
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .starts_with("  Created at an unknown time\n"),
+            "old repositories have no creation time"
+        );
+    }
 
-        let ds = DiffSide::Tree(src_tree);
+    #[test]
+    fn fast_check_is_invalidated_by_a_new_object() {
+        let (_dir, repo) = repo();
 
-        let dl = DiffSide::Local(self.workspace.clone());
+        let span = Span::current();
 
-        trace!("Prepared diffsides.");
+        assert_eq!(
+            repo.check(&span, false, true, false).unwrap(),
+            None,
+            "the first check runs"
+        );
+        assert!(
+            repo.check(&span, false, true, false).unwrap().is_some(),
+            "an unchanged store is skipped"
+        );
 
-        let global_filter = [AsRef::<Path>::as_ref("").to_path_buf()];
+        repo.store
+            .insert(&span, Object::Blob(b"new".to_vec()))
+            .unwrap();
 
-        let empty_set = AHashSet::new();
+        assert_eq!(
+            repo.check(&span, false, true, false).unwrap(),
+            None,
+            "a new object forces a full run"
+        );
+    }
 
-        let ignores = self.get_ignores(&current, options)?;
+    #[test]
+    fn checkout_clean_deletes_untracked_files() {
+        let (dir, mut repo) = repo();
 
-        let ds = ds.read(
-            &current,
-            "",
-            &self.store,
-            &global_filter,
-            &ignores,
-            &empty_set,
-        )?;
+        let span = Span::current();
+        let options = cli(["status"]);
 
-        let dl = dl.read(&current, "", &self.store, &global_filter, &ignores, &ds.0)?;
+        let first = commit_file(&dir, &mut repo, ".evsignore", "kept\n");
+        let second = commit_file(&dir, &mut repo, "a", "1");
 
-        let dd = dd.read(
-            &current,
-            "",
-            &self.store,
-            &global_filter,
-            &ignores,
-            &empty_set,
-        )?;
+        let extra = dir.write("extra", "untracked");
+        let kept = dir.write("kept", "ignored");
 
-        trace!("Read diffsides.");
+        repo.checkout(&span, name(first), true, false, &options)
+            .unwrap();
 
-        #[allow(clippy::indexing_slicing, reason = "The keys are in the map as well.")]
-        if ds.0.difference(&dl.0).count() > 0
-            || dl.0.difference(&ds.0).any(|k| dd.0.contains(k))
-            || ds.0.intersection(&dl.0).any(|k| ds.1[k] != dl.1[k])
-        {
-            if force
-                || confirmation!(
-                    false,
-                    "There are unstaged changes, do you want to discard them?"
-                )?
-            {
-                warn!("Discarding local changes...");
-            } else {
-                return Err(EvsError::UncommittedChanges);
-            }
-        }
+        assert!(extra.exists(), "untracked files are kept without clean");
+        assert!(!dir.path().join("a").exists(), "the first commit has no a");
 
-        self.info.set_head(hash);
+        repo.checkout(&span, name(second), true, true, &options)
+            .unwrap();
 
-        //TODO: SET BRANCH OR SOMETHING
+        assert!(!extra.exists(), "untracked files are deleted with clean");
+        assert!(kept.exists(), "ignored files survive clean");
+        assert!(dir.path().join("a").exists(), "the second commit has a");
+    }
 
-        self.info.set_stage(dest_tree);
+    #[test]
+    fn check_rejects_a_commit_whose_tree_is_a_blob() {
+        let (dir, mut repo) = repo();
 
-        trace!("Modified repository info.");
+        let span = Span::current();
 
-        for file in ds.0.difference(&dd.0) {
-            let file = self.workspace.join(file);
+        let head = commit_file(&dir, &mut repo, "a", "1");
 
-            trace!("Deleting file {:?}...", file);
+        let (_, Object::Commit(mut commit)) = repo.store.lookup(&span, &name(head)).unwrap() else {
+            unreachable!("HEAD is a commit");
+        };
 
-            fs::remove_file(&file).map_err(|e| (e, file.clone()))?;
+        let blob = repo
+            .store
+            .insert(&span, Object::Blob(b"not a tree".to_vec()))
+            .unwrap();
 
-            for ancestor in file.ancestors().skip(1) {
-                if let Ok(dir) = ancestor.read_dir()
-                    && dir.count() == 0
-                {
-                    trace!("Pruning empty dir {:?}...", ancestor);
+        commit.tree = blob;
 
-                    if fs::remove_dir(ancestor).is_err() {
-                        break;
-                    }
-                }
-            }
-        }
+        let bad = repo.store.insert(&span, Object::Commit(commit)).unwrap();
 
-        trace!("Deleted files...");
+        repo.set_head(bad).unwrap();
 
-        #[allow(clippy::indexing_slicing, reason = "The keys are in the map as well.")]
-        for file in dd.0.difference(&ds.0) {
-            let content = &dd.1[file];
+        assert!(
+            matches!(
+                repo.check(&span, false, false, true),
+                Err(EvsError::CorruptStateDetected(CorruptState::TypeMismatch(referrer, referenced, "blob", _)))
+                    if referrer == bad && referenced == blob
+            ),
+            "the blob is reported as the tree of the commit"
+        );
+    }
 
-            let file = self.workspace.join(file);
+    #[test]
+    fn check_cache_still_requires_verified_blobs() {
+        let (dir, mut repo) = repo();
 
-            // workspace is parent
-            let parent = file.parent().unwrap();
+        let span = Span::current();
 
-            trace!("Creating dir {:?}...", parent);
+        commit_file(&dir, &mut repo, "a", "1");
 
-            fs::create_dir_all(parent).map_err(|e| (e, file.clone()))?;
+        repo.check(&span, false, false, false).unwrap();
 
-            trace!("Creating file {:?}...", file);
+        commit_file(&dir, &mut repo, "b", "2");
 
-            fs::write(&file, content).map_err(|e| (e, file))?;
-        }
+        let blob = repo
+            .store
+            .insert(&span, Object::Blob(b"1".to_vec()))
+            .unwrap();
 
-        trace!("Created new files...");
+        repo.store.remove(&span, blob).unwrap();
 
-        #[allow(clippy::indexing_slicing, reason = "The keys are in the map as well.")]
-        for (file, _, content) in
-            ds.0.intersection(&dd.0)
-                .map(|k| (k, &ds.1[k], &dd.1[k]))
-                .filter(|(_, lhs, rhs)| lhs != rhs)
-        {
-            let file = self.workspace.join(file);
+        assert!(
+            matches!(
+                repo.check(&span, false, false, false),
+                Err(EvsError::CorruptStateDetected(CorruptState::MissingObjects(missing)))
+                    if missing == AHashSet::from([blob])
+            ),
+            "the cached blob is reported missing"
+        );
+    }
 
-            trace!("Modifying file {:?}...", file);
+    #[test]
+    fn sub_leaves_stage_and_workspace_alone_on_error() {
+        let (dir, mut repo) = repo();
 
-            fs::write(&file, content).map_err(|e| (e, file))?;
-        }
+        commit_file(&dir, &mut repo, "a", "1");
 
-        trace!("Modified files...");
+        let span = Span::current();
 
-        trace!("Checkout complete.");
+        let stage = repo.info.stage();
 
-        Ok(hash)
-    }
+        let paths = [repo.workspace.join("a"), repo.workspace.join("missing")];
 
-    #[inline]
-    #[must_use]
-    pub fn gc_roots(&self) -> Vec<Hash> {
-        vec![self.info.head(), self.info.stage()]
-    }
+        assert!(
+            matches!(
+                repo.sub(&span, &paths, true, false, true),
+                Err(EvsError::PathNotInStage(_))
+            ),
+            "the missing path fails the whole command"
+        );
+        assert_eq!(repo.info.stage(), stage, "the stage is unchanged");
+        assert!(dir.path().join("a").exists(), "nothing is deleted");
 
-    #[inline]
-    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn tree_lookup(
-        &self,
-        parent: &Span,
-        tree: Hash,
-        mut path: Components,
-    ) -> Result<Option<Hash>, EvsError> {
-        debug!(
-            "Repository::tree_lookup(\"{}\", {:?})",
-            HashDisplay(&tree),
-            path
+        repo.sub(&span, &paths[..1], true, false, true).unwrap();
+
+        assert_eq!(
+            repo.info.stage(),
+            repo.store.empty_tree_hash(),
+            "a is no longer staged"
         );
+        assert!(!dir.path().join("a").exists(), "a is deleted");
+    }
 
-        let current = Span::current();
+    #[test]
+    fn unbundle_rejects_a_diverged_branch_before_importing() {
+        let (other_dir, mut other) = repo();
+        let (dir, mut repo) = repo();
 
-        let Some(name) = path.next() else {
-            trace!("Found \"{}\".", HashDisplay(&tree));
+        let span = Span::current();
+        let options = cli(["status"]);
 
-            return Ok(Some(tree));
-        };
+        let bundled = commit_file(&dir, &mut repo, "a", "1");
+        commit_file(&other_dir, &mut other, "b", "2");
 
-        let name = name.as_os_str();
+        let outside = TempDir::new();
+        let bundle = outside.path().join("main.bundle");
 
-        trace!("Looking for {:?}...", name);
+        repo.bundle(&span, "HEAD", &bundle).unwrap();
 
-        match self
-            .store
-            .lookup(&current, &format!("{}", HashDisplay(&tree)))?
-            .1
-        {
-            Object::Tree(entries) => Ok(entries
-                .iter()
-                .find_map(|entry| (*entry.name == *name).then_some(entry.content))
-                .map(|content| self.tree_lookup(&current, content, path.clone()))
-                .transpose()?
-                .flatten()),
-            _ => Ok(None),
-        }
+        assert!(
+            matches!(
+                other.unbundle(&span, &bundle, None, &options),
+                Err(EvsError::BranchDiverged(branch)) if branch == DEFAULT_BRANCH
+            ),
+            "main has diverged"
+        );
+        assert!(
+            matches!(
+                other.store.lookup(&span, &name(bundled)),
+                Err(EvsError::ObjectNotInStore(_))
+            ),
+            "nothing was imported"
+        );
+
+        other
+            .unbundle(&span, &bundle, Some("imported"), &options)
+            .unwrap();
+
+        assert_eq!(
+            other.read_branch("imported").unwrap(),
+            Some(bundled),
+            "another branch is created"
+        );
     }
-}
 
-impl Drop for Repository {
-    #[inline]
-    fn drop(&mut self) {
-        let r = || -> Result<(), io::Error> {
-            if self.info.modified {
-                self.lockfile.set_len(0)?;
-                self.lockfile.seek(SeekFrom::Start(0))?;
-                self.lockfile
-                    .write_all(&rmp_serde::to_vec(&self.info).map_err(|_e| {
-                        io::Error::new(ErrorKind::InvalidData, "encoder failed")
-                    })?)?;
-            }
+    #[cfg(unix)]
+    #[test]
+    fn self_referential_symlinks_are_stored_not_followed() {
+        use std::os::unix::fs::symlink;
 
-            Ok(())
-        }();
+        let (dir, mut repo) = repo();
 
-        if let Err(err) = r {
-            error!("Writing back Repository Info failed: {}", err);
-        }
-    }
-}
+        dir.write("d/file", "content");
 
-/// All of the info about the repository.
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RepositoryInfo {
-    head: Hash,
-    stage: Hash,
-    #[serde(skip)]
-    modified: bool,
-}
+        symlink(".", dir.path().join("d/self")).unwrap();
+        symlink("..", dir.path().join("d/up")).unwrap();
 
-impl RepositoryInfo {
-    #[inline]
-    #[must_use]
-    pub fn head(&self) -> Hash {
-        self.head
-    }
+        add(&mut repo, "");
 
-    #[inline]
-    pub fn set_head(
-        &mut self,
-        new_head: Hash,
-    ) {
-        self.modified = self.head != new_head;
-        self.head = new_head;
+        let mut entries = AHashMap::new();
+
+        repo.tree_entries(
+            &Span::current(),
+            repo.info.stage(),
+            Path::new(""),
+            &mut entries,
+        )
+        .unwrap();
+
+        assert_eq!(
+            entries.len(),
+            3,
+            "only the file and the two links are staged"
+        );
+        assert!(
+            ["d/self", "d/up"].iter().all(|link| entries
+                .get(Path::new(link))
+                .is_some_and(|&(_, mode)| mode == MODE_SYMLINK)),
+            "the links are stored as symlinks"
+        );
     }
 
-    #[inline]
-    #[must_use]
-    pub fn stage(&self) -> Hash {
-        self.stage
+    #[test]
+    fn commits_without_changes_need_allow_empty() {
+        let (dir, mut repo) = repo();
+
+        let head = commit_file(&dir, &mut repo, "a", "1");
+
+        assert!(
+            matches!(try_commit(&mut repo, None), Err(EvsError::NothingToCommit)),
+            "the stage equals the tree of HEAD"
+        );
+        assert_eq!(repo.info.head(), head, "HEAD did not move");
+
+        let empty = repo
+            .commit(
+                &Span::current(),
+                None,
+                "empty".to_owned(),
+                "Test".to_owned(),
+                "test@example.com".to_owned(),
+                OffsetDateTime::UNIX_EPOCH,
+                None,
+                false,
+                true,
+                true,
+                false,
+                &cli(["commit"]),
+            )
+            .unwrap();
+
+        assert_ne!(empty, head, "--allow-empty records a new commit");
     }
 
-    #[inline]
-    pub fn set_stage(
-        &mut self,
-        new_stage: Hash,
-    ) {
-        self.modified = self.stage != new_stage;
-        self.stage = new_stage;
+    #[test]
+    fn open_rejects_a_truncated_lockfile() {
+        let (dir, repo) = repo();
+
+        let lockfile = repo.repository.join("lock");
+
+        drop(repo);
+
+        let mut content = fs::read(&lockfile).unwrap();
+
+        content.truncate(content.len() / 2);
+
+        fs::write(&lockfile, content).unwrap();
+
+        assert!(
+            matches!(
+                Repository::open(
+                    &Span::current(),
+                    dir.path(),
+                    OpenMode::Normal,
+                    &cli(["status"])
+                ),
+                Err(EvsError::RepositoryInfoCorrupt(_))
+            ),
+            "the truncated info does not decode"
+        );
     }
 }
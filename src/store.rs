@@ -1,34 +1,88 @@
 use std::{
-    fmt::{self, Display, Formatter},
-    fs::{self, OpenOptions},
-    io::{Read as _, Write as _},
-    path::PathBuf,
-    sync::Mutex,
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    fmt::{self, Debug, Display, Formatter},
+    fs::{self, DirBuilder, File, OpenOptions},
+    io::{self, BufRead as _, BufReader, BufWriter, Read as _, Seek as _, SeekFrom, Write as _},
+    num::NonZeroUsize,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+    sync::{Mutex, RwLock},
     thread,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use ahash::{AHashSet, HashSet};
-use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ahash::{AHashMap, AHashSet, HashSet};
+use clap::ValueEnum;
+use flate2::{Compression, GzBuilder, read::GzDecoder};
+use lru::LruCache;
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
 use rayon::{
-    iter::{IntoParallelRefIterator as _, ParallelBridge as _, ParallelIterator as _},
+    iter::{IntoParallelIterator as _, IntoParallelRefIterator as _, ParallelIterator as _},
     join,
 };
+use serde::{Deserialize, Serialize};
 use sha2::{Digest as _, Sha256};
 use tracing::{Span, debug, instrument, trace, warn};
 
 use crate::{
+    delta,
     error::{CorruptState, EvsError},
     objects::Object,
+    util::{Progress, set_shared_mode},
 };
 
-// This is basically as good as 9 (best), but significantly faster. Later it will be configurable.
-const COMPRESSION_LEVEL: u32 = 4;
+// This is basically as good as 9 (best), but significantly faster.
+pub const DEFAULT_COMPRESSION_LEVEL: u32 = 4;
+
+/// Files larger than this are split into chunks of this size.
+pub const DEFAULT_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+/// The number of objects kept in the lookup cache.
+const OBJECT_CACHE_SIZE: NonZeroUsize = NonZeroUsize::new(4096).unwrap();
+
+/// Starts objects stored without compression, gzip data always starts with `0x1f` instead.
+const RAW_MARKER: u8 = 0x00;
+
+/// Marks an object file holding an `Object::Delta` in place of the object it is named after.
+const DELTA_MARKER: u8 = 0x01;
+
+/// The gzip extra subfield recording the compression level of an object, see `stored_level`.
+const LEVEL_SUBFIELD: [u8; 4] = [b'E', b'V', 1, 0];
+
+/// The most deltas read to rebuild one object, `pack_delta` never builds longer chains.
+const MAX_DELTA_DEPTH: usize = 16;
 
 pub type Hash = [u8; 32];
 pub type PartialHash<'a> = &'a [u8];
 
 const FORMATTED_HASH_SIZE: usize = size_of::<Hash>() * 2;
 
+/// Objects are stored as `<shard>/<rest of the name>`, where the shard is this many leading hex digits.
+const SHARD_SIZE: usize = 2;
+
+/// Objects are written here first and then renamed into their shard.
+const TMP_DIR: &str = "tmp";
+
+/// Holds the pack files, which combine many objects into one file.
+const PACK_DIR: &str = "pack";
+
+/// The extension of pack files in `PACK_DIR`.
+const PACK_EXTENSION: &str = "pack";
+
+/// The start of every pack file.
+const PACK_MAGIC: &[u8; 8] = b"EVSPACK\0";
+
+/// The version of the pack format written, others are refused.
+const PACK_VERSION: u32 = 1;
+
+/// The magic and the version.
+const PACK_HEADER_SIZE: usize = 12;
+
 /// Needs to double the length of a hash (it does).
 #[derive(Debug)]
 pub struct HashDisplay<'a>(pub PartialHash<'a>);
@@ -47,506 +101,3179 @@ impl Display for HashDisplay<'_> {
     }
 }
 
-#[derive(Debug)]
-pub struct Store {
-    path: PathBuf,
+fn is_lower_hex(bytes: &[u8]) -> bool {
+    bytes
+        .iter()
+        .all(|b| matches!(*b, b'0'..=b'9' | b'a'..=b'f'))
 }
 
-impl Store {
-    #[inline]
-    #[must_use]
-    pub fn new(path: PathBuf) -> Self {
-        Self { path }
-    }
-
-    #[inline]
-    #[must_use]
-    pub fn path(&self) -> &PathBuf {
-        &self.path
+/// Parses a full lowercase hex hash as printed by `HashDisplay`.
+#[inline]
+#[must_use]
+pub fn parse_hash(hex: &str) -> Option<Hash> {
+    if size_of_val(hex) != FORMATTED_HASH_SIZE {
+        return None;
     }
 
-    /// Assumes a valid store and might cause unintended behaviour
-    #[inline]
-    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn insert(
-        &self,
-        parent: &Span,
-        mut obj: Object,
-    ) -> Result<Hash, EvsError> {
-        debug!("Store::insert(self, ...)");
+    let mut hash = Hash::default();
 
-        if let Object::Tree(entries) = &mut obj {
-            entries.sort_by(|a, b| a.name.cmp(&b.name));
+    for (byte, pair) in hash.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        if !is_lower_hex(pair) {
+            return None;
         }
 
-        let data = rmp_serde::to_vec(&obj)?;
-
-        trace!("Serialized object to size {}.", data.len());
+        *byte = u8::from_str_radix(str::from_utf8(pair).ok()?, 16).ok()?;
+    }
 
-        let hash: Hash = Sha256::digest(&data).into();
+    Some(hash)
+}
 
-        let hash_display = format!("{}", HashDisplay(&hash));
+/// The compressed bytes of an object file.
+enum ObjectBytes {
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(Mmap),
+}
 
-        trace!("Data hashed to \"{}\".", hash_display);
+impl ObjectBytes {
+    fn read(path: &Path) -> Result<Self, EvsError> {
+        #[cfg(feature = "mmap")]
+        {
+            let file = File::open(path).map_err(|e| (e, path.to_path_buf()))?;
 
-        let mut encoder = GzEncoder::new(Vec::new(), Compression::new(COMPRESSION_LEVEL));
+            // SAFETY: Objects are never modified in place, only renamed into place or removed.
+            match unsafe { Mmap::map(&file) } {
+                Ok(map) => {
+                    trace!("Mapped object {:?}.", path);
 
-        if encoder.write_all(&data).is_err() {
-            unreachable!("gzip encoder failed: io error on vec");
+                    return Ok(ObjectBytes::Mapped(map));
+                }
+                Err(e) => trace!("Mapping object {:?} failed, reading instead: {}", path, e),
+            }
         }
 
-        let Ok(compressed) = encoder.finish() else {
-            unreachable!("gzip encoder failed: io error on vec");
-        };
-
-        trace!(
-            "Compressed data from {} to {} bytes.",
-            data.len(),
-            compressed.len()
-        );
-
-        let target = self.path.join(&hash_display);
-
-        if target.exists() {
-            trace!("Object path exists, assuming it is valid.");
-
-            Ok(hash)
-        } else {
-            trace!("Object path does not exist, inserting...");
-
-            let tmp = self
-                .path
-                .join(format!("{}-{:?}", hash_display, thread::current().id()));
-
-            trace!("Using temporary path {:?}.", tmp);
+        Ok(ObjectBytes::Owned(
+            fs::read(path).map_err(|e| (e, path.to_path_buf()))?,
+        ))
+    }
+}
 
-            let mut file = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(&tmp)
-                .map_err(|e| (e, target.clone()))?;
+impl Deref for ObjectBytes {
+    type Target = [u8];
 
-            file.write_all(&compressed)
-                .map_err(|e| (e, target.clone()))?;
+    fn deref(&self) -> &Self::Target {
+        match self {
+            ObjectBytes::Owned(bytes) => bytes,
+            #[cfg(feature = "mmap")]
+            ObjectBytes::Mapped(map) => map,
+        }
+    }
+}
 
-            drop(file);
+/// A description and the allowed kinds of the object on one side of a reference.
+type Expected = (&'static str, &'static [&'static str]);
+
+const TREE_CONTENT: Expected = (
+    "a blob, a chunk list, a tree or a symlink",
+    &["blob", "chunk list", "tree", "symlink"],
+);
+const CHUNK_CONTENT: Expected = ("a blob", &["blob"]);
+const TAG_TARGET: Expected = ("a commit or a tag", &["commit", "tag"]);
+const COMMIT_TREE: Expected = ("a tree", &["tree"]);
+const COMMIT_PARENT: Expected = ("a commit or null", &["commit", "null"]);
+
+/// Shared state of a running store check.
+struct CheckState {
+    /// Blobs that were verified before, which only have to exist.
+    verified: AHashSet<Hash>,
+    found: Mutex<AHashSet<Hash>>,
+    found_cache: Mutex<AHashSet<String>>,
+    missing: Mutex<AHashSet<Hash>>,
+    kinds: Mutex<AHashMap<Hash, &'static str>>,
+    /// Referrer, referenced object and what the latter has to be.
+    references: Mutex<Vec<(Hash, Hash, Expected)>>,
+    /// Unreadable objects by name, only collected instead of failing if this is given.
+    corrupt: Option<Mutex<Vec<(OsString, EvsError)>>>,
+    progress: Progress,
+}
 
-            fs::rename(tmp, &target).map_err(|e| (e, target.clone()))?;
+/// The outcome of [`ObjectStore::check_report`].
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub found: AHashSet<Hash>,
+    /// The found blobs reachable from the required objects.
+    pub blobs: AHashSet<Hash>,
+    /// The objects that were only found because of `all`.
+    pub extra: AHashSet<Hash>,
+    /// Required objects that are missing or unreadable.
+    pub missing: AHashSet<Hash>,
+    /// Unreadable objects by name, with why they could not be read.
+    pub corrupt: Vec<(OsString, EvsError)>,
+}
 
-            trace!("Wrote object to store.");
+/// The hashes of `Object::Null` and the empty tree, which every repository contains.
+const SHA256_NULL_HASH: Hash = [
+    0x07, 0xf9, 0x43, 0x5a, 0x87, 0x8f, 0xb2, 0xe6, 0x32, 0xfd, 0xa8, 0xd4, 0xd1, 0x0a, 0x8c, 0x1a,
+    0xbc, 0x0f, 0xca, 0x65, 0x95, 0x3e, 0x72, 0x1d, 0xe5, 0xe1, 0x88, 0xa1, 0x36, 0x6c, 0x32, 0xc0,
+];
+const SHA256_EMPTY_TREE_HASH: Hash = [
+    0x0f, 0x73, 0xdf, 0x45, 0x66, 0x93, 0x55, 0xfa, 0x69, 0x38, 0x8f, 0x06, 0x0c, 0x0a, 0x2c, 0x1e,
+    0x8b, 0x31, 0x05, 0xf7, 0x0c, 0x37, 0xef, 0x8c, 0x94, 0x75, 0xdf, 0x27, 0x2d, 0x20, 0x21, 0xae,
+];
+const BLAKE3_NULL_HASH: Hash = [
+    0x1b, 0xa1, 0x83, 0x30, 0x09, 0x40, 0xf8, 0x26, 0x69, 0xe5, 0x6b, 0xa2, 0x2a, 0x21, 0x41, 0x40,
+    0x93, 0xaf, 0xf2, 0xbc, 0xd2, 0xda, 0xf3, 0xad, 0x6a, 0x08, 0xaa, 0x06, 0xed, 0x77, 0x67, 0x92,
+];
+const BLAKE3_EMPTY_TREE_HASH: Hash = [
+    0xd8, 0x80, 0x1a, 0x06, 0x23, 0xa7, 0x82, 0x9f, 0x42, 0x33, 0xf1, 0xd0, 0x98, 0xbd, 0x69, 0x44,
+    0xbf, 0x02, 0x85, 0x17, 0x3c, 0x1f, 0xdd, 0x69, 0x3b, 0x3c, 0x71, 0xd8, 0x40, 0x3e, 0xf8, 0x78,
+];
+
+/// A digest of `HashAlgo` computed incrementally.
+enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
 
-            Ok(hash)
+impl Hasher {
+    fn new(algo: HashAlgo) -> Self {
+        match algo {
+            HashAlgo::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgo::Blake3 => Hasher::Blake3(Box::default()),
         }
     }
 
-    #[inline]
-    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn lookup(
-        &self,
-        parent: &Span,
-        id: &str,
-    ) -> Result<(Hash, Object), EvsError> {
-        if size_of_val(id) > FORMATTED_HASH_SIZE {
-            debug!("Store::lookup(self, <overlength hash>)");
+    fn update(
+        &mut self,
+        data: &[u8],
+    ) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
 
-            return Err(EvsError::ObjectNotInStore(id.to_owned()));
+    fn finalize(self) -> Hash {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().into(),
+            Hasher::Blake3(hasher) => hasher.finalize().into(),
         }
+    }
+}
 
-        debug!(
-            "Store::lookup(self, \"{}{}\")",
-            id,
-            if size_of_val(id) < FORMATTED_HASH_SIZE {
-                "..."
-            } else {
-                ""
-            }
-        );
+/// The hash, offset and length of an object in a pack.
+type PackEntry = (Hash, u64, u64);
 
-        let mut target = None;
+/// A pack file holds the stored bytes of its objects one after another,
+/// followed by the index and the offset of the index as the last 8 bytes.
+#[derive(Debug)]
+struct Pack {
+    path: PathBuf,
+    /// Sorted by hash.
+    index: Vec<PackEntry>,
+}
 
-        if size_of_val(id) == FORMATTED_HASH_SIZE {
-            let path = self.path.join(id);
+impl Pack {
+    /// Reads the index of the pack at `path`.
+    fn open(path: PathBuf) -> Result<Self, EvsError> {
+        let invalid = |path: &Path, reason| {
+            EvsError::CorruptStateDetected(CorruptState::InvalidPack(path.to_path_buf(), reason))
+        };
 
-            target = fs::exists(&path).is_ok_and(|e| e).then_some(path);
-        } else {
-            for obj in self.path.read_dir().map_err(|e| (e, self.path.clone()))? {
-                let obj = obj.map_err(|e| (e, self.path.clone()))?;
+        let mut file = File::open(&path).map_err(|e| (e, path.clone()))?;
 
-                let name = obj.path();
+        let mut header = [0u8; PACK_HEADER_SIZE];
 
-                if let Some(hash) = name.file_name()
-                    && hash.as_encoded_bytes().starts_with(id.as_bytes())
-                {
-                    if let Some(target) = target {
-                        return Err(EvsError::AmbiguousObject(
-                            id.to_owned(),
-                            target.file_name().unwrap().to_os_string(),
-                        ));
-                    }
+        file.read_exact(&mut header)
+            .map_err(|_e| invalid(&path, "it is truncated"))?;
 
-                    target = Some(name);
-                }
-            }
-        }
+        let (magic, version) = header.split_at(PACK_MAGIC.len());
 
-        if target.is_none() {
-            return Err(EvsError::ObjectNotInStore(id.to_owned()));
+        if magic != PACK_MAGIC {
+            return Err(invalid(&path, "it is not a pack file"));
         }
 
-        let target = target.unwrap();
-
-        let target_name = target.file_name().unwrap();
-
-        if size_of_val(target_name) != FORMATTED_HASH_SIZE
-            || !target_name
-                .as_encoded_bytes()
-                .iter()
-                .all(|b| matches!(*b, b'0'..=b'9' | b'a'..=b'f'))
-        {
-            return Err(EvsError::CorruptStateDetected(
-                CorruptState::InvalidObjectName(target_name.to_owned()),
-            ));
+        if version != PACK_VERSION.to_le_bytes() {
+            return Err(invalid(&path, "its version is not supported"));
         }
 
-        trace!("Found object {:?}.", target);
-
-        let content = fs::read(&target).map_err(|e| (e, target.clone()))?;
+        let end = file
+            .seek(SeekFrom::End(-8))
+            .map_err(|_e| invalid(&path, "it is truncated"))?;
 
-        trace!("Read object of compressed size {}.", content.len());
+        let mut start = [0u8; 8];
 
-        let mut decoder = GzDecoder::new(&*content);
+        file.read_exact(&mut start).map_err(|e| (e, path.clone()))?;
 
-        let mut decompressed = vec![];
+        let start = u64::from_le_bytes(start);
 
-        decoder.read_to_end(&mut decompressed).map_err(|e| {
-            EvsError::CorruptStateDetected(CorruptState::InvalidCompression(target.clone(), e))
-        })?;
+        if usize::try_from(start).is_ok_and(|start| start < PACK_HEADER_SIZE) || start > end {
+            return Err(invalid(&path, "its index offset is out of bounds"));
+        }
 
-        trace!("Decompressed to size {}.", decompressed.len());
+        file.seek(SeekFrom::Start(start))
+            .map_err(|e| (e, path.clone()))?;
 
-        let real_hash: Hash = Sha256::digest(&decompressed).into();
+        let mut index = Vec::new();
 
-        if *target_name != *format!("{}", HashDisplay(&real_hash)) {
-            return Err(EvsError::CorruptStateDetected(CorruptState::HashMismatch(
-                target_name.to_owned(),
-                real_hash.to_vec(),
-            )));
-        }
+        file.take(end.saturating_sub(start))
+            .read_to_end(&mut index)
+            .map_err(|e| (e, path.clone()))?;
 
-        trace!("Validated hash.");
+        let index = rmp_serde::from_slice::<Vec<PackEntry>>(&index)
+            .map_err(|_e| invalid(&path, "its index is unreadable"))?;
 
-        let deserialized =
-            rmp_serde::from_slice::<Object>(&decompressed).map_err(|e| (e, real_hash))?;
+        if !index.is_sorted_by_key(|entry| entry.0) {
+            return Err(invalid(&path, "its index is not sorted"));
+        }
 
-        trace!("Deserialized successfully.");
+        trace!("Opened pack {:?} of {} object(s).", path, index.len());
 
-        Ok((real_hash, deserialized))
+        Ok(Pack { path, index })
     }
 
-    #[inline]
-    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn check<T: AsRef<[Hash]>>(
+    /// The offset and length of `hash` in the pack.
+    fn get(
         &self,
-        parent: &Span,
-        found: AHashSet<Hash>,
-        required: T,
-        all: bool,
-    ) -> Result<(AHashSet<Hash>, AHashSet<Hash>), EvsError> {
-        debug!("Store::check(self, <{} hash(es)>)", required.as_ref().len());
-
-        self.check_(found, required.as_ref(), all)
+        hash: &Hash,
+    ) -> Option<(u64, u64)> {
+        let position = self
+            .index
+            .binary_search_by_key(hash, |entry| entry.0)
+            .ok()?;
+
+        self.index
+            .get(position)
+            .map(|&(_, offset, len)| (offset, len))
     }
 
-    fn check_(
-        &self,
-        found: AHashSet<Hash>,
-        required: &[Hash],
-        all: bool,
-    ) -> Result<(AHashSet<Hash>, AHashSet<Hash>), EvsError> {
-        let current = Span::current();
-
-        let mut extra = AHashSet::new();
-        let found = Mutex::new(found);
-        let missing = Mutex::new(AHashSet::new());
+    /// Reads the stored bytes of an object in the pack at `path`.
+    fn read_at(
+        path: &Path,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, EvsError> {
+        let mut file = File::open(path).map_err(|e| (e, path.to_path_buf()))?;
 
-        let found_cache = Mutex::new(AHashSet::new());
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| (e, path.to_path_buf()))?;
 
-        trace!("Initially required to find {} object(s).", required.len());
+        let mut content = Vec::new();
 
-        required
-            .par_iter()
-            .map(|item| self.check_one(&current, *item, &found, &found_cache, &missing))
-            .collect::<Result<(), EvsError>>()?;
+        file.take(len)
+            .read_to_end(&mut content)
+            .map_err(|e| (e, path.to_path_buf()))?;
 
-        let mut found = found.into_inner().unwrap();
-        let missing = missing.into_inner().unwrap();
+        if u64::try_from(content.len()).ok() != Some(len) {
+            return Err(EvsError::CorruptStateDetected(CorruptState::InvalidPack(
+                path.to_path_buf(),
+                "it is truncated",
+            )));
+        }
 
-        let found_cache = found_cache.into_inner().unwrap();
+        Ok(content)
+    }
+}
 
-        if all {
-            extra = self
-                .path
-                .read_dir()
-                .map_err(|e| (e, self.path.clone()))?
-                .par_bridge()
-                .filter_map(|obj| {
-                    let _entered = current.enter();
+/// The decompressing part of a `RawObject`.
+enum RawSource {
+    Raw(BufReader<File>),
+    Gzip(Box<GzDecoder<BufReader<File>>>),
+    /// Packed objects and deltas are read into memory at once.
+    Rebuilt(io::Cursor<Vec<u8>>),
+}
 
-                    let obj = match obj.map_err(|e| (e, self.path.clone())) {
-                        Ok(obj) => obj,
-                        Err(e) => return Some(Err(e.into())),
-                    };
+/// Streams the serialized bytes of a stored object, see `Store::read_raw`.
+/// Errors are `EvsError`s wrapped in an `io::Error`, including a mismatching hash at the end.
+pub struct RawObject {
+    source: RawSource,
+    path: PathBuf,
+    hash: Hash,
+    /// Taken once the end is reached and the hash was validated.
+    hasher: Option<Hasher>,
+}
 
-                    let name = obj.file_name();
+impl io::Read for RawObject {
+    #[inline]
+    fn read(
+        &mut self,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        let read = match &mut self.source {
+            RawSource::Raw(reader) => reader.read(buf),
+            RawSource::Gzip(decoder) => decoder.read(buf),
+            RawSource::Rebuilt(cursor) => cursor.read(buf),
+        }
+        .map_err(|e| {
+            io::Error::other(EvsError::CorruptStateDetected(
+                CorruptState::InvalidCompression(self.path.clone(), e),
+            ))
+        })?;
 
-                    let bytes = name.as_encoded_bytes();
+        if let (Some(hasher), Some(data)) = (&mut self.hasher, buf.get(..read)) {
+            hasher.update(data);
+        }
 
-                    if size_of_val(bytes) != FORMATTED_HASH_SIZE || name.to_str().is_none() {
-                        return Some(Err(EvsError::CorruptStateDetected(
-                            CorruptState::InvalidObjectName(name),
-                        )));
-                    }
+        if read == 0
+            && !buf.is_empty()
+            && let Some(hasher) = self.hasher.take()
+        {
+            let real_hash = hasher.finalize();
 
-                    let name = name.to_str().unwrap();
+            if real_hash != self.hash {
+                return Err(io::Error::other(EvsError::CorruptStateDetected(
+                    CorruptState::HashMismatch(
+                        format!("{}", HashDisplay(&self.hash)).into(),
+                        real_hash.to_vec(),
+                    ),
+                )));
+            }
 
-                    if found_cache.contains(name) {
-                        return None;
-                    }
+            trace!("Validated hash of streamed object.");
+        }
 
-                    let (hash, _) = match self.lookup(&current, name) {
-                        Ok(res) => res,
-                        Err(e) => return Some(Err(e)),
-                    };
+        Ok(read)
+    }
+}
 
-                    trace!("Validated extra \"{}\".", name);
+/// The digest objects are named by, chosen when the repository is created.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Blake3,
+}
 
-                    Some(Ok(hash))
-                })
-                .collect::<Result<HashSet<Hash>, _>>()?
-                .into();
+impl HashAlgo {
+    #[inline]
+    #[must_use]
+    pub fn digest(
+        self,
+        data: &[u8],
+    ) -> Hash {
+        match self {
+            HashAlgo::Sha256 => Sha256::digest(data).into(),
+            HashAlgo::Blake3 => blake3::hash(data).into(),
+        }
+    }
+}
 
-            found.extend(extra.iter());
+impl Display for HashAlgo {
+    #[inline]
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            HashAlgo::Sha256 => write!(f, "SHA-256"),
+            HashAlgo::Blake3 => write!(f, "BLAKE3"),
         }
+    }
+}
 
-        let normal_found = found.difference(&extra).count();
-        #[allow(
-            clippy::arithmetic_side_effects,
-            reason = "Not going to happen + impossible."
-        )]
-        let normal_total = normal_found + missing.len();
+/// The object operations shared by the filesystem `Store` and the in-memory `MemStore`.
+pub trait ObjectStore: Debug + Send + Sync {
+    fn algo(&self) -> HashAlgo;
 
-        trace!(
-            "Finished validating {}/{} (+{}) objects.",
-            normal_found,
-            normal_total,
-            extra.len(),
-        );
+    /// The hash of `Object::Null`, which every repository contains.
+    #[inline]
+    #[must_use]
+    fn null_hash(&self) -> Hash {
+        match self.algo() {
+            HashAlgo::Sha256 => SHA256_NULL_HASH,
+            HashAlgo::Blake3 => BLAKE3_NULL_HASH,
+        }
+    }
 
-        if !missing.is_empty() {
-            return Err(EvsError::CorruptStateDetected(
-                CorruptState::MissingObjects(missing),
-            ));
+    /// The hash of the empty tree, which every repository contains.
+    #[inline]
+    #[must_use]
+    fn empty_tree_hash(&self) -> Hash {
+        match self.algo() {
+            HashAlgo::Sha256 => SHA256_EMPTY_TREE_HASH,
+            HashAlgo::Blake3 => BLAKE3_EMPTY_TREE_HASH,
         }
+    }
+
+    /// The number of stored objects and their total size in bytes.
+    fn status(
+        &self,
+        parent: &Span,
+    ) -> Result<(usize, usize), EvsError>;
 
-        Ok((found, extra))
+    /// Picks up the pack files other processes wrote, stores without packs have nothing to do.
+    #[inline]
+    fn load_packs(
+        &self,
+        _parent: &Span,
+    ) -> Result<(), EvsError> {
+        Ok(())
     }
 
+    /// Only checks for existence and not validity.
+    fn contains(
+        &self,
+        hash: &Hash,
+    ) -> bool;
+
+    /// Inserts the file at `path`, which is `len` bytes long, as a blob.
+    #[inline]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    fn check_one(
+    fn insert_file(
         &self,
         parent: &Span,
-        hash: Hash,
-        found: &Mutex<AHashSet<Hash>>,
-        found_cache: &Mutex<AHashSet<String>>,
-        missing: &Mutex<AHashSet<Hash>>,
-    ) -> Result<(), EvsError> {
-        debug!("Store::check_one(self, \"{}\")", HashDisplay(&hash));
+        path: &Path,
+        len: u64,
+    ) -> Result<Hash, EvsError> {
+        debug!("ObjectStore::insert_file(self, {:?}, {})", path, len);
 
-        let current = Span::current();
+        let content = fs::read(path).map_err(|e| (e, path.to_owned()))?;
 
-        if found.lock().unwrap().contains(&hash) {
-            return Ok(());
-        }
+        self.insert(&Span::current(), Object::Blob(content))
+    }
 
-        let name = format!("{}", HashDisplay(&hash));
+    fn insert(
+        &self,
+        parent: &Span,
+        obj: Object,
+    ) -> Result<Hash, EvsError>;
 
-        let (hash, obj) = match self.lookup(&current, &name) {
-            Ok(res) => res,
-            Err(EvsError::ObjectNotInStore(_)) => {
-                warn!("Missing \"{}\"", name);
-                missing.lock().unwrap().insert(hash);
-                return Ok(());
-            }
-            Err(err) => return Err(err),
-        };
+    /// Reads an object as it is stored, without reassembling chunk lists.
+    fn lookup_raw(
+        &self,
+        parent: &Span,
+        id: &str,
+    ) -> Result<(Hash, Object), EvsError>;
+
+    /// Like `lookup_raw`, but always reads the stored object instead of a cached copy.
+    #[inline]
+    fn lookup_uncached(
+        &self,
+        parent: &Span,
+        id: &str,
+    ) -> Result<(Hash, Object), EvsError> {
+        self.lookup_raw(parent, id)
+    }
 
-        found_cache.lock().unwrap().insert(name);
+    /// Returns the size of the deleted object.
+    fn remove(
+        &self,
+        parent: &Span,
+        hash: Hash,
+    ) -> Result<usize, EvsError>;
 
-        trace!("Validated \"{}\".", HashDisplay(&hash));
+    /// Resolves a hash or unique prefix to the full object name.
+    fn resolve_rest(
+        &self,
+        parent: &Span,
+        r#ref: String,
+    ) -> Result<String, EvsError>;
 
-        match obj {
-            Object::Null => trace!("Found the NULL object! :)"),
-            Object::Blob(data) => trace!("Found blob of size {}.", data.len()),
-            Object::Tree(items) => {
-                trace!("Found tree with {} child(ren).", items.len());
+    /// The names of all stored objects, including invalid ones, which `check` reports.
+    fn names(&self) -> Result<Vec<OsString>, EvsError>;
 
-                items
-                    .par_iter()
-                    .map(|item| {
-                        let _entered = current.enter();
+    /// Like `lookup_raw`, but returns chunk lists as the blob they were split from.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn lookup(
+        &self,
+        parent: &Span,
+        id: &str,
+    ) -> Result<(Hash, Object), EvsError> {
+        debug!("ObjectStore::lookup(self, \"{}\")", id);
 
-                        trace!(
-                            "Requiring \"{}\" for \"{}\".",
-                            HashDisplay(&item.content),
-                            HashDisplay(&hash)
-                        );
+        let current = Span::current();
 
-                        self.check_one(&current, item.content, found, found_cache, missing)
-                    })
-                    .collect::<Result<(), _>>()?;
-            }
-            Object::Commit(commit) => {
-                trace!(
-                    "Found commit with state \"{}\" and parent \"{}\".",
-                    HashDisplay(&commit.tree),
-                    HashDisplay(&commit.parent)
-                );
+        let (hash, obj) = self.lookup_raw(&current, id)?;
 
-                trace!(
-                    "Requiring \"{}\" for \"{}\".",
-                    HashDisplay(&commit.tree),
-                    HashDisplay(&hash)
-                );
+        let Object::ChunkList(chunks) = obj else {
+            return Ok((hash, obj));
+        };
 
-                trace!(
-                    "Requiring \"{}\" for \"{}\".",
-                    HashDisplay(&commit.parent),
-                    HashDisplay(&hash)
-                );
+        trace!("Reassembling {} chunk(s)...", chunks.len());
 
-                let (parent, tree) = join(
-                    || self.check_one(&current, commit.parent, found, found_cache, missing),
-                    || self.check_one(&current, commit.tree, found, found_cache, missing),
-                );
+        let mut data = Vec::new();
 
-                parent?;
-                tree?;
+        for chunk in chunks {
+            match self.lookup_raw(&current, &format!("{}", HashDisplay(&chunk)))? {
+                (_, Object::Blob(content)) => data.extend_from_slice(&content),
+                (chunk, other) => {
+                    return Err(EvsError::CorruptStateDetected(CorruptState::TypeMismatch(
+                        hash,
+                        chunk,
+                        other.kind(),
+                        CHUNK_CONTENT.0,
+                    )));
+                }
             }
         }
 
-        found.lock().unwrap().insert(hash);
-
-        Ok(())
+        Ok((hash, Object::Blob(data)))
     }
 
+    /// Validates everything reachable from `required` and, with `all`, every other object.
+    /// The blobs in `verified` are only checked for existence instead of being read again.
+    /// The returned report never contains missing or corrupt objects.
     #[inline]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn remove(
+    fn check(
         &self,
         parent: &Span,
-        path: Hash,
-    ) -> Result<(), EvsError> {
-        debug!("Store::remove(self, \"{}\")", HashDisplay(&path));
+        verified: AHashSet<Hash>,
+        required: &[Hash],
+        all: bool,
+    ) -> Result<CheckReport, EvsError> {
+        debug!("ObjectStore::check(self, <{} hash(es)>)", required.len());
+
+        let report = check_objects(self, verified, required, all, false)?;
+
+        if !report.missing.is_empty() {
+            return Err(EvsError::CorruptStateDetected(
+                CorruptState::MissingObjects(report.missing),
+            ));
+        }
+
+        Ok(report)
+    }
+
+    /// Like `check`, but collects unreadable objects and treats required ones as missing instead of failing.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn check_report(
+        &self,
+        parent: &Span,
+        required: &[Hash],
+        all: bool,
+    ) -> Result<CheckReport, EvsError> {
+        debug!(
+            "ObjectStore::check_report(self, <{} hash(es)>)",
+            required.len()
+        );
+
+        check_objects(self, AHashSet::new(), required, all, true)
+    }
+}
+
+/// Object counts and sizes over the whole store, see [`Store::stats`].
+#[derive(Debug, Default)]
+pub struct StoreStats {
+    pub count: usize,
+    /// Object counts by [`Object::kind`].
+    pub kinds: BTreeMap<&'static str, usize>,
+    /// The size of the object files.
+    pub compressed: usize,
+    /// The size of the serialized objects.
+    pub decompressed: usize,
+}
+
+impl StoreStats {
+    #[inline]
+    #[must_use]
+    fn merge(
+        mut self,
+        other: Self,
+    ) -> Self {
+        self.count = self.count.saturating_add(other.count);
+        self.compressed = self.compressed.saturating_add(other.compressed);
+        self.decompressed = self.decompressed.saturating_add(other.decompressed);
+
+        for (kind, count) in other.kinds {
+            let entry = self.kinds.entry(kind).or_default();
+
+            *entry = entry.saturating_add(count);
+        }
+
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct Store {
+    path: PathBuf,
+    shared: bool,
+    algo: HashAlgo,
+    compression: u32,
+    chunk_size: u64,
+    /// Recently looked up objects other than blobs, which can be large.
+    cache: Option<Mutex<LruCache<Hash, Object>>>,
+    /// Consulted before the loose objects, see `load_packs`.
+    packs: RwLock<Vec<Pack>>,
+    /// The number of workspace files read by `insert_file`.
+    #[cfg(test)]
+    files_read: AtomicUsize,
+}
+
+impl Store {
+    /// Objects written to a `shared` store are group-writable.
+    #[inline]
+    #[must_use]
+    pub fn new(
+        path: PathBuf,
+        shared: bool,
+        algo: HashAlgo,
+        compression: u32,
+        chunk_size: u64,
+        cache: bool,
+    ) -> Self {
+        Self {
+            path,
+            shared,
+            algo,
+            compression,
+            chunk_size,
+            cache: cache.then(|| Mutex::new(LruCache::new(OBJECT_CACHE_SIZE))),
+            packs: RwLock::new(Vec::new()),
+            #[cfg(test)]
+            files_read: AtomicUsize::new(0),
+        }
+    }
+
+    #[cfg(test)]
+    #[inline]
+    pub fn files_read(&self) -> usize {
+        self.files_read.load(Ordering::Relaxed)
+    }
+
+    /// Creates the store directory with its temporary and pack directories, keeping existing ones.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn create_layout(
+        &self,
+        parent: &Span,
+    ) -> Result<(), EvsError> {
+        debug!("Store::create_layout(self)");
+
+        for dir in [
+            self.path.clone(),
+            self.path.join(TMP_DIR),
+            self.path.join(PACK_DIR),
+        ] {
+            DirBuilder::new()
+                .recursive(true)
+                .create(&dir)
+                .map_err(|e| (e, dir.clone()))?;
+
+            if self.shared {
+                set_shared_mode(&dir)?;
+            }
+
+            trace!("Created {:?}.", dir);
+        }
+
+        Ok(())
+    }
+
+    /// The pack file holding `hash` with the offset and length of the object in it.
+    fn packed(
+        &self,
+        hash: &Hash,
+    ) -> Option<(PathBuf, u64, u64)> {
+        self.packs.read().unwrap().iter().find_map(|pack| {
+            pack.get(hash)
+                .map(|(offset, len)| (pack.path.clone(), offset, len))
+        })
+    }
+
+    /// Whether `hash` is in a pack file instead of a loose object file.
+    #[inline]
+    #[must_use]
+    pub fn is_packed(
+        &self,
+        hash: &Hash,
+    ) -> bool {
+        self.packed(hash).is_some()
+    }
+
+    /// The paths packed objects would have as loose objects, which all other methods accept for them.
+    fn packed_objects(&self) -> Vec<PathBuf> {
+        self.packs
+            .read()
+            .unwrap()
+            .iter()
+            .flat_map(|pack| &pack.index)
+            .map(|(hash, _, _)| self.object_path(&format!("{}", HashDisplay(hash))))
+            .collect()
+    }
+
+    /// The stored bytes of `hash` from its pack, or else from the loose object file at `target`.
+    fn object_bytes(
+        &self,
+        target: &Path,
+        hash: &Hash,
+    ) -> Result<ObjectBytes, EvsError> {
+        if let Some((pack, offset, len)) = self.packed(hash) {
+            trace!("Reading packed object from {:?}.", pack);
+
+            return Ok(ObjectBytes::Owned(Pack::read_at(&pack, offset, len)?));
+        }
+
+        ObjectBytes::read(target)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// The path of the object with the full name `name`.
+    fn object_path(
+        &self,
+        name: &str,
+    ) -> PathBuf {
+        let (shard, rest) = name.split_at_checked(SHARD_SIZE).unwrap_or((name, ""));
+
+        self.path.join(shard).join(rest)
+    }
+
+    /// The full object name of a path in the store, for files outside of a shard this is just the file name.
+    fn object_name(
+        &self,
+        path: &Path,
+    ) -> OsString {
+        let file_name = path.file_name().unwrap_or_default();
+
+        match path.parent() {
+            Some(shard) if shard != self.path => {
+                let mut name = shard.file_name().unwrap_or_default().to_os_string();
+
+                name.push(file_name);
+
+                name
+            }
+            _ => file_name.to_os_string(),
+        }
+    }
+
+    /// Lists every loose object file in the store, with the shards expanded.
+    fn objects(&self) -> Result<Vec<PathBuf>, EvsError> {
+        let mut objects = Vec::new();
+
+        for entry in self.path.read_dir().map_err(|e| (e, self.path.clone()))? {
+            let entry = entry.map_err(|e| (e, self.path.clone()))?.path();
+
+            if entry.file_name() == Some(OsStr::new(TMP_DIR))
+                || entry.file_name() == Some(OsStr::new(PACK_DIR))
+            {
+                continue;
+            }
+
+            if entry.is_dir() {
+                for obj in entry.read_dir().map_err(|e| (e, entry.clone()))? {
+                    objects.push(obj.map_err(|e| (e, entry.clone()))?.path());
+                }
+            } else {
+                objects.push(entry);
+            }
+        }
+
+        trace!("Listed {} object file(s).", objects.len());
+
+        Ok(objects)
+    }
+
+    /// Finds the paths of all objects starting with `prefix`, only reading the shards that can match.
+    /// Packed objects are included with the path they would have as loose objects.
+    fn find_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<PathBuf>, EvsError> {
+        let (shard_prefix, rest) = prefix.split_at_checked(SHARD_SIZE).unwrap_or((prefix, ""));
+
+        let mut shards = Vec::new();
+
+        if size_of_val(shard_prefix) == SHARD_SIZE {
+            shards.push(self.path.join(shard_prefix));
+        } else {
+            for entry in self.path.read_dir().map_err(|e| (e, self.path.clone()))? {
+                let entry = entry.map_err(|e| (e, self.path.clone()))?;
+
+                if entry
+                    .file_name()
+                    .as_encoded_bytes()
+                    .starts_with(shard_prefix.as_bytes())
+                    && entry.file_name() != TMP_DIR
+                    && entry.file_name() != PACK_DIR
+                {
+                    shards.push(entry.path());
+                }
+            }
+        }
+
+        let mut found = Vec::new();
+
+        for shard in shards.into_iter().filter(|shard| shard.is_dir()) {
+            for obj in shard.read_dir().map_err(|e| (e, shard.clone()))? {
+                let obj = obj.map_err(|e| (e, shard.clone()))?;
+
+                if obj
+                    .file_name()
+                    .as_encoded_bytes()
+                    .starts_with(rest.as_bytes())
+                {
+                    trace!("Found {:?}.", obj.path());
+
+                    found.push(obj.path());
+                }
+            }
+        }
+
+        for path in self.packed_objects() {
+            if self
+                .object_name(&path)
+                .as_encoded_bytes()
+                .starts_with(prefix.as_bytes())
+                && !found.contains(&path)
+            {
+                trace!("Found packed {:?}.", path);
+
+                found.push(path);
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Finds the single object named or prefixed by `id`.
+    fn find(
+        &self,
+        id: &str,
+    ) -> Result<Option<PathBuf>, EvsError> {
+        if !is_lower_hex(id.as_bytes()) {
+            trace!("\"{}\" is not a hash or prefix.", id);
+
+            return Ok(None);
+        }
+
+        if size_of_val(id) == FORMATTED_HASH_SIZE {
+            let path = self.object_path(id);
+
+            trace!("Fast lookup of {:?}...", path);
+
+            let packed = parse_hash(id).is_some_and(|hash| self.is_packed(&hash));
+
+            return Ok((packed || fs::exists(&path).unwrap_or(false)).then_some(path));
+        }
+
+        trace!("Slow lookup of prefix \"{}\" in {:?}...", id, self.path);
+
+        let mut found = self.find_prefix(id)?;
+
+        if found.len() > 1 {
+            let mut candidates = found
+                .iter()
+                .map(|path| self.object_name(path).to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+
+            candidates.sort_unstable();
+
+            return Err(EvsError::AmbiguousObject(id.to_owned(), candidates));
+        }
+
+        Ok(found.pop())
+    }
+
+    /// The full names of all objects starting with `prefix`.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn names_with_prefix(
+        &self,
+        parent: &Span,
+        prefix: &str,
+    ) -> Result<Vec<String>, EvsError> {
+        debug!("Store::names_with_prefix(self, \"{}\")", prefix);
+
+        if !is_lower_hex(prefix.as_bytes()) {
+            return Ok(Vec::new());
+        }
+
+        Ok(self
+            .find_prefix(prefix)?
+            .iter()
+            .filter_map(|path| self.object_name(path).into_string().ok())
+            .collect())
+    }
+
+    /// Moves the objects of the old flat layout into their shards and returns how many were moved.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn migrate(
+        &self,
+        parent: &Span,
+    ) -> Result<usize, EvsError> {
+        debug!("Store::migrate(self)");
+
+        let mut moved = 0usize;
+
+        for entry in self.path.read_dir().map_err(|e| (e, self.path.clone()))? {
+            let entry = entry.map_err(|e| (e, self.path.clone()))?.path();
+
+            let Some(name) = entry.file_name().and_then(OsStr::to_str) else {
+                continue;
+            };
+
+            if size_of_val(name) != FORMATTED_HASH_SIZE
+                || !is_lower_hex(name.as_bytes())
+                || !entry.is_file()
+            {
+                continue;
+            }
+
+            let target = self.object_path(name);
+
+            Self::create_shard(&target, self.shared)?;
+
+            trace!("Moving {:?} to {:?}.", entry, target);
+
+            fs::rename(&entry, &target).map_err(|e| (e, entry.clone()))?;
+
+            moved = moved.saturating_add(1);
+        }
+
+        if moved > 0 {
+            warn!("Moved {} object(s) into the sharded store layout.", moved);
+        }
+
+        Ok(moved)
+    }
+
+    /// Creates the shard directory of `target` if it is missing.
+    fn create_shard(
+        target: &Path,
+        shared: bool,
+    ) -> Result<(), EvsError> {
+        // Objects are always in a shard
+        let dir = target.parent().unwrap();
+
+        if dir.is_dir() {
+            return Ok(());
+        }
+
+        trace!("Creating shard {:?}.", dir);
+
+        DirBuilder::new()
+            .recursive(true)
+            .create(dir)
+            .map_err(|e| (e, dir.to_path_buf()))?;
+
+        if shared {
+            set_shared_mode(dir)?;
+        }
+
+        Ok(())
+    }
+
+    /// Falls back to the raw bytes behind `RAW_MARKER` if gzip does not shrink the data.
+    fn compress(
+        &self,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut extra = LEVEL_SUBFIELD.to_vec();
+
+        // The level is at most 9, see `Cli::compression`
+        extra.push(u8::try_from(self.compression).unwrap_or(u8::MAX));
+
+        let mut encoder = GzBuilder::new()
+            .extra(extra)
+            .write(Vec::new(), Compression::new(self.compression));
+
+        if encoder.write_all(data).is_err() {
+            unreachable!("gzip encoder failed: io error on vec");
+        }
+
+        let Ok(compressed) = encoder.finish() else {
+            unreachable!("gzip encoder failed: io error on vec");
+        };
+
+        if compressed.len() < data.len() {
+            return compressed;
+        }
+
+        trace!("Compression did not help, storing raw bytes.");
+
+        let mut raw = Vec::with_capacity(data.len().saturating_add(1));
+
+        raw.push(RAW_MARKER);
+        raw.extend_from_slice(data);
+
+        raw
+    }
+
+    /// The level recorded by `compress` in the gzip header of a stored object.
+    /// Objects stored raw or written before the level was recorded have none.
+    fn stored_level(stored: &[u8]) -> Option<u32> {
+        const FEXTRA: u8 = 0x04;
+
+        let [0x1f, 0x8b, _, flags, _, _, _, _, _, _, 5, 0, rest @ ..] = stored else {
+            return None;
+        };
+
+        if flags & FEXTRA == 0 {
+            return None;
+        }
+
+        rest.strip_prefix(&LEVEL_SUBFIELD)?
+            .first()
+            .map(|&level| u32::from(level))
+    }
+
+    /// Writes to a temporary file first and renames it, so an object is never partially written.
+    fn write_object(
+        &self,
+        target: &Path,
+        compressed: &[u8],
+    ) -> Result<(), EvsError> {
+        let tmp = self.tmp_path(target)?;
+
+        Self::create_shard(target, self.shared)?;
+
+        let mut file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&tmp)
+            .map_err(|e| (e, target.to_path_buf()))?;
+
+        if let Err(e) = file.write_all(compressed) {
+            drop(file);
+
+            let _ = fs::remove_file(&tmp);
+
+            return Err((e, target.to_path_buf()).into());
+        }
+
+        drop(file);
+
+        if self.shared {
+            set_shared_mode(&tmp)?;
+        }
+
+        fs::rename(tmp, target).map_err(|e| (e, target.to_path_buf()))?;
+
+        Ok(())
+    }
+
+    /// A unique path in `TMP_DIR` to write `target` to before renaming it into place.
+    fn tmp_path(
+        &self,
+        target: &Path,
+    ) -> Result<PathBuf, EvsError> {
+        let tmp_dir = self.path.join(TMP_DIR);
+
+        if !tmp_dir.is_dir() {
+            DirBuilder::new()
+                .recursive(true)
+                .create(&tmp_dir)
+                .map_err(|e| (e, tmp_dir.clone()))?;
+
+            if self.shared {
+                set_shared_mode(&tmp_dir)?;
+            }
+        }
+
+        let tmp = tmp_dir.join(format!(
+            "{}-{}-{:?}-{}",
+            target.file_name().unwrap().display(),
+            process::id(),
+            thread::current().id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+        ));
+
+        trace!("Using temporary path {:?}.", tmp);
+
+        Ok(tmp)
+    }
+
+    /// Reads, decompresses and validates the object at `target`, returning the serialized object.
+    fn read_object(
+        &self,
+        target: &Path,
+        target_name: &OsStr,
+    ) -> Result<(Hash, Vec<u8>), EvsError> {
+        self.read_object_at(target, target_name, 0)
+    }
+
+    /// Like `read_object`, with `depth` deltas already read to get here.
+    fn read_object_at(
+        &self,
+        target: &Path,
+        target_name: &OsStr,
+        depth: usize,
+    ) -> Result<(Hash, Vec<u8>), EvsError> {
+        if size_of_val(target_name) != FORMATTED_HASH_SIZE
+            || !is_lower_hex(target_name.as_encoded_bytes())
+        {
+            return Err(EvsError::CorruptStateDetected(
+                CorruptState::InvalidObjectName(target_name.to_owned()),
+            ));
+        }
+
+        trace!("Found object {:?}.", target);
+
+        // The name was validated above
+        let hash = target_name.to_str().and_then(parse_hash).unwrap();
+
+        let content = self.object_bytes(target, &hash)?;
+
+        trace!("Read object of compressed size {}.", content.len());
+
+        let decompressed = if let Some((&DELTA_MARKER, stored)) = content.split_first() {
+            self.read_delta(target, hash, stored, depth)?
+        } else {
+            Self::decompress(&content).map_err(|e| {
+                EvsError::CorruptStateDetected(CorruptState::InvalidCompression(
+                    target.to_path_buf(),
+                    e,
+                ))
+            })?
+        };
+
+        drop(content);
+
+        trace!("Decompressed to size {}.", decompressed.len());
+
+        let real_hash = self.algo.digest(&decompressed);
+
+        if *target_name != *format!("{}", HashDisplay(&real_hash)) {
+            return Err(EvsError::CorruptStateDetected(CorruptState::HashMismatch(
+                target_name.to_owned(),
+                real_hash.to_vec(),
+            )));
+        }
+
+        trace!("Validated hash.");
+
+        Ok((real_hash, decompressed))
+    }
+
+    /// Rebuilds the serialized object `hash` from the stored delta behind `DELTA_MARKER`.
+    fn read_delta(
+        &self,
+        target: &Path,
+        hash: Hash,
+        stored: &[u8],
+        depth: usize,
+    ) -> Result<Vec<u8>, EvsError> {
+        let decompressed = Self::decompress(stored).map_err(|e| {
+            EvsError::CorruptStateDetected(CorruptState::InvalidCompression(
+                target.to_path_buf(),
+                e,
+            ))
+        })?;
+
+        let Object::Delta { base, patch } =
+            rmp_serde::from_slice::<Object>(&decompressed).map_err(|e| (e, hash))?
+        else {
+            return Err(EvsError::CorruptStateDetected(CorruptState::InvalidDelta(
+                hash,
+                "it is marked as one but holds another object",
+            )));
+        };
+
+        if depth >= MAX_DELTA_DEPTH {
+            return Err(EvsError::CorruptStateDetected(CorruptState::InvalidDelta(
+                hash,
+                "its chain of bases is too long",
+            )));
+        }
+
+        let base_name = format!("{}", HashDisplay(&base));
+
+        let base_path = self.object_path(&base_name);
+
+        if !self.contains(&base) {
+            return Err(EvsError::CorruptStateDetected(CorruptState::InvalidDelta(
+                hash,
+                "its base is missing",
+            )));
+        }
+
+        trace!("Reading delta base \"{}\".", base_name);
+
+        let (_, base_data) =
+            self.read_object_at(&base_path, OsStr::new(&base_name), depth.saturating_add(1))?;
+
+        delta::apply(&base_data, &patch).ok_or(EvsError::CorruptStateDetected(
+            CorruptState::InvalidDelta(hash, "its patch does not fit the base"),
+        ))
+    }
+
+    /// The base of the object `hash` at `target` if it is stored as a delta.
+    fn delta_base(
+        &self,
+        target: &Path,
+        hash: Hash,
+    ) -> Result<Option<Hash>, EvsError> {
+        Self::stored_delta_base(target, hash, &self.object_bytes(target, &hash)?)
+    }
+
+    /// Like `delta_base`, for stored bytes that were already read.
+    fn stored_delta_base(
+        target: &Path,
+        hash: Hash,
+        content: &[u8],
+    ) -> Result<Option<Hash>, EvsError> {
+        let Some((&DELTA_MARKER, stored)) = content.split_first() else {
+            return Ok(None);
+        };
+
+        let decompressed = Self::decompress(stored).map_err(|e| {
+            EvsError::CorruptStateDetected(CorruptState::InvalidCompression(
+                target.to_path_buf(),
+                e,
+            ))
+        })?;
+
+        match rmp_serde::from_slice::<Object>(&decompressed).map_err(|e| (e, hash))? {
+            Object::Delta { base, .. } => Ok(Some(base)),
+            _ => Err(EvsError::CorruptStateDetected(CorruptState::InvalidDelta(
+                hash,
+                "it is marked as one but holds another object",
+            ))),
+        }
+    }
+
+    /// Hashes, compresses and writes a serialized object unless it is already stored.
+    fn insert_data(
+        &self,
+        data: &[u8],
+    ) -> Result<Hash, EvsError> {
+        let hash = self.algo.digest(data);
+
+        let hash_display = format!("{}", HashDisplay(&hash));
+
+        trace!("Data hashed to \"{}\".", hash_display);
+
+        if self.contains(&hash) {
+            trace!("Object exists, assuming it is valid.");
+
+            return Ok(hash);
+        }
+
+        let compressed = self.compress(data);
+
+        trace!(
+            "Compressed data from {} to {} bytes.",
+            data.len(),
+            compressed.len()
+        );
+
+        trace!("Object path does not exist, inserting...");
+
+        self.write_object(&self.object_path(&hash_display), &compressed)?;
+
+        trace!("Wrote object to store.");
+
+        Ok(hash)
+    }
+
+    /// Like `lookup_uncached`, but streams the serialized object instead of decoding it into memory.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn read_raw(
+        &self,
+        parent: &Span,
+        id: &str,
+    ) -> Result<(Hash, RawObject), EvsError> {
+        debug!("Store::read_raw(self, \"{}\")", id);
+
+        let Some(target) = self.find(id)? else {
+            return Err(EvsError::ObjectNotInStore(id.to_owned()));
+        };
+
+        let name = self.object_name(&target);
+
+        let Some(hash) = name.to_str().and_then(parse_hash) else {
+            return Err(EvsError::CorruptStateDetected(
+                CorruptState::InvalidObjectName(name),
+            ));
+        };
+
+        if self.is_packed(&hash) {
+            trace!("Object is packed, reading it at once.");
+
+            let (_, data) = self.read_object(&target, &name)?;
+
+            return Ok((
+                hash,
+                RawObject {
+                    source: RawSource::Rebuilt(io::Cursor::new(data)),
+                    path: target,
+                    hash,
+                    hasher: Some(Hasher::new(self.algo)),
+                },
+            ));
+        }
+
+        let mut reader = BufReader::new(File::open(&target).map_err(|e| (e, target.clone()))?);
+
+        let source = match reader.fill_buf().map_err(|e| (e, target.clone()))?.first() {
+            Some(&RAW_MARKER) => {
+                trace!("Object is stored raw.");
+
+                reader.consume(1);
+
+                RawSource::Raw(reader)
+            }
+            Some(&DELTA_MARKER) => {
+                trace!("Object is stored as a delta, rebuilding it.");
+
+                drop(reader);
+
+                let (_, data) = self.read_object(&target, &name)?;
+
+                RawSource::Rebuilt(io::Cursor::new(data))
+            }
+            _ => RawSource::Gzip(Box::new(GzDecoder::new(reader))),
+        };
+
+        Ok((
+            hash,
+            RawObject {
+                source,
+                path: target,
+                hash,
+                hasher: Some(Hasher::new(self.algo)),
+            },
+        ))
+    }
+
+    /// Reads the object file of `hash` as it is stored, see `decompress`.
+    /// Deltas are rebuilt and compressed on their own, so the result never needs another object.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn read_stored(
+        &self,
+        parent: &Span,
+        hash: Hash,
+    ) -> Result<Vec<u8>, EvsError> {
+        let name = format!("{}", HashDisplay(&hash));
+
+        debug!("Store::read_stored(self, \"{}\")", name);
+
+        let target = self.object_path(&name);
+
+        if !self.contains(&hash) {
+            return Err(EvsError::ObjectNotInStore(name));
+        }
+
+        let content = self.object_bytes(&target, &hash)?;
+
+        if content.first() == Some(&DELTA_MARKER) {
+            trace!("Object is stored as a delta, rebuilding it.");
+
+            return Ok(self.compress(&self.read_object(&target, OsStr::new(&name))?.1));
+        }
+
+        Ok(content.to_vec())
+    }
+
+    /// Undoes the compression of a stored object, returning the serialized object.
+    #[inline]
+    pub fn decompress(stored: &[u8]) -> io::Result<Vec<u8>> {
+        if let Some((&RAW_MARKER, raw)) = stored.split_first() {
+            trace!("Object is stored raw.");
+
+            return Ok(raw.to_vec());
+        }
+
+        let mut decompressed = vec![];
+
+        GzDecoder::new(stored).read_to_end(&mut decompressed)?;
+
+        Ok(decompressed)
+    }
+
+    /// Inserts a serialized object after checking that it decodes.
+    /// Unlike `insert`, this keeps the exact encoding, so objects written by older versions keep their hash.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn insert_serialized(
+        &self,
+        parent: &Span,
+        data: &[u8],
+    ) -> Result<Hash, EvsError> {
+        debug!("Store::insert_serialized(self, <{} byte(s)>)", data.len());
+
+        rmp_serde::from_slice::<Object>(data).map_err(|e| (e, self.algo.digest(data)))?;
+
+        self.insert_data(data)
+    }
+
+    /// Returns when the object file was last written, for packed objects when their pack was.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn modified(
+        &self,
+        parent: &Span,
+        hash: Hash,
+    ) -> Result<SystemTime, EvsError> {
+        debug!("Store::modified(self, \"{}\")", HashDisplay(&hash));
+
+        let path = match self.packed(&hash) {
+            Some((pack, _, _)) => pack,
+            None => self.object_path(&format!("{}", HashDisplay(&hash))),
+        };
+
+        fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .map_err(|e| (e, path).into())
+    }
+
+    /// Rewrites every object with the current compression setting and returns the
+    /// number of rewritten objects and the total size before and after.
+    /// Deltas are kept as they are, see `pack_delta`, packs are rewritten if any of their objects changes.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn recompress(
+        &self,
+        parent: &Span,
+    ) -> Result<(usize, usize, usize), EvsError> {
+        debug!("Store::recompress(self)");
+
+        let current = Span::current();
+
+        let loose = self
+            .objects()?
+            .into_par_iter()
+            .map(|target| {
+                let _entered = current.enter();
+
+                let old = fs::read(&target).map_err(|e| (e, target.clone()))?;
+
+                let before = old.len();
+
+                let Some(compressed) = self.recompressed(&target, &old)? else {
+                    return Ok::<_, EvsError>((0usize, before, before));
+                };
+
+                self.write_object(&target, &compressed)?;
+
+                trace!(
+                    "Recompressed {:?} from {} to {} bytes.",
+                    target,
+                    before,
+                    compressed.len()
+                );
+
+                Ok((1, before, compressed.len()))
+            })
+            .try_reduce(
+                || (0, 0, 0),
+                |a, b| {
+                    Ok((
+                        a.0.saturating_add(b.0),
+                        a.1.saturating_add(b.1),
+                        a.2.saturating_add(b.2),
+                    ))
+                },
+            )?;
+
+        let packed = self.recompress_packs()?;
+
+        Ok((
+            loose.0.saturating_add(packed.0),
+            loose.1.saturating_add(packed.1),
+            loose.2.saturating_add(packed.2),
+        ))
+    }
+
+    /// Like `recompress`, but for the packed objects, writing a new pack for each pack with a changed object.
+    fn recompress_packs(&self) -> Result<(usize, usize, usize), EvsError> {
+        let packs = self
+            .packs
+            .read()
+            .unwrap()
+            .iter()
+            .map(|pack| (pack.path.clone(), pack.index.clone()))
+            .collect::<Vec<_>>();
+
+        let mut total = (0usize, 0usize, 0usize);
+
+        for (path, index) in packs {
+            let read = |&(hash, offset, len): &PackEntry| {
+                let old = Pack::read_at(&path, offset, len)?;
+
+                let target = self.object_path(&format!("{}", HashDisplay(&hash)));
+
+                Ok::<_, EvsError>((hash, self.recompressed(&target, &old)?, old))
+            };
+
+            let stored = index.iter().fold(0usize, |size, &(_, _, len)| {
+                size.saturating_add(usize::try_from(len).unwrap_or(usize::MAX))
+            });
+
+            if !index.iter().try_fold(false, |found, entry| {
+                Ok::<_, EvsError>(found || read(entry)?.1.is_some())
+            })? {
+                trace!("Pack {:?} is already compressed.", path);
+
+                total = (
+                    total.0,
+                    total.1.saturating_add(stored),
+                    total.2.saturating_add(stored),
+                );
+
+                continue;
+            }
+
+            let (mut count, mut after) = (0usize, 0usize);
+
+            let target = self.write_pack(index.iter().map(|entry| {
+                let (hash, compressed, old) = read(entry)?;
+
+                let content = compressed
+                    .inspect(|_| count = count.saturating_add(1))
+                    .unwrap_or(old);
+
+                after = after.saturating_add(content.len());
+
+                Ok((hash, content))
+            }))?;
+
+            trace!(
+                "Rewrote pack {:?} as {:?} with {} recompressed object(s).",
+                path, target, count
+            );
+
+            if target != path {
+                self.packs.write().unwrap().retain(|pack| pack.path != path);
+
+                fs::remove_file(&path).map_err(|e| (e, path.clone()))?;
+            }
+
+            total = (
+                total.0.saturating_add(count),
+                total.1.saturating_add(stored),
+                total.2.saturating_add(after),
+            );
+        }
+
+        Ok(total)
+    }
+
+    /// The stored bytes `old` of the object at `target` with the current compression setting,
+    /// or `None` if they stay as they are.
+    fn recompressed(
+        &self,
+        target: &Path,
+        old: &[u8],
+    ) -> Result<Option<Vec<u8>>, EvsError> {
+        if old.first() == Some(&DELTA_MARKER) {
+            trace!("Keeping delta {:?}.", target);
+
+            return Ok(None);
+        }
+
+        if Self::stored_level(old) == Some(self.compression) {
+            trace!("Object {:?} is already compressed.", target);
+
+            return Ok(None);
+        }
+
+        let name = self.object_name(target);
+
+        let decompressed = Self::decompress(old).map_err(|e| {
+            EvsError::CorruptStateDetected(CorruptState::InvalidCompression(
+                target.to_path_buf(),
+                e,
+            ))
+        })?;
+
+        let hash = self.algo.digest(&decompressed);
+
+        if *name != *format!("{}", HashDisplay(&hash)) {
+            return Err(EvsError::CorruptStateDetected(CorruptState::HashMismatch(
+                name,
+                hash.to_vec(),
+            )));
+        }
+
+        let compressed = self.compress(&decompressed);
+
+        if old == compressed {
+            trace!("Object \"{}\" stays raw.", HashDisplay(&hash));
+
+            return Ok(None);
+        }
+
+        Ok(Some(compressed))
+    }
+
+    /// Stores `target` as a delta against `base` if that makes its file smaller and returns the size before and after.
+    /// Packed objects and ones that already are deltas are kept, as are the ones whose base chain would get too long or cyclic.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn pack_delta(
+        &self,
+        parent: &Span,
+        target: Hash,
+        base: Hash,
+    ) -> Result<Option<(usize, usize)>, EvsError> {
+        debug!(
+            "Store::pack_delta(self, \"{}\", \"{}\")",
+            HashDisplay(&target),
+            HashDisplay(&base)
+        );
+
+        let target_name = format!("{}", HashDisplay(&target));
+        let base_name = format!("{}", HashDisplay(&base));
+
+        let target_path = self.object_path(&target_name);
+        let base_path = self.object_path(&base_name);
+
+        if self.is_packed(&target) {
+            trace!("Object is packed, its pack is never rewritten for a delta.");
+
+            return Ok(None);
+        }
+
+        if self.delta_base(&target_path, target)?.is_some() {
+            trace!("Object is a delta already.");
+
+            return Ok(None);
+        }
+
+        let mut depth = 1usize;
+        let mut next = Some(base);
+
+        while let Some(hash) = next {
+            if hash == target || depth > MAX_DELTA_DEPTH {
+                trace!("Base chain would be cyclic or too long.");
+
+                return Ok(None);
+            }
+
+            next = self.delta_base(&self.object_path(&format!("{}", HashDisplay(&hash))), hash)?;
+
+            if next.is_some() {
+                depth = depth.saturating_add(1);
+            }
+        }
+
+        let (_, target_data) = self.read_object(&target_path, OsStr::new(&target_name))?;
+        let (_, base_data) = self.read_object(&base_path, OsStr::new(&base_name))?;
+
+        let patch = delta::encode(&base_data, &target_data);
+
+        let mut stored = vec![DELTA_MARKER];
+
+        stored
+            .extend_from_slice(&self.compress(&rmp_serde::to_vec(&Object::Delta { base, patch })?));
+
+        let before = usize::try_from(
+            fs::metadata(&target_path)
+                .map_err(|e| (e, target_path.clone()))?
+                .len(),
+        )
+        .unwrap_or(usize::MAX);
+
+        if stored.len() >= before {
+            trace!(
+                "Delta of {} bytes does not shrink {} bytes.",
+                stored.len(),
+                before
+            );
+
+            return Ok(None);
+        }
+
+        self.write_object(&target_path, &stored)?;
+
+        trace!(
+            "Packed \"{}\" from {} to {} bytes.",
+            target_name,
+            before,
+            stored.len()
+        );
+
+        Ok(Some((before, stored.len())))
+    }
+
+    /// Rewrites every delta against one of `removed` as the full object, so `removed` can be deleted.
+    /// Returns the number of rewritten deltas.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn expand_deltas(
+        &self,
+        parent: &Span,
+        removed: &AHashSet<Hash>,
+    ) -> Result<usize, EvsError> {
+        debug!("Store::expand_deltas(self, <{} hash(es)>)", removed.len());
+
+        let current = Span::current();
+
+        self.objects()?
+            .into_par_iter()
+            .map(|target| {
+                let _entered = current.enter();
+
+                let name = self.object_name(&target);
+
+                let Some(hash) = name.to_str().and_then(parse_hash) else {
+                    return Ok(0usize);
+                };
+
+                if removed.contains(&hash)
+                    || !self
+                        .delta_base(&target, hash)?
+                        .is_some_and(|base| removed.contains(&base))
+                {
+                    return Ok(0);
+                }
+
+                let (_, data) = self.read_object(&target, &name)?;
+
+                self.write_object(&target, &self.compress(&data))?;
+
+                trace!("Expanded delta \"{}\".", HashDisplay(&hash));
+
+                Ok(1)
+            })
+            .try_reduce(|| 0, |a, b| Ok(a.saturating_add(b)))
+    }
+
+    /// Moves the loose objects among `hashes` into a new pack file, packed ones stay where they are.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn pack(
+        &self,
+        parent: &Span,
+        hashes: &[Hash],
+    ) -> Result<(), EvsError> {
+        debug!("Store::pack(self, <{} hash(es)>)", hashes.len());
+
+        let mut loose = hashes
+            .iter()
+            .copied()
+            .filter(|hash| !self.is_packed(hash))
+            .collect::<Vec<_>>();
+
+        loose.sort_unstable();
+        loose.dedup();
+
+        if loose.is_empty() {
+            trace!("Nothing to pack.");
+
+            return Ok(());
+        }
+
+        let paths = loose
+            .iter()
+            .map(|hash| self.object_path(&format!("{}", HashDisplay(hash))))
+            .collect::<Vec<_>>();
+
+        self.write_pack(
+            loose
+                .iter()
+                .zip(&paths)
+                .map(|(&hash, path)| Ok((hash, ObjectBytes::read(path)?.to_vec()))),
+        )?;
+
+        for path in paths {
+            fs::remove_file(&path).map_err(|e| (e, path.clone()))?;
+        }
+
+        trace!("Removed {} packed loose object(s).", loose.len());
+
+        Ok(())
+    }
+
+    /// Rewrites every pack holding one of `removed` without it, expanding the deltas against one of them.
+    /// Returns the number and the stored size of the dropped objects.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn drop_packed(
+        &self,
+        parent: &Span,
+        removed: &AHashSet<Hash>,
+    ) -> Result<(usize, usize), EvsError> {
+        debug!("Store::drop_packed(self, <{} hash(es)>)", removed.len());
+
+        let packs = self
+            .packs
+            .read()
+            .unwrap()
+            .iter()
+            .map(|pack| (pack.path.clone(), pack.index.clone()))
+            .collect::<Vec<_>>();
+
+        let mut dropped = (0usize, 0usize);
+
+        for (path, index) in packs {
+            // The stored bytes of a kept object and whether they have to be expanded
+            let survivor = |&(hash, offset, len): &PackEntry| {
+                let content = Pack::read_at(&path, offset, len)?;
+
+                let target = self.object_path(&format!("{}", HashDisplay(&hash)));
+
+                let expand = Self::stored_delta_base(&target, hash, &content)?
+                    .is_some_and(|base| removed.contains(&base));
+
+                Ok::<_, EvsError>((content, expand))
+            };
+
+            let kept = index
+                .iter()
+                .filter(|entry| !removed.contains(&entry.0))
+                .collect::<Vec<_>>();
+
+            if kept.len() == index.len()
+                && !kept.iter().try_fold(false, |found, entry| {
+                    Ok::<_, EvsError>(found || survivor(entry)?.1)
+                })?
+            {
+                continue;
+            }
+
+            trace!("Rewriting pack {:?} with {} object(s).", path, kept.len());
+
+            if !kept.is_empty() {
+                self.write_pack(kept.into_iter().map(|entry| {
+                    let (content, expand) = survivor(entry)?;
+
+                    if !expand {
+                        return Ok((entry.0, content));
+                    }
+
+                    let name = format!("{}", HashDisplay(&entry.0));
+
+                    trace!("Expanding packed delta \"{}\".", name);
+
+                    let (_, data) =
+                        self.read_object(&self.object_path(&name), OsStr::new(&name))?;
+
+                    Ok((entry.0, self.compress(&data)))
+                }))?;
+            }
+
+            self.packs.write().unwrap().retain(|pack| pack.path != path);
+
+            fs::remove_file(&path).map_err(|e| (e, path.clone()))?;
+
+            for &(hash, _, len) in index.iter().filter(|entry| removed.contains(&entry.0)) {
+                if let Some(cache) = &self.cache {
+                    cache.lock().unwrap().pop(&hash);
+                }
+
+                dropped = (
+                    dropped.0.saturating_add(1),
+                    dropped
+                        .1
+                        .saturating_add(usize::try_from(len).unwrap_or(usize::MAX)),
+                );
+            }
+        }
+
+        Ok(dropped)
+    }
+
+    /// Writes the stored bytes of objects in hash order into a new pack file and starts using it.
+    fn write_pack<I: IntoIterator<Item = Result<(Hash, Vec<u8>), EvsError>>>(
+        &self,
+        objects: I,
+    ) -> Result<PathBuf, EvsError> {
+        let dir = self.path.join(PACK_DIR);
+
+        let tmp = self.tmp_path(&dir.join(PACK_EXTENSION))?;
+
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&tmp)
+            .map_err(|e| (e, tmp.clone()))?;
+
+        let (index, id) = match self.write_pack_to(&mut BufWriter::new(file), &tmp, objects) {
+            Ok(written) => written,
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+
+                return Err(e);
+            }
+        };
+
+        debug_assert!(
+            index.is_sorted_by_key(|entry| entry.0),
+            "Packs are written in hash order"
+        );
+
+        let target = dir.join(format!("{}.{}", HashDisplay(&id), PACK_EXTENSION));
+
+        Self::create_shard(&target, self.shared)?;
+
+        if self.shared {
+            set_shared_mode(&tmp)?;
+        }
+
+        fs::rename(&tmp, &target).map_err(|e| (e, target.clone()))?;
+
+        trace!("Wrote pack {:?} of {} object(s).", target, index.len());
+
+        let mut packs = self.packs.write().unwrap();
+
+        packs.retain(|pack| pack.path != target);
+
+        packs.push(Pack {
+            path: target.clone(),
+            index,
+        });
+
+        Ok(target)
+    }
+
+    /// Writes a pack to `writer` and returns its index and its id, the digest of the index.
+    fn write_pack_to<W: io::Write, I: IntoIterator<Item = Result<(Hash, Vec<u8>), EvsError>>>(
+        &self,
+        writer: &mut W,
+        tmp: &Path,
+        objects: I,
+    ) -> Result<(Vec<PackEntry>, Hash), EvsError> {
+        let failed = |e| EvsError::from((e, tmp.to_path_buf()));
+
+        writer.write_all(PACK_MAGIC).map_err(failed)?;
+        writer
+            .write_all(&PACK_VERSION.to_le_bytes())
+            .map_err(failed)?;
+
+        let mut offset = u64::try_from(PACK_HEADER_SIZE).unwrap();
+        let mut index = Vec::new();
+
+        for object in objects {
+            let (hash, content) = object?;
+
+            writer.write_all(&content).map_err(failed)?;
+
+            let len = u64::try_from(content.len()).unwrap();
+
+            index.push((hash, offset, len));
+
+            offset = offset.saturating_add(len);
+        }
+
+        let encoded = rmp_serde::to_vec(&index)?;
+
+        writer.write_all(&encoded).map_err(failed)?;
+        writer.write_all(&offset.to_le_bytes()).map_err(failed)?;
+        writer.flush().map_err(failed)?;
+
+        Ok((index, self.algo.digest(&encoded)))
+    }
+
+    /// Moves the object files with the given names into `target` and returns their new paths.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn quarantine(
+        &self,
+        parent: &Span,
+        names: &[&OsStr],
+        target: &Path,
+    ) -> Result<Vec<PathBuf>, EvsError> {
+        debug!(
+            "Store::quarantine(self, <{} name(s)>, {:?})",
+            names.len(),
+            target
+        );
+
+        if !target.is_dir() {
+            DirBuilder::new()
+                .recursive(true)
+                .create(target)
+                .map_err(|e| (e, target.to_path_buf()))?;
+
+            if self.shared {
+                set_shared_mode(target)?;
+            }
+        }
+
+        let mut moved = Vec::new();
+
+        // Invalid names can not be mapped to a path, so they are looked up in the listing
+        for object in self.objects()? {
+            let name = self.object_name(&object);
+
+            if !names.contains(&name.as_os_str()) {
+                continue;
+            }
+
+            if let Some(cache) = &self.cache
+                && let Some(hash) = name.to_str().and_then(parse_hash)
+            {
+                cache.lock().unwrap().pop(&hash);
+            }
+
+            let destination = target.join(&name);
+
+            fs::rename(&object, &destination).map_err(|e| (e, object.clone()))?;
+
+            trace!("Moved {:?} to {:?}.", object, destination);
+
+            moved.push(destination);
+        }
+
+        Ok(moved)
+    }
+
+    /// Reads and validates every object once to count them by kind and sum their sizes.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn stats(
+        &self,
+        parent: &Span,
+    ) -> Result<StoreStats, EvsError> {
+        debug!("Store::stats(self)");
+
+        let current = Span::current();
+
+        let mut objects = self.objects()?;
+
+        objects.extend(self.packed_objects());
+
+        objects
+            .into_par_iter()
+            .map(|target| {
+                let _entered = current.enter();
+
+                let name = self.object_name(&target);
+
+                let compressed = match name
+                    .to_str()
+                    .and_then(parse_hash)
+                    .and_then(|hash| self.packed(&hash))
+                {
+                    Some((_, _, len)) => len,
+                    None => fs::metadata(&target)
+                        .map_err(|e| (e, target.clone()))?
+                        .len(),
+                };
+
+                let compressed = usize::try_from(compressed).unwrap_or(usize::MAX);
+
+                let (hash, decompressed) = self.read_object(&target, &name)?;
+
+                let kind = rmp_serde::from_slice::<Object>(&decompressed)
+                    .map_err(|e| (e, hash))?
+                    .kind();
+
+                trace!("Counted {} \"{}\".", kind, HashDisplay(&hash));
+
+                Ok(StoreStats {
+                    count: 1,
+                    kinds: BTreeMap::from([(kind, 1)]),
+                    compressed,
+                    decompressed: decompressed.len(),
+                })
+            })
+            .try_reduce(StoreStats::default, |a, b| Ok(a.merge(b)))
+    }
+
+    /// Hashes the names, sizes and modification times of all object and pack files, so any change to the store changes it.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    pub fn fingerprint(
+        &self,
+        parent: &Span,
+    ) -> Result<Hash, EvsError> {
+        debug!("Store::fingerprint(self)");
+
+        let mut files = self.objects()?;
+
+        files.extend(
+            self.packs
+                .read()
+                .unwrap()
+                .iter()
+                .map(|pack| pack.path.clone()),
+        );
+
+        let mut entries = files
+            .into_iter()
+            .map(|entry| {
+                let metadata = fs::metadata(&entry).map_err(|e| (e, entry.clone()))?;
+
+                let modified = metadata
+                    .modified()
+                    .map_err(|e| (e, entry.clone()))?
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+
+                Ok((
+                    self.object_name(&entry),
+                    metadata.len(),
+                    modified.as_nanos(),
+                ))
+            })
+            .collect::<Result<Vec<_>, EvsError>>()?;
+
+        entries.sort_unstable();
+
+        trace!("Fingerprinting {} object(s).", entries.len());
+
+        let mut hasher = Sha256::new();
+
+        for (name, size, modified) in entries {
+            hasher.update(name.as_encoded_bytes());
+            hasher.update(size.to_le_bytes());
+            hasher.update(modified.to_le_bytes());
+        }
+
+        Ok(hasher.finalize().into())
+    }
+}
+
+impl ObjectStore for Store {
+    /// Reads the indexes of all pack files, lookups only find packed objects afterwards.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn load_packs(
+        &self,
+        parent: &Span,
+    ) -> Result<(), EvsError> {
+        debug!("Store::load_packs(self)");
+
+        let dir = self.path.join(PACK_DIR);
+
+        if !dir.is_dir() {
+            trace!("No pack directory.");
+
+            return Ok(());
+        }
+
+        let mut packs = Vec::new();
+
+        for entry in dir.read_dir().map_err(|e| (e, dir.clone()))? {
+            let entry = entry.map_err(|e| (e, dir.clone()))?.path();
+
+            if entry.extension() == Some(OsStr::new(PACK_EXTENSION)) {
+                packs.push(Pack::open(entry)?);
+            }
+        }
+
+        trace!("Loaded {} pack(s).", packs.len());
+
+        *self.packs.write().unwrap() = packs;
+
+        Ok(())
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn status(
+        &self,
+        parent: &Span,
+    ) -> Result<(usize, usize), EvsError> {
+        debug!("Store::status(self)");
+
+        let packed = self
+            .packs
+            .read()
+            .unwrap()
+            .iter()
+            .flat_map(|pack| &pack.index)
+            .fold((0usize, 0usize), |(count, size), &(_, _, len)| {
+                (
+                    count.saturating_add(1),
+                    size.saturating_add(usize::try_from(len).unwrap_or(usize::MAX)),
+                )
+            });
+
+        self.objects()?
+            .into_iter()
+            .try_fold(packed, |(count, size), entry| {
+                #[allow(clippy::arithmetic_side_effects, reason = "Never going to happen.")]
+                Ok((
+                    count + 1,
+                    size.saturating_add(
+                        usize::try_from(fs::metadata(&entry).map_err(|e| (e, entry))?.len())
+                            .unwrap(),
+                    ),
+                ))
+            })
+    }
+    #[inline]
+    fn algo(&self) -> HashAlgo {
+        self.algo
+    }
+
+    /// Inserts a file as a blob, or as a chunk list if it is larger than the chunk size, holding one chunk at a time.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn insert_file(
+        &self,
+        parent: &Span,
+        path: &Path,
+        len: u64,
+    ) -> Result<Hash, EvsError> {
+        debug!("Store::insert_file(self, {:?}, {})", path, len);
+
+        let current = Span::current();
+
+        #[cfg(test)]
+        self.files_read.fetch_add(1, Ordering::Relaxed);
+
+        if len <= self.chunk_size {
+            let content = fs::read(path).map_err(|e| (e, path.to_owned()))?;
+
+            return self.insert(&current, Object::Blob(content));
+        }
+
+        let mut reader = BufReader::new(File::open(path).map_err(|e| (e, path.to_owned()))?);
+
+        let mut chunks = Vec::new();
+
+        loop {
+            let mut chunk = Vec::new();
+
+            (&mut reader)
+                .take(self.chunk_size)
+                .read_to_end(&mut chunk)
+                .map_err(|e| (e, path.to_owned()))?;
+
+            if chunk.is_empty() {
+                break;
+            }
+
+            chunks.push(self.insert(&current, Object::Blob(chunk))?);
+        }
+
+        trace!("Split file into {} chunk(s).", chunks.len());
+
+        self.insert(&current, Object::ChunkList(chunks))
+    }
+
+    /// Only checks for existence and not validity.
+    #[inline]
+    fn contains(
+        &self,
+        hash: &Hash,
+    ) -> bool {
+        self.is_packed(hash)
+            || self
+                .object_path(&format!("{}", HashDisplay(hash)))
+                .try_exists()
+                .unwrap_or(false)
+    }
+
+    /// Assumes a valid store and might cause unintended behaviour
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn insert(
+        &self,
+        parent: &Span,
+        mut obj: Object,
+    ) -> Result<Hash, EvsError> {
+        debug!("Store::insert(self, ...)");
+
+        if let Object::Tree(entries) = &mut obj {
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+
+        let data = rmp_serde::to_vec(&obj)?;
+
+        trace!("Serialized object to size {}.", data.len());
+
+        self.insert_data(&data)
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn lookup_raw(
+        &self,
+        parent: &Span,
+        id: &str,
+    ) -> Result<(Hash, Object), EvsError> {
+        if size_of_val(id) > FORMATTED_HASH_SIZE {
+            debug!("Store::lookup_raw(self, <overlength hash>)");
+
+            return Err(EvsError::ObjectNotInStore(id.to_owned()));
+        }
+
+        debug!(
+            "Store::lookup_raw(self, \"{}{}\")",
+            id,
+            if size_of_val(id) < FORMATTED_HASH_SIZE {
+                "..."
+            } else {
+                ""
+            }
+        );
+
+        let Some(cache) = &self.cache else {
+            return self.lookup_uncached(&Span::current(), id);
+        };
+
+        if let Some(hash) = parse_hash(id)
+            && let Some(obj) = cache.lock().unwrap().get(&hash)
+        {
+            trace!("Found cached object.");
+
+            return Ok((hash, obj.clone()));
+        }
+
+        let (hash, obj) = self.lookup_uncached(&Span::current(), id)?;
+
+        if !matches!(obj, Object::Blob(_)) {
+            cache.lock().unwrap().put(hash, obj.clone());
+        }
+
+        Ok((hash, obj))
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn lookup_uncached(
+        &self,
+        parent: &Span,
+        id: &str,
+    ) -> Result<(Hash, Object), EvsError> {
+        debug!("Store::lookup_uncached(self, \"{}\")", id);
+
+        let Some(target) = self.find(id)? else {
+            return Err(EvsError::ObjectNotInStore(id.to_owned()));
+        };
+
+        let (real_hash, decompressed) = self.read_object(&target, &self.object_name(&target))?;
+
+        let deserialized =
+            rmp_serde::from_slice::<Object>(&decompressed).map_err(|e| (e, real_hash))?;
+
+        trace!("Deserialized successfully.");
+
+        Ok((real_hash, deserialized))
+    }
+
+    /// Returns the size of the deleted object file, packed objects are dropped from their pack.
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn remove(
+        &self,
+        parent: &Span,
+        hash: Hash,
+    ) -> Result<usize, EvsError> {
+        debug!("Store::remove(self, \"{}\")", HashDisplay(&hash));
 
-        let path = self.path.join(format!("{}", HashDisplay(&path)));
+        if self.is_packed(&hash) {
+            return Ok(self
+                .drop_packed(&Span::current(), &AHashSet::from([hash]))?
+                .1);
+        }
+
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap().pop(&hash);
+        }
+
+        let path = self.object_path(&format!("{}", HashDisplay(&hash)));
 
-        trace!("Deleting {:?}", &path);
+        let size = usize::try_from(fs::metadata(&path).map_err(|e| (e, path.clone()))?.len())
+            .unwrap_or(usize::MAX);
+
+        trace!("Deleting {:?} of {} bytes", &path, size);
 
         fs::remove_file(&path).map_err(|e| (e, path))?;
 
-        Ok(())
+        Ok(size)
     }
 
     #[inline]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn resolve_rest(
+    fn resolve_rest(
         &self,
         parent: &Span,
         r#ref: String,
     ) -> Result<String, EvsError> {
         debug!("Store::resolve_rest(self, \"{}\")", r#ref);
 
-        let mut target = None;
+        let Some(target) = self.find(&r#ref)? else {
+            return Err(EvsError::ObjectNotInStore(r#ref));
+        };
+
+        trace!("Found object {:?}.", target);
 
-        if size_of_val(r#ref.as_str()) == FORMATTED_HASH_SIZE {
-            let path = self.path.join(&r#ref);
+        let target_name = self.object_name(&target);
 
-            trace!("Fast lookup of {:?}...", path);
+        if size_of_val(target_name.as_os_str()) != FORMATTED_HASH_SIZE
+            || !is_lower_hex(target_name.as_encoded_bytes())
+        {
+            return Err(EvsError::CorruptStateDetected(
+                CorruptState::InvalidObjectName(target_name),
+            ));
+        }
 
-            target = fs::exists(&path).is_ok().then_some(path);
-        } else {
-            trace!("Slow lookup...");
+        trace!("Validated name successfully.");
+
+        let resolved = target_name.into_string().unwrap();
+
+        Ok(resolved)
+    }
+
+    #[inline]
+    fn names(&self) -> Result<Vec<OsString>, EvsError> {
+        let mut names = self
+            .objects()?
+            .iter()
+            .chain(&self.packed_objects())
+            .map(|path| self.object_name(path))
+            .collect::<Vec<_>>();
+
+        names.sort_unstable();
+        names.dedup();
+
+        Ok(names)
+    }
+}
+
+/// With `collect`, unreadable objects end up in the report instead of failing the check.
+#[allow(clippy::too_many_lines, reason = "This is fine.")]
+fn check_objects<S: ObjectStore + ?Sized>(
+    store: &S,
+    verified: AHashSet<Hash>,
+    required: &[Hash],
+    all: bool,
+    collect: bool,
+) -> Result<CheckReport, EvsError> {
+    let current = Span::current();
+
+    let mut extra = AHashSet::new();
+
+    let state = CheckState {
+        verified,
+        found: Mutex::new(AHashSet::new()),
+        found_cache: Mutex::new(AHashSet::new()),
+        missing: Mutex::new(AHashSet::new()),
+        kinds: Mutex::new(AHashMap::new()),
+        references: Mutex::new(Vec::new()),
+        corrupt: collect.then(|| Mutex::new(Vec::new())),
+        progress: Progress::new("checking", None),
+    };
+
+    trace!("Initially required to find {} object(s).", required.len());
+
+    required
+        .par_iter()
+        .map(|item| check_one(store, &current, *item, &state))
+        .collect::<Result<(), EvsError>>()?;
+
+    state.progress.finish();
+
+    let mut found = state.found.into_inner().unwrap();
+    let missing = state.missing.into_inner().unwrap();
+
+    let found_cache = state.found_cache.into_inner().unwrap();
+
+    let mut corrupt = state
+        .corrupt
+        .map(|corrupt| corrupt.into_inner().unwrap())
+        .unwrap_or_default();
+
+    let kinds = state.kinds.into_inner().unwrap();
+
+    let blobs = kinds
+        .iter()
+        .filter(|&(_, &kind)| kind == "blob")
+        .map(|(hash, _)| *hash)
+        .collect();
+
+    for (referrer, referenced, (expected, allowed)) in state.references.into_inner().unwrap() {
+        if let Some(kind) = kinds.get(&referenced)
+            && !allowed.contains(kind)
+        {
+            return Err(EvsError::CorruptStateDetected(CorruptState::TypeMismatch(
+                referrer, referenced, kind, expected,
+            )));
+        }
+    }
+
+    trace!("All references have consistent types.");
+
+    if all {
+        let progress = Progress::new("scanning", None);
 
-            for obj in self.path.read_dir().map_err(|e| (e, self.path.clone()))? {
-                let obj = obj.map_err(|e| (e, self.path.clone()))?;
+        let unreadable = Mutex::new(Vec::new());
 
-                let name = obj.path();
+        extra = store
+            .names()?
+            .into_par_iter()
+            .filter_map(|name| {
+                let _entered = current.enter();
 
-                if let Some(hash) = name.file_name()
-                    && hash.as_encoded_bytes().starts_with(r#ref.as_bytes())
+                let bytes = name.as_encoded_bytes();
+
+                let result = if size_of_val(bytes) != FORMATTED_HASH_SIZE || name.to_str().is_none()
                 {
-                    trace!("Found {:?}.", hash);
+                    Err(EvsError::CorruptStateDetected(
+                        CorruptState::InvalidObjectName(name.clone()),
+                    ))
+                } else {
+                    let name = name.to_str().unwrap();
 
-                    if let Some(target) = target {
-                        return Err(EvsError::AmbiguousObject(
-                            r#ref,
-                            target.file_name().unwrap().to_os_string(),
-                        ));
+                    if found_cache.contains(name) {
+                        return None;
                     }
 
-                    target = Some(name);
-                }
+                    store.lookup_uncached(&current, name)
+                };
+
+                let hash = match result {
+                    Ok((hash, _)) => hash,
+                    Err(e @ EvsError::CorruptStateDetected(_)) if collect => {
+                        warn!("Unreadable {:?}: {}", name, e);
+                        unreadable.lock().unwrap().push((name, e));
+                        return None;
+                    }
+                    Err(e) => return Some(Err(e)),
+                };
+
+                progress.step();
+
+                trace!("Validated extra {:?}.", name);
+
+                Some(Ok(hash))
+            })
+            .collect::<Result<HashSet<Hash>, _>>()?
+            .into();
+
+        corrupt.extend(unreadable.into_inner().unwrap());
+
+        progress.finish();
+
+        found.extend(extra.iter());
+    }
+
+    let normal_found = found.difference(&extra).count();
+    #[allow(
+        clippy::arithmetic_side_effects,
+        reason = "Not going to happen + impossible."
+    )]
+    let normal_total = normal_found + missing.len();
+
+    trace!(
+        "Finished validating {}/{} (+{}) objects.",
+        normal_found,
+        normal_total,
+        extra.len(),
+    );
+
+    // Concurrent visits and the scan can both record the same object
+    corrupt.sort_by(|left, right| left.0.cmp(&right.0));
+    corrupt.dedup_by(|left, right| left.0 == right.0);
+
+    Ok(CheckReport {
+        found,
+        blobs,
+        extra,
+        missing,
+        corrupt,
+    })
+}
+
+#[allow(clippy::too_many_lines, reason = "This is fine.")]
+#[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+fn check_one<S: ObjectStore + ?Sized>(
+    store: &S,
+    parent: &Span,
+    hash: Hash,
+    state: &CheckState,
+) -> Result<(), EvsError> {
+    debug!("check_one(store, \"{}\")", HashDisplay(&hash));
+
+    let current = Span::current();
+
+    if state.found.lock().unwrap().contains(&hash) {
+        return Ok(());
+    }
+
+    let name = format!("{}", HashDisplay(&hash));
+
+    if state.verified.contains(&hash) {
+        match store.resolve_rest(&current, name.clone()) {
+            Ok(_) => {}
+            Err(EvsError::ObjectNotInStore(_)) => {
+                warn!("Missing \"{}\"", name);
+                state.missing.lock().unwrap().insert(hash);
+                return Ok(());
             }
+            Err(err) => return Err(err),
         }
 
-        if target.is_none() {
-            return Err(EvsError::ObjectNotInStore(r#ref));
+        state.found_cache.lock().unwrap().insert(name);
+
+        state.kinds.lock().unwrap().insert(hash, "blob");
+
+        state.progress.step();
+
+        trace!("Found verified blob \"{}\".", HashDisplay(&hash));
+
+        state.found.lock().unwrap().insert(hash);
+
+        return Ok(());
+    }
+
+    let (hash, obj) = match store.lookup_uncached(&current, &name) {
+        Ok(res) => res,
+        Err(EvsError::ObjectNotInStore(_)) => {
+            warn!("Missing \"{}\"", name);
+            state.missing.lock().unwrap().insert(hash);
+            return Ok(());
+        }
+        Err(err @ EvsError::CorruptStateDetected(_)) if let Some(corrupt) = &state.corrupt => {
+            warn!("Unreadable \"{}\": {}", name, err);
+            state.missing.lock().unwrap().insert(hash);
+            corrupt.lock().unwrap().push((name.into(), err));
+            return Ok(());
         }
+        Err(err) => return Err(err),
+    };
 
-        let target = target.unwrap();
+    state.found_cache.lock().unwrap().insert(name);
 
-        let target_name = target.file_name().unwrap();
+    state.kinds.lock().unwrap().insert(hash, obj.kind());
 
-        if size_of_val(target_name) != FORMATTED_HASH_SIZE
-            || !target_name
-                .as_encoded_bytes()
-                .iter()
-                .all(|b| matches!(*b, b'0'..=b'9' | b'a'..=b'f'))
-        {
-            return Err(EvsError::CorruptStateDetected(
-                CorruptState::InvalidObjectName(target_name.to_owned()),
-            ));
+    state.progress.step();
+
+    trace!("Validated \"{}\".", HashDisplay(&hash));
+
+    match obj {
+        Object::Null => trace!("Found the NULL object! :)"),
+        Object::Blob(data) => trace!("Found blob of size {}.", data.len()),
+        Object::Symlink(target) => trace!("Found symlink to {}.", target.escape_ascii()),
+        Object::Delta { .. } => {
+            return Err(EvsError::CorruptStateDetected(CorruptState::InvalidDelta(
+                hash,
+                "it is not stored as one",
+            )));
+        }
+        Object::Tag(tag) => {
+            trace!("Found tag {:?}.", tag.name);
+
+            trace!(
+                "Requiring \"{}\" for \"{}\".",
+                HashDisplay(&tag.target),
+                HashDisplay(&hash)
+            );
+
+            state
+                .references
+                .lock()
+                .unwrap()
+                .push((hash, tag.target, TAG_TARGET));
+
+            check_one(store, &current, tag.target, state)?;
         }
+        Object::ChunkList(chunks) => {
+            trace!("Found chunk list with {} chunk(s).", chunks.len());
 
-        trace!("Validated name successfully.");
+            chunks
+                .par_iter()
+                .map(|chunk| {
+                    let _entered = current.enter();
 
-        let resolved = target_name.to_str().unwrap().to_owned();
+                    trace!(
+                        "Requiring \"{}\" for \"{}\".",
+                        HashDisplay(chunk),
+                        HashDisplay(&hash)
+                    );
 
-        Ok(resolved)
+                    state
+                        .references
+                        .lock()
+                        .unwrap()
+                        .push((hash, *chunk, CHUNK_CONTENT));
+
+                    check_one(store, &current, *chunk, state)
+                })
+                .collect::<Result<(), _>>()?;
+        }
+        Object::Tree(items) => {
+            trace!("Found tree with {} child(ren).", items.len());
+
+            items
+                .par_iter()
+                .map(|item| {
+                    let _entered = current.enter();
+
+                    trace!(
+                        "Requiring \"{}\" for \"{}\".",
+                        HashDisplay(&item.content),
+                        HashDisplay(&hash)
+                    );
+
+                    state
+                        .references
+                        .lock()
+                        .unwrap()
+                        .push((hash, item.content, TREE_CONTENT));
+
+                    check_one(store, &current, item.content, state)
+                })
+                .collect::<Result<(), _>>()?;
+        }
+        Object::Commit(commit) => {
+            trace!(
+                "Found commit with state \"{}\" and {} parent(s).",
+                HashDisplay(&commit.tree),
+                commit.parents.len()
+            );
+
+            if commit.parents.is_empty() {
+                return Err(EvsError::CorruptStateDetected(
+                    CorruptState::CommitWithoutParent(hash),
+                ));
+            }
+
+            trace!(
+                "Requiring \"{}\" for \"{}\".",
+                HashDisplay(&commit.tree),
+                HashDisplay(&hash)
+            );
+
+            let mut references = state.references.lock().unwrap();
+
+            for parent in &commit.parents {
+                trace!(
+                    "Requiring \"{}\" for \"{}\".",
+                    HashDisplay(parent),
+                    HashDisplay(&hash)
+                );
+
+                references.push((hash, *parent, COMMIT_PARENT));
+            }
+
+            references.push((hash, commit.tree, COMMIT_TREE));
+
+            drop(references);
+
+            let (parents, tree) = join(
+                || {
+                    commit
+                        .parents
+                        .par_iter()
+                        .map(|parent| check_one(store, &current, *parent, state))
+                        .collect::<Result<(), _>>()
+                },
+                || check_one(store, &current, commit.tree, state),
+            );
+
+            parents?;
+            tree?;
+        }
+    }
+
+    state.found.lock().unwrap().insert(hash);
+
+    Ok(())
+}
+
+/// Keeps serialized objects in memory, so repository logic can run without touching the filesystem.
+#[derive(Debug, Default)]
+pub struct MemStore {
+    objects: Mutex<AHashMap<Hash, Vec<u8>>>,
+    algo: HashAlgo,
+}
+
+impl MemStore {
+    #[inline]
+    #[must_use]
+    pub fn new(algo: HashAlgo) -> Self {
+        Self {
+            objects: Mutex::new(AHashMap::new()),
+            algo,
+        }
     }
+}
 
+impl ObjectStore for MemStore {
     #[inline]
     #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
-    pub fn status(
+    fn status(
         &self,
         parent: &Span,
     ) -> Result<(usize, usize), EvsError> {
-        debug!("Store::status(self)");
+        debug!("MemStore::status(self)");
 
-        self.path
-            .read_dir()
-            .map_err(|e| (e, self.path.clone()))?
-            .try_fold((0, 0usize), |(count, size), entry| match entry {
-                #[allow(clippy::arithmetic_side_effects, reason = "Never going to happen.")]
-                Ok(entry) => Ok((
-                    count + 1,
-                    size.saturating_add(
-                        usize::try_from(entry.metadata().map_err(|e| (e, entry.path()))?.len())
-                            .unwrap(),
-                    ),
-                )),
-                Err(err) => Err((err, self.path.clone()).into()),
-            })
+        let objects = self.objects.lock().unwrap();
+
+        Ok((
+            objects.len(),
+            objects
+                .values()
+                .fold(0usize, |size, data| size.saturating_add(data.len())),
+        ))
+    }
+
+    #[inline]
+    fn algo(&self) -> HashAlgo {
+        self.algo
+    }
+
+    #[inline]
+    fn contains(
+        &self,
+        hash: &Hash,
+    ) -> bool {
+        self.objects.lock().unwrap().contains_key(hash)
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn insert(
+        &self,
+        parent: &Span,
+        mut obj: Object,
+    ) -> Result<Hash, EvsError> {
+        debug!("MemStore::insert(self, ...)");
+
+        if let Object::Tree(entries) = &mut obj {
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+
+        let data = rmp_serde::to_vec(&obj)?;
+
+        let hash = self.algo.digest(&data);
+
+        trace!("Data hashed to \"{}\".", HashDisplay(&hash));
+
+        self.objects.lock().unwrap().entry(hash).or_insert(data);
+
+        Ok(hash)
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn lookup_raw(
+        &self,
+        parent: &Span,
+        id: &str,
+    ) -> Result<(Hash, Object), EvsError> {
+        debug!("MemStore::lookup_raw(self, \"{}\")", id);
+
+        let name = self.resolve_rest(&Span::current(), id.to_owned())?;
+
+        // Names come from the keys of the map
+        let hash = parse_hash(&name).unwrap();
+
+        let data = self
+            .objects
+            .lock()
+            .unwrap()
+            .get(&hash)
+            .cloned()
+            .ok_or(EvsError::ObjectNotInStore(name))?;
+
+        let deserialized = rmp_serde::from_slice::<Object>(&data).map_err(|e| (e, hash))?;
+
+        Ok((hash, deserialized))
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn remove(
+        &self,
+        parent: &Span,
+        hash: Hash,
+    ) -> Result<usize, EvsError> {
+        debug!("MemStore::remove(self, \"{}\")", HashDisplay(&hash));
+
+        self.objects
+            .lock()
+            .unwrap()
+            .remove(&hash)
+            .map(|data| data.len())
+            .ok_or_else(|| EvsError::ObjectNotInStore(format!("{}", HashDisplay(&hash))))
+    }
+
+    #[inline]
+    #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+    fn resolve_rest(
+        &self,
+        parent: &Span,
+        r#ref: String,
+    ) -> Result<String, EvsError> {
+        debug!("MemStore::resolve_rest(self, \"{}\")", r#ref);
+
+        if size_of_val(r#ref.as_str()) > FORMATTED_HASH_SIZE || !is_lower_hex(r#ref.as_bytes()) {
+            return Err(EvsError::ObjectNotInStore(r#ref));
+        }
+
+        let mut found = self
+            .names()?
+            .into_iter()
+            .filter(|name| name.as_encoded_bytes().starts_with(r#ref.as_bytes()))
+            .collect::<Vec<_>>();
+
+        if found.len() > 1 {
+            let mut candidates = found
+                .iter()
+                .map(|name| name.to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+
+            candidates.sort_unstable();
+
+            return Err(EvsError::AmbiguousObject(r#ref, candidates));
+        }
+
+        let Some(target) = found.pop() else {
+            return Err(EvsError::ObjectNotInStore(r#ref));
+        };
+
+        trace!("Found object {:?}.", target);
+
+        // Names come from `HashDisplay`
+        Ok(target.into_string().unwrap())
+    }
+
+    #[inline]
+    fn names(&self) -> Result<Vec<OsString>, EvsError> {
+        Ok(self
+            .objects
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|hash| format!("{}", HashDisplay(hash)).into())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, iter};
+
+    use tracing::Span;
+
+    use super::{
+        DEFAULT_CHUNK_SIZE, DEFAULT_COMPRESSION_LEVEL, HashAlgo, HashDisplay, ObjectStore as _,
+        Store, TMP_DIR,
+    };
+    use crate::{error::EvsError, objects::Object, testing::TempDir};
+
+    fn store(
+        dir: &TempDir,
+        algo: HashAlgo,
+    ) -> Store {
+        Store::new(
+            dir.path().to_path_buf(),
+            false,
+            algo,
+            DEFAULT_COMPRESSION_LEVEL,
+            DEFAULT_CHUNK_SIZE,
+            false,
+        )
+    }
+
+    #[test]
+    fn bootstrap_hashes_match_insert() {
+        for algo in [HashAlgo::Sha256, HashAlgo::Blake3] {
+            let dir = TempDir::new();
+            let store = store(&dir, algo);
+
+            assert_eq!(
+                store.insert(&Span::current(), Object::Null).unwrap(),
+                store.null_hash(),
+                "null hash of {:?}",
+                algo
+            );
+            assert_eq!(
+                store
+                    .insert(&Span::current(), Object::Tree(vec![]))
+                    .unwrap(),
+                store.empty_tree_hash(),
+                "empty tree hash of {:?}",
+                algo
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_stores_are_group_writable() {
+        use std::{fs, os::unix::fs::PermissionsExt as _, path::Path};
+
+        let dir = TempDir::new();
+        let store = Store::new(
+            dir.path().join("store"),
+            true,
+            HashAlgo::Sha256,
+            DEFAULT_COMPRESSION_LEVEL,
+            DEFAULT_CHUNK_SIZE,
+            false,
+        );
+
+        store.create_layout(&Span::current()).unwrap();
+
+        let hash = store
+            .insert(&Span::current(), Object::Blob(b"shared".to_vec()))
+            .unwrap();
+
+        let object = store.object_path(&format!("{}", HashDisplay(&hash)));
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+        assert_eq!(mode(&object), 0o664, "objects are group-writable");
+        assert_eq!(
+            mode(object.parent().unwrap()),
+            0o2775,
+            "shards keep their group"
+        );
+        assert_eq!(mode(store.path()), 0o2775, "the store keeps its group");
+    }
+
+    #[test]
+    fn looking_up_a_missing_full_hash_fails_cleanly() {
+        let dir = TempDir::new();
+        let store = store(&dir, HashAlgo::Sha256);
+
+        let missing = format!("{}", HashDisplay(&[0xab; 32]));
+
+        assert!(
+            matches!(
+                store.lookup(&Span::current(), &missing),
+                Err(EvsError::ObjectNotInStore(name)) if name == missing
+            ),
+            "a missing object is not found"
+        );
+        assert!(
+            matches!(
+                store.resolve_rest(&Span::current(), missing.clone()),
+                Err(EvsError::ObjectNotInStore(name)) if name == missing
+            ),
+            "a missing object is not resolved"
+        );
+    }
+
+    #[test]
+    fn insert_leaves_no_temporary_files() {
+        let dir = TempDir::new();
+        let store = store(&dir, HashAlgo::Sha256);
+
+        let hash = store
+            .insert(&Span::current(), Object::Blob(b"atomic".to_vec()))
+            .unwrap();
+
+        // Inserting it again must not leave a second copy behind either
+        store
+            .insert(&Span::current(), Object::Blob(b"atomic".to_vec()))
+            .unwrap();
+
+        assert!(
+            store
+                .object_path(&format!("{}", HashDisplay(&hash)))
+                .is_file(),
+            "the object is at its final path"
+        );
+        assert!(
+            fs::read_dir(dir.path().join(TMP_DIR))
+                .map_or(true, |mut entries| entries.next().is_none()),
+            "nothing is left in the temporary directory"
+        );
+    }
+
+    #[test]
+    fn incompressible_blobs_are_stored_raw() {
+        let dir = TempDir::new();
+        let store = store(&dir, HashAlgo::Sha256);
+
+        // xorshift, gzip cannot shrink its output
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let data = iter::repeat_with(|| {
+            state ^= state << 13u32;
+            state ^= state >> 7u32;
+            state ^= state << 17u32;
+
+            state.to_le_bytes()
+        })
+        .take(8192)
+        .flatten()
+        .collect::<Vec<_>>();
+
+        let serialized = rmp_serde::to_vec(&Object::Blob(data.clone())).unwrap();
+
+        let hash = store
+            .insert(&Span::current(), Object::Blob(data.clone()))
+            .unwrap();
+
+        let stored = fs::metadata(store.object_path(&format!("{}", HashDisplay(&hash))))
+            .unwrap()
+            .len();
+
+        assert!(
+            stored <= u64::try_from(serialized.len()).unwrap() + 1,
+            "only the marker is added to the object"
+        );
+        assert!(
+            matches!(
+                store.lookup(&Span::current(), &format!("{}", HashDisplay(&hash))).unwrap(),
+                (_, Object::Blob(read)) if read == data
+            ),
+            "the raw object reads back"
+        );
+    }
+
+    #[test]
+    fn deltas_shrink_nearly_identical_blobs() {
+        let dir = TempDir::new();
+        let store = store(&dir, HashAlgo::Sha256);
+
+        let span = Span::current();
+
+        // Incompressible, so only the delta can save space
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let base_data = iter::repeat_with(|| {
+            state ^= state << 13u32;
+            state ^= state >> 7u32;
+            state ^= state << 17u32;
+
+            state.to_le_bytes()
+        })
+        .take(2048)
+        .flatten()
+        .collect::<Vec<_>>();
+
+        let mut target_data = base_data.clone();
+        target_data.extend_from_slice(b"one more line");
+
+        let base = store
+            .insert(&span, Object::Blob(base_data.clone()))
+            .unwrap();
+        let target = store
+            .insert(&span, Object::Blob(target_data.clone()))
+            .unwrap();
+
+        let on_disk = || {
+            [base, target]
+                .iter()
+                .map(|hash| {
+                    fs::metadata(store.object_path(&format!("{}", HashDisplay(hash))))
+                        .unwrap()
+                        .len()
+                })
+                .sum::<u64>()
+        };
+
+        let before = on_disk();
+
+        assert!(
+            store.pack_delta(&span, target, base).unwrap().is_some(),
+            "the target is stored as a delta"
+        );
+        assert!(
+            on_disk() * 3 < before * 2,
+            "the total size drops by about the size of the target"
+        );
+        assert!(
+            matches!(
+                store.lookup(&span, &format!("{}", HashDisplay(&target))).unwrap(),
+                (_, Object::Blob(read)) if read == target_data
+            ),
+            "the delta reads back as the full blob"
+        );
+        assert!(
+            matches!(
+                store.lookup(&span, &format!("{}", HashDisplay(&base))).unwrap(),
+                (_, Object::Blob(read)) if read == base_data
+            ),
+            "the base is unchanged"
+        );
     }
 }
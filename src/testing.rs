@@ -0,0 +1,190 @@
+//! Helpers shared by the unit tests, which run against real repositories in temporary directories.
+
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use ahash::AHashSet;
+use clap::Parser as _;
+use time::OffsetDateTime;
+use tracing::Span;
+
+use crate::{
+    cli::Cli,
+    repo::{Repository, Subtrees},
+    store::{Hash, HashAlgo, MemStore, ObjectStore},
+};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh directory below the system temporary directory, deleted again on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let path = env::temp_dir().join(format!(
+            "evs-test-{}-{}",
+            process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        fs::create_dir_all(&path).unwrap();
+
+        Self(path.canonicalize().unwrap())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes `content` to `name` below the directory, creating missing parents.
+    pub fn write<C: AsRef<[u8]>>(
+        &self,
+        name: &str,
+        content: C,
+    ) -> PathBuf {
+        let path = self.0.join(name);
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A writer collecting everything written to any of its clones.
+#[derive(Clone, Default)]
+pub struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Capture {
+    /// Everything written so far.
+    pub fn output(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for Capture {
+    fn write(
+        &mut self,
+        buf: &[u8],
+    ) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The options of `evs` followed by `args`.
+pub fn cli<const N: usize>(args: [&str; N]) -> Cli {
+    Cli::parse_from(["evs"].into_iter().chain(args))
+}
+
+/// A new repository using `algo` in a new temporary directory.
+pub fn repo_with(algo: HashAlgo) -> (TempDir, Repository) {
+    let dir = TempDir::new();
+
+    let repo = Repository::create(
+        &Span::current(),
+        dir.path(),
+        false,
+        false,
+        algo,
+        &cli(["status"]),
+    )
+    .unwrap();
+
+    (dir, repo)
+}
+
+/// A new repository in a new temporary directory.
+pub fn repo() -> (TempDir, Repository) {
+    repo_with(HashAlgo::Sha256)
+}
+
+/// A new repository in a new temporary directory that keeps its objects in memory.
+pub fn mem_repo() -> (TempDir, Repository<MemStore>) {
+    let dir = TempDir::new();
+
+    let repo = Repository::create_with(
+        &Span::current(),
+        dir.path(),
+        false,
+        false,
+        &cli(["status"]),
+        |_| Ok(MemStore::new(HashAlgo::Sha256)),
+    )
+    .unwrap();
+
+    (dir, repo)
+}
+
+/// Stages `path` below the workspace.
+pub fn add<S: ObjectStore>(
+    repo: &mut Repository<S>,
+    path: &str,
+) {
+    let path = repo.workspace.join(path);
+
+    repo.add(
+        &Span::current(),
+        path,
+        &AHashSet::new(),
+        None,
+        &Subtrees::default(),
+        &cli(["status"]),
+    )
+    .unwrap();
+}
+
+/// Commits the stage on top of HEAD with a fixed identity and date.
+pub fn commit<S: ObjectStore>(
+    repo: &mut Repository<S>,
+    message: &str,
+) -> Hash {
+    repo.commit(
+        &Span::current(),
+        None,
+        message.to_owned(),
+        "Test".to_owned(),
+        "test@example.com".to_owned(),
+        OffsetDateTime::UNIX_EPOCH,
+        None,
+        false,
+        true,
+        false,
+        false,
+        &cli(["status"]),
+    )
+    .unwrap()
+}
+
+/// Writes `content` to `path`, stages the whole workspace and commits it.
+pub fn commit_file<S: ObjectStore>(
+    dir: &TempDir,
+    repo: &mut Repository<S>,
+    path: &str,
+    content: &str,
+) -> Hash {
+    dir.write(path, content);
+
+    add(repo, "");
+
+    commit(repo, path)
+}
@@ -1,19 +1,35 @@
 use std::{
     env::{current_dir, var_os},
-    ffi::OsStr,
+    ffi::{OsStr, OsString},
     fmt::{self, Arguments, Display, Formatter},
+    fs::{self, Metadata},
     io::{self, BufRead as _, ErrorKind, IsTerminal as _, Write as _, stdin, stdout},
+    mem::replace,
     path::{self, Path, PathBuf, absolute},
+    process::Command,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
 };
 
 use clap_complete::CompletionCandidate;
-use glob::glob;
-use tracing::{Span, debug, instrument, trace};
+use glob::{Pattern, glob};
+use serde::Serialize;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tracing::{Level, Span, Subscriber, debug, instrument, trace};
+use tracing_subscriber::{
+    EnvFilter, FmtSubscriber,
+    fmt::{MakeWriter, format::FmtSpan},
+};
 
 use crate::{
-    cli::{Cli, Commands},
+    cli::{Cli, Commands, VERBOSITY_LOG, VERBOSITY_NONE, VERBOSITY_TRACE},
     error::EvsError,
-    repo::Repository,
+    objects::{MODE_DIR, MODE_EXECUTABLE, MODE_FILE, MODE_SYMLINK},
+    repo::{DEFAULT_MAX_TREE_DEPTH, OpenMode, Repository},
+    store::{DEFAULT_CHUNK_SIZE, DEFAULT_COMPRESSION_LEVEL},
 };
 
 #[macro_export]
@@ -23,6 +39,114 @@ macro_rules! confirmation {
     };
 }
 
+/// Prints a summary line to the summary sink, which defaults to stdout.
+#[macro_export]
+macro_rules! summary {
+    ($fmt:literal $($arg:tt)*) => {
+        $crate::util::summary_impl(format_args!($fmt $($arg)*))
+    };
+}
+
+type SummarySink = Option<Box<dyn io::Write + Send>>;
+
+static SUMMARY_SINK: Mutex<SummarySink> = Mutex::new(None);
+
+/// Replaces the summary sink (`None` meaning stdout) and returns the previous one.
+#[inline]
+pub fn set_summary_sink(sink: SummarySink) -> SummarySink {
+    replace(&mut *SUMMARY_SINK.lock().unwrap(), sink)
+}
+
+#[inline]
+pub fn summary_impl(message: Arguments) {
+    let mut sink = SUMMARY_SINK.lock().unwrap();
+
+    let _ = match sink.as_mut() {
+        Some(sink) => writeln!(sink, "{}", message),
+        None => writeln!(stdout(), "{}", message),
+    };
+}
+
+static PROGRESS_EVENTS: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables writing progress events to stderr.
+#[inline]
+pub fn set_progress_events(enabled: bool) {
+    PROGRESS_EVENTS.store(enabled, Ordering::Relaxed);
+}
+
+/// Counts the steps of one phase of a long operation and reports each as a JSON line on stderr if enabled.
+#[derive(Debug)]
+pub struct Progress {
+    phase: &'static str,
+    done: AtomicUsize,
+    total: Option<usize>,
+}
+
+impl Progress {
+    #[inline]
+    #[must_use]
+    pub fn new(
+        phase: &'static str,
+        total: Option<usize>,
+    ) -> Progress {
+        Progress {
+            phase,
+            done: AtomicUsize::new(0),
+            total,
+        }
+    }
+
+    #[inline]
+    pub fn step(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed).saturating_add(1);
+
+        self.emit(done, self.total);
+    }
+
+    /// Reports the final count as the total.
+    #[inline]
+    pub fn finish(&self) {
+        let done = self.done.load(Ordering::Relaxed);
+
+        self.emit(done, Some(done));
+    }
+
+    fn emit(
+        &self,
+        done: usize,
+        total: Option<usize>,
+    ) {
+        if !PROGRESS_EVENTS.load(Ordering::Relaxed) {
+            return;
+        }
+
+        eprintln!("{}", self.event(done, total));
+    }
+
+    /// One line of JSON, `total` is `null` if unknown.
+    fn event(
+        &self,
+        done: usize,
+        total: Option<usize>,
+    ) -> String {
+        #[derive(Serialize)]
+        struct Event {
+            phase: &'static str,
+            done: usize,
+            total: Option<usize>,
+        }
+
+        // Plain strings and numbers always serialize
+        serde_json::to_string(&Event {
+            phase: self.phase,
+            done,
+            total,
+        })
+        .unwrap()
+    }
+}
+
 #[inline]
 #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
 pub fn confirmation_impl(
@@ -60,6 +184,76 @@ pub fn confirmation_impl(
     Ok(response)
 }
 
+/// Lets the user write a message in `$EDITOR`, dropping the lines starting with `#`.
+#[inline]
+#[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+pub fn edit_message(
+    parent: &Span,
+    repository: &Path,
+    template: &str,
+) -> Result<String, EvsError> {
+    debug!("edit_message({:?})", repository);
+
+    let path = repository.join("MESSAGE_EDIT");
+
+    fs::write(&path, template).map_err(|e| (e, path.clone()))?;
+
+    let editor = editor_words(var_os("EDITOR"));
+
+    trace!("Launching editor {:?}...", editor);
+
+    // The words of the editor are never empty
+    let (program, args) = editor.split_first().unwrap();
+
+    let status = Command::new(program)
+        .args(args)
+        .arg(&path)
+        .status()
+        .map_err(|e| (e, PathBuf::from(program)))?;
+
+    if !status.success() {
+        let _ = fs::remove_file(&path);
+
+        return Err(EvsError::EditorFailed(status));
+    }
+
+    let content = fs::read_to_string(&path).map_err(|e| (e, path.clone()))?;
+
+    fs::remove_file(&path).map_err(|e| (e, path.clone()))?;
+
+    let message = content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_owned();
+
+    if message.is_empty() {
+        return Err(EvsError::EmptyCommitMessage);
+    }
+
+    trace!("Read message of length {}.", message.len());
+
+    Ok(message)
+}
+
+/// The program and arguments given by `editor`, falling back to the platform default if it is unset or blank.
+fn editor_words(editor: Option<OsString>) -> Vec<String> {
+    let editor = editor
+        .map(|editor| editor.to_string_lossy().into_owned())
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| {
+            if cfg!(windows) {
+                "notepad".to_owned()
+            } else {
+                "vi".to_owned()
+            }
+        });
+
+    editor.split_whitespace().map(str::to_owned).collect()
+}
+
 #[inline]
 #[must_use]
 pub fn get_color(options: &Cli) -> bool {
@@ -69,6 +263,42 @@ pub fn get_color(options: &Cli) -> bool {
         || options.force_color
 }
 
+/// The level up to which events are logged at the verbosity `verbose`.
+#[inline]
+#[must_use]
+pub fn log_level(verbose: u8) -> Level {
+    match verbose {
+        VERBOSITY_NONE => Level::WARN,
+        VERBOSITY_LOG => Level::INFO,
+        VERBOSITY_TRACE => Level::DEBUG,
+        _ => Level::TRACE,
+    }
+}
+
+/// The subscriber logging the events up to the verbosity of `options` to `writer`.
+#[inline]
+pub fn log_subscriber<W: for<'w> MakeWriter<'w> + Send + Sync + 'static>(
+    options: &Cli,
+    writer: W,
+) -> impl Subscriber + Send + Sync {
+    FmtSubscriber::builder()
+        .with_ansi(get_color(options))
+        .with_ansi_sanitization(true)
+        .with_file(true)
+        .with_level(true)
+        .with_line_number(true)
+        .with_span_events(FmtSpan::NONE)
+        .with_target(true)
+        .with_thread_ids(true)
+        .with_thread_names(false)
+        .with_env_filter(EnvFilter::from_default_env())
+        .without_time()
+        .with_max_level(log_level(options.verbose))
+        .with_writer(writer)
+        .compact()
+        .finish()
+}
+
 pub const INFO_COLOR: &str = "\x1b[36m";
 pub const ADD_COLOR: &str = "\x1b[32m";
 pub const SUB_COLOR: &str = "\x1b[31m";
@@ -121,6 +351,203 @@ impl Display for SizeDisplay {
     }
 }
 
+/// Whether any pattern matches the path or one of its ancestors.
+/// Makes a file or directory of a shared repository group-writable, directories also keep their group for new entries.
+#[cfg(unix)]
+#[inline]
+pub fn set_shared_mode(path: &Path) -> Result<(), EvsError> {
+    use std::{fs, os::unix::fs::PermissionsExt as _};
+
+    let mode = if path.is_dir() { 0o2775 } else { 0o664 };
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .map_err(|e| (e, path.to_path_buf()).into())
+}
+
+/// Does nothing on non-Unix platforms.
+#[cfg(not(unix))]
+#[inline]
+pub fn set_shared_mode(_path: &Path) -> Result<(), EvsError> {
+    Ok(())
+}
+
+/// Tells a directory, an executable and a regular file apart by their metadata.
+#[cfg(unix)]
+#[inline]
+#[must_use]
+pub fn file_mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    if metadata.is_symlink() {
+        MODE_SYMLINK
+    } else if metadata.is_dir() {
+        MODE_DIR
+    } else if metadata.permissions().mode() & 0o111 != 0 {
+        MODE_EXECUTABLE
+    } else {
+        MODE_FILE
+    }
+}
+
+/// Only tells directories apart on non-Unix platforms.
+#[cfg(not(unix))]
+#[inline]
+#[must_use]
+pub fn file_mode(metadata: &Metadata) -> u32 {
+    if metadata.is_dir() {
+        MODE_DIR
+    } else {
+        MODE_FILE
+    }
+}
+
+/// Sets or clears the executable bits of a file wherever it is readable, keeping the other bits.
+#[cfg(unix)]
+#[inline]
+pub fn set_file_mode(
+    path: &Path,
+    mode: u32,
+) -> Result<(), EvsError> {
+    use std::{fs, os::unix::fs::PermissionsExt as _};
+
+    let mut permissions = fs::metadata(path)
+        .map_err(|e| (e, path.to_path_buf()))?
+        .permissions();
+
+    let old = permissions.mode();
+
+    let new = if mode == MODE_EXECUTABLE {
+        old | ((old & 0o444) >> 2u32)
+    } else {
+        old & !0o111
+    };
+
+    if new == old {
+        return Ok(());
+    }
+
+    permissions.set_mode(new);
+
+    fs::set_permissions(path, permissions).map_err(|e| (e, path.to_path_buf()).into())
+}
+
+/// Does nothing on non-Unix platforms.
+#[cfg(not(unix))]
+#[inline]
+pub fn set_file_mode(
+    _path: &Path,
+    _mode: u32,
+) -> Result<(), EvsError> {
+    Ok(())
+}
+
+/// Creates a symlink pointing at the raw `target` bytes.
+#[cfg(unix)]
+#[inline]
+pub fn create_symlink(
+    target: &[u8],
+    path: &Path,
+) -> Result<(), EvsError> {
+    use std::os::unix::{ffi::OsStrExt as _, fs::symlink};
+
+    symlink(OsStr::from_bytes(target), path).map_err(|e| (e, path.to_path_buf()).into())
+}
+
+/// Writes the target into a regular file on non-Unix platforms, where creating symlinks needs privileges.
+#[cfg(not(unix))]
+#[inline]
+pub fn create_symlink(
+    target: &[u8],
+    path: &Path,
+) -> Result<(), EvsError> {
+    std::fs::write(path, target).map_err(|e| (e, path.to_path_buf()).into())
+}
+
+/// Parses a duration like `90`, `90s`, `5m`, `2h` or `7d`, where a bare number means seconds.
+#[inline]
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(split) => text.split_at(split),
+        None => (text, "s"),
+    };
+
+    let number: u64 = number
+        .parse()
+        .map_err(|e| format!("invalid duration {:?}: {}", text, e))?;
+
+    let factor = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown duration unit {:?}", unit)),
+    };
+
+    Ok(Duration::from_secs(number.saturating_mul(factor)))
+}
+
+/// Parses an RFC 3339 date like `2024-01-31T12:00:00Z`, keeping its offset.
+#[inline]
+pub fn parse_date(text: &str) -> Result<OffsetDateTime, String> {
+    OffsetDateTime::parse(text, &Rfc3339)
+        .map_err(|e| format!("invalid RFC 3339 date {:?}: {}", text, e))
+}
+
+/// Parses an identity like `Name <email>` into the name and the email.
+#[inline]
+pub fn parse_identity(text: &str) -> Result<(String, String), String> {
+    text.strip_suffix('>')
+        .and_then(|rest| rest.rsplit_once('<'))
+        .map(|(name, email)| (name.trim().to_owned(), email.to_owned()))
+        .ok_or_else(|| format!("invalid identity {:?}, expected `Name <email>`", text))
+}
+
+/// The current time in the local offset, or in UTC if the offset cannot be determined.
+#[inline]
+#[must_use]
+pub fn local_now() -> OffsetDateTime {
+    OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc())
+}
+
+#[inline]
+#[must_use]
+pub fn matches_any(
+    patterns: &[Pattern],
+    path: &Path,
+) -> bool {
+    patterns
+        .iter()
+        .any(|p| path.ancestors().any(|a| p.matches_path(a)))
+}
+
+/// The stack reserved for each level of nesting on the threads walking directories and trees, generous for debug builds.
+const STACK_PER_LEVEL: usize = 64 * 1024;
+
+/// The default thread stack size of the standard library.
+const MIN_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+/// A thread stack size deep enough for walks of `max_depth` levels, see `check_depth`.
+#[inline]
+#[must_use]
+pub fn walk_stack_size(max_depth: usize) -> usize {
+    max_depth
+        .saturating_mul(STACK_PER_LEVEL)
+        .max(MIN_STACK_SIZE)
+}
+
+/// Fails if `path` has more than `limit` components, which stops walks of deeply nested trees before the stack runs out.
+#[inline]
+pub fn check_depth(
+    path: &Path,
+    limit: usize,
+) -> Result<(), EvsError> {
+    if path.components().count() > limit {
+        return Err(EvsError::TreeTooDeep(path.to_path_buf(), limit));
+    }
+
+    Ok(())
+}
+
 #[inline]
 pub fn repo_ref_completer(current: &OsStr) -> Vec<CompletionCandidate> {
     let Some(current) = current.to_str() else {
@@ -131,42 +558,81 @@ pub fn repo_ref_completer(current: &OsStr) -> Vec<CompletionCandidate> {
         verbose: 0,
         no_color: true,
         force_color: false,
+        quiet: true,
+        repo: None,
+        progress: None,
+        compression: DEFAULT_COMPRESSION_LEVEL,
+        chunk_size: DEFAULT_CHUNK_SIZE,
+        no_cache: false,
+        max_tree_depth: DEFAULT_MAX_TREE_DEPTH,
         command: Commands::Completion,
     };
 
-    let Ok(repo) = Repository::find(&Span::current(), ".", &cli) else {
-        return Vec::new();
-    };
-
-    let Some(store_dir) = repo.store.path().to_str() else {
+    let Ok(repo) = Repository::find(&Span::current(), ".", OpenMode::Normal, &cli) else {
         return Vec::new();
     };
 
-    //TODO: BRANCHES
-
     let mut result = if "HEAD".starts_with(current) {
         vec![CompletionCandidate::new("HEAD")]
     } else {
         Vec::new()
     };
 
-    if let Ok(paths) = glob(&format!(
-        "{}{}{}*",
-        store_dir,
-        path::MAIN_SEPARATOR,
-        current
-    )) {
+    if let Ok(branches) = repo.list_branches(&Span::current()) {
         result.extend(
-            paths
-                .flatten()
-                .flat_map(|p| p.strip_prefix(store_dir).map(Path::to_path_buf))
-                .map(CompletionCandidate::new),
+            branches
+                .into_iter()
+                .filter(|(name, _)| name.starts_with(current))
+                .map(|(name, _)| CompletionCandidate::new(name)),
         );
     }
 
+    if let Ok(names) = repo.store.names_with_prefix(&Span::current(), current) {
+        result.extend(names.into_iter().map(CompletionCandidate::new));
+    }
+
     result
 }
 
+/// Returns the paths matching `path` if it contains glob characters, failing if none match.
+/// An existing path is taken literally even if it contains glob characters.
+#[inline]
+#[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
+pub fn expand_glob(
+    parent: &Span,
+    path: &Path,
+) -> Result<Option<Vec<PathBuf>>, EvsError> {
+    debug!("expand_glob({:?})", path);
+
+    let Some(pattern) = path.to_str().filter(|p| p.contains(['*', '?', '['])) else {
+        return Ok(None);
+    };
+
+    if fs::symlink_metadata(path).is_ok() {
+        trace!("Taking existing {:?} literally.", path);
+
+        return Ok(None);
+    }
+
+    let matches = glob(pattern)?
+        .map(|entry| {
+            entry.map_err(|e| {
+                let path = e.path().to_path_buf();
+
+                EvsError::from((e.into_error(), path))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    trace!("Pattern {:?} matched {} path(s).", pattern, matches.len());
+
+    if matches.is_empty() {
+        return Err(EvsError::NoGlobMatches(pattern.to_owned()));
+    }
+
+    Ok(Some(matches))
+}
+
 #[inline]
 #[instrument(parent = parent, level = "debug", err(level = "debug"), skip_all)]
 pub fn partial_canonicalize<T: AsRef<Path>>(
@@ -213,3 +679,105 @@ pub fn partial_canonicalize<T: AsRef<Path>>(
 
     unreachable!("An absolute path always has an ancestor.")
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+    use tracing::{Span, debug, info, subscriber::with_default, trace, warn};
+
+    use super::{Progress, editor_words, expand_glob, log_subscriber};
+    use crate::testing::{Capture, TempDir, cli};
+
+    #[test]
+    fn progress_events_are_json_lines() {
+        let progress = Progress::new("hashing \"files\"", Some(2));
+
+        let lines = [progress.event(1, Some(2)), progress.event(3, None)].join("\n");
+
+        let events = lines
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            events,
+            [
+                json!({"phase": "hashing \"files\"", "done": 1u64, "total": 2u64}),
+                json!({"phase": "hashing \"files\"", "done": 3u64, "total": null}),
+            ],
+            "every line is one event"
+        );
+    }
+
+    #[test]
+    fn blank_editors_fall_back_to_the_default() {
+        let default = if cfg!(windows) { "notepad" } else { "vi" };
+
+        for editor in [None, Some(""), Some("  "), Some("\t\n")] {
+            assert_eq!(
+                editor_words(editor.map(Into::into)),
+                [default],
+                "{editor:?} is blank"
+            );
+        }
+
+        assert_eq!(
+            editor_words(Some("code  --wait".into())),
+            ["code", "--wait"],
+            "arguments are split off"
+        );
+    }
+
+    #[test]
+    fn existing_paths_are_not_globbed() {
+        let dir = TempDir::new();
+
+        let literal = dir.write("[a]", "literal");
+        let other = dir.write("a", "other");
+
+        assert!(
+            expand_glob(&Span::current(), &literal).unwrap().is_none(),
+            "the existing file is taken literally"
+        );
+
+        dir.write("b", "b");
+
+        assert!(
+            expand_glob(&Span::current(), &dir.path().join("[ab]"))
+                .unwrap()
+                .is_some_and(|matches| matches.len() == 2 && matches.contains(&other)),
+            "missing paths are still globbed"
+        );
+    }
+
+    #[test]
+    fn log_output_follows_the_verbosity() {
+        for verbose in 0u8..=3u8 {
+            let capture = Capture::default();
+            let writer = capture.clone();
+
+            let mut options = cli(["status"]);
+            options.verbose = verbose;
+
+            with_default(log_subscriber(&options, move || writer.clone()), || {
+                warn!("at warn");
+                info!("at info");
+                debug!("at debug");
+                trace!("at trace");
+            });
+
+            let output = capture.output();
+
+            for (threshold, message) in ["at warn", "at info", "at debug", "at trace"]
+                .into_iter()
+                .enumerate()
+            {
+                assert_eq!(
+                    output.contains(message),
+                    threshold <= verbose.into(),
+                    "{message:?} is logged from its verbosity on, at {verbose}"
+                );
+            }
+        }
+    }
+}